@@ -0,0 +1,176 @@
+use std::env;
+use std::str::FromStr;
+use crate::utils::PasswordParams;
+
+// Tunable settings loaded from environment variables
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub jwt_algorithm: String,
+    pub jwt_signing_key_file: String,
+    pub jwt_signing_key_id: String,
+    pub jwt_verification_key_files: String,
+    pub jwt_accept_legacy_hs256: bool,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub access_token_ttl_minutes: i64,
+    pub refresh_token_ttl_days: i64,
+    pub password_reset_ttl_minutes: i64,
+    pub app_base_url: String,
+    pub mail_transport: String,
+    pub mail_from: String,
+    pub mail_dir: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_security: String,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    pub email_verification_ttl_hours: i64,
+    pub unverified_can_create_groups: bool,
+    pub unverified_can_message_strangers: bool,
+    pub totp_issuer: String,
+    pub two_factor_challenge_ttl_minutes: i64,
+    pub login_max_failures: i64,
+    pub login_max_failures_per_ip: i64,
+    pub login_failure_window_minutes: i64,
+    pub login_lockout_base_seconds: i64,
+    pub login_lockout_max_seconds: i64,
+    pub password_hash_memory_kib: u32,
+    pub password_hash_iterations: u32,
+    pub password_hash_parallelism: u32,
+    pub ws_ticket_ttl_seconds: i64,
+    pub ws_auth_timeout_seconds: u64,
+    pub max_bots_per_user: u64,
+    pub oidc_issuer_url: String,
+    pub oidc_client_id: String,
+    pub oidc_client_secret: String,
+    pub oidc_redirect_uri: String,
+    pub oidc_scopes: String,
+    pub oidc_auto_provision: bool,
+    pub oidc_link_by_email: bool,
+    pub oidc_state_ttl_minutes: i64,
+    pub admin_emails: String,
+    pub storage_backend: String,
+    pub storage_local_dir: String,
+    pub storage_public_url: String,
+    pub avatar_max_bytes: u64,
+    pub presence_idle_timeout_seconds: u64,
+    pub message_edit_window_seconds: i64,
+    pub message_recall_window_seconds: i64,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            jwt_algorithm: env_or("JWT_ALGORITHM", "HS256".to_string()),
+            jwt_signing_key_file: env_or("JWT_SIGNING_KEY_FILE", String::new()),
+            jwt_signing_key_id: env_or("JWT_SIGNING_KEY_ID", String::new()),
+            jwt_verification_key_files: env_or("JWT_VERIFICATION_KEY_FILES", String::new()),
+            jwt_accept_legacy_hs256: env_or("JWT_ACCEPT_LEGACY_HS256", false),
+            jwt_issuer: env_or("JWT_ISSUER", "im-tt".to_string()),
+            jwt_audience: env_or("JWT_AUDIENCE", "im-tt-api".to_string()),
+            access_token_ttl_minutes: env_or("ACCESS_TOKEN_TTL_MINUTES", 15),
+            refresh_token_ttl_days: env_or("REFRESH_TOKEN_TTL_DAYS", 30),
+            password_reset_ttl_minutes: env_or("PASSWORD_RESET_TTL_MINUTES", 30),
+            app_base_url: env_or("APP_BASE_URL", "http://localhost:1420".to_string()),
+            mail_transport: env_or("MAIL_TRANSPORT", "log".to_string()),
+            mail_from: env_or("MAIL_FROM", "IM-TT <noreply@localhost>".to_string()),
+            mail_dir: env_or("MAIL_DIR", "mail".to_string()),
+            smtp_host: env_or("SMTP_HOST", "localhost".to_string()),
+            smtp_port: env_or("SMTP_PORT", 587),
+            smtp_security: env_or("SMTP_SECURITY", "starttls".to_string()),
+            smtp_username: env_or("SMTP_USERNAME", String::new()),
+            smtp_password: env_or("SMTP_PASSWORD", String::new()),
+            email_verification_ttl_hours: env_or("EMAIL_VERIFICATION_TTL_HOURS", 24),
+            unverified_can_create_groups: env_or("UNVERIFIED_CAN_CREATE_GROUPS", false),
+            unverified_can_message_strangers: env_or("UNVERIFIED_CAN_MESSAGE_STRANGERS", false),
+            totp_issuer: env_or("TOTP_ISSUER", "IM-TT".to_string()),
+            two_factor_challenge_ttl_minutes: env_or("TWO_FACTOR_CHALLENGE_TTL_MINUTES", 5),
+            login_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            login_max_failures_per_ip: env_or("LOGIN_MAX_FAILURES_PER_IP", 20),
+            login_failure_window_minutes: env_or("LOGIN_FAILURE_WINDOW_MINUTES", 15),
+            login_lockout_base_seconds: env_or("LOGIN_LOCKOUT_BASE_SECONDS", 30),
+            login_lockout_max_seconds: env_or("LOGIN_LOCKOUT_MAX_SECONDS", 3600),
+            password_hash_memory_kib: env_or("PASSWORD_HASH_MEMORY_KIB", 19456),
+            password_hash_iterations: env_or("PASSWORD_HASH_ITERATIONS", 2),
+            password_hash_parallelism: env_or("PASSWORD_HASH_PARALLELISM", 1),
+            ws_ticket_ttl_seconds: env_or("WS_TICKET_TTL_SECONDS", 30),
+            ws_auth_timeout_seconds: env_or("WS_AUTH_TIMEOUT_SECONDS", 10),
+            max_bots_per_user: env_or("MAX_BOTS_PER_USER", 10),
+            oidc_issuer_url: env_or("OIDC_ISSUER_URL", String::new()),
+            oidc_client_id: env_or("OIDC_CLIENT_ID", String::new()),
+            oidc_client_secret: env_or("OIDC_CLIENT_SECRET", String::new()),
+            oidc_redirect_uri: env_or("OIDC_REDIRECT_URI", "http://localhost:1420/oidc/callback".to_string()),
+            oidc_scopes: env_or("OIDC_SCOPES", "openid email profile".to_string()),
+            oidc_auto_provision: env_or("OIDC_AUTO_PROVISION", true),
+            oidc_link_by_email: env_or("OIDC_LINK_BY_EMAIL", true),
+            oidc_state_ttl_minutes: env_or("OIDC_STATE_TTL_MINUTES", 10),
+            admin_emails: env_or("ADMIN_EMAILS", String::new()),
+            storage_backend: env_or("STORAGE_BACKEND", "local".to_string()),
+            storage_local_dir: env_or("STORAGE_LOCAL_DIR", "uploads".to_string()),
+            storage_public_url: env_or("STORAGE_PUBLIC_URL", "http://localhost:8080/uploads".to_string()),
+            avatar_max_bytes: env_or("AVATAR_MAX_BYTES", 5 * 1024 * 1024),
+            presence_idle_timeout_seconds: env_or("PRESENCE_IDLE_TIMEOUT_SECONDS", 300),
+            message_edit_window_seconds: env_or("MESSAGE_EDIT_WINDOW_SECONDS", 900),
+            message_recall_window_seconds: env_or("MESSAGE_RECALL_WINDOW_SECONDS", 120),
+        }
+    }
+
+    pub fn access_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.access_token_ttl_minutes)
+    }
+
+    pub fn refresh_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::days(self.refresh_token_ttl_days)
+    }
+
+    pub fn password_reset_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.password_reset_ttl_minutes)
+    }
+
+    pub fn email_verification_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.email_verification_ttl_hours)
+    }
+
+    pub fn two_factor_challenge_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.two_factor_challenge_ttl_minutes)
+    }
+
+    pub fn ws_ticket_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.ws_ticket_ttl_seconds)
+    }
+
+    pub fn ws_auth_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ws_auth_timeout_seconds)
+    }
+
+    pub fn presence_idle_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.presence_idle_timeout_seconds)
+    }
+
+    pub fn message_edit_window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.message_edit_window_seconds)
+    }
+
+    pub fn message_recall_window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.message_recall_window_seconds)
+    }
+
+    pub fn oidc_state_ttl(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.oidc_state_ttl_minutes)
+    }
+
+    pub fn password_params(&self) -> PasswordParams {
+        PasswordParams {
+            memory_kib: self.password_hash_memory_kib,
+            iterations: self.password_hash_iterations,
+            parallelism: self.password_hash_parallelism,
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,5 +1,7 @@
 pub mod mysql;
 pub mod redis_client;
+#[cfg(test)]
+pub mod sqlite;
 
 pub use mysql::*;
 pub use redis_client::*;
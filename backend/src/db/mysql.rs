@@ -18,16 +18,73 @@ pub async fn run_migrations(db: &DbConn) -> Result<(), DbErr> {
             username VARCHAR(50) NOT NULL UNIQUE,
             email VARCHAR(100) NOT NULL UNIQUE,
             password_hash VARCHAR(255) NOT NULL,
+            display_name VARCHAR(64),
+            bio VARCHAR(500),
             avatar VARCHAR(255),
-            status VARCHAR(20) DEFAULT 'offline',
+            status VARCHAR(20) NOT NULL DEFAULT 'online',
+            last_seen_at DATETIME DEFAULT NULL,
+            status_text VARCHAR(100),
+            status_emoji VARCHAR(32),
+            status_expires_at DATETIME DEFAULT NULL,
+            email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+            totp_secret VARCHAR(64),
+            totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+            is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+            owner_id BIGINT,
+            role VARCHAR(20) NOT NULL DEFAULT 'user',
+            suspended_at DATETIME DEFAULT NULL,
             created_at DATETIME DEFAULT NULL,
             updated_at DATETIME DEFAULT NULL,
             INDEX idx_username (username),
-            INDEX idx_email (email)
+            INDEX idx_email (email),
+            INDEX idx_owner (owner_id),
+            INDEX idx_directory (suspended_at, username, email),
+            INDEX idx_suspended (suspended_at),
+            INDEX idx_status_expires (status_expires_at)
         ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
         "#.to_owned()
     )).await?;
 
+    // Accounts that existed before email verification are treated as verified
+    if add_column_if_missing(db, "users", "email_verified", "BOOLEAN NOT NULL DEFAULT FALSE").await? {
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "UPDATE users SET email_verified = TRUE".to_owned()
+        )).await?;
+    }
+    add_column_if_missing(db, "users", "totp_secret", "VARCHAR(64)").await?;
+    add_column_if_missing(db, "users", "totp_enabled", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    add_column_if_missing(db, "users", "is_bot", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    if add_column_if_missing(db, "users", "owner_id", "BIGINT").await? {
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "CREATE INDEX idx_owner ON users (owner_id)".to_owned()
+        )).await?;
+    }
+    add_column_if_missing(db, "users", "role", "VARCHAR(20) NOT NULL DEFAULT 'user'").await?;
+    add_column_if_missing(db, "users", "suspended_at", "DATETIME DEFAULT NULL").await?;
+    add_column_if_missing(db, "users", "display_name", "VARCHAR(64)").await?;
+    add_column_if_missing(db, "users", "bio", "VARCHAR(500)").await?;
+    // status used to mirror login and logout; it now holds the presence state
+    // a user picked, and whether they are connected is tracked in memory
+    if add_column_if_missing(db, "users", "last_seen_at", "DATETIME DEFAULT NULL").await? {
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "UPDATE users SET status = 'online'".to_owned()
+        )).await?;
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "ALTER TABLE users MODIFY status VARCHAR(20) NOT NULL DEFAULT 'online'".to_owned()
+        )).await?;
+    }
+    add_column_if_missing(db, "users", "status_text", "VARCHAR(100)").await?;
+    add_column_if_missing(db, "users", "status_emoji", "VARCHAR(32)").await?;
+    add_column_if_missing(db, "users", "status_expires_at", "DATETIME DEFAULT NULL").await?;
+    add_index_if_missing(db, "users", "idx_status_expires", "INDEX idx_status_expires (status_expires_at)").await?;
+    // Directory listing and search, see services::directory
+    add_index_if_missing(db, "users", "idx_directory", "INDEX idx_directory (suspended_at, username, email)").await?;
+    add_index_if_missing(db, "users", "idx_suspended", "INDEX idx_suspended (suspended_at)").await?;
+
     db.execute(Statement::from_string(
         db.get_database_backend(),
         r#"
@@ -75,16 +132,245 @@ pub async fn run_migrations(db: &DbConn) -> Result<(), DbErr> {
             message_type VARCHAR(20) DEFAULT 'text',
             created_at DATETIME DEFAULT NULL,
             is_read BOOLEAN DEFAULT FALSE,
+            edited_at DATETIME DEFAULT NULL,
+            deleted_at DATETIME DEFAULT NULL,
             FOREIGN KEY (sender_id) REFERENCES users(id) ON DELETE CASCADE,
             FOREIGN KEY (receiver_id) REFERENCES users(id) ON DELETE CASCADE,
             FOREIGN KEY (group_id) REFERENCES groups_table(id) ON DELETE CASCADE,
             INDEX idx_sender (sender_id),
             INDEX idx_receiver (receiver_id),
             INDEX idx_group (group_id),
+            INDEX idx_created (created_at),
+            INDEX idx_conversation (sender_id, receiver_id, id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+    // Direct message history, see services::message_history
+    add_index_if_missing(db, "messages", "idx_conversation", "INDEX idx_conversation (sender_id, receiver_id, id)").await?;
+    add_column_if_missing(db, "messages", "edited_at", "DATETIME DEFAULT NULL").await?;
+    add_column_if_missing(db, "messages", "deleted_at", "DATETIME DEFAULT NULL").await?;
+
+    // Earlier revisions of edited messages, see services::message_edit
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS message_edits (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            message_id BIGINT NOT NULL,
+            content TEXT NOT NULL,
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+            INDEX idx_message (message_id, id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    // Messages a user deleted for themselves, see services::message_delete
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS message_hides (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            message_id BIGINT NOT NULL,
+            user_id BIGINT NOT NULL,
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            UNIQUE KEY unique_hide (user_id, message_id),
+            INDEX idx_message (message_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            id VARCHAR(64) PRIMARY KEY,
+            user_id BIGINT NOT NULL,
+            device_name VARCHAR(100),
+            user_agent VARCHAR(255),
+            ip_address VARCHAR(45),
+            created_at DATETIME DEFAULT NULL,
+            last_active_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user (user_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS recovery_codes (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            user_id BIGINT NOT NULL,
+            code_hash CHAR(64) NOT NULL,
+            used_at DATETIME DEFAULT NULL,
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user (user_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS login_lockouts (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            scope VARCHAR(20) NOT NULL,
+            identifier VARCHAR(255) NOT NULL,
+            failures INT NOT NULL,
+            locked_until DATETIME NOT NULL,
+            created_at DATETIME DEFAULT NULL,
+            INDEX idx_identifier (scope, identifier),
             INDEX idx_created (created_at)
         ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
         "#.to_owned()
     )).await?;
 
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            user_id BIGINT NOT NULL,
+            name VARCHAR(100) NOT NULL,
+            key_prefix VARCHAR(16) NOT NULL,
+            key_hash CHAR(64) NOT NULL UNIQUE,
+            scopes TEXT NOT NULL,
+            expires_at DATETIME DEFAULT NULL,
+            last_used_at DATETIME DEFAULT NULL,
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            INDEX idx_user (user_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS oidc_identities (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            user_id BIGINT NOT NULL,
+            issuer VARCHAR(255) NOT NULL,
+            subject VARCHAR(255) NOT NULL,
+            email VARCHAR(100),
+            created_at DATETIME DEFAULT NULL,
+            last_login_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            UNIQUE KEY unique_subject (issuer, subject),
+            INDEX idx_user (user_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS blocks (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            blocker_id BIGINT NOT NULL,
+            blocked_id BIGINT NOT NULL,
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (blocker_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY (blocked_id) REFERENCES users(id) ON DELETE CASCADE,
+            UNIQUE KEY unique_block (blocker_id, blocked_id),
+            INDEX idx_blocked (blocked_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS friend_requests (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            sender_id BIGINT NOT NULL,
+            receiver_id BIGINT NOT NULL,
+            message VARCHAR(200),
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (sender_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY (receiver_id) REFERENCES users(id) ON DELETE CASCADE,
+            UNIQUE KEY unique_request (sender_id, receiver_id),
+            INDEX idx_receiver (receiver_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        r#"
+        CREATE TABLE IF NOT EXISTS contacts (
+            id BIGINT AUTO_INCREMENT PRIMARY KEY,
+            user_id BIGINT NOT NULL,
+            contact_id BIGINT NOT NULL,
+            created_at DATETIME DEFAULT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY (contact_id) REFERENCES users(id) ON DELETE CASCADE,
+            UNIQUE KEY unique_contact (user_id, contact_id),
+            INDEX idx_contact (contact_id)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+        "#.to_owned()
+    )).await?;
+
     Ok(())
 }
+
+// CREATE TABLE IF NOT EXISTS leaves existing tables alone, so columns added
+// after a table was first created are applied here. Returns true if the
+// column was added.
+async fn add_column_if_missing(db: &DbConn, table: &str, column: &str, definition: &str) -> Result<bool, DbErr> {
+    use sea_orm::{ConnectionTrait, Statement};
+
+    let row = db.query_one(Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT COUNT(*) AS cnt FROM information_schema.COLUMNS \
+         WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?",
+        [table.into(), column.into()],
+    )).await?;
+
+    let exists = match row {
+        Some(row) => row.try_get::<i64>("", "cnt")? > 0,
+        None => false,
+    };
+    if exists {
+        return Ok(false);
+    }
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+    )).await?;
+
+    Ok(true)
+}
+
+// Same as add_column_if_missing, for indexes added to existing tables
+async fn add_index_if_missing(db: &DbConn, table: &str, index: &str, definition: &str) -> Result<bool, DbErr> {
+    use sea_orm::{ConnectionTrait, Statement};
+
+    let row = db.query_one(Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT COUNT(*) AS cnt FROM information_schema.STATISTICS \
+         WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND INDEX_NAME = ?",
+        [table.into(), index.into()],
+    )).await?;
+
+    let exists = match row {
+        Some(row) => row.try_get::<i64>("", "cnt")? > 0,
+        None => false,
+    };
+    if exists {
+        return Ok(false);
+    }
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        format!("ALTER TABLE {} ADD {}", table, definition),
+    )).await?;
+
+    Ok(true)
+}
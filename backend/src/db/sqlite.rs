@@ -0,0 +1,84 @@
+use sea_orm::{ActiveModelTrait, ConnectOptions, ConnectionTrait, Database, EntityTrait, Schema, Set};
+use super::DbConn;
+use crate::entity::{self, users};
+use crate::models::PresenceStatus;
+use crate::services::admin;
+use crate::utils::UNUSABLE_PASSWORD;
+
+// In-memory SQLite database with every table, for tests of code that needs
+// real queries. The schema comes from the entities rather than the MySQL
+// migrations, so it has no column defaults or secondary indexes apart from
+// the unique keys the services rely on to detect duplicates.
+pub async fn create_test_connection() -> DbConn {
+    // Each SQLite connection would get its own empty in-memory database
+    let mut options = ConnectOptions::new("sqlite::memory:");
+    options.max_connections(1).min_connections(1).sqlx_logging(false);
+    let db = Database::connect(options).await.expect("Failed to open SQLite");
+
+    create_table(&db, entity::users::Entity).await;
+    create_table(&db, entity::messages::Entity).await;
+    create_table(&db, entity::groups::Entity).await;
+    create_table(&db, entity::group_members::Entity).await;
+    create_table(&db, entity::sessions::Entity).await;
+    create_table(&db, entity::recovery_codes::Entity).await;
+    create_table(&db, entity::login_lockouts::Entity).await;
+    create_table(&db, entity::api_keys::Entity).await;
+    create_table(&db, entity::oidc_identities::Entity).await;
+    create_table(&db, entity::blocks::Entity).await;
+    create_table(&db, entity::friend_requests::Entity).await;
+    create_table(&db, entity::contacts::Entity).await;
+    create_table(&db, entity::message_edits::Entity).await;
+    create_table(&db, entity::message_hides::Entity).await;
+
+    for statement in [
+        "CREATE UNIQUE INDEX unique_member ON group_members (group_id, user_id)",
+        "CREATE UNIQUE INDEX unique_hide ON message_hides (user_id, message_id)",
+        "CREATE UNIQUE INDEX unique_subject ON oidc_identities (issuer, subject)",
+        "CREATE UNIQUE INDEX unique_block ON blocks (blocker_id, blocked_id)",
+        "CREATE UNIQUE INDEX unique_request ON friend_requests (sender_id, receiver_id)",
+        "CREATE UNIQUE INDEX unique_contact ON contacts (user_id, contact_id)",
+    ] {
+        db.execute_unprepared(statement)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to run {}: {:?}", statement, e));
+    }
+    db
+}
+
+async fn create_table<E: EntityTrait>(db: &DbConn, entity: E) {
+    let backend = db.get_database_backend();
+    let statement = Schema::new(backend).create_table_from_entity(entity);
+    db.execute(backend.build(&statement))
+        .await
+        .unwrap_or_else(|e| panic!("Failed to create {}: {:?}", entity.table_name(), e));
+}
+
+// Verified human account with the given username and username@example.com
+pub async fn create_test_user(db: &DbConn, username: &str) -> users::Model {
+    users::ActiveModel {
+        id: sea_orm::ActiveValue::NotSet,
+        username: Set(username.to_string()),
+        email: Set(format!("{}@example.com", username)),
+        password_hash: Set(UNUSABLE_PASSWORD.to_string()),
+        display_name: Set(None),
+        bio: Set(None),
+        avatar: Set(None),
+        status: Set(PresenceStatus::Online.as_str().to_string()),
+        last_seen_at: Set(None),
+        status_text: Set(None),
+        status_emoji: Set(None),
+        status_expires_at: Set(None),
+        email_verified: Set(true),
+        totp_secret: Set(None),
+        totp_enabled: Set(false),
+        is_bot: Set(false),
+        owner_id: Set(None),
+        role: Set(admin::ROLE_USER.to_string()),
+        suspended_at: Set(None),
+        created_at: Set(Some(chrono::Utc::now().naive_utc())),
+        updated_at: Set(None),
+    }
+    .insert(db)
+    .await
+    .unwrap()
+}
@@ -0,0 +1,37 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "friend_requests")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub sender_id: i64,
+    pub receiver_id: i64,
+    pub message: Option<String>,
+    pub created_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::users::Entity",
+        from = "Column::SenderId",
+        to = "super::users::Column::Id"
+    )]
+    Sender,
+    #[sea_orm(
+        belongs_to = "super::users::Entity",
+        from = "Column::ReceiverId",
+        to = "super::users::Column::Id"
+    )]
+    Receiver,
+}
+
+impl Related<super::users::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Sender.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -13,6 +13,8 @@ pub struct Model {
     pub message_type: String,
     pub created_at: Option<DateTime>,
     pub is_read: bool,
+    pub edited_at: Option<DateTime>,
+    pub deleted_at: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
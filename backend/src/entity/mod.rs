@@ -2,8 +2,13 @@ pub mod users;
 pub mod messages;
 pub mod groups;
 pub mod group_members;
-
-pub use users::Entity as Users;
-pub use messages::Entity as Messages;
-pub use groups::Entity as Groups;
-pub use group_members::Entity as GroupMembers;
+pub mod sessions;
+pub mod recovery_codes;
+pub mod login_lockouts;
+pub mod api_keys;
+pub mod oidc_identities;
+pub mod blocks;
+pub mod friend_requests;
+pub mod contacts;
+pub mod message_edits;
+pub mod message_hides;
@@ -12,8 +12,22 @@ pub struct Model {
     pub email: String,
     #[serde(skip_serializing)]
     pub password_hash: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
     pub avatar: Option<String>,
-    pub status: String,
+    pub status: String, // presence picked by the user, see models::PresenceStatus
+    pub last_seen_at: Option<DateTime>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_expires_at: Option<DateTime>,
+    pub email_verified: bool,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub is_bot: bool,
+    pub owner_id: Option<i64>, // human account that manages this bot
+    pub role: String,
+    pub suspended_at: Option<DateTime>,
     pub created_at: Option<DateTime>,
     pub updated_at: Option<DateTime>,
 }
@@ -26,6 +40,14 @@ pub enum Relation {
     Groups,
     #[sea_orm(has_many = "super::group_members::Entity")]
     GroupMembers,
+    #[sea_orm(has_many = "super::sessions::Entity")]
+    Sessions,
+    #[sea_orm(has_many = "super::recovery_codes::Entity")]
+    RecoveryCodes,
+    #[sea_orm(has_many = "super::api_keys::Entity")]
+    ApiKeys,
+    #[sea_orm(has_many = "super::oidc_identities::Entity")]
+    OidcIdentities,
 }
 
 impl Related<super::messages::Entity> for Entity {
@@ -46,4 +68,28 @@ impl Related<super::group_members::Entity> for Entity {
     }
 }
 
+impl Related<super::sessions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Sessions.def()
+    }
+}
+
+impl Related<super::recovery_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RecoveryCodes.def()
+    }
+}
+
+impl Related<super::api_keys::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKeys.def()
+    }
+}
+
+impl Related<super::oidc_identities::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OidcIdentities.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
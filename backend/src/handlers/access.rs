@@ -0,0 +1,30 @@
+use salvo::prelude::*;
+use sea_orm::DatabaseConnection;
+use crate::services::membership;
+use crate::services::message_history::Conversation;
+
+// Renders 403 and returns false when the user may not access the
+// conversation, so that handlers reject outsiders the same way everywhere
+pub(super) async fn require_access(db: &DatabaseConnection, res: &mut Response, user_id: i64, conversation: &Conversation) -> bool {
+    match membership::can_access(db, user_id, conversation).await {
+        Ok(true) => true,
+        Ok(false) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": match conversation {
+                    Conversation::Direct { .. } => "You are not part of this conversation",
+                    Conversation::Group(_) => "You are not a member of this group",
+                }
+            })));
+            false
+        }
+        Err(e) => {
+            tracing::error!("Failed to check access of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to check access"
+            })));
+            false
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use salvo::prelude::*;
+use super::request::{parse_validated, parse_validated_query};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use redis::aio::MultiplexedConnection;
+use crate::config::Config;
+use crate::models::{AdminUserQuery, UpdateRoleRequest};
+use crate::entity::{users, users::Entity as Users};
+use crate::services::admin;
+use crate::websocket::Clients;
+
+// Look up the target account from the <id> path parameter. Admins may not
+// suspend, demote or sign out themselves, so a server cannot lose its last
+// admin by accident.
+async fn find_target_user(req: &mut Request, res: &mut Response, depot: &Depot, allow_self: bool) -> Option<users::Model> {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let admin_id = depot.get::<i64>("user_id").unwrap();
+    let user_id = req.param::<i64>("id").unwrap_or(0);
+
+    if !allow_self && user_id == *admin_id {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(serde_json::json!({
+            "error": "You cannot do this to your own account"
+        })));
+        return None;
+    }
+
+    match Users::find_by_id(user_id).one(db).await {
+        Ok(Some(user)) => Some(user),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+            None
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch user"
+            })));
+            None
+        }
+    }
+}
+
+#[handler]
+pub async fn get_server_stats(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+
+    match admin::stats(db, clients, config).await {
+        Ok(stats) => {
+            res.render(Json(stats));
+        }
+        Err(e) => {
+            tracing::error!("Failed to collect server stats: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch server stats"
+            })));
+        }
+    }
+}
+
+// Every account including suspended ones and bots, paged with ?cursor=
+#[handler]
+pub async fn admin_get_users(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+
+    let Some(params) = parse_validated_query::<AdminUserQuery>(req, res) else {
+        return;
+    };
+
+    match admin::list_users(db, &params).await {
+        Ok(Some(page)) => {
+            res.render(Json(page));
+        }
+        Ok(None) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid cursor"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to list users: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch users"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn update_user_role(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+
+    let Some(user) = find_target_user(req, res, depot, false).await else {
+        return;
+    };
+
+    let Some(role_data) = parse_validated::<UpdateRoleRequest>(req, res).await else {
+        return;
+    };
+
+    if user.is_bot && role_data.role == admin::ROLE_ADMIN {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(serde_json::json!({
+            "error": "Bots cannot be administrators"
+        })));
+        return;
+    }
+
+    let user_id = user.id;
+    match admin::set_role(db, user, &role_data.role).await {
+        Ok(user) => {
+            tracing::info!("User {} is now {}", user_id, role_data.role);
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to change role of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to update role"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn suspend_user(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(user) = find_target_user(req, res, depot, false).await else {
+        return;
+    };
+
+    if user.suspended_at.is_some() {
+        res.status_code(StatusCode::CONFLICT);
+        res.render(Json(serde_json::json!({
+            "error": "User is already suspended"
+        })));
+        return;
+    }
+
+    let user_id = user.id;
+    match admin::suspend(db, &mut redis, clients, config, user).await {
+        Ok(user) => {
+            tracing::info!("Suspended user {}", user_id);
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to suspend user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to suspend user"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn unsuspend_user(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+
+    let Some(user) = find_target_user(req, res, depot, false).await else {
+        return;
+    };
+
+    if user.suspended_at.is_none() {
+        res.status_code(StatusCode::CONFLICT);
+        res.render(Json(serde_json::json!({
+            "error": "User is not suspended"
+        })));
+        return;
+    }
+
+    let user_id = user.id;
+    match admin::unsuspend(db, user).await {
+        Ok(user) => {
+            tracing::info!("Lifted suspension of user {}", user_id);
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to unsuspend user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to unsuspend user"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn force_logout_user(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(user) = find_target_user(req, res, depot, false).await else {
+        return;
+    };
+
+    let user_id = user.id;
+    match admin::force_logout(db, &mut redis, clients, config, user).await {
+        Ok(()) => {
+            tracing::info!("Signed out user {} on all devices", user_id);
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "User logged out on all devices"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to sign out user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to log out user"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn admin_delete_group(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let group_id = req.param::<i64>("id").unwrap_or(0);
+
+    match admin::delete_group(db, group_id).await {
+        Ok(true) => {
+            tracing::info!("Deleted group {}", group_id);
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Group deleted"
+            })));
+        }
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Group not found"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete group {}: {:?}", group_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to delete group"
+            })));
+        }
+    }
+}
+
+// Lockouts recorded by login throttling; ?active=true hides expired ones
+#[handler]
+pub async fn get_login_lockouts(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let active_only = req.query::<bool>("active").unwrap_or(false);
+
+    match admin::list_lockouts(db, active_only).await {
+        Ok(lockouts) => {
+            res.render(Json(lockouts));
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch lockouts"
+            })));
+        }
+    }
+}
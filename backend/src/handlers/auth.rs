@@ -1,24 +1,49 @@
 use salvo::prelude::*;
+use super::request::{parse_validated, parse_validated_query};
+use salvo::http::header::{CACHE_CONTROL, RETRY_AFTER};
 use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
-use crate::models::{LoginRequest, RegisterRequest, AuthResponse};
+use redis::aio::MultiplexedConnection;
+use crate::config::Config;
+use crate::models::{DirectoryQuery, LoginRequest, PresenceStatus, RegisterRequest, RefreshRequest, VerifyEmailRequest, AuthResponse, TokenResponse, TwoFactorChallengeResponse};
 use crate::entity::{users, users::Entity as Users};
-use crate::utils::{hash_password, verify_password, create_token};
+use crate::services::{admin, custom_status, directory, email_verification, login_throttle, refresh_token, session, token_denylist, two_factor};
+use crate::services::login_throttle::client_ip;
+use crate::services::mailer::SharedMailer;
+use crate::services::session::DeviceInfo;
+use crate::websocket::Clients;
+use crate::utils::{hash_password, needs_rehash, verify_password, create_token, JwtKeys, SharedJwtKeys};
 
+// Public keys other services use to verify our access tokens
 #[handler]
-pub async fn get_all_users(res: &mut Response, depot: &mut Depot) {
+pub async fn jwks(res: &mut Response, depot: &mut Depot) {
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let _ = res.add_header(CACHE_CONTROL, "public, max-age=300", true);
+    res.render(Json(jwt_keys.jwks()));
+}
+
+// Directory of other active accounts, paged with ?cursor=
+#[handler]
+pub async fn get_all_users(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
 
-    let users_list = Users::find()
-        .filter(users::Column::Id.ne(*user_id))
-        .all(db)
-        .await;
+    let Some(params) = parse_validated_query::<DirectoryQuery>(req, res) else {
+        return;
+    };
 
-    match users_list {
-        Ok(users) => {
-            res.render(Json(users));
+    match directory::list(db, clients, *user_id, &params).await {
+        Ok(Some(page)) => {
+            res.render(Json(page));
         }
-        Err(_) => {
+        Ok(None) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid cursor"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to list users: {:?}", e);
             res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
             res.render(Json(serde_json::json!({
                 "error": "Failed to fetch users"
@@ -30,14 +55,24 @@ pub async fn get_all_users(res: &mut Response, depot: &mut Depot) {
 #[handler]
 pub async fn logout(res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
-    let user_id = depot.get::<i64>("user_id").unwrap();
+    let jti = depot.get::<String>("jti").unwrap();
+    let session_id = depot.get::<String>("session_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
 
-    let user = Users::find_by_id(*user_id).one(db).await;
-
-    if let Ok(Some(user)) = user {
-        let mut user_active: users::ActiveModel = user.into();
-        user_active.status = Set("offline".to_string());
-        let _ = user_active.update(db).await;
+    // Revoke the presented access token and end the whole login session
+    let revoked = match token_denylist::revoke(&mut redis, jti, config.access_token_ttl()).await {
+        Ok(()) => session::revoke(db, &mut redis, clients, config, session_id).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = revoked {
+        tracing::error!("Failed to revoke session {}: {:?}", session_id, e);
+        res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        res.render(Json(serde_json::json!({
+            "error": "Failed to log out"
+        })));
+        return;
     }
 
     res.render(Json(serde_json::json!({
@@ -49,17 +84,13 @@ pub async fn logout(res: &mut Response, depot: &mut Depot) {
 #[handler]
 pub async fn register(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
-    let jwt_secret = depot.get::<String>("jwt_secret").unwrap();
-    
-    let register_data = match req.parse_json::<RegisterRequest>().await {
-        Ok(data) => data,
-        Err(_) => {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Json(serde_json::json!({
-                "error": "Invalid request data"
-            })));
-            return;
-        }
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+    let mailer = depot.get::<SharedMailer>("mailer").unwrap();
+
+    let Some(register_data) = parse_validated::<RegisterRequest>(req, res).await else {
+        return;
     };
 
     // Check if user already exists
@@ -80,7 +111,7 @@ pub async fn register(req: &mut Request, res: &mut Response, depot: &mut Depot)
     }
 
     // Hash password
-    let password_hash = match hash_password(&register_data.password) {
+    let password_hash = match hash_password(&register_data.password, config.password_params()).await {
         Ok(hash) => hash,
         Err(_) => {
             res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
@@ -98,18 +129,38 @@ pub async fn register(req: &mut Request, res: &mut Response, depot: &mut Depot)
         username: Set(register_data.username.clone()),
         email: Set(register_data.email.clone()),
         password_hash: Set(password_hash),
+        display_name: Set(None),
+        bio: Set(None),
         avatar: Set(None),
-        status: Set("offline".to_string()),
+        status: Set(PresenceStatus::Online.as_str().to_string()),
+        last_seen_at: Set(None),
+        status_text: Set(None),
+        status_emoji: Set(None),
+        status_expires_at: Set(None),
+        email_verified: Set(false),
+        totp_secret: Set(None),
+        totp_enabled: Set(false),
+        is_bot: Set(false),
+        owner_id: Set(None),
+        role: Set(admin::ROLE_USER.to_string()),
+        suspended_at: Set(None),
         created_at: Set(Some(now)),
         updated_at: Set(None),
     };
 
     match new_user.insert(db).await {
         Ok(user) => {
+            // Accounts start unverified; a failed mail can be resent later
+            if let Err(e) = email_verification::send_verification(&mut redis, mailer.as_ref(), config, &user).await {
+                tracing::error!("Failed to send verification mail to user {}: {:?}", user.id, e);
+            }
+
             // Generate JWT token
-            let token = match create_token(user.id, jwt_secret) {
+            let device = DeviceInfo::from_request(req, register_data.device_name.clone());
+            let (token, refresh_token) = match session::start(db, &mut redis, jwt_keys, config, user.id, device).await {
                 Ok(t) => t,
-                Err(_) => {
+                Err(e) => {
+                    tracing::error!("Failed to issue tokens: {:?}", e);
                     res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
                     res.render(Json(serde_json::json!({
                         "error": "Failed to generate authentication token"
@@ -117,7 +168,8 @@ pub async fn register(req: &mut Request, res: &mut Response, depot: &mut Depot)
                     return;
                 }
             };
-            res.render(Json(AuthResponse { token, user }));
+            let expires_in = config.access_token_ttl().num_seconds();
+            res.render(Json(AuthResponse { token, refresh_token, expires_in, user }));
         }
         Err(e) => {
             tracing::error!("Failed to create user: {:?}", e);
@@ -132,18 +184,27 @@ pub async fn register(req: &mut Request, res: &mut Response, depot: &mut Depot)
 #[handler]
 pub async fn login(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
-    let jwt_secret = depot.get::<String>("jwt_secret").unwrap();
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
 
-    let login_data = match req.parse_json::<LoginRequest>().await {
-        Ok(data) => data,
-        Err(_) => {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Json(serde_json::json!({
-                "error": "Invalid request data"
-            })));
+    let Some(login_data) = parse_validated::<LoginRequest>(req, res).await else {
+        return;
+    };
+
+    let ip = client_ip(req);
+
+    // Refuse early while the account or IP is locked out
+    match login_throttle::check(&mut redis, &login_data.email, ip.as_deref()).await {
+        Ok(Some(retry_after)) => {
+            render_locked_out(res, retry_after);
             return;
         }
-    };
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("Failed to check login throttle: {:?}", e);
+        }
+    }
 
     // Find user by email
     let user = Users::find()
@@ -151,42 +212,306 @@ pub async fn login(req: &mut Request, res: &mut Response, depot: &mut Depot) {
         .one(db)
         .await;
 
-    match user {
+    // Verify password
+    let user = match user {
+        Ok(Some(user)) if matches!(verify_password(&login_data.password, &user.password_hash).await, Ok(true)) => user,
+        _ => {
+            render_invalid_credentials(res, db, &mut redis, config, &login_data.email, ip.as_deref(), "Invalid credentials").await;
+            return;
+        }
+    };
+
+    // Move bcrypt or outdated Argon2 hashes to the current parameters while
+    // the plaintext is at hand
+    let user = if needs_rehash(&user.password_hash, config.password_params()) {
+        upgrade_password_hash(db, config, user, &login_data.password).await
+    } else {
+        user
+    };
+
+    let device = DeviceInfo::from_request(req, login_data.device_name.clone());
+    sign_in(res, db, &mut redis, jwt_keys, config, user, device).await;
+}
+
+// Finish a login whose first factor succeeded. Accounts with 2FA get a
+// challenge and complete signing in through /auth/login/2fa.
+pub(super) async fn sign_in(
+    res: &mut Response,
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    jwt_keys: &JwtKeys,
+    config: &Config,
+    user: users::Model,
+    device: DeviceInfo,
+) {
+    if user.suspended_at.is_some() {
+        render_suspended(res);
+        return;
+    }
+
+    if !user.totp_enabled {
+        complete_login(res, db, redis, jwt_keys, config, user, device).await;
+        return;
+    }
+
+    let ttl = config.two_factor_challenge_ttl();
+    match two_factor::create_challenge(redis, user.id, ttl).await {
+        Ok(challenge_token) => {
+            res.render(Json(TwoFactorChallengeResponse {
+                two_factor_required: true,
+                challenge_token,
+                expires_in: ttl.num_seconds(),
+            }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to create login challenge: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to log in"
+            })));
+        }
+    }
+}
+
+async fn upgrade_password_hash(db: &DatabaseConnection, config: &Config, user: users::Model, password: &str) -> users::Model {
+    let password_hash = match hash_password(password, config.password_params()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::warn!("Failed to rehash password for user {}: {:?}", user.id, e);
+            return user;
+        }
+    };
+
+    let user_id = user.id;
+    let mut user_active: users::ActiveModel = user.clone().into();
+    user_active.password_hash = Set(password_hash);
+    match user_active.update(db).await {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::warn!("Failed to store rehashed password for user {}: {:?}", user_id, e);
+            user
+        }
+    }
+}
+
+pub(super) fn render_suspended(res: &mut Response) {
+    res.status_code(StatusCode::FORBIDDEN);
+    res.render(Json(serde_json::json!({
+        "error": "This account has been suspended"
+    })));
+}
+
+pub(super) fn render_locked_out(res: &mut Response, retry_after: u64) {
+    res.status_code(StatusCode::TOO_MANY_REQUESTS);
+    let _ = res.add_header(RETRY_AFTER, retry_after, true);
+    res.render(Json(serde_json::json!({
+        "error": "Too many failed login attempts, try again later",
+        "retry_after": retry_after
+    })));
+}
+
+// Count a failed login and answer 401, or 429 if this attempt caused a lockout
+pub(super) async fn render_invalid_credentials(
+    res: &mut Response,
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    config: &Config,
+    email: &str,
+    ip: Option<&str>,
+    message: &str,
+) {
+    match login_throttle::record_failure(db, redis, config, email, ip).await {
+        Ok(Some(retry_after)) => {
+            render_locked_out(res, retry_after);
+            return;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("Failed to record login failure: {:?}", e);
+        }
+    }
+
+    res.status_code(StatusCode::UNAUTHORIZED);
+    res.render(Json(serde_json::json!({
+        "error": message
+    })));
+}
+
+// Passwords and codes checked from a signed-in session count towards the
+// same per-account and per-IP lockout as login attempts, so a stolen session
+// cannot be used to guess them without limit
+pub(super) async fn check_locked_out(res: &mut Response, redis: &mut MultiplexedConnection, user: &users::Model, ip: Option<&str>) -> bool {
+    match login_throttle::check(redis, &user.email, ip).await {
+        Ok(Some(retry_after)) => {
+            render_locked_out(res, retry_after);
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            tracing::error!("Failed to check login throttle: {:?}", e);
+            false
+        }
+    }
+}
+
+pub(super) async fn clear_failures(redis: &mut MultiplexedConnection, email: &str) {
+    if let Err(e) = login_throttle::record_success(redis, email).await {
+        tracing::warn!("Failed to clear login failures: {:?}", e);
+    }
+}
+
+// Start a session for a fully authenticated user and render the tokens
+pub(super) async fn complete_login(
+    res: &mut Response,
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    jwt_keys: &JwtKeys,
+    config: &Config,
+    user: users::Model,
+    device: DeviceInfo,
+) {
+    // Generate JWT token
+    let (token, refresh_token) = match session::start(db, redis, jwt_keys, config, user.id, device).await {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("Failed to issue tokens: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to generate authentication token"
+            })));
+            return;
+        }
+    };
+
+    if let Err(e) = login_throttle::record_success(redis, &user.email).await {
+        tracing::warn!("Failed to reset login failures for user {}: {:?}", user.id, e);
+    }
+
+    let expires_in = config.access_token_ttl().num_seconds();
+    res.render(Json(AuthResponse { token, refresh_token, expires_in, user }));
+}
+
+#[handler]
+pub async fn refresh(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(refresh_data) = parse_validated::<RefreshRequest>(req, res).await else {
+        return;
+    };
+
+    let rotated = refresh_token::rotate(&mut redis, &refresh_data.refresh_token, config.refresh_token_ttl()).await;
+
+    let (user_id, session_id, refresh_token) = match rotated {
+        Ok(Some(rotated)) => rotated,
+        Ok(None) => {
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired refresh token"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to rotate refresh token: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to refresh token"
+            })));
+            return;
+        }
+    };
+
+    if let Err(e) = session::touch(db, &session_id).await {
+        tracing::warn!("Failed to update session {}: {:?}", session_id, e);
+    }
+
+    match create_token(user_id, &session_id, jwt_keys, config.access_token_ttl()) {
+        Ok(token) => {
+            res.render(Json(TokenResponse {
+                token,
+                refresh_token,
+                expires_in: config.access_token_ttl().num_seconds(),
+            }));
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to generate authentication token"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn verify_email(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+
+    let Some(verify_data) = parse_validated::<VerifyEmailRequest>(req, res).await else {
+        return;
+    };
+
+    match email_verification::verify(db, &mut redis, &verify_data.token).await {
         Ok(Some(user)) => {
-            // Verify password
-            match verify_password(&login_data.password, &user.password_hash) {
-                Ok(true) => {
-                    // Generate JWT token
-                    let token = match create_token(user.id, jwt_secret) {
-                        Ok(t) => t,
-                        Err(_) => {
-                            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
-                            res.render(Json(serde_json::json!({
-                                "error": "Failed to generate authentication token"
-                            })));
-                            return;
-                        }
-                    };
-
-                    // Update user status to online
-                    let mut user_active: users::ActiveModel = user.clone().into();
-                    user_active.status = Set("online".to_string());
-                    let _ = user_active.update(db).await;
-
-                    res.render(Json(AuthResponse { token, user }));
-                }
-                _ => {
-                    res.status_code(StatusCode::UNAUTHORIZED);
-                    res.render(Json(serde_json::json!({
-                        "error": "Invalid credentials"
-                    })));
-                }
-            }
+            res.render(Json(user));
+        }
+        Ok(None) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired verification token"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to verify email: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to verify email"
+            })));
         }
+    }
+}
+
+#[handler]
+pub async fn resend_verification(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+    let mailer = depot.get::<SharedMailer>("mailer").unwrap();
+
+    let user = match Users::find_by_id(*user_id).one(db).await {
+        Ok(Some(user)) => user,
         _ => {
-            res.status_code(StatusCode::UNAUTHORIZED);
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+            return;
+        }
+    };
+
+    if user.email_verified {
+        res.status_code(StatusCode::CONFLICT);
+        res.render(Json(serde_json::json!({
+            "error": "Email already verified"
+        })));
+        return;
+    }
+
+    match email_verification::send_verification(&mut redis, mailer.as_ref(), config, &user).await {
+        Ok(()) => {
             res.render(Json(serde_json::json!({
-                "error": "Invalid credentials"
+                "success": true,
+                "message": "Verification email sent"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to send verification mail to user {}: {:?}", user.id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to send verification email"
             })));
         }
     }
@@ -200,7 +525,8 @@ pub async fn get_current_user(res: &mut Response, depot: &mut Depot) {
     let user = Users::find_by_id(*user_id).one(db).await;
 
     match user {
-        Ok(Some(user)) => {
+        Ok(Some(mut user)) => {
+            custom_status::hide_expired(&mut user);
             res.render(Json(user));
         }
         _ => {
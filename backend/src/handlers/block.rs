@@ -0,0 +1,103 @@
+use salvo::prelude::*;
+use super::request::parse_validated;
+use sea_orm::DatabaseConnection;
+use crate::models::BlockUserRequest;
+use crate::services::blocks::{self, BlockOutcome};
+use crate::websocket::Clients;
+
+#[handler]
+pub async fn get_blocked_users(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    match blocks::list(db, *user_id).await {
+        Ok(blocked) => {
+            res.render(Json(blocked));
+        }
+        Err(e) => {
+            tracing::error!("Failed to list blocked users of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch blocked users"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn block_user(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(block_data) = parse_validated::<BlockUserRequest>(req, res).await else {
+        return;
+    };
+
+    if block_data.user_id == *user_id {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(serde_json::json!({
+            "error": "You cannot block yourself"
+        })));
+        return;
+    }
+
+    match blocks::block(db, clients, *user_id, block_data.user_id).await {
+        Ok(BlockOutcome::Blocked) => {
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "User blocked"
+            })));
+        }
+        Ok(BlockOutcome::AlreadyBlocked) => {
+            res.status_code(StatusCode::CONFLICT);
+            res.render(Json(serde_json::json!({
+                "error": "User is already blocked"
+            })));
+        }
+        Ok(BlockOutcome::UserNotFound) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to block user {} for user {}: {:?}", block_data.user_id, user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to block user"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn unblock_user(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let blocked_id = req.param::<i64>("id").unwrap_or(0);
+
+    match blocks::unblock(db, clients, *user_id, blocked_id).await {
+        Ok(true) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "User unblocked"
+            })));
+        }
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User is not blocked"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to unblock user {} for user {}: {:?}", blocked_id, user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to unblock user"
+            })));
+        }
+    }
+}
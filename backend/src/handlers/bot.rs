@@ -0,0 +1,226 @@
+use salvo::prelude::*;
+use super::request::parse_validated;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait};
+use crate::config::Config;
+use crate::models::{ApiKeyResponse, CreateApiKeyRequest, CreateBotRequest, CreatedApiKeyResponse};
+use crate::entity::{users, users::Entity as Users};
+use crate::services::{api_key, bot};
+
+// Look up a bot of the current user from the <id> path parameter. Bots owned
+// by someone else are reported as missing.
+async fn find_owned_bot(req: &mut Request, res: &mut Response, db: &DatabaseConnection, owner_id: i64) -> Option<users::Model> {
+    let bot_id = req.param::<i64>("id").unwrap_or(0);
+
+    match bot::find_owned(db, owner_id, bot_id).await {
+        Ok(Some(bot)) => Some(bot),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Bot not found"
+            })));
+            None
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch bot"
+            })));
+            None
+        }
+    }
+}
+
+#[handler]
+pub async fn create_bot(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(bot_data) = parse_validated::<CreateBotRequest>(req, res).await else {
+        return;
+    };
+
+    match bot::count_owned(db, *user_id).await {
+        Ok(count) if count >= config.max_bots_per_user => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": format!("You can own at most {} bots", config.max_bots_per_user)
+            })));
+            return;
+        }
+        Ok(_) => {}
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to create bot"
+            })));
+            return;
+        }
+    }
+
+    let existing_user = Users::find()
+        .filter(
+            users::Column::Username.eq(&bot_data.username)
+                .or(users::Column::Email.eq(bot::bot_email(&bot_data.username)))
+        )
+        .one(db)
+        .await;
+
+    if let Ok(Some(_)) = existing_user {
+        res.status_code(StatusCode::CONFLICT);
+        res.render(Json(serde_json::json!({
+            "error": "Username already taken"
+        })));
+        return;
+    }
+
+    match bot::create(db, *user_id, &bot_data.username).await {
+        Ok(bot) => {
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(bot));
+        }
+        Err(e) => {
+            tracing::error!("Failed to create bot for user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to create bot"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn get_bots(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    match bot::list_owned(db, *user_id).await {
+        Ok(bots) => {
+            res.render(Json(bots));
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch bots"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn delete_bot(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    let Some(bot) = find_owned_bot(req, res, db, *user_id).await else {
+        return;
+    };
+
+    let bot_id = bot.id;
+    match bot::delete(db, bot).await {
+        Ok(()) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Bot deleted"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete bot {}: {:?}", bot_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to delete bot"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn create_api_key(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    let Some(bot) = find_owned_bot(req, res, db, *user_id).await else {
+        return;
+    };
+
+    let Some(key_data) = parse_validated::<CreateApiKeyRequest>(req, res).await else {
+        return;
+    };
+
+    let expires_at = key_data
+        .expires_in_days
+        .map(|days| chrono::Utc::now().naive_utc() + chrono::Duration::days(days));
+
+    match api_key::create(db, bot.id, key_data.name.trim(), &key_data.scopes, expires_at).await {
+        Ok((api_key, key)) => {
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(CreatedApiKeyResponse {
+                key,
+                api_key: api_key.into(),
+            }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to create API key for bot {}: {:?}", bot.id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to create API key"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn get_api_keys(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    let Some(bot) = find_owned_bot(req, res, db, *user_id).await else {
+        return;
+    };
+
+    match api_key::list(db, bot.id).await {
+        Ok(keys) => {
+            let keys: Vec<ApiKeyResponse> = keys.into_iter().map(Into::into).collect();
+            res.render(Json(keys));
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch API keys"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn revoke_api_key(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    let Some(bot) = find_owned_bot(req, res, db, *user_id).await else {
+        return;
+    };
+    let key_id = req.param::<i64>("key_id").unwrap_or(0);
+
+    match api_key::revoke(db, bot.id, key_id).await {
+        Ok(true) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "API key revoked"
+            })));
+        }
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "API key not found"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to revoke API key {}: {:?}", key_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to revoke API key"
+            })));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use salvo::prelude::*;
+use super::request::parse_validated;
+use sea_orm::DatabaseConnection;
+use crate::models::SendFriendRequestRequest;
+use crate::services::contacts::{self, SendOutcome};
+use crate::websocket::Clients;
+
+fn render_request_not_found(res: &mut Response) {
+    res.status_code(StatusCode::NOT_FOUND);
+    res.render(Json(serde_json::json!({
+        "error": "Friend request not found"
+    })));
+}
+
+#[handler]
+pub async fn get_contacts(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    match contacts::list(db, clients, *user_id).await {
+        Ok(contacts) => {
+            res.render(Json(contacts));
+        }
+        Err(e) => {
+            tracing::error!("Failed to list contacts of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch contacts"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn remove_contact(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let contact_id = req.param::<i64>("id").unwrap_or(0);
+
+    match contacts::remove(db, *user_id, contact_id).await {
+        Ok(true) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Contact removed"
+            })));
+        }
+        Ok(false) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Contact not found"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to remove contact {} of user {}: {:?}", contact_id, user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to remove contact"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn get_friend_requests(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
+    match contacts::list_requests(db, *user_id).await {
+        Ok(requests) => {
+            res.render(Json(requests));
+        }
+        Err(e) => {
+            tracing::error!("Failed to list friend requests of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch friend requests"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn send_friend_request(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(request_data) = parse_validated::<SendFriendRequestRequest>(req, res).await else {
+        return;
+    };
+
+    if request_data.user_id == *user_id {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(serde_json::json!({
+            "error": "You cannot add yourself as a contact"
+        })));
+        return;
+    }
+
+    match contacts::send(db, clients, *user_id, request_data.user_id, request_data.message).await {
+        Ok(SendOutcome::Sent(request)) => {
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(request));
+        }
+        Ok(SendOutcome::Accepted(contact)) => {
+            res.render(Json(contact));
+        }
+        Ok(SendOutcome::AlreadyContacts) => {
+            res.status_code(StatusCode::CONFLICT);
+            res.render(Json(serde_json::json!({
+                "error": "User is already a contact"
+            })));
+        }
+        Ok(SendOutcome::AlreadyRequested) => {
+            res.status_code(StatusCode::CONFLICT);
+            res.render(Json(serde_json::json!({
+                "error": "Friend request already sent"
+            })));
+        }
+        Ok(SendOutcome::UserNotFound) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to send friend request from user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to send friend request"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn accept_friend_request(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let request_id = req.param::<i64>("id").unwrap_or(0);
+
+    match contacts::accept(db, clients, *user_id, request_id).await {
+        Ok(Some(contact)) => {
+            res.render(Json(contact));
+        }
+        Ok(None) => render_request_not_found(res),
+        Err(e) => {
+            tracing::error!("Failed to accept friend request {}: {:?}", request_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to accept friend request"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn decline_friend_request(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let request_id = req.param::<i64>("id").unwrap_or(0);
+
+    match contacts::decline(db, *user_id, request_id).await {
+        Ok(true) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Friend request declined"
+            })));
+        }
+        Ok(false) => render_request_not_found(res),
+        Err(e) => {
+            tracing::error!("Failed to decline friend request {}: {:?}", request_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to decline friend request"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn cancel_friend_request(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let request_id = req.param::<i64>("id").unwrap_or(0);
+
+    match contacts::cancel(db, *user_id, request_id).await {
+        Ok(true) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Friend request cancelled"
+            })));
+        }
+        Ok(false) => render_request_not_found(res),
+        Err(e) => {
+            tracing::error!("Failed to cancel friend request {}: {:?}", request_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to cancel friend request"
+            })));
+        }
+    }
+}
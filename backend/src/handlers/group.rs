@@ -1,23 +1,48 @@
 use salvo::prelude::*;
-use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait, QuerySelect};
+use std::collections::HashSet;
+use super::request::{parse_validated, read_upload};
+use super::access::require_access;
+use super::scope::require_scope;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
 use crate::models::{CreateGroupRequest, JoinGroupRequest};
 use crate::entity::{groups, groups::Entity as Groups, group_members, group_members::Entity as GroupMembers};
+use crate::config::Config;
+use crate::services::{api_key, avatar, email_verification, membership};
+use crate::services::message_history::Conversation;
+use crate::services::storage::SharedStorage;
+use crate::websocket::{send_to_users, Clients, WsEvent};
 
 #[handler]
 pub async fn create_group(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
 
-    let group_data = match req.parse_json::<CreateGroupRequest>().await {
-        Ok(data) => data,
+    if !require_scope(depot, res, "groups:write", None) {
+        return;
+    }
+
+    let Some(group_data) = parse_validated::<CreateGroupRequest>(req, res).await else {
+        return;
+    };
+
+    match email_verification::can_create_group(db, config, *user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "Verify your email address to create groups"
+            })));
+            return;
+        }
         Err(_) => {
-            res.status_code(StatusCode::BAD_REQUEST);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
             res.render(Json(serde_json::json!({
-                "error": "Invalid request data"
+                "error": "Failed to create group"
             })));
             return;
         }
-    };
+    }
 
     let new_group = groups::ActiveModel {
         name: Set(group_data.name),
@@ -53,17 +78,14 @@ pub async fn join_group(req: &mut Request, res: &mut Response, depot: &mut Depot
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
 
-    let join_data = match req.parse_json::<JoinGroupRequest>().await {
-        Ok(data) => data,
-        Err(_) => {
-            res.status_code(StatusCode::BAD_REQUEST);
-            res.render(Json(serde_json::json!({
-                "error": "Invalid request data"
-            })));
-            return;
-        }
+    let Some(join_data) = parse_validated::<JoinGroupRequest>(req, res).await else {
+        return;
     };
 
+    if !require_scope(depot, res, "groups:write", Some(&api_key::group_target(join_data.group_id))) {
+        return;
+    }
+
     // Check if group exists
     let group_exists = Groups::find_by_id(join_data.group_id).one(db).await;
 
@@ -130,6 +152,10 @@ pub async fn get_user_groups(res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
 
+    if !require_scope(depot, res, "groups:read", None) {
+        return;
+    }
+
     // Get all groups where user is a member
     let members = GroupMembers::find()
         .filter(group_members::Column::UserId.eq(*user_id))
@@ -158,12 +184,20 @@ pub async fn get_user_groups(res: &mut Response, depot: &mut Depot) {
 #[handler]
 pub async fn get_group_members(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
-    
+    let user_id = depot.get::<i64>("user_id").unwrap();
+
     let group_id: i64 = match req.param::<String>("id") {
         Some(id) => id.parse().unwrap_or(0),
         None => 0,
     };
 
+    if !require_scope(depot, res, "groups:read", Some(&api_key::group_target(group_id))) {
+        return;
+    }
+    if !require_access(db, res, *user_id, &Conversation::Group(group_id)).await {
+        return;
+    }
+
     let members = GroupMembers::find()
         .filter(group_members::Column::GroupId.eq(group_id))
         .all(db)
@@ -187,6 +221,10 @@ pub async fn get_available_groups(res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
 
+    if !require_scope(depot, res, "groups:read", None) {
+        return;
+    }
+
     // Get group IDs the user is already a member of
     let member_group_ids: Vec<i64> = GroupMembers::find()
         .filter(group_members::Column::UserId.eq(*user_id))
@@ -214,3 +252,121 @@ pub async fn get_available_groups(res: &mut Response, depot: &mut Depot) {
         }
     }
 }
+
+// Owners and admins of a group can change its avatar
+#[handler]
+pub async fn upload_group_avatar(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let storage = depot.get::<SharedStorage>("storage").unwrap();
+    let group_id = req.param::<i64>("id").unwrap_or(0);
+
+    if !require_scope(depot, res, "groups:write", Some(&api_key::group_target(group_id))) {
+        return;
+    }
+
+    let group = match Groups::find_by_id(group_id).one(db).await {
+        Ok(Some(group)) => group,
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Group not found"
+            })));
+            return;
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to check group"
+            })));
+            return;
+        }
+    };
+
+    let can_edit = match membership::is_group_admin(db, group_id, *user_id).await {
+        Ok(can_edit) => can_edit,
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to check group"
+            })));
+            return;
+        }
+    };
+    if !can_edit {
+        res.status_code(StatusCode::FORBIDDEN);
+        res.render(Json(serde_json::json!({
+            "error": "Only group owners and admins can change the avatar"
+        })));
+        return;
+    }
+
+    let Some(bytes) = read_upload(req, res, "avatar", config.avatar_max_bytes).await else {
+        return;
+    };
+
+    let url = match avatar::store(storage.as_ref(), &format!("groups/{}", group_id), bytes).await {
+        Ok(Some(url)) => url,
+        Ok(None) => {
+            res.status_code(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            res.render(Json(serde_json::json!({
+                "error": "Avatar must be a PNG, JPEG, GIF or WebP image of at most 4096x4096 pixels"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to store avatar of group {}: {:?}", group_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to upload avatar"
+            })));
+            return;
+        }
+    };
+
+    let previous = group.avatar.clone();
+    let mut group_active: groups::ActiveModel = group.into();
+    group_active.avatar = Set(Some(url));
+    group_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+
+    match group_active.update(db).await {
+        Ok(group) => {
+            if let Some(previous) = previous {
+                if let Err(e) = avatar::remove(storage.as_ref(), &previous).await {
+                    tracing::warn!("Failed to delete old avatar {}: {:?}", previous, e);
+                }
+            }
+
+            let event = WsEvent {
+                event_type: "group_updated".to_string(),
+                user_id: *user_id,
+                receiver_id: None,
+                group_id: Some(group.id),
+                content: None,
+                data: serde_json::to_value(&group).ok(),
+            };
+            match GroupMembers::find()
+                .filter(group_members::Column::GroupId.eq(group.id))
+                .all(db)
+                .await
+            {
+                Ok(members) => {
+                    let member_ids: HashSet<i64> = members.iter().map(|m| m.user_id).collect();
+                    send_to_users(clients, &member_ids, &event).await;
+                }
+                Err(e) => tracing::warn!("Failed to find members of group {}: {:?}", group.id, e),
+            }
+
+            res.render(Json(group));
+        }
+        Err(e) => {
+            tracing::error!("Failed to save avatar of group {}: {:?}", group_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to upload avatar"
+            })));
+        }
+    }
+}
@@ -1,48 +1,117 @@
 use salvo::prelude::*;
-use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait, QueryOrder};
-use crate::models::SendMessageRequest;
+use super::request::{parse_validated, parse_validated_query};
+use super::access::require_access;
+use super::scope::require_scope;
+use sea_orm::{DatabaseConnection, EntityTrait, Set, ActiveModelTrait};
+use crate::models::{EditMessageRequest, MessageHistoryQuery, SendMessageRequest};
 use crate::entity::{messages, messages::Entity as Messages};
+use crate::config::Config;
+use crate::websocket::Clients;
+use crate::services::{api_key, message_delete, message_dispatch, message_edit, message_history};
+use crate::services::message_delete::RecallOutcome;
+use crate::services::message_dispatch::SendOutcome;
+use crate::services::message_edit::EditOutcome;
+use crate::services::message_history::Conversation;
+
+// API key target of the conversation a message belongs to, as user_id sees it
+fn message_target(message: &messages::Model, user_id: i64) -> String {
+    match message.receiver_id {
+        Some(receiver_id) if receiver_id != user_id => api_key::user_target(receiver_id),
+        Some(_) => api_key::user_target(message.sender_id),
+        None => api_key::group_target(message.group_id.unwrap_or_default()),
+    }
+}
+
+async fn load_message(db: &DatabaseConnection, res: &mut Response, message_id: i64) -> Option<messages::Model> {
+    match Messages::find_by_id(message_id).one(db).await {
+        Ok(Some(message)) => Some(message),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Message not found"
+            })));
+            None
+        }
+        Err(e) => {
+            tracing::error!("Failed to load message {}: {:?}", message_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to load message"
+            })));
+            None
+        }
+    }
+}
 
 #[handler]
 pub async fn send_message(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(message_data) = parse_validated::<SendMessageRequest>(req, res).await else {
+        return;
+    };
 
-    let message_data = match req.parse_json::<SendMessageRequest>().await {
-        Ok(data) => data,
-        Err(_) => {
+    // Validate that either receiver_id or group_id is provided
+    let target = match (message_data.receiver_id, message_data.group_id) {
+        (Some(receiver_id), _) => api_key::user_target(receiver_id),
+        (None, Some(group_id)) => api_key::group_target(group_id),
+        (None, None) => {
             res.status_code(StatusCode::BAD_REQUEST);
             res.render(Json(serde_json::json!({
-                "error": "Invalid request data"
+                "error": "Either receiver_id or group_id must be provided"
             })));
             return;
         }
     };
+    if !require_scope(depot, res, "messages:write", Some(&target)) {
+        return;
+    }
 
-    // Validate that either receiver_id or group_id is provided
-    if message_data.receiver_id.is_none() && message_data.group_id.is_none() {
-        res.status_code(StatusCode::BAD_REQUEST);
-        res.render(Json(serde_json::json!({
-            "error": "Either receiver_id or group_id must be provided"
-        })));
-        return;
-    }
-
-    let new_message = messages::ActiveModel {
-        sender_id: Set(*user_id),
-        receiver_id: Set(message_data.receiver_id),
-        group_id: Set(message_data.group_id),
-        content: Set(message_data.content),
-        message_type: Set(message_data.message_type),
-        is_read: Set(false),
-        ..Default::default()
-    };
-
-    match new_message.insert(db).await {
-        Ok(message) => {
+    match message_dispatch::send(db, clients, config, *user_id, None, message_data).await {
+        Ok(SendOutcome::Sent(message)) => {
             res.render(Json(message));
         }
-        Err(_) => {
+        Ok(SendOutcome::MissingTarget) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Either receiver_id or group_id must be provided"
+            })));
+        }
+        Ok(SendOutcome::UnknownReceiver) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+        }
+        Ok(SendOutcome::SelfMessage) => {
+            res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+            res.render(Json(serde_json::json!({
+                "error": "You cannot message yourself"
+            })));
+        }
+        Ok(SendOutcome::NotMember) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "You are not a member of this group"
+            })));
+        }
+        Ok(SendOutcome::Blocked) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "You cannot message this user"
+            })));
+        }
+        Ok(SendOutcome::EmailUnverified) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "Verify your email address to message this user"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to send message from user {}: {:?}", user_id, e);
             res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
             res.render(Json(serde_json::json!({
                 "error": "Failed to send message"
@@ -56,43 +125,41 @@ pub async fn get_messages(req: &mut Request, res: &mut Response, depot: &mut Dep
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
 
-    let receiver_id: Option<i64> = req.query("receiver_id");
-    let group_id: Option<i64> = req.query("group_id");
-    
-    let messages_result = if let Some(receiver_id) = receiver_id {
-        // Get personal chat messages
-        Messages::find()
-            .filter(
-                messages::Column::SenderId.eq(*user_id)
-                    .and(messages::Column::ReceiverId.eq(receiver_id))
-                    .or(
-                        messages::Column::SenderId.eq(receiver_id)
-                            .and(messages::Column::ReceiverId.eq(*user_id))
-                    )
-            )
-            .order_by_asc(messages::Column::CreatedAt)
-            .all(db)
-            .await
-    } else if let Some(group_id) = group_id {
-        // Get group chat messages
-        Messages::find()
-            .filter(messages::Column::GroupId.eq(group_id))
-            .order_by_asc(messages::Column::CreatedAt)
-            .all(db)
-            .await
-    } else {
-        res.status_code(StatusCode::BAD_REQUEST);
-        res.render(Json(serde_json::json!({
-            "error": "Either receiver_id or group_id must be provided"
-        })));
+    let Some(params) = parse_validated_query::<MessageHistoryQuery>(req, res) else {
+        return;
+    };
+
+    let target = match (params.receiver_id, params.group_id) {
+        (Some(receiver_id), _) => Some(api_key::user_target(receiver_id)),
+        (None, Some(group_id)) => Some(api_key::group_target(group_id)),
+        (None, None) => None,
+    };
+    if !require_scope(depot, res, "messages:read", target.as_deref()) {
         return;
+    }
+
+    let conversation = match (params.receiver_id, params.group_id) {
+        (Some(receiver_id), _) => Conversation::Direct { user_id: *user_id, other_id: receiver_id },
+        (None, Some(group_id)) => Conversation::Group(group_id),
+        (None, None) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Either receiver_id or group_id must be provided"
+            })));
+            return;
+        }
     };
 
-    match messages_result {
-        Ok(messages) => {
-            res.render(Json(messages));
+    if !require_access(db, res, *user_id, &conversation).await {
+        return;
+    }
+
+    match message_history::page(db, *user_id, conversation, &params).await {
+        Ok(page) => {
+            res.render(Json(page));
         }
-        Err(_) => {
+        Err(e) => {
+            tracing::error!("Failed to fetch messages for user {}: {:?}", user_id, e);
             res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
             res.render(Json(serde_json::json!({
                 "error": "Failed to fetch messages"
@@ -101,11 +168,185 @@ pub async fn get_messages(req: &mut Request, res: &mut Response, depot: &mut Dep
     }
 }
 
+#[handler]
+pub async fn edit_message(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let message_id = req.param::<i64>("id").unwrap_or(0);
+
+    let Some(edit_data) = parse_validated::<EditMessageRequest>(req, res).await else {
+        return;
+    };
+    let Some(message) = load_message(db, res, message_id).await else {
+        return;
+    };
+    if !require_scope(depot, res, "messages:write", Some(&message_target(&message, *user_id))) {
+        return;
+    }
+
+    match message_edit::edit(db, clients, config, *user_id, message, edit_data.content).await {
+        Ok(EditOutcome::Edited(message)) => {
+            res.render(Json(message));
+        }
+        Ok(EditOutcome::NotSender) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "You can only edit your own messages"
+            })));
+        }
+        Ok(EditOutcome::NotMember) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "You are not a member of this group"
+            })));
+        }
+        Ok(EditOutcome::WindowClosed) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "This message can no longer be edited"
+            })));
+        }
+        Ok(EditOutcome::Recalled) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "This message was recalled"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to edit message {}: {:?}", message_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to edit message"
+            })));
+        }
+    }
+}
+
+// Earlier revisions of a message, for anyone who can read the conversation
+#[handler]
+pub async fn get_message_edits(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let message_id = req.param::<i64>("id").unwrap_or(0);
+
+    let Some(message) = load_message(db, res, message_id).await else {
+        return;
+    };
+    if !require_scope(depot, res, "messages:read", Some(&message_target(&message, *user_id))) {
+        return;
+    }
+    if !require_access(db, res, *user_id, &Conversation::of(&message)).await {
+        return;
+    }
+
+    match message_edit::revisions(db, message_id).await {
+        Ok(revisions) => {
+            res.render(Json(revisions));
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch edits of message {}: {:?}", message_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch message edits"
+            })));
+        }
+    }
+}
+
+// Delete a message for the current user only
+#[handler]
+pub async fn delete_message(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let message_id = req.param::<i64>("id").unwrap_or(0);
+
+    let Some(message) = load_message(db, res, message_id).await else {
+        return;
+    };
+    if !require_scope(depot, res, "messages:write", Some(&message_target(&message, *user_id))) {
+        return;
+    }
+    if !require_access(db, res, *user_id, &Conversation::of(&message)).await {
+        return;
+    }
+
+    match message_delete::delete_for_me(db, clients, *user_id, &message).await {
+        Ok(()) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Message deleted"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete message {} for user {}: {:?}", message_id, user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to delete message"
+            })));
+        }
+    }
+}
+
+// Recall a message for everyone in the conversation
+#[handler]
+pub async fn recall_message(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let message_id = req.param::<i64>("id").unwrap_or(0);
+
+    let Some(message) = load_message(db, res, message_id).await else {
+        return;
+    };
+    if !require_scope(depot, res, "messages:write", Some(&message_target(&message, *user_id))) {
+        return;
+    }
+
+    match message_delete::recall(db, clients, config, *user_id, message).await {
+        Ok(RecallOutcome::Recalled(message)) => {
+            res.render(Json(message));
+        }
+        Ok(RecallOutcome::NotSender) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "Only the sender or a group admin can recall this message"
+            })));
+        }
+        Ok(RecallOutcome::NotMember) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "You are not a member of this group"
+            })));
+        }
+        Ok(RecallOutcome::WindowClosed) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "This message can no longer be recalled"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to recall message {}: {:?}", message_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to recall message"
+            })));
+        }
+    }
+}
+
 #[handler]
 pub async fn mark_as_read(req: &mut Request, res: &mut Response, depot: &mut Depot) {
     let db = depot.get::<DatabaseConnection>("db").unwrap();
     let user_id = depot.get::<i64>("user_id").unwrap();
     
+    if !require_scope(depot, res, "messages:read", None) {
+        return;
+    }
+
     let message_id: i64 = match req.param::<String>("id") {
         Some(id) => id.parse().unwrap_or(0),
         None => 0,
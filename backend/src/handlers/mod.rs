@@ -1,7 +1,30 @@
 pub mod auth;
 pub mod message;
 pub mod group;
+pub mod password;
+pub mod session;
+pub mod two_factor;
+pub mod bot;
+pub mod oidc;
+pub mod admin;
+pub mod profile;
+pub mod contact;
+pub mod block;
+pub mod presence;
+mod access;
+mod request;
+mod scope;
 
 pub use auth::*;
 pub use message::*;
 pub use group::*;
+pub use password::*;
+pub use session::*;
+pub use two_factor::*;
+pub use bot::*;
+pub use oidc::*;
+pub use admin::*;
+pub use profile::*;
+pub use contact::*;
+pub use block::*;
+pub use presence::*;
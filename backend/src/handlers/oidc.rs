@@ -0,0 +1,108 @@
+use salvo::prelude::*;
+use super::request::parse_validated;
+use super::auth::sign_in;
+use sea_orm::DatabaseConnection;
+use redis::aio::MultiplexedConnection;
+use crate::config::Config;
+use crate::models::{OidcAuthorizeResponse, OidcCallbackRequest};
+use crate::services::oidc::{self, Resolution, SharedOidcClient};
+use crate::services::session::DeviceInfo;
+use crate::utils::SharedJwtKeys;
+
+fn render_not_configured(res: &mut Response) {
+    res.status_code(StatusCode::NOT_FOUND);
+    res.render(Json(serde_json::json!({
+        "error": "Single sign-on is not configured"
+    })));
+}
+
+// Start an SSO login: returns the identity provider URL to redirect to and
+// the binding the same client has to present with the callback
+#[handler]
+pub async fn oidc_authorize(res: &mut Response, depot: &mut Depot) {
+    let Ok(oidc) = depot.get::<SharedOidcClient>("oidc") else {
+        render_not_configured(res);
+        return;
+    };
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    match oidc.authorization_url(&mut redis, config.oidc_state_ttl()).await {
+        Ok((authorization_url, binding)) => {
+            res.render(Json(OidcAuthorizeResponse { authorization_url, binding }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to start SSO login: {:?}", e);
+            res.status_code(StatusCode::BAD_GATEWAY);
+            res.render(Json(serde_json::json!({
+                "error": "Identity provider is unavailable"
+            })));
+        }
+    }
+}
+
+// Finish an SSO login with the code and state the provider redirected back
+// with, and the binding from oidc_authorize
+#[handler]
+pub async fn oidc_callback(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let Ok(oidc) = depot.get::<SharedOidcClient>("oidc") else {
+        render_not_configured(res);
+        return;
+    };
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(callback_data) = parse_validated::<OidcCallbackRequest>(req, res).await else {
+        return;
+    };
+
+    let claims = match oidc.exchange_code(&mut redis, &callback_data.code, &callback_data.state, &callback_data.binding).await {
+        Ok(Some(claims)) => claims,
+        Ok(None) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired login state"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::warn!("SSO code exchange failed: {:?}", e);
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(serde_json::json!({
+                "error": "Single sign-on failed"
+            })));
+            return;
+        }
+    };
+
+    let user = match oidc::resolve_user(db, config, &claims).await {
+        Ok(Resolution::User(user)) => *user,
+        Ok(Resolution::ProvisioningDisabled) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "No account is linked to this identity"
+            })));
+            return;
+        }
+        Ok(Resolution::EmailInUse) => {
+            res.status_code(StatusCode::CONFLICT);
+            res.render(Json(serde_json::json!({
+                "error": "An account with this email already exists"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to resolve SSO user {}: {:?}", claims.sub, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to log in"
+            })));
+            return;
+        }
+    };
+
+    let device = DeviceInfo::from_request(req, callback_data.device_name.clone());
+    sign_in(res, db, &mut redis, jwt_keys, config, user, device).await;
+}
@@ -0,0 +1,196 @@
+use salvo::prelude::*;
+use super::request::parse_validated;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
+use redis::aio::MultiplexedConnection;
+use crate::config::Config;
+use crate::models::{ChangePasswordRequest, ForgotPasswordRequest, ResetPasswordRequest, TokenResponse};
+use crate::entity::{users, users::Entity as Users};
+use crate::services::session;
+use crate::services::one_time_token::{self, Purpose};
+use crate::services::mailer::{Mail, SharedMailer};
+use crate::services::login_throttle::client_ip;
+use crate::services::session::DeviceInfo;
+use crate::websocket::Clients;
+use crate::utils::{hash_password, verify_password, SharedJwtKeys};
+use super::auth::{check_locked_out, clear_failures, render_invalid_credentials};
+
+async fn set_password(db: &DatabaseConnection, config: &Config, user: users::Model, new_password: &str) -> anyhow::Result<()> {
+    let password_hash = hash_password(new_password, config.password_params()).await?;
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.password_hash = Set(password_hash);
+    user_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+    user_active.update(db).await?;
+    Ok(())
+}
+
+#[handler]
+pub async fn change_password(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(password_data) = parse_validated::<ChangePasswordRequest>(req, res).await else {
+        return;
+    };
+
+    let user = match Users::find_by_id(*user_id).one(db).await {
+        Ok(Some(user)) => user,
+        _ => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+            return;
+        }
+    };
+
+    let ip = client_ip(req);
+    if check_locked_out(res, &mut redis, &user, ip.as_deref()).await {
+        return;
+    }
+
+    if !matches!(verify_password(&password_data.current_password, &user.password_hash).await, Ok(true)) {
+        render_invalid_credentials(res, db, &mut redis, config, &user.email, ip.as_deref(), "Current password is incorrect").await;
+        return;
+    }
+    clear_failures(&mut redis, &user.email).await;
+
+    if let Err(e) = set_password(db, config, user, &password_data.new_password).await {
+        tracing::error!("Failed to change password for user {}: {:?}", user_id, e);
+        res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        res.render(Json(serde_json::json!({
+            "error": "Failed to change password"
+        })));
+        return;
+    }
+
+    // Sign out every device, then give the caller a fresh session
+    if let Err(e) = session::revoke_all_for_user(db, &mut redis, clients, config, *user_id).await {
+        tracing::error!("Failed to revoke sessions for user {}: {:?}", user_id, e);
+    }
+
+    let device = DeviceInfo::from_request(req, password_data.device_name.clone());
+    match session::start(db, &mut redis, jwt_keys, config, *user_id, device).await {
+        Ok((token, refresh_token)) => {
+            res.render(Json(TokenResponse {
+                token,
+                refresh_token,
+                expires_in: config.access_token_ttl().num_seconds(),
+            }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to issue tokens: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to generate authentication token"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn forgot_password(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+    let mailer = depot.get::<SharedMailer>("mailer").unwrap();
+
+    let Some(forgot_data) = parse_validated::<ForgotPasswordRequest>(req, res).await else {
+        return;
+    };
+
+    let user = Users::find()
+        .filter(users::Column::Email.eq(&forgot_data.email))
+        .one(db)
+        .await;
+
+    // Respond the same way whether or not the account exists
+    if let Ok(Some(user)) = user {
+        match one_time_token::create(&mut redis, Purpose::PasswordReset, user.id, config.password_reset_ttl()).await {
+            Ok(token) => {
+                let mail = Mail {
+                    to: user.email.clone(),
+                    subject: "Reset your IM-TT password".to_string(),
+                    body: format!(
+                        "Hi {},\n\nUse the link below to reset your password. It expires in {} minutes.\n\n{}/reset-password?token={}\n\nIf you did not request this, you can ignore this email.",
+                        user.username, config.password_reset_ttl_minutes, config.app_base_url, token
+                    ),
+                };
+                if let Err(e) = mailer.send(&mail).await {
+                    tracing::error!("Failed to send password reset mail to user {}: {:?}", user.id, e);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to create password reset token for user {}: {:?}", user.id, e);
+            }
+        }
+    }
+
+    res.render(Json(serde_json::json!({
+        "success": true,
+        "message": "If the account exists, a reset link has been sent"
+    })));
+}
+
+#[handler]
+pub async fn reset_password(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(reset_data) = parse_validated::<ResetPasswordRequest>(req, res).await else {
+        return;
+    };
+
+    let user_id = match one_time_token::consume(&mut redis, Purpose::PasswordReset, &reset_data.token).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired reset token"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to check password reset token: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to reset password"
+            })));
+            return;
+        }
+    };
+
+    let user = match Users::find_by_id(user_id).one(db).await {
+        Ok(Some(user)) => user,
+        _ => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired reset token"
+            })));
+            return;
+        }
+    };
+
+    if let Err(e) = set_password(db, config, user, &reset_data.new_password).await {
+        tracing::error!("Failed to reset password for user {}: {:?}", user_id, e);
+        res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        res.render(Json(serde_json::json!({
+            "error": "Failed to reset password"
+        })));
+        return;
+    }
+
+    if let Err(e) = session::revoke_all_for_user(db, &mut redis, clients, config, user_id).await {
+        tracing::error!("Failed to revoke sessions for user {}: {:?}", user_id, e);
+    }
+
+    res.render(Json(serde_json::json!({
+        "success": true,
+        "message": "Password has been reset"
+    })));
+}
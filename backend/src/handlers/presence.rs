@@ -0,0 +1,56 @@
+use salvo::prelude::*;
+use super::request::{parse_validated, parse_validated_query};
+use sea_orm::DatabaseConnection;
+use crate::models::{PresenceQuery, SetPresenceRequest};
+use crate::services::presence;
+use crate::websocket::Clients;
+
+#[handler]
+pub async fn set_presence(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(presence_data) = parse_validated::<SetPresenceRequest>(req, res).await else {
+        return;
+    };
+
+    match presence::set_status(db, clients, *user_id, presence_data.status).await {
+        Ok(()) => {
+            res.render(Json(serde_json::json!({
+                "status": presence_data.status
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to set presence of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to update presence"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn get_presence(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(query) = parse_validated_query::<PresenceQuery>(req, res) else {
+        return;
+    };
+
+    match presence::lookup(db, clients, *user_id, &query.user_ids()).await {
+        Ok(presences) => {
+            res.render(Json(presences));
+        }
+        Err(e) => {
+            tracing::error!("Failed to look up presence for user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch presence"
+            })));
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use salvo::prelude::*;
+use super::request::{parse_validated, read_upload};
+use salvo::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use crate::config::Config;
+use crate::models::{SetCustomStatusRequest, UpdateProfileRequest};
+use crate::entity::{users, users::Entity as Users};
+use crate::services::{avatar, custom_status, profile, storage};
+use crate::services::profile::ProfileUpdate;
+use crate::services::storage::SharedStorage;
+use crate::websocket::Clients;
+
+async fn find_current_user(res: &mut Response, db: &DatabaseConnection, user_id: i64) -> Option<users::Model> {
+    match Users::find_by_id(user_id).one(db).await {
+        Ok(Some(user)) => Some(user),
+        _ => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+            None
+        }
+    }
+}
+
+// Tell peers about the change; the update itself already succeeded
+async fn notify_peers(db: &DatabaseConnection, clients: &Clients, user: &users::Model) {
+    if let Err(e) = profile::notify_peers(db, clients, user).await {
+        tracing::warn!("Failed to broadcast profile of user {}: {:?}", user.id, e);
+    }
+}
+
+#[handler]
+pub async fn update_profile(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(profile_data) = parse_validated::<UpdateProfileRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    match profile::update(db, user, &profile_data).await {
+        Ok(ProfileUpdate::Updated(user)) => {
+            notify_peers(db, clients, &user).await;
+            res.render(Json(user));
+        }
+        Ok(ProfileUpdate::UsernameTaken) => {
+            res.status_code(StatusCode::CONFLICT);
+            res.render(Json(serde_json::json!({
+                "error": "Username already taken"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to update profile of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to update profile"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn upload_avatar(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let storage = depot.get::<SharedStorage>("storage").unwrap();
+
+    let Some(bytes) = read_upload(req, res, "avatar", config.avatar_max_bytes).await else {
+        return;
+    };
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    let url = match avatar::store(storage.as_ref(), &format!("users/{}", user.id), bytes).await {
+        Ok(Some(url)) => url,
+        Ok(None) => {
+            res.status_code(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            res.render(Json(serde_json::json!({
+                "error": "Avatar must be a PNG, JPEG, GIF or WebP image of at most 4096x4096 pixels"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to store avatar of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to upload avatar"
+            })));
+            return;
+        }
+    };
+
+    let previous = user.avatar.clone();
+    match profile::set_avatar(db, user, Some(url)).await {
+        Ok(user) => {
+            if let Some(previous) = previous {
+                if let Err(e) = avatar::remove(storage.as_ref(), &previous).await {
+                    tracing::warn!("Failed to delete old avatar {}: {:?}", previous, e);
+                }
+            }
+            notify_peers(db, clients, &user).await;
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to save avatar of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to upload avatar"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn delete_avatar(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let storage = depot.get::<SharedStorage>("storage").unwrap();
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    let Some(previous) = user.avatar.clone() else {
+        res.render(Json(user));
+        return;
+    };
+
+    match profile::set_avatar(db, user, None).await {
+        Ok(user) => {
+            if let Err(e) = avatar::remove(storage.as_ref(), &previous).await {
+                tracing::warn!("Failed to delete old avatar {}: {:?}", previous, e);
+            }
+            notify_peers(db, clients, &user).await;
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to remove avatar of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to remove avatar"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn set_custom_status(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(status_data) = parse_validated::<SetCustomStatusRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    match custom_status::set(db, clients, user, &status_data).await {
+        Ok(user) => {
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to set status of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to set status"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn clear_custom_status(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    match custom_status::clear(db, clients, user).await {
+        Ok(user) => {
+            res.render(Json(user));
+        }
+        Err(e) => {
+            tracing::error!("Failed to clear status of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to clear status"
+            })));
+        }
+    }
+}
+
+// Serve files from the storage backend. Keys contain a random token that
+// changes on every upload, so responses can be cached indefinitely.
+#[handler]
+pub async fn get_upload(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let storage = depot.get::<SharedStorage>("storage").unwrap();
+    let key = req.param::<String>("key").unwrap_or_default();
+
+    if !storage::is_valid_key(&key) {
+        res.status_code(StatusCode::NOT_FOUND);
+        return;
+    }
+
+    match storage.get(&key).await {
+        Ok(Some(object)) => {
+            let _ = res.add_header(CONTENT_TYPE, object.content_type, true);
+            let _ = res.add_header(CACHE_CONTROL, "public, max-age=31536000, immutable", true);
+            let _ = res.write_body(object.bytes);
+        }
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            tracing::error!("Failed to read upload {}: {:?}", key, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+}
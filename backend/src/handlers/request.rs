@@ -0,0 +1,109 @@
+use salvo::prelude::*;
+use serde::de::DeserializeOwned;
+use validator::Validate;
+use crate::models::validation::field_errors;
+
+// Parse a JSON body and run its declarative validation. Renders 400 for a
+// body that does not parse and 422 with per-field messages for one that
+// breaks a rule.
+pub(super) async fn parse_validated<T>(req: &mut Request, res: &mut Response) -> Option<T>
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    let data = match req.parse_json::<T>().await {
+        Ok(data) => data,
+        Err(_) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid request data"
+            })));
+            return None;
+        }
+    };
+
+    if let Err(errors) = data.validate() {
+        res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+        res.render(Json(serde_json::json!({
+            "error": "Validation failed",
+            "fields": field_errors(&errors)
+        })));
+        return None;
+    }
+
+    Some(data)
+}
+
+// Read a file field from a multipart upload. Renders 413 when the request or
+// the file exceeds max_bytes and 400 when the field is missing.
+pub(super) async fn read_upload(req: &mut Request, res: &mut Response, field: &str, max_bytes: u64) -> Option<Vec<u8>> {
+    let too_large = |res: &mut Response| {
+        res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+        res.render(Json(serde_json::json!({
+            "error": format!("File must be at most {} bytes", max_bytes)
+        })));
+    };
+
+    // Refuse obviously oversized bodies before spooling them to disk; the
+    // allowance covers the multipart framing around the file
+    let content_length = req
+        .headers()
+        .get(salvo::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > max_bytes + 16 * 1024) {
+        too_large(res);
+        return None;
+    }
+
+    let Some(file) = req.file(field).await else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Json(serde_json::json!({
+            "error": format!("Expected a multipart upload with a {} file", field)
+        })));
+        return None;
+    };
+    if file.size() > max_bytes {
+        too_large(res);
+        return None;
+    }
+
+    match tokio::fs::read(file.path()).await {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            tracing::error!("Failed to read uploaded file: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to read upload"
+            })));
+            None
+        }
+    }
+}
+
+// Query string counterpart of parse_validated
+pub(super) fn parse_validated_query<T>(req: &mut Request, res: &mut Response) -> Option<T>
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    let data = match req.parse_queries::<T>() {
+        Ok(data) => data,
+        Err(_) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid query parameters"
+            })));
+            return None;
+        }
+    };
+
+    if let Err(errors) = data.validate() {
+        res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+        res.render(Json(serde_json::json!({
+            "error": "Validation failed",
+            "fields": field_errors(&errors)
+        })));
+        return None;
+    }
+
+    Some(data)
+}
@@ -0,0 +1,20 @@
+use salvo::prelude::*;
+use crate::services::api_key::ApiScopes;
+
+// Requests authenticated with an API key carry the key's scopes in the depot.
+// Session tokens have no scopes entry and act with the user's full rights.
+// Renders 403 and returns false when the key does not grant the scope.
+pub(super) fn require_scope(depot: &Depot, res: &mut Response, scope: &str, target: Option<&str>) -> bool {
+    let Ok(scopes) = depot.get::<ApiScopes>("scopes") else {
+        return true;
+    };
+    if scopes.allows(scope, target) {
+        return true;
+    }
+
+    res.status_code(StatusCode::FORBIDDEN);
+    res.render(Json(serde_json::json!({
+        "error": format!("API key is missing the {} scope", scope)
+    })));
+    false
+}
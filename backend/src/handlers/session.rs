@@ -0,0 +1,115 @@
+use salvo::prelude::*;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use redis::aio::MultiplexedConnection;
+use crate::config::Config;
+use crate::models::{SessionResponse, WsTicketResponse};
+use crate::entity::sessions::Entity as Sessions;
+use crate::services::session;
+use crate::services::ws_ticket::{self, TicketOwner};
+use crate::websocket::Clients;
+
+#[handler]
+pub async fn get_sessions(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let session_id = depot.get::<String>("session_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+
+    match session::list_active(db, *user_id, config).await {
+        Ok(sessions) => {
+            let sessions: Vec<SessionResponse> = sessions
+                .into_iter()
+                .map(|s| SessionResponse {
+                    current: s.id == *session_id,
+                    session: s,
+                })
+                .collect();
+            res.render(Json(sessions));
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch sessions"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn revoke_session(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let clients = depot.get::<Clients>("clients").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let target_id = req.param::<String>("id").unwrap_or_default();
+
+    // Only the owner may revoke a session; other users' sessions look missing
+    match Sessions::find_by_id(target_id.clone()).one(db).await {
+        Ok(Some(s)) if s.user_id == *user_id => {}
+        Ok(_) => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Session not found"
+            })));
+            return;
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to fetch session"
+            })));
+            return;
+        }
+    }
+
+    match session::revoke(db, &mut redis, clients, config, &target_id).await {
+        Ok(()) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Session revoked"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to revoke session {}: {:?}", target_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to revoke session"
+            })));
+        }
+    }
+}
+
+// Mint a single-use ticket for opening /api/ws without a token in the URL
+#[handler]
+pub async fn create_ws_ticket(res: &mut Response, depot: &mut Depot) {
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let session_id = depot.get::<String>("session_id").unwrap();
+    let jti = depot.get::<String>("jti").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let owner = TicketOwner {
+        user_id: *user_id,
+        session_id: session_id.clone(),
+        jti: jti.clone(),
+    };
+    let ttl = config.ws_ticket_ttl();
+
+    match ws_ticket::issue(&mut redis, &owner, ttl).await {
+        Ok(ticket) => {
+            res.render(Json(WsTicketResponse {
+                ticket,
+                expires_in: ttl.num_seconds(),
+            }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to issue WebSocket ticket: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to issue WebSocket ticket"
+            })));
+        }
+    }
+}
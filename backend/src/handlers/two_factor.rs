@@ -0,0 +1,278 @@
+use salvo::prelude::*;
+use super::request::parse_validated;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use redis::aio::MultiplexedConnection;
+use crate::config::Config;
+use crate::models::{DisableTwoFactorRequest, RecoveryCodesResponse, TwoFactorCodeRequest, TwoFactorLoginRequest, TwoFactorSetupResponse};
+use crate::entity::{users, users::Entity as Users};
+use crate::services::{login_throttle, two_factor};
+use crate::services::login_throttle::client_ip;
+use crate::services::session::DeviceInfo;
+use crate::utils::{verify_password, SharedJwtKeys};
+use super::auth::{check_locked_out, clear_failures, complete_login, render_invalid_credentials, render_locked_out, render_suspended};
+
+async fn find_current_user(res: &mut Response, db: &DatabaseConnection, user_id: i64) -> Option<users::Model> {
+    match Users::find_by_id(user_id).one(db).await {
+        Ok(Some(user)) => Some(user),
+        _ => {
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "User not found"
+            })));
+            None
+        }
+    }
+}
+
+#[handler]
+pub async fn setup_two_factor(res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    if user.totp_enabled {
+        res.status_code(StatusCode::CONFLICT);
+        res.render(Json(serde_json::json!({
+            "error": "Two-factor authentication is already enabled"
+        })));
+        return;
+    }
+
+    match two_factor::begin_enrollment(db, config, user).await {
+        Ok((secret, otpauth_uri)) => {
+            res.render(Json(TwoFactorSetupResponse { secret, otpauth_uri }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to start 2FA enrollment for user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to set up two-factor authentication"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn confirm_two_factor(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+
+    let Some(code_data) = parse_validated::<TwoFactorCodeRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    if user.totp_enabled {
+        res.status_code(StatusCode::CONFLICT);
+        res.render(Json(serde_json::json!({
+            "error": "Two-factor authentication is already enabled"
+        })));
+        return;
+    }
+
+    let ip = client_ip(req);
+    if check_locked_out(res, &mut redis, &user, ip.as_deref()).await {
+        return;
+    }
+
+    let email = user.email.clone();
+    match two_factor::confirm_enrollment(db, &mut redis, user, &code_data.code).await {
+        Ok(Some(recovery_codes)) => {
+            clear_failures(&mut redis, &email).await;
+            res.render(Json(RecoveryCodesResponse { recovery_codes }));
+        }
+        Ok(None) => {
+            render_invalid_credentials(res, db, &mut redis, config, &email, ip.as_deref(), "Invalid verification code").await;
+        }
+        Err(e) => {
+            tracing::error!("Failed to confirm 2FA enrollment for user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to enable two-factor authentication"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn disable_two_factor(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+
+    let Some(disable_data) = parse_validated::<DisableTwoFactorRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    let ip = client_ip(req);
+    if check_locked_out(res, &mut redis, &user, ip.as_deref()).await {
+        return;
+    }
+
+    if !matches!(verify_password(&disable_data.password, &user.password_hash).await, Ok(true)) {
+        render_invalid_credentials(res, db, &mut redis, config, &user.email, ip.as_deref(), "Password is incorrect").await;
+        return;
+    }
+
+    if !matches!(two_factor::verify_code(db, &mut redis, &user, &disable_data.code).await, Ok(true)) {
+        render_invalid_credentials(res, db, &mut redis, config, &user.email, ip.as_deref(), "Invalid verification code").await;
+        return;
+    }
+    clear_failures(&mut redis, &user.email).await;
+
+    match two_factor::disable(db, user).await {
+        Ok(()) => {
+            res.render(Json(serde_json::json!({
+                "success": true,
+                "message": "Two-factor authentication disabled"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to disable 2FA for user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to disable two-factor authentication"
+            })));
+        }
+    }
+}
+
+#[handler]
+pub async fn regenerate_recovery_codes(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let user_id = depot.get::<i64>("user_id").unwrap();
+    let config = depot.get::<Config>("config").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+
+    let Some(code_data) = parse_validated::<TwoFactorCodeRequest>(req, res).await else {
+        return;
+    };
+
+    let Some(user) = find_current_user(res, db, *user_id).await else {
+        return;
+    };
+
+    let ip = client_ip(req);
+    if check_locked_out(res, &mut redis, &user, ip.as_deref()).await {
+        return;
+    }
+
+    if !matches!(two_factor::verify_code(db, &mut redis, &user, &code_data.code).await, Ok(true)) {
+        render_invalid_credentials(res, db, &mut redis, config, &user.email, ip.as_deref(), "Invalid verification code").await;
+        return;
+    }
+    clear_failures(&mut redis, &user.email).await;
+
+    match two_factor::replace_recovery_codes(db, user.id).await {
+        Ok(recovery_codes) => {
+            res.render(Json(RecoveryCodesResponse { recovery_codes }));
+        }
+        Err(e) => {
+            tracing::error!("Failed to regenerate recovery codes for user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to regenerate recovery codes"
+            })));
+        }
+    }
+}
+
+// Second login step: exchange the password challenge and a code for tokens
+#[handler]
+pub async fn login_two_factor(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let db = depot.get::<DatabaseConnection>("db").unwrap();
+    let jwt_keys = depot.get::<SharedJwtKeys>("jwt_keys").unwrap();
+    let mut redis = depot.get::<MultiplexedConnection>("redis").unwrap().clone();
+    let config = depot.get::<Config>("config").unwrap();
+
+    let Some(login_data) = parse_validated::<TwoFactorLoginRequest>(req, res).await else {
+        return;
+    };
+
+    let ttl = config.two_factor_challenge_ttl();
+    let user_id = match two_factor::check_challenge(&mut redis, &login_data.challenge_token, ttl).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired login challenge"
+            })));
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to check login challenge: {:?}", e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to log in"
+            })));
+            return;
+        }
+    };
+
+    let user = match Users::find_by_id(user_id).one(db).await {
+        Ok(Some(user)) => user,
+        _ => {
+            res.status_code(StatusCode::UNAUTHORIZED);
+            res.render(Json(serde_json::json!({
+                "error": "Invalid or expired login challenge"
+            })));
+            return;
+        }
+    };
+
+    // Wrong codes count towards the same lockout as wrong passwords
+    let ip = client_ip(req);
+    match login_throttle::check(&mut redis, &user.email, ip.as_deref()).await {
+        Ok(Some(retry_after)) => {
+            render_locked_out(res, retry_after);
+            return;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("Failed to check login throttle: {:?}", e);
+        }
+    }
+
+    match two_factor::verify_code(db, &mut redis, &user, &login_data.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            render_invalid_credentials(res, db, &mut redis, config, &user.email, ip.as_deref(), "Invalid verification code").await;
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Failed to verify 2FA code for user {}: {:?}", user.id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to log in"
+            })));
+            return;
+        }
+    }
+
+    if let Err(e) = two_factor::finish_challenge(&mut redis, &login_data.challenge_token).await {
+        tracing::warn!("Failed to clear login challenge: {:?}", e);
+    }
+
+    // The account may have been suspended since the challenge was issued
+    if user.suspended_at.is_some() {
+        render_suspended(res);
+        return;
+    }
+
+    let device = DeviceInfo::from_request(req, login_data.device_name.clone());
+    complete_login(res, db, &mut redis, jwt_keys, config, user, device).await;
+}
@@ -4,15 +4,17 @@ mod db;
 mod utils;
 mod websocket;
 mod entity;
+mod config;
+mod services;
 
 use salvo::prelude::*;
 use salvo::cors::{Cors, CorsHandler};
 use salvo::http::Method;
-use tracing_subscriber;
 use dotenv::dotenv;
 use std::env;
 use std::sync::Arc;
 use sea_orm::DatabaseConnection;
+use redis::aio::MultiplexedConnection;
 use once_cell::sync::OnceCell;
 
 // Global application state
@@ -20,15 +22,25 @@ pub static APP_STATE: OnceCell<AppState> = OnceCell::new();
 
 // Depot keys for dependency injection
 pub const DB_KEY: &str = "db";
-pub const JWT_SECRET_KEY: &str = "jwt_secret";
+pub const JWT_KEYS_KEY: &str = "jwt_keys";
 pub const CLIENTS_KEY: &str = "clients";
+pub const REDIS_KEY: &str = "redis";
+pub const CONFIG_KEY: &str = "config";
+pub const MAILER_KEY: &str = "mailer";
+pub const OIDC_KEY: &str = "oidc";
+pub const STORAGE_KEY: &str = "storage";
 
 // Application shared state
 #[derive(Clone,Debug)]
 pub struct AppState {
     pub db: Arc<DatabaseConnection>,
-    pub jwt_secret: Arc<String>,
+    pub jwt_keys: utils::SharedJwtKeys,
     pub clients: websocket::Clients,
+    pub redis: MultiplexedConnection,
+    pub config: Arc<config::Config>,
+    pub mailer: services::mailer::SharedMailer,
+    pub oidc: Option<services::oidc::SharedOidcClient>,
+    pub storage: services::storage::SharedStorage,
 }
 
 impl AppState {
@@ -42,35 +54,79 @@ impl AppState {
 async fn inject_app_state(depot: &mut Depot, _req: &mut Request, _res: &mut Response, ctrl: &mut FlowCtrl) {
     let app_state = AppState::global();
     depot.insert(DB_KEY, app_state.db.as_ref().clone());
-    depot.insert(JWT_SECRET_KEY, app_state.jwt_secret.as_ref().clone());
+    depot.insert(JWT_KEYS_KEY, app_state.jwt_keys.clone());
     depot.insert(CLIENTS_KEY, app_state.clients.clone());
+    depot.insert(REDIS_KEY, app_state.redis.clone());
+    depot.insert(CONFIG_KEY, app_state.config.as_ref().clone());
+    depot.insert(MAILER_KEY, app_state.mailer.clone());
+    depot.insert(STORAGE_KEY, app_state.storage.clone());
+    if let Some(oidc) = &app_state.oidc {
+        depot.insert(OIDC_KEY, oidc.clone());
+    }
     ctrl.call_next(_req, depot, _res).await;
 }
 
 // Middleware to verify JWT token
 #[handler]
 async fn auth_middleware(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
-    // Try Authorization header first, then query parameter (for WebSocket)
-    let token_from_header = req
+    // WebSocket clients authenticate with a ticket instead, see /ws/ticket
+    let token = req
         .headers()
         .get("Authorization")
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.strip_prefix("Bearer "))
         .map(|s| s.to_string());
 
-    let token_from_query = req.query::<String>("token");
+    // Bots authenticate with an API key; handlers check its scopes
+    if let Some(key) = req.headers().get("X-Api-Key").and_then(|v| v.to_str().ok()) {
+        let app_state = AppState::global();
+        match services::api_key::authenticate(&app_state.db, key).await {
+            Ok(Some((user_id, scopes))) => {
+                depot.insert("user_id", user_id);
+                depot.insert("scopes", scopes);
+                ctrl.call_next(req, depot, res).await;
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("Failed to check API key: {:?}", e);
+            }
+        }
 
-    let token = token_from_header.or(token_from_query);
+        res.status_code(StatusCode::UNAUTHORIZED);
+        res.render(Json(serde_json::json!({
+            "error": "Invalid or expired API key"
+        })));
+        return;
+    }
 
     if let Some(token) = token {
         let app_state = AppState::global();
-        match utils::verify_token(&token, &app_state.jwt_secret) {
-            Ok(claims) => {
-                depot.insert("user_id", claims.sub);
-                ctrl.call_next(req, depot, res).await;
-                return;
+        if let Ok(claims) = utils::verify_token(&token, &app_state.jwt_keys) {
+            let mut redis = app_state.redis.clone();
+            match services::token_denylist::is_revoked(&mut redis, &claims.jti, &claims.sid).await {
+                Ok(false) => {
+                    // Keep the device list's "last active" current without
+                    // delaying the request
+                    let db = app_state.db.as_ref().clone();
+                    let session_id = claims.sid.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = services::session::touch_throttled(&db, &mut redis, &session_id).await {
+                            tracing::warn!("Failed to record activity of session {}: {:?}", session_id, e);
+                        }
+                    });
+                    depot.insert("user_id", claims.sub);
+                    depot.insert("jti", claims.jti);
+                    depot.insert("session_id", claims.sid);
+                    ctrl.call_next(req, depot, res).await;
+                    return;
+                }
+                Ok(true) => {}
+                Err(e) => {
+                    // Fail closed: a token we cannot check is not trusted
+                    tracing::error!("Failed to check token denylist: {:?}", e);
+                }
             }
-            Err(_) => {}
         }
     }
 
@@ -80,6 +136,162 @@ async fn auth_middleware(req: &mut Request, depot: &mut Depot, res: &mut Respons
     })));
 }
 
+// Routes that manage the account or its login sessions refuse API keys
+#[handler]
+async fn session_required(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    if depot.get::<services::api_key::ApiScopes>("scopes").is_ok() {
+        res.status_code(StatusCode::FORBIDDEN);
+        res.render(Json(serde_json::json!({
+            "error": "This endpoint cannot be used with an API key"
+        })));
+        return;
+    }
+    ctrl.call_next(req, depot, res).await;
+}
+
+// Admin routes additionally require the server-level admin role
+#[handler]
+async fn admin_required(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let user_id = *depot.get::<i64>("user_id").unwrap();
+    let app_state = AppState::global();
+
+    match services::admin::is_admin(&app_state.db, user_id).await {
+        Ok(true) => {
+            ctrl.call_next(req, depot, res).await;
+        }
+        Ok(false) => {
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Json(serde_json::json!({
+                "error": "Administrator role required"
+            })));
+        }
+        Err(e) => {
+            tracing::error!("Failed to check admin role of user {}: {:?}", user_id, e);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(Json(serde_json::json!({
+                "error": "Failed to check permissions"
+            })));
+        }
+    }
+}
+
+// Every route of the server. Application state is injected by the service,
+// see main.
+fn router() -> Router {
+    Router::new()
+        .push(
+            Router::with_path("/.well-known/jwks.json")
+                .get(handlers::jwks)
+        )
+        .push(
+            Router::with_path("/uploads/{**key}")
+                .get(handlers::get_upload)
+        )
+        .push(
+            Router::with_path("/api")
+                .push(
+                    Router::with_path("/auth")
+                        .push(Router::with_path("/register").post(handlers::register))
+                        .push(Router::with_path("/login").post(handlers::login))
+                        .push(Router::with_path("/login/2fa").post(handlers::login_two_factor))
+                        .push(Router::with_path("/oidc/authorize").get(handlers::oidc_authorize))
+                        .push(Router::with_path("/oidc/callback").post(handlers::oidc_callback))
+                        .push(Router::with_path("/refresh").post(handlers::refresh))
+                        .push(Router::with_path("/password/forgot").post(handlers::forgot_password))
+                        .push(Router::with_path("/password/reset").post(handlers::reset_password))
+                        .push(Router::with_path("/verify-email").post(handlers::verify_email))
+                        .push(Router::with_path("/logout").hoop(auth_middleware).hoop(session_required).post(handlers::logout))
+                )
+                .push(
+                    Router::with_path("/user")
+                        .hoop(auth_middleware)
+                        .hoop(session_required)
+                        .push(Router::with_path("/me").get(handlers::get_current_user).put(handlers::update_profile))
+                        .push(Router::with_path("/me/avatar").post(handlers::upload_avatar).delete(handlers::delete_avatar))
+                        .push(Router::with_path("/me/presence").put(handlers::set_presence))
+                        .push(Router::with_path("/me/status").put(handlers::set_custom_status).delete(handlers::clear_custom_status))
+                        .push(Router::with_path("/presence").get(handlers::get_presence))
+                        .push(Router::with_path("/password").put(handlers::change_password))
+                        .push(Router::with_path("/verify-email/resend").post(handlers::resend_verification))
+                        .push(Router::with_path("/2fa/setup").post(handlers::setup_two_factor))
+                        .push(Router::with_path("/2fa/confirm").post(handlers::confirm_two_factor))
+                        .push(Router::with_path("/2fa/disable").post(handlers::disable_two_factor))
+                        .push(Router::with_path("/2fa/recovery-codes").post(handlers::regenerate_recovery_codes))
+                        .push(Router::with_path("/list").get(handlers::get_all_users))
+                        .push(Router::with_path("/sessions").get(handlers::get_sessions))
+                        .push(Router::with_path("/sessions/{id}").delete(handlers::revoke_session))
+                )
+                .push(
+                    Router::with_path("/contacts")
+                        .hoop(auth_middleware)
+                        .hoop(session_required)
+                        .get(handlers::get_contacts)
+                        .push(Router::with_path("/requests").get(handlers::get_friend_requests).post(handlers::send_friend_request))
+                        .push(Router::with_path("/requests/{id}").delete(handlers::cancel_friend_request))
+                        .push(Router::with_path("/requests/{id}/accept").post(handlers::accept_friend_request))
+                        .push(Router::with_path("/requests/{id}/decline").post(handlers::decline_friend_request))
+                        .push(Router::with_path("/{id}").delete(handlers::remove_contact))
+                )
+                .push(
+                    Router::with_path("/blocks")
+                        .hoop(auth_middleware)
+                        .hoop(session_required)
+                        .get(handlers::get_blocked_users)
+                        .post(handlers::block_user)
+                        .push(Router::with_path("/{id}").delete(handlers::unblock_user))
+                )
+                .push(
+                    Router::with_path("/bots")
+                        .hoop(auth_middleware)
+                        .hoop(session_required)
+                        .get(handlers::get_bots)
+                        .post(handlers::create_bot)
+                        .push(Router::with_path("/{id}").delete(handlers::delete_bot))
+                        .push(Router::with_path("/{id}/keys").get(handlers::get_api_keys).post(handlers::create_api_key))
+                        .push(Router::with_path("/{id}/keys/{key_id}").delete(handlers::revoke_api_key))
+                )
+                .push(
+                    Router::with_path("/messages")
+                        .hoop(auth_middleware)
+                        .push(Router::with_path("/send").post(handlers::send_message))
+                        .push(Router::with_path("/list").get(handlers::get_messages))
+                        .push(Router::with_path("/{id}/read").put(handlers::mark_as_read))
+                        .push(Router::with_path("/{id}/edits").get(handlers::get_message_edits))
+                        .push(Router::with_path("/{id}/recall").post(handlers::recall_message))
+                        .push(Router::with_path("/{id}").put(handlers::edit_message).delete(handlers::delete_message))
+                )
+                .push(
+                    Router::with_path("/groups")
+                        .hoop(auth_middleware)
+                        .push(Router::with_path("/create").post(handlers::create_group))
+                        .push(Router::with_path("/join").post(handlers::join_group))
+                        .push(Router::with_path("/list").get(handlers::get_user_groups))
+                        .push(Router::with_path("/available").get(handlers::get_available_groups))
+                        .push(Router::with_path("/{id}/members").get(handlers::get_group_members))
+                        .push(Router::with_path("/{id}/avatar").post(handlers::upload_group_avatar))
+                )
+                .push(
+                    Router::with_path("/admin")
+                        .hoop(auth_middleware)
+                        .hoop(session_required)
+                        .hoop(admin_required)
+                        .push(Router::with_path("/stats").get(handlers::get_server_stats))
+                        .push(Router::with_path("/users").get(handlers::admin_get_users))
+                        .push(Router::with_path("/users/{id}/role").put(handlers::update_user_role))
+                        .push(Router::with_path("/users/{id}/suspend").post(handlers::suspend_user))
+                        .push(Router::with_path("/users/{id}/unsuspend").post(handlers::unsuspend_user))
+                        .push(Router::with_path("/users/{id}/logout").post(handlers::force_logout_user))
+                        .push(Router::with_path("/groups/{id}").delete(handlers::admin_delete_group))
+                        .push(Router::with_path("/lockouts").get(handlers::get_login_lockouts))
+                )
+                .push(
+                    Router::with_path("/ws")
+                        .goal(websocket::websocket_handler)
+                        .push(Router::with_path("/ticket").hoop(auth_middleware).hoop(session_required).post(handlers::create_ws_ticket))
+                )
+        )
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
@@ -89,8 +301,7 @@ async fn main() {
         .expect("DATABASE_URL must be set");
     let redis_url = env::var("REDIS_URL")
         .expect("REDIS_URL must be set");
-    let jwt_secret = env::var("JWT_SECRET")
-        .expect("JWT_SECRET must be set");
+    let jwt_secret = env::var("JWT_SECRET").ok().filter(|s| !s.is_empty());
     let server_host = env::var("SERVER_HOST")
         .unwrap_or_else(|_| "127.0.0.1".to_string());
     let server_port = env::var("SERVER_PORT")
@@ -106,18 +317,41 @@ async fn main() {
         .expect("Failed to run migrations");
 
     // Initialize Redis
-    let _redis_client = db::create_redis_client(&redis_url)
+    let redis_client = db::create_redis_client(&redis_url)
         .await
         .expect("Failed to create Redis client");
+    let redis = db::get_redis_connection(&redis_client)
+        .await
+        .expect("Failed to connect to Redis");
+
+    let config = config::Config::from_env();
+    services::admin::promote_configured(&db, &config)
+        .await
+        .expect("Failed to grant configured admin roles");
+    let jwt_keys = utils::JwtKeys::load(&config, jwt_secret)
+        .expect("Failed to load JWT keys");
+    let mailer = services::mailer::create_mailer(&config)
+        .expect("Failed to create mailer");
+    let storage = services::storage::create_storage(&config)
+        .expect("Failed to create storage backend");
+    let oidc = services::oidc::OidcClient::from_config(&config).map(Arc::new);
 
     // Create WebSocket clients map
     let clients = websocket::create_clients();
+    services::presence::spawn_idle_sweeper(db.clone(), clients.clone(), config.presence_idle_timeout());
+    services::custom_status::spawn_expiry_task(db.clone(), clients.clone());
+    services::session::spawn_prune_task(db.clone(), config.refresh_token_ttl());
 
     // Create and initialize global app state
     let app_state = AppState {
         db: Arc::new(db),
-        jwt_secret: Arc::new(jwt_secret),
+        jwt_keys: Arc::new(jwt_keys),
         clients: clients.clone(),
+        redis,
+        config: Arc::new(config),
+        mailer,
+        oidc,
+        storage,
     };
 
     APP_STATE.set(app_state).expect("Failed to set APP_STATE");
@@ -126,53 +360,71 @@ async fn main() {
     let cors_handler: CorsHandler = Cors::new()
         .allow_origin("*")
         .allow_methods(vec![Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
-        .allow_headers(vec!["Content-Type", "Authorization"])
+        .allow_headers(vec!["Content-Type", "Authorization", "X-Api-Key"])
         .into_handler();
 
-    let router = Router::new()
-        .push(
-            Router::with_path("/api")
-                .hoop(inject_app_state)
-                .push(
-                    Router::with_path("/auth")
-                        .push(Router::with_path("/register").post(handlers::register))
-                        .push(Router::with_path("/login").post(handlers::login))
-                        .push(Router::with_path("/logout").hoop(auth_middleware).post(handlers::logout))
-                )
-                .push(
-                    Router::with_path("/user")
-                        .hoop(auth_middleware)
-                        .push(Router::with_path("/me").get(handlers::get_current_user))
-                        .push(Router::with_path("/list").get(handlers::get_all_users))
-                )
-                .push(
-                    Router::with_path("/messages")
-                        .hoop(auth_middleware)
-                        .push(Router::with_path("/send").post(handlers::send_message))
-                        .push(Router::with_path("/list").get(handlers::get_messages))
-                        .push(Router::with_path("/<id>/read").put(handlers::mark_as_read))
-                )
-                .push(
-                    Router::with_path("/groups")
-                        .hoop(auth_middleware)
-                        .push(Router::with_path("/create").post(handlers::create_group))
-                        .push(Router::with_path("/join").post(handlers::join_group))
-                        .push(Router::with_path("/list").get(handlers::get_user_groups))
-                        .push(Router::with_path("/available").get(handlers::get_available_groups))
-                        .push(Router::with_path("/<id>/members").get(handlers::get_group_members))
-                )
-                .push(
-                    Router::with_path("/ws")
-                        .hoop(auth_middleware)
-                        .goal(websocket::websocket_handler)
-                )
-        );
-
     let acceptor = TcpListener::new(format!("{}:{}", server_host, server_port))
         .bind()
         .await;
 
     tracing::info!("Server running on http://{}:{}", server_host, server_port);
-    let service = Service::new(router).hoop(cors_handler);
+    let service = Service::new(router()).hoop(cors_handler).hoop(inject_app_state);
     Server::new(acceptor).serve(service).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use salvo::test::TestClient;
+
+    // A protected route that matches answers 401 without credentials; one
+    // whose path syntax the router does not understand answers 404
+    #[tokio::test]
+    async fn parameterised_routes_match() {
+        let service = Service::new(router());
+        let routes = [
+            ("DELETE", "/api/user/sessions/abc123"),
+            ("DELETE", "/api/contacts/5"),
+            ("DELETE", "/api/contacts/requests/5"),
+            ("POST", "/api/contacts/requests/5/accept"),
+            ("POST", "/api/contacts/requests/5/decline"),
+            ("DELETE", "/api/blocks/5"),
+            ("DELETE", "/api/bots/5"),
+            ("GET", "/api/bots/5/keys"),
+            ("POST", "/api/bots/5/keys"),
+            ("DELETE", "/api/bots/5/keys/9"),
+            ("PUT", "/api/messages/5"),
+            ("DELETE", "/api/messages/5"),
+            ("PUT", "/api/messages/5/read"),
+            ("GET", "/api/messages/5/edits"),
+            ("POST", "/api/messages/5/recall"),
+            ("GET", "/api/groups/5/members"),
+            ("POST", "/api/groups/5/avatar"),
+            ("PUT", "/api/admin/users/5/role"),
+            ("POST", "/api/admin/users/5/suspend"),
+            ("POST", "/api/admin/users/5/unsuspend"),
+            ("POST", "/api/admin/users/5/logout"),
+            ("DELETE", "/api/admin/groups/5"),
+        ];
+
+        for (method, path) in routes {
+            let url = format!("http://127.0.0.1{}", path);
+            let client = match method {
+                "GET" => TestClient::get(url),
+                "POST" => TestClient::post(url),
+                "PUT" => TestClient::put(url),
+                "DELETE" => TestClient::delete(url),
+                _ => unreachable!(),
+            };
+            let res = client.send(&service).await;
+            assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED), "{} {}", method, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_routes_are_not_found() {
+        let service = Service::new(router());
+        let res = TestClient::get("http://127.0.0.1/api/bots/5/unknown").send(&service).await;
+        assert_eq!(res.status_code, Some(StatusCode::NOT_FOUND));
+    }
+}
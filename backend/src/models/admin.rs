@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::validation::validate_role;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateRoleRequest {
+    #[validate(custom(function = "validate_role"))]
+    pub role: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AdminUserQuery {
+    #[validate(range(min = 1, max = 200, message = "Limit must be 1 to 200"))]
+    pub limit: Option<u64>,
+    #[validate(length(max = 256, message = "Invalid cursor"))]
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserPage {
+    pub users: Vec<crate::entity::users::Model>,
+    // Pass as ?cursor= to get the next page; null on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerStats {
+    pub users: u64,
+    pub bots: u64,
+    pub admins: u64,
+    pub suspended_users: u64,
+    pub groups: u64,
+    pub messages: u64,
+    pub messages_last_24h: u64,
+    pub active_sessions: u64,
+    pub websocket_connections: u64,
+    pub online_users: u64,
+}
@@ -0,0 +1,15 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::PublicProfile;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BlockUserRequest {
+    pub user_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockedUserResponse {
+    pub user: PublicProfile,
+    pub blocked_at: Option<NaiveDateTime>,
+}
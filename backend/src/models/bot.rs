@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::validation::{validate_api_scopes, validate_not_blank, validate_username};
+use crate::entity::api_keys;
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateBotRequest {
+    #[validate(custom(function = "validate_username"))]
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(
+        length(min = 1, max = 100, message = "Name must be 1 to 100 characters"),
+        custom(function = "validate_not_blank")
+    )]
+    pub name: String,
+    #[validate(
+        length(min = 1, max = 50, message = "Provide 1 to 50 scopes"),
+        custom(function = "validate_api_scopes")
+    )]
+    pub scopes: Vec<String>,
+    #[validate(range(min = 1, max = 3650, message = "Expiry must be 1 to 3650 days"))]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: i64,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<api_keys::Model> for ApiKeyResponse {
+    fn from(key: api_keys::Model) -> Self {
+        ApiKeyResponse {
+            id: key.id,
+            name: key.name,
+            key_prefix: key.key_prefix,
+            scopes: key.scopes.split_whitespace().map(str::to_string).collect(),
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+            created_at: key.created_at,
+        }
+    }
+}
+
+// The plaintext key is only ever returned here
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
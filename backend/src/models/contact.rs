@@ -0,0 +1,34 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::{PresenceStatus, PublicProfile};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SendFriendRequestRequest {
+    pub user_id: i64,
+    #[validate(length(max = 200, message = "Message must be at most 200 characters"))]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContactResponse {
+    pub user: PublicProfile,
+    pub online: bool,
+    pub status: PresenceStatus,
+    pub since: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FriendRequestResponse {
+    pub id: i64,
+    pub sender: PublicProfile,
+    pub receiver: PublicProfile,
+    pub message: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FriendRequestsResponse {
+    pub incoming: Vec<FriendRequestResponse>,
+    pub outgoing: Vec<FriendRequestResponse>,
+}
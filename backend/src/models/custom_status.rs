@@ -0,0 +1,42 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+use super::validation::validate_future;
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_not_empty"))]
+pub struct SetCustomStatusRequest {
+    #[validate(length(max = 100, message = "Status text must be at most 100 characters"))]
+    pub text: Option<String>,
+    #[validate(length(max = 32, message = "Status emoji must be at most 32 characters"))]
+    pub emoji: Option<String>,
+    // Omit to keep the status until it is cleared
+    #[validate(custom(function = "validate_future"))]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+fn validate_not_empty(request: &SetCustomStatusRequest) -> Result<(), ValidationError> {
+    let blank = |value: &Option<String>| value.as_deref().is_none_or(|value| value.trim().is_empty());
+    if blank(&request.text) && blank(&request.emoji) {
+        return Err(ValidationError::new("status").with_message("Status needs text or an emoji".into()));
+    }
+    Ok(())
+}
+
+// Payload of status_changed events; all fields are null once cleared
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomStatus {
+    pub text: Option<String>,
+    pub emoji: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl From<&crate::entity::users::Model> for CustomStatus {
+    fn from(user: &crate::entity::users::Model) -> Self {
+        CustomStatus {
+            text: user.status_text.clone(),
+            emoji: user.status_emoji.clone(),
+            expires_at: user.status_expires_at,
+        }
+    }
+}
@@ -1,13 +1,20 @@
 use serde::Deserialize;
+use validator::Validate;
+use super::validation::validate_not_blank;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct CreateGroupRequest {
+    #[validate(
+        length(min = 1, max = 100, message = "Group name must be 1 to 100 characters"),
+        custom(function = "validate_not_blank")
+    )]
     pub name: String,
+    #[validate(length(max = 1000, message = "Description must be at most 1000 characters"))]
     pub description: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct JoinGroupRequest {
+    #[validate(range(min = 1, message = "Invalid group id"))]
     pub group_id: i64,
 }
-
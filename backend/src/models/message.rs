@@ -1,15 +1,92 @@
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+use super::validation::validate_message_type;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct SendMessageRequest {
+    #[validate(range(min = 1, message = "Invalid receiver id"))]
     pub receiver_id: Option<i64>,
+    #[validate(range(min = 1, message = "Invalid group id"))]
     pub group_id: Option<i64>,
+    #[validate(length(min = 1, max = 10000, message = "Message must be 1 to 10000 characters"))]
     pub content: String,
+    #[validate(custom(function = "validate_message_type"))]
     pub message_type: String,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct EditMessageRequest {
+    #[validate(length(min = 1, max = 10000, message = "Message must be 1 to 10000 characters"))]
+    pub content: String,
+}
+
+// Query string of GET /api/messages/list. Without a cursor the newest
+// messages are returned; pass the first id of a page as `before` to scroll
+// back, or the last id as `after` to catch up.
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_single_cursor"))]
+pub struct MessageHistoryQuery {
+    pub receiver_id: Option<i64>,
+    pub group_id: Option<i64>,
+    #[validate(range(min = 1, message = "Invalid cursor"))]
+    pub before: Option<i64>,
+    #[validate(range(min = 0, message = "Invalid cursor"))]
+    pub after: Option<i64>,
+    #[validate(range(min = 1, max = 100, message = "Limit must be 1 to 100"))]
+    pub limit: Option<u64>,
+}
+
+fn validate_single_cursor(query: &MessageHistoryQuery) -> Result<(), ValidationError> {
+    if query.before.is_some() && query.after.is_some() {
+        return Err(ValidationError::new("cursor").with_message("Pass either before or after, not both".into()));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Serialize)]
-pub struct MessageResponse {
-    pub message: crate::entity::messages::Model,
+pub struct MessagePage {
+    // Oldest first, whichever direction was paged
+    pub messages: Vec<crate::entity::messages::Model>,
+    // Whether more messages lie beyond this page in the paging direction
+    pub has_more: bool,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(before: Option<i64>, after: Option<i64>, limit: Option<u64>) -> MessageHistoryQuery {
+        MessageHistoryQuery { receiver_id: Some(2), group_id: None, before, after, limit }
+    }
+
+    fn fields(query: &MessageHistoryQuery) -> Vec<String> {
+        match query.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => {
+                let mut fields: Vec<String> = errors.errors().keys().map(|field| field.to_string()).collect();
+                fields.sort();
+                fields
+            }
+        }
+    }
+
+    #[test]
+    fn history_query_accepts_one_cursor() {
+        assert!(fields(&query(None, None, None)).is_empty());
+        assert!(fields(&query(Some(10), None, Some(50))).is_empty());
+        assert!(fields(&query(None, Some(0), Some(100))).is_empty());
+    }
+
+    #[test]
+    fn history_query_rejects_both_cursors() {
+        assert_eq!(fields(&query(Some(10), Some(5), None)), ["__all__"]);
+    }
+
+    #[test]
+    fn history_query_checks_ranges() {
+        assert_eq!(fields(&query(Some(0), None, None)), ["before"]);
+        assert_eq!(fields(&query(None, Some(-1), None)), ["after"]);
+        assert_eq!(fields(&query(None, None, Some(0))), ["limit"]);
+        assert_eq!(fields(&query(None, None, Some(101))), ["limit"]);
+    }
+}
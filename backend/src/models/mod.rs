@@ -1,7 +1,22 @@
 pub mod user;
 pub mod message;
 pub mod group;
+pub mod bot;
+pub mod session;
+pub mod admin;
+pub mod contact;
+pub mod block;
+pub mod presence;
+pub mod custom_status;
+pub mod validation;
 
 pub use user::*;
 pub use message::*;
 pub use group::*;
+pub use bot::*;
+pub use session::*;
+pub use admin::*;
+pub use contact::*;
+pub use block::*;
+pub use presence::*;
+pub use custom_status::*;
@@ -0,0 +1,65 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::validation::{parse_id_list, validate_id_list, validate_presence_status};
+
+// users.status holds the state a user picked; Offline is only ever computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    Away,
+    Busy,
+    Dnd,
+    Invisible,
+    Offline,
+}
+
+impl PresenceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceStatus::Online => "online",
+            PresenceStatus::Away => "away",
+            PresenceStatus::Busy => "busy",
+            PresenceStatus::Dnd => "dnd",
+            PresenceStatus::Invisible => "invisible",
+            PresenceStatus::Offline => "offline",
+        }
+    }
+
+    // Unknown values, e.g. rows written before presence states existed,
+    // read as online
+    pub fn parse(value: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(value.to_string()))
+            .ok()
+            .filter(|status| *status != PresenceStatus::Offline)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SetPresenceRequest {
+    #[validate(custom(function = "validate_presence_status"))]
+    pub status: PresenceStatus,
+}
+
+// Query string of GET /api/user/presence, e.g. ?ids=1,2,3
+#[derive(Debug, Deserialize, Validate)]
+pub struct PresenceQuery {
+    #[validate(custom(function = "validate_id_list"))]
+    pub ids: String,
+}
+
+impl PresenceQuery {
+    pub fn user_ids(&self) -> Vec<i64> {
+        parse_id_list(&self.ids).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresenceResponse {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub last_seen_at: Option<NaiveDateTime>,
+}
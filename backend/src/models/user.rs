@@ -1,27 +1,248 @@
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+use super::validation::{validate_not_blank, validate_password_strength, validate_username};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct RegisterRequest {
+    #[validate(custom(function = "validate_username"))]
     pub username: String,
+    #[validate(email(message = "Must be a valid email address"), length(max = 100, message = "Email must be at most 100 characters"))]
     pub email: String,
+    #[validate(custom(function = "validate_password_strength"))]
     pub password: String,
+    #[validate(length(max = 100, message = "Device name must be at most 100 characters"))]
+    pub device_name: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct LoginRequest {
+    #[validate(custom(function = "validate_not_blank"))]
     pub email: String,
+    #[validate(length(min = 1, message = "Password is required"))]
     pub password: String,
+    #[validate(length(max = 100, message = "Device name must be at most 100 characters"))]
+    pub device_name: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
     pub user: crate::entity::users::Model,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct RefreshRequest {
+    #[validate(length(min = 1, max = 256, message = "Invalid refresh token"))]
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifyEmailRequest {
+    #[validate(length(min = 1, max = 256, message = "Invalid token"))]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1, message = "Current password is required"))]
+    pub current_password: String,
+    #[validate(custom(function = "validate_password_strength"))]
+    pub new_password: String,
+    #[validate(length(max = 100, message = "Device name must be at most 100 characters"))]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(email(message = "Must be a valid email address"))]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1, max = 256, message = "Invalid token"))]
+    pub token: String,
+    #[validate(custom(function = "validate_password_strength"))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TwoFactorCodeRequest {
+    #[validate(length(min = 6, max = 32, message = "Code must be 6 to 32 characters"))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct DisableTwoFactorRequest {
+    #[validate(length(min = 1, message = "Password is required"))]
+    pub password: String,
+    #[validate(length(min = 6, max = 32, message = "Code must be 6 to 32 characters"))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TwoFactorLoginRequest {
+    #[validate(length(min = 1, max = 256, message = "Invalid challenge token"))]
+    pub challenge_token: String,
+    #[validate(length(min = 6, max = 32, message = "Code must be 6 to 32 characters"))]
+    pub code: String,
+    #[validate(length(max = 100, message = "Device name must be at most 100 characters"))]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct OidcCallbackRequest {
+    #[validate(length(min = 1, max = 2048, message = "Invalid authorization code"))]
+    pub code: String,
+    #[validate(length(min = 1, max = 256, message = "Invalid state"))]
+    pub state: String,
+    #[validate(length(min = 1, max = 256, message = "Invalid login binding"))]
+    pub binding: String,
+    #[validate(length(max = 100, message = "Device name must be at most 100 characters"))]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcAuthorizeResponse {
+    pub authorization_url: String,
+    // Kept by the client and sent back with the callback
+    pub binding: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+// Returned by login instead of tokens when the account has 2FA enabled
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallengeResponse {
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: i64, // user id
     pub exp: usize,
+    pub jti: String, // unique token id, used for revocation
+    pub sid: String, // login session id, shared with the refresh token family
+    pub iss: String,
+    pub aud: String,
+}
+
+
+// Omitted fields are left unchanged; an empty display name or bio clears it
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateProfileRequest {
+    #[validate(custom(function = "validate_username"))]
+    pub username: Option<String>,
+    #[validate(length(max = 64, message = "Display name must be at most 64 characters"))]
+    pub display_name: Option<String>,
+    #[validate(length(max = 500, message = "Bio must be at most 500 characters"))]
+    pub bio: Option<String>,
+}
+
+// What other users get to see of an account
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicProfile {
+    pub id: i64,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub is_bot: bool,
 }
 
+impl From<&crate::entity::users::Model> for PublicProfile {
+    fn from(user: &crate::entity::users::Model) -> Self {
+        PublicProfile {
+            id: user.id,
+            username: user.username.clone(),
+            display_name: user.display_name.clone(),
+            bio: user.bio.clone(),
+            avatar: user.avatar.clone(),
+            is_bot: user.is_bot,
+        }
+    }
+}
+
+// Directory entry: the public profile plus presence and custom status as the
+// viewer sees them
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryUser {
+    #[serde(flatten)]
+    pub profile: PublicProfile,
+    pub status: super::PresenceStatus,
+    pub last_seen_at: Option<chrono::NaiveDateTime>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_expires_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectorySearch {
+    #[default]
+    Prefix,
+    Substring,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectorySort {
+    #[default]
+    Username,
+    // Account age; ids grow with creation order and, unlike created_at, are never null
+    CreatedAt,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// Query string of GET /api/user/list
+#[derive(Debug, Deserialize, Validate)]
+pub struct DirectoryQuery {
+    #[validate(length(max = 100, message = "Search must be at most 100 characters"))]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub search: DirectorySearch,
+    #[serde(default)]
+    pub sort: DirectorySort,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[validate(range(min = 1, max = 100, message = "Limit must be 1 to 100"))]
+    pub limit: Option<u64>,
+    #[validate(length(max = 256, message = "Invalid cursor"))]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub exclude_blocked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryPage {
+    pub users: Vec<DirectoryUser>,
+    // Pass as ?cursor= to get the next page; null on the last page
+    pub next_cursor: Option<String>,
+}
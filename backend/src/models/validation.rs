@@ -0,0 +1,211 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+// Shared rules for #[validate(custom(...))] on the request models
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+// Argon2 hashes input of any length; the cap only stops huge bodies from
+// tying up the hashing pool
+pub const PASSWORD_MAX_BYTES: usize = 1024;
+pub const MESSAGE_TYPES: &[&str] = &["text", "image", "file"];
+pub const ID_LIST_MAX: usize = 100;
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Borrowed(message))
+}
+
+// Letters, digits, '_', '.' and '-', starting with a letter or digit
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let len = username.chars().count();
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&len) {
+        return Err(error("length", "Username must be 3 to 32 characters"));
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(error("username_start", "Username must start with a letter or digit"));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')) {
+        return Err(error("username_charset", "Username may only contain letters, digits, '_', '.' and '-'"));
+    }
+    Ok(())
+}
+
+pub fn validate_password_strength(password: &str) -> Result<(), ValidationError> {
+    if password.chars().count() < PASSWORD_MIN_LENGTH {
+        return Err(error("password_length", "Password must be at least 8 characters"));
+    }
+    if password.len() > PASSWORD_MAX_BYTES {
+        return Err(error("password_length", "Password must be at most 1024 bytes"));
+    }
+    if !password.chars().any(|c| c.is_alphabetic()) || !password.chars().any(|c| c.is_ascii_digit()) {
+        return Err(error("password_strength", "Password must contain at least one letter and one digit"));
+    }
+    Ok(())
+}
+
+pub fn validate_not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(error("blank", "Must not be blank"));
+    }
+    Ok(())
+}
+
+pub fn validate_message_type(message_type: &str) -> Result<(), ValidationError> {
+    if !MESSAGE_TYPES.contains(&message_type) {
+        return Err(error("message_type", "Message type must be one of text, image, file"));
+    }
+    Ok(())
+}
+
+pub fn validate_api_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if !scopes.iter().all(|scope| crate::services::api_key::is_valid_scope(scope)) {
+        return Err(error(
+            "scope",
+            "Scopes must be messages:read, messages:write, groups:read or groups:write, optionally followed by :group:<id> or :user:<id>",
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_role(role: &str) -> Result<(), ValidationError> {
+    if !crate::services::admin::ROLES.contains(&role) {
+        return Err(error("role", "Role must be user or admin"));
+    }
+    Ok(())
+}
+
+pub fn validate_presence_status(status: &crate::models::PresenceStatus) -> Result<(), ValidationError> {
+    if *status == crate::models::PresenceStatus::Offline {
+        return Err(error("status", "Status must be online, away, busy, dnd or invisible"));
+    }
+    Ok(())
+}
+
+pub fn validate_future(time: &chrono::DateTime<chrono::Utc>) -> Result<(), ValidationError> {
+    if *time <= chrono::Utc::now() {
+        return Err(error("future", "Time must be in the future"));
+    }
+    Ok(())
+}
+
+// Comma-separated ids such as "1,2,3"
+pub fn parse_id_list(ids: &str) -> Option<Vec<i64>> {
+    ids.split(',').map(|id| id.trim().parse().ok()).collect()
+}
+
+pub fn validate_id_list(ids: &str) -> Result<(), ValidationError> {
+    match parse_id_list(ids) {
+        Some(ids) if !ids.is_empty() && ids.len() <= ID_LIST_MAX => Ok(()),
+        _ => Err(error("ids", "Ids must be 1 to 100 comma-separated user ids")),
+    }
+}
+
+// Flatten validator output into { "field": ["message", ...] } for 422 responses
+pub fn field_errors(errors: &ValidationErrors) -> serde_json::Value {
+    let mut fields: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    collect_errors(errors, "", &mut fields);
+    serde_json::json!(fields)
+}
+
+fn collect_errors(errors: &ValidationErrors, prefix: &str, fields: &mut BTreeMap<String, Vec<String>>) {
+    for (field, kind) in errors.errors() {
+        let name = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(list) => {
+                let messages = fields.entry(name).or_default();
+                for e in list {
+                    messages.push(match &e.message {
+                        Some(message) => message.to_string(),
+                        None => format!("Invalid value ({})", e.code),
+                    });
+                }
+            }
+            ValidationErrorsKind::Struct(inner) => collect_errors(inner, &name, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, inner) in items {
+                    collect_errors(inner, &format!("{}[{}]", name, index), fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::Validate;
+
+    #[test]
+    fn username_rules() {
+        assert!(validate_username("alice").is_ok());
+        assert!(validate_username("a.b_c-1").is_ok());
+        assert!(validate_username("9lives").is_ok());
+        assert_eq!(validate_username("ab").unwrap_err().code, "length");
+        assert_eq!(validate_username(&"a".repeat(33)).unwrap_err().code, "length");
+        assert_eq!(validate_username("_alice").unwrap_err().code, "username_start");
+        assert_eq!(validate_username("alice bob").unwrap_err().code, "username_charset");
+        assert_eq!(validate_username("ålice").unwrap_err().code, "username_start");
+    }
+
+    #[test]
+    fn password_rules() {
+        assert!(validate_password_strength("hunter22").is_ok());
+        assert_eq!(validate_password_strength("abc123").unwrap_err().code, "password_length");
+        assert_eq!(validate_password_strength("onlyletters").unwrap_err().code, "password_strength");
+        assert_eq!(validate_password_strength("1234567890").unwrap_err().code, "password_strength");
+        let longest = format!("a1{}", "x".repeat(PASSWORD_MAX_BYTES - 2));
+        assert!(validate_password_strength(&longest).is_ok());
+        assert_eq!(validate_password_strength(&format!("{}x", longest)).unwrap_err().code, "password_length");
+    }
+
+    #[test]
+    fn blank_and_message_type() {
+        assert!(validate_not_blank("hi").is_ok());
+        assert!(validate_not_blank(" \n\t").is_err());
+        for message_type in MESSAGE_TYPES {
+            assert!(validate_message_type(message_type).is_ok());
+        }
+        assert!(validate_message_type("video").is_err());
+    }
+
+    #[test]
+    fn id_lists() {
+        assert_eq!(parse_id_list("1, 2,3"), Some(vec![1, 2, 3]));
+        assert_eq!(parse_id_list("1,x"), None);
+        assert!(validate_id_list("42").is_ok());
+        assert!(validate_id_list("").is_err());
+        let too_many = (0..=ID_LIST_MAX).map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+        assert!(validate_id_list(&too_many).is_err());
+    }
+
+    #[derive(Validate)]
+    struct Inner {
+        #[validate(custom(function = "validate_not_blank"))]
+        name: String,
+    }
+
+    #[derive(Validate)]
+    struct Outer {
+        #[validate(custom(function = "validate_username"))]
+        username: String,
+        #[validate(nested)]
+        inner: Inner,
+    }
+
+    #[test]
+    fn field_errors_are_keyed_by_path() {
+        let request = Outer {
+            username: "x".to_string(),
+            inner: Inner { name: " ".to_string() },
+        };
+        let errors = field_errors(&request.validate().unwrap_err());
+        assert_eq!(errors["username"], serde_json::json!(["Username must be 3 to 32 characters"]));
+        assert_eq!(errors["inner.name"], serde_json::json!(["Must not be blank"]));
+    }
+}
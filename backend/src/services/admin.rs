@@ -0,0 +1,269 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, ColumnTrait, Set, ActiveModelTrait, PaginatorTrait};
+use sea_orm::sea_query::Expr;
+use redis::aio::MultiplexedConnection;
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::{
+    groups::Entity as Groups,
+    login_lockouts, login_lockouts::Entity as LoginLockouts,
+    messages, messages::Entity as Messages,
+    sessions, sessions::Entity as Sessions,
+    users, users::Entity as Users,
+};
+use crate::models::{AdminUserPage, AdminUserQuery, ServerStats};
+use crate::services::directory::Cursor;
+use crate::services::session;
+use crate::websocket::Clients;
+
+// Server-level roles, stored in users.role. Group roles are separate and
+// live in group_members.
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLES: &[&str] = &[ROLE_USER, ROLE_ADMIN];
+
+const LOCKOUT_LIST_LIMIT: u64 = 200;
+const DEFAULT_USER_PAGE_SIZE: u64 = 100;
+
+// Grant the admin role to the accounts listed in ADMIN_EMAILS, but only while
+// the server has no admin at all. This bootstraps a fresh server without
+// undoing demotions made through the admin API on every restart.
+pub async fn promote_configured(db: &DatabaseConnection, config: &Config) -> Result<()> {
+    let emails: Vec<String> = config
+        .admin_emails
+        .split(',')
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty())
+        .collect();
+    if emails.is_empty() {
+        return Ok(());
+    }
+
+    let admins = Users::find()
+        .filter(users::Column::Role.eq(ROLE_ADMIN))
+        .count(db)
+        .await?;
+    if admins > 0 {
+        return Ok(());
+    }
+
+    let result = Users::update_many()
+        .col_expr(users::Column::Role, Expr::value(ROLE_ADMIN))
+        .filter(users::Column::Email.is_in(emails))
+        .filter(users::Column::Role.ne(ROLE_ADMIN))
+        .exec(db)
+        .await?;
+    if result.rows_affected > 0 {
+        tracing::info!("Granted the admin role to {} configured accounts", result.rows_affected);
+    }
+    Ok(())
+}
+
+pub async fn is_admin(db: &DatabaseConnection, user_id: i64) -> Result<bool> {
+    let user = Users::find_by_id(user_id).one(db).await?;
+    Ok(user.is_some_and(|user| user.role == ROLE_ADMIN && user.suspended_at.is_none()))
+}
+
+// All accounts in id order, paged with the directory's id cursor. Returns
+// None when the cursor is malformed.
+pub async fn list_users(db: &DatabaseConnection, params: &AdminUserQuery) -> Result<Option<AdminUserPage>> {
+    let mut query = Users::find().order_by_asc(users::Column::Id);
+    match params.cursor.as_deref().map(Cursor::decode) {
+        Some(Some(Cursor::Id(after))) => query = query.filter(users::Column::Id.gt(after)),
+        Some(_) => return Ok(None),
+        None => {}
+    }
+
+    // Fetch one extra row to learn whether another page follows
+    let limit = params.limit.unwrap_or(DEFAULT_USER_PAGE_SIZE);
+    let mut users = query.limit(limit + 1).all(db).await?;
+    let has_more = users.len() as u64 > limit;
+    users.truncate(limit as usize);
+
+    let next_cursor = match users.last() {
+        Some(last) if has_more => Some(Cursor::Id(last.id).encode()),
+        _ => None,
+    };
+
+    Ok(Some(AdminUserPage { users, next_cursor }))
+}
+
+pub async fn set_role(db: &DatabaseConnection, user: users::Model, role: &str) -> Result<users::Model> {
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.role = Set(role.to_string());
+    user_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+    Ok(user_active.update(db).await?)
+}
+
+// Suspend an account and sign it out everywhere. Suspended accounts cannot
+// log in, and API keys of suspended bots or of bots they own stop working.
+pub async fn suspend(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    clients: &Clients,
+    config: &Config,
+    user: users::Model,
+) -> Result<users::Model> {
+    let user_id = user.id;
+    let now = chrono::Utc::now().naive_utc();
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.suspended_at = Set(Some(now));
+    user_active.updated_at = Set(Some(now));
+    let user = user_active.update(db).await?;
+
+    session::revoke_all_for_user(db, redis, clients, config, user_id).await?;
+    Ok(user)
+}
+
+pub async fn unsuspend(db: &DatabaseConnection, user: users::Model) -> Result<users::Model> {
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.suspended_at = Set(None);
+    user_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+    Ok(user_active.update(db).await?)
+}
+
+// End every session of a user without suspending the account
+pub async fn force_logout(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    clients: &Clients,
+    config: &Config,
+    user: users::Model,
+) -> Result<()> {
+    session::revoke_all_for_user(db, redis, clients, config, user.id).await?;
+    Ok(())
+}
+
+// Members and messages go with the group through ON DELETE CASCADE
+pub async fn delete_group(db: &DatabaseConnection, group_id: i64) -> Result<bool> {
+    let result = Groups::delete_by_id(group_id).exec(db).await?;
+    Ok(result.rows_affected > 0)
+}
+
+// Most recent lockouts first, optionally only those still in force
+pub async fn list_lockouts(db: &DatabaseConnection, active_only: bool) -> Result<Vec<login_lockouts::Model>> {
+    let mut query = LoginLockouts::find();
+    if active_only {
+        query = query.filter(login_lockouts::Column::LockedUntil.gt(chrono::Utc::now().naive_utc()));
+    }
+    let lockouts = query
+        .order_by_desc(login_lockouts::Column::CreatedAt)
+        .limit(LOCKOUT_LIST_LIMIT)
+        .all(db)
+        .await?;
+    Ok(lockouts)
+}
+
+pub async fn stats(db: &DatabaseConnection, clients: &Clients, config: &Config) -> Result<ServerStats> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let users = Users::find().filter(users::Column::IsBot.eq(false)).count(db).await?;
+    let bots = Users::find().filter(users::Column::IsBot.eq(true)).count(db).await?;
+    let admins = Users::find().filter(users::Column::Role.eq(ROLE_ADMIN)).count(db).await?;
+    let suspended_users = Users::find().filter(users::Column::SuspendedAt.is_not_null()).count(db).await?;
+    let groups = Groups::find().count(db).await?;
+    let messages = Messages::find().count(db).await?;
+    let messages_last_24h = Messages::find()
+        .filter(messages::Column::CreatedAt.gte(now - chrono::Duration::hours(24)))
+        .count(db)
+        .await?;
+    let active_sessions = Sessions::find()
+        .filter(sessions::Column::LastActiveAt.gte(now - config.refresh_token_ttl()))
+        .count(db)
+        .await?;
+
+    let (websocket_connections, online_users) = {
+        let clients_lock = clients.lock().await;
+        let mut online: Vec<i64> = clients_lock.values().map(|entry| entry.user_id).collect();
+        online.sort_unstable();
+        online.dedup();
+        (clients_lock.len() as u64, online.len() as u64)
+    };
+
+    Ok(ServerStats {
+        users,
+        bots,
+        admins,
+        suspended_users,
+        groups,
+        messages,
+        messages_last_24h,
+        active_sessions,
+        websocket_connections,
+        online_users,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+
+    fn config(admin_emails: &str) -> Config {
+        let mut config = Config::from_env();
+        config.admin_emails = admin_emails.to_string();
+        config
+    }
+
+    async fn role_of(db: &DatabaseConnection, user_id: i64) -> String {
+        Users::find_by_id(user_id).one(db).await.unwrap().unwrap().role
+    }
+
+    #[tokio::test]
+    async fn configured_admins_bootstrap_a_server_without_admins() {
+        let db = create_test_connection().await;
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+
+        promote_configured(&db, &config(" Alice@Example.com ,nobody@example.com")).await.unwrap();
+        assert_eq!(role_of(&db, alice.id).await, ROLE_ADMIN);
+        assert_eq!(role_of(&db, bob.id).await, ROLE_USER);
+    }
+
+    #[tokio::test]
+    async fn demotions_survive_a_restart() {
+        let db = create_test_connection().await;
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        promote_configured(&db, &config("alice@example.com,bob@example.com")).await.unwrap();
+
+        // bob is demoted through the API, then the server restarts
+        set_role(&db, Users::find_by_id(bob.id).one(&db).await.unwrap().unwrap(), ROLE_USER).await.unwrap();
+        promote_configured(&db, &config("alice@example.com,bob@example.com")).await.unwrap();
+
+        assert_eq!(role_of(&db, alice.id).await, ROLE_ADMIN);
+        assert_eq!(role_of(&db, bob.id).await, ROLE_USER);
+    }
+
+    #[tokio::test]
+    async fn user_list_pages_through_every_account() {
+        let db = create_test_connection().await;
+        let mut expected = Vec::new();
+        for username in ["carol", "alice", "erin", "bob", "dave"] {
+            expected.push(create_test_user(&db, username).await.id);
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let params = AdminUserQuery { limit: Some(2), cursor: cursor.take() };
+            let page = list_users(&db, &params).await.unwrap().unwrap();
+            assert!(page.users.len() <= 2);
+            seen.extend(page.users.iter().map(|user| user.id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen, expected);
+    }
+
+    #[tokio::test]
+    async fn user_list_rejects_foreign_cursors() {
+        let db = create_test_connection().await;
+        let username_cursor = Cursor::Username("alice".to_string()).encode();
+        for cursor in [username_cursor, "not-a-cursor".to_string()] {
+            let params = AdminUserQuery { limit: None, cursor: Some(cursor) };
+            assert!(list_users(&db, &params).await.unwrap().is_none());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, ColumnTrait, Set, ActiveModelTrait};
+use sha2::{Digest, Sha256};
+use anyhow::Result;
+use crate::entity::{api_keys, api_keys::Entity as ApiKeys, users::Entity as Users};
+
+// Long-lived credentials for bot accounts. Only a SHA-256 hash of each key
+// is stored; the plaintext is shown once when the key is created.
+//
+// A scope is an action such as "messages:write", optionally narrowed to one
+// target: "messages:write:group:3" only allows posting into group 3.
+
+pub const KEY_PREFIX: &str = "imtt_";
+pub const SCOPES: &[&str] = &["messages:read", "messages:write", "groups:read", "groups:write"];
+
+// last_used_at is only rewritten once it is this stale
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+// Scopes granted to the API key that authenticated the current request
+#[derive(Debug, Clone)]
+pub struct ApiScopes(Vec<String>);
+
+impl ApiScopes {
+    pub fn parse(scopes: &str) -> Self {
+        ApiScopes(scopes.split_whitespace().map(str::to_string).collect())
+    }
+
+    pub fn allows(&self, scope: &str, target: Option<&str>) -> bool {
+        self.0.iter().any(|granted| {
+            granted == scope
+                || target.is_some_and(|target| {
+                    granted.strip_prefix(scope).and_then(|rest| rest.strip_prefix(':')) == Some(target)
+                })
+        })
+    }
+}
+
+pub fn group_target(group_id: i64) -> String {
+    format!("group:{}", group_id)
+}
+
+pub fn user_target(user_id: i64) -> String {
+    format!("user:{}", user_id)
+}
+
+pub fn is_valid_scope(scope: &str) -> bool {
+    SCOPES.iter().any(|base| {
+        let Some(rest) = scope.strip_prefix(base) else {
+            return false;
+        };
+        if rest.is_empty() {
+            return true;
+        }
+        let target = match rest.strip_prefix(":group:") {
+            Some(id) => id,
+            // Direct messages can be narrowed to one recipient
+            None if base.starts_with("messages:") => match rest.strip_prefix(":user:") {
+                Some(id) => id,
+                None => return false,
+            },
+            None => return false,
+        };
+        target.parse::<i64>().is_ok_and(|id| id > 0)
+    })
+}
+
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+// Returns the stored row and the plaintext key
+pub async fn create(
+    db: &DatabaseConnection,
+    user_id: i64,
+    name: &str,
+    scopes: &[String],
+    expires_at: Option<chrono::NaiveDateTime>,
+) -> Result<(api_keys::Model, String)> {
+    let key = format!("{}{}{}", KEY_PREFIX, uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+
+    let mut scopes = scopes.to_vec();
+    scopes.sort();
+    scopes.dedup();
+
+    let new_key = api_keys::ActiveModel {
+        id: sea_orm::ActiveValue::NotSet,
+        user_id: Set(user_id),
+        name: Set(name.to_string()),
+        key_prefix: Set(key.chars().take(12).collect()),
+        key_hash: Set(hash_key(&key)),
+        scopes: Set(scopes.join(" ")),
+        expires_at: Set(expires_at),
+        last_used_at: Set(None),
+        created_at: Set(Some(chrono::Utc::now().naive_utc())),
+    };
+
+    Ok((new_key.insert(db).await?, key))
+}
+
+// A bot is shut out when it or the account that owns it is suspended
+async fn is_suspended(db: &DatabaseConnection, user_id: i64) -> Result<bool> {
+    let Some(user) = Users::find_by_id(user_id).one(db).await? else {
+        return Ok(true);
+    };
+    if user.suspended_at.is_some() {
+        return Ok(true);
+    }
+    match user.owner_id {
+        Some(owner_id) => Ok(Users::find_by_id(owner_id)
+            .one(db)
+            .await?
+            .is_none_or(|owner| owner.suspended_at.is_some())),
+        None => Ok(false),
+    }
+}
+
+// Resolve a presented key to its bot user and scopes
+pub async fn authenticate(db: &DatabaseConnection, key: &str) -> Result<Option<(i64, ApiScopes)>> {
+    if !key.starts_with(KEY_PREFIX) {
+        return Ok(None);
+    }
+
+    let Some(api_key) = ApiKeys::find()
+        .filter(api_keys::Column::KeyHash.eq(hash_key(key)))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    if api_key.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Ok(None);
+    }
+
+    let user_id = api_key.user_id;
+    if is_suspended(db, user_id).await? {
+        return Ok(None);
+    }
+    let scopes = ApiScopes::parse(&api_key.scopes);
+
+    let stale = api_key
+        .last_used_at
+        .is_none_or(|last| (now - last).num_seconds() >= LAST_USED_RESOLUTION_SECS);
+    if stale {
+        let mut key_active: api_keys::ActiveModel = api_key.into();
+        key_active.last_used_at = Set(Some(now));
+        if let Err(e) = key_active.update(db).await {
+            tracing::warn!("Failed to update API key usage: {:?}", e);
+        }
+    }
+
+    Ok(Some((user_id, scopes)))
+}
+
+pub async fn list(db: &DatabaseConnection, user_id: i64) -> Result<Vec<api_keys::Model>> {
+    Ok(ApiKeys::find()
+        .filter(api_keys::Column::UserId.eq(user_id))
+        .order_by_desc(api_keys::Column::CreatedAt)
+        .all(db)
+        .await?)
+}
+
+// Returns false if the key does not belong to the user
+pub async fn revoke(db: &DatabaseConnection, user_id: i64, key_id: i64) -> Result<bool> {
+    let result = ApiKeys::delete_many()
+        .filter(api_keys::Column::Id.eq(key_id))
+        .filter(api_keys::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untargeted_scopes_allow_every_target() {
+        let scopes = ApiScopes::parse("messages:write groups:read");
+        assert!(scopes.allows("messages:write", None));
+        assert!(scopes.allows("messages:write", Some(&group_target(3))));
+        assert!(scopes.allows("groups:read", Some(&group_target(3))));
+        assert!(!scopes.allows("messages:read", None));
+        assert!(!scopes.allows("groups:write", Some(&group_target(3))));
+    }
+
+    #[test]
+    fn targeted_scopes_only_allow_their_target() {
+        let scopes = ApiScopes::parse("messages:write:group:3 messages:write:user:9");
+        assert!(scopes.allows("messages:write", Some(&group_target(3))));
+        assert!(scopes.allows("messages:write", Some(&user_target(9))));
+        assert!(!scopes.allows("messages:write", Some(&group_target(4))));
+        assert!(!scopes.allows("messages:write", Some(&group_target(33))));
+        assert!(!scopes.allows("messages:write", Some(&user_target(3))));
+        assert!(!scopes.allows("messages:write", None));
+        assert!(!scopes.allows("messages:read", Some(&group_target(3))));
+    }
+
+    #[test]
+    fn scopes_only_match_whole_names() {
+        let scopes = ApiScopes::parse("messages:writex messages:write:group:3x");
+        assert!(!scopes.allows("messages:write", None));
+        assert!(!scopes.allows("messages:write", Some(&group_target(3))));
+        assert!(!ApiScopes::parse("").allows("messages:read", None));
+    }
+
+    #[test]
+    fn valid_scopes() {
+        for scope in ["messages:read", "messages:write", "groups:read", "groups:write", "messages:write:group:3", "messages:read:user:1", "groups:write:group:12"] {
+            assert!(is_valid_scope(scope), "{} should be valid", scope);
+        }
+    }
+
+    #[test]
+    fn invalid_scopes() {
+        for scope in [
+            "messages:writex",
+            "groups:read:user:1",
+            "messages:write:group:0",
+            "messages:write:group:-1",
+            "messages:write:group:",
+            "messages:write:group:abc",
+            "messages:write:channel:1",
+            "messages",
+            "admin",
+            "",
+        ] {
+            assert!(!is_valid_scope(scope), "{} should be invalid", scope);
+        }
+    }
+}
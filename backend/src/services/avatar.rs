@@ -0,0 +1,76 @@
+use std::io::Cursor;
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, Limits};
+use crate::services::storage::Storage;
+
+// Every avatar is stored as square PNGs in these sizes. The URL saved on
+// the user or group points at the largest; the others sit next to it, e.g.
+// .../<token>/256.png and .../<token>/64.png.
+pub const AVATAR_SIZES: [u32; 2] = [256, 64];
+
+// Refuse to decode anything larger, whatever the file size
+const MAX_SOURCE_DIMENSION: u32 = 4096;
+const ACCEPTED_FORMATS: [ImageFormat; 4] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif, ImageFormat::WebP];
+
+// Decode an uploaded image and render it at every avatar size, center-cropped
+// to a square. Returns None if the bytes are not a supported image.
+fn render(bytes: &[u8]) -> Option<Vec<(u32, Vec<u8>)>> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format().ok()?;
+    if !reader.format().is_some_and(|format| ACCEPTED_FORMATS.contains(&format)) {
+        return None;
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    reader.limits(limits);
+    let source = reader.decode().ok()?;
+
+    let mut rendered = Vec::with_capacity(AVATAR_SIZES.len());
+    for size in AVATAR_SIZES {
+        let resized = source.resize_to_fill(size, size, FilterType::Lanczos3);
+        let mut png = Cursor::new(Vec::new());
+        resized.write_to(&mut png, ImageFormat::Png).ok()?;
+        rendered.push((size, png.into_inner()));
+    }
+    Some(rendered)
+}
+
+fn key(owner: &str, token: &str, size: u32) -> String {
+    format!("avatars/{}/{}/{}.png", owner, token, size)
+}
+
+// Resize and store an uploaded avatar for owner ("users/7", "groups/3").
+// Returns the URL of the largest size, or None if the upload is not an image.
+pub async fn store(storage: &dyn Storage, owner: &str, bytes: Vec<u8>) -> Result<Option<String>> {
+    let Some(rendered) = tokio::task::spawn_blocking(move || render(&bytes)).await? else {
+        return Ok(None);
+    };
+
+    // A fresh token per upload keeps URLs cacheable forever
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    for (size, png) in rendered {
+        storage.put(&key(owner, &token, size), png, "image/png").await?;
+    }
+    Ok(Some(storage.url(&key(owner, &token, AVATAR_SIZES[0]))))
+}
+
+// Delete every size of an avatar previously returned by store(). URLs that
+// point elsewhere, e.g. set before uploads existed, are left alone.
+pub async fn remove(storage: &dyn Storage, url: &str) -> Result<()> {
+    let Some(key) = storage.key_from_url(url) else {
+        return Ok(());
+    };
+    let Some(dir) = key.strip_suffix(&format!("/{}.png", AVATAR_SIZES[0])) else {
+        return Ok(());
+    };
+    if !dir.starts_with("avatars/") {
+        return Ok(());
+    }
+
+    for size in AVATAR_SIZES {
+        storage.delete(&format!("{}/{}.png", dir, size)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::collections::{HashMap, HashSet};
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, ColumnTrait, Condition, Set, ActiveModelTrait, TransactionTrait, SqlErr};
+use anyhow::Result;
+use crate::entity::{
+    blocks, blocks::Entity as Blocks,
+    contacts, contacts::Entity as Contacts,
+    friend_requests, friend_requests::Entity as FriendRequests,
+    users, users::Entity as Users,
+};
+use crate::models::{BlockedUserResponse, PublicProfile};
+use crate::services::presence;
+use crate::websocket::Clients;
+
+pub enum BlockOutcome {
+    Blocked,
+    AlreadyBlocked,
+    UserNotFound,
+}
+
+// Rows linking the two users in either direction
+fn between<C: ColumnTrait>(a_column: C, b_column: C, a: i64, b: i64) -> Condition {
+    Condition::any()
+        .add(Condition::all().add(a_column.eq(a)).add(b_column.eq(b)))
+        .add(Condition::all().add(a_column.eq(b)).add(b_column.eq(a)))
+}
+
+// True if either user has blocked the other. Both directions count so that
+// a blocked user cannot tell the difference by writing first.
+pub async fn is_blocked_between(db: &DatabaseConnection, user_id: i64, other_id: i64) -> Result<bool> {
+    let block = Blocks::find()
+        .filter(between(blocks::Column::BlockerId, blocks::Column::BlockedId, user_id, other_id))
+        .one(db)
+        .await?;
+    Ok(block.is_some())
+}
+
+// Users who must not see this user's presence, and whose presence this user
+// must not see: everyone they blocked or were blocked by
+pub async fn hidden_user_ids(db: &DatabaseConnection, user_id: i64) -> Result<HashSet<i64>> {
+    let blocks = Blocks::find()
+        .filter(
+            Condition::any()
+                .add(blocks::Column::BlockerId.eq(user_id))
+                .add(blocks::Column::BlockedId.eq(user_id)),
+        )
+        .all(db)
+        .await?;
+    Ok(blocks
+        .into_iter()
+        .map(|block| if block.blocker_id == user_id { block.blocked_id } else { block.blocker_id })
+        .collect())
+}
+
+pub async fn list(db: &DatabaseConnection, user_id: i64) -> Result<Vec<BlockedUserResponse>> {
+    let blocks = Blocks::find()
+        .filter(blocks::Column::BlockerId.eq(user_id))
+        .order_by_desc(blocks::Column::Id)
+        .all(db)
+        .await?;
+    if blocks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let users: HashMap<i64, users::Model> = Users::find()
+        .filter(users::Column::Id.is_in(blocks.iter().map(|block| block.blocked_id)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+    Ok(blocks
+        .into_iter()
+        .filter_map(|block| {
+            Some(BlockedUserResponse {
+                user: PublicProfile::from(users.get(&block.blocked_id)?),
+                blocked_at: block.created_at,
+            })
+        })
+        .collect())
+}
+
+// Block a user. Any contact relationship and pending friend requests between
+// the two are removed, and each stops seeing the other online.
+pub async fn block(db: &DatabaseConnection, clients: &Clients, blocker_id: i64, blocked_id: i64) -> Result<BlockOutcome> {
+    if Users::find_by_id(blocked_id).one(db).await?.is_none() {
+        return Ok(BlockOutcome::UserNotFound);
+    }
+
+    let txn = db.begin().await?;
+    let inserted = blocks::ActiveModel {
+        blocker_id: Set(blocker_id),
+        blocked_id: Set(blocked_id),
+        created_at: Set(Some(chrono::Utc::now().naive_utc())),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await;
+    match inserted {
+        Ok(_) => {}
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+            return Ok(BlockOutcome::AlreadyBlocked);
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Contacts::delete_many()
+        .filter(between(contacts::Column::UserId, contacts::Column::ContactId, blocker_id, blocked_id))
+        .exec(&txn)
+        .await?;
+    FriendRequests::delete_many()
+        .filter(between(friend_requests::Column::SenderId, friend_requests::Column::ReceiverId, blocker_id, blocked_id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    {
+        let clients_lock = clients.lock().await;
+        presence::conceal(&clients_lock, blocker_id, blocked_id);
+        presence::conceal(&clients_lock, blocked_id, blocker_id);
+    }
+
+    Ok(BlockOutcome::Blocked)
+}
+
+// Lift a block. If no block remains in the other direction, the two see
+// each other's presence again.
+pub async fn unblock(db: &DatabaseConnection, clients: &Clients, blocker_id: i64, blocked_id: i64) -> Result<bool> {
+    let result = Blocks::delete_many()
+        .filter(blocks::Column::BlockerId.eq(blocker_id))
+        .filter(blocks::Column::BlockedId.eq(blocked_id))
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(false);
+    }
+
+    if !is_blocked_between(db, blocker_id, blocked_id).await? {
+        let clients_lock = clients.lock().await;
+        presence::reveal(&clients_lock, blocker_id, blocked_id);
+        presence::reveal(&clients_lock, blocked_id, blocker_id);
+    }
+    Ok(true)
+}
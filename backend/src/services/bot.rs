@@ -0,0 +1,76 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, ColumnTrait, Set, ActiveModelTrait, ModelTrait, PaginatorTrait};
+use anyhow::Result;
+use crate::entity::{users, users::Entity as Users};
+use crate::models::PresenceStatus;
+use crate::services::admin;
+use crate::utils::UNUSABLE_PASSWORD;
+
+// Bots are regular user rows flagged with is_bot and owned by a human
+// account. They have no usable password and authenticate only with API keys.
+
+// Bots get an address on the reserved .invalid TLD, which never receives mail
+const BOT_EMAIL_DOMAIN: &str = "bots.invalid";
+
+pub fn bot_email(username: &str) -> String {
+    format!("{}@{}", username.to_lowercase(), BOT_EMAIL_DOMAIN)
+}
+
+pub async fn count_owned(db: &DatabaseConnection, owner_id: i64) -> Result<u64> {
+    Ok(Users::find()
+        .filter(users::Column::OwnerId.eq(owner_id))
+        .filter(users::Column::IsBot.eq(true))
+        .count(db)
+        .await?)
+}
+
+pub async fn create(db: &DatabaseConnection, owner_id: i64, username: &str) -> Result<users::Model> {
+    let now = chrono::Utc::now().naive_utc();
+    let bot = users::ActiveModel {
+        id: sea_orm::ActiveValue::NotSet,
+        username: Set(username.to_string()),
+        email: Set(bot_email(username)),
+        password_hash: Set(UNUSABLE_PASSWORD.to_string()),
+        display_name: Set(None),
+        bio: Set(None),
+        avatar: Set(None),
+        status: Set(PresenceStatus::Online.as_str().to_string()),
+        last_seen_at: Set(None),
+        status_text: Set(None),
+        status_emoji: Set(None),
+        status_expires_at: Set(None),
+        email_verified: Set(true),
+        totp_secret: Set(None),
+        totp_enabled: Set(false),
+        is_bot: Set(true),
+        owner_id: Set(Some(owner_id)),
+        role: Set(admin::ROLE_USER.to_string()),
+        suspended_at: Set(None),
+        created_at: Set(Some(now)),
+        updated_at: Set(Some(now)),
+    };
+
+    Ok(bot.insert(db).await?)
+}
+
+pub async fn list_owned(db: &DatabaseConnection, owner_id: i64) -> Result<Vec<users::Model>> {
+    Ok(Users::find()
+        .filter(users::Column::OwnerId.eq(owner_id))
+        .filter(users::Column::IsBot.eq(true))
+        .order_by_asc(users::Column::Id)
+        .all(db)
+        .await?)
+}
+
+pub async fn find_owned(db: &DatabaseConnection, owner_id: i64, bot_id: i64) -> Result<Option<users::Model>> {
+    Ok(Users::find_by_id(bot_id)
+        .filter(users::Column::OwnerId.eq(owner_id))
+        .filter(users::Column::IsBot.eq(true))
+        .one(db)
+        .await?)
+}
+
+// Deleting the user row cascades to the bot's API keys, memberships and messages
+pub async fn delete(db: &DatabaseConnection, bot: users::Model) -> Result<()> {
+    bot.delete(db).await?;
+    Ok(())
+}
@@ -0,0 +1,391 @@
+use std::collections::{HashMap, HashSet};
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, ColumnTrait, Condition, Set, ActiveModelTrait, TransactionTrait, SqlErr};
+use anyhow::Result;
+use crate::entity::{
+    contacts, contacts::Entity as Contacts,
+    friend_requests, friend_requests::Entity as FriendRequests,
+    users, users::Entity as Users,
+};
+use crate::models::{ContactResponse, FriendRequestResponse, FriendRequestsResponse, PresenceStatus, PublicProfile};
+use crate::services::blocks;
+use crate::services::presence;
+use crate::websocket::{send_to_users, Clients, WsEvent};
+
+pub enum SendOutcome {
+    Sent(Box<FriendRequestResponse>),
+    // The other user had already asked, so their request was accepted instead
+    Accepted(Box<ContactResponse>),
+    AlreadyContacts,
+    AlreadyRequested,
+    UserNotFound,
+}
+
+pub async fn are_contacts(db: &DatabaseConnection, user_id: i64, other_id: i64) -> Result<bool> {
+    let contact = Contacts::find()
+        .filter(contacts::Column::UserId.eq(user_id))
+        .filter(contacts::Column::ContactId.eq(other_id))
+        .one(db)
+        .await?;
+    Ok(contact.is_some())
+}
+
+async fn profiles(db: &DatabaseConnection, user_ids: Vec<i64>) -> Result<HashMap<i64, PublicProfile>> {
+    if user_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let users = Users::find()
+        .filter(users::Column::Id.is_in(user_ids))
+        .all(db)
+        .await?;
+    Ok(users.iter().map(|user| (user.id, PublicProfile::from(user))).collect())
+}
+
+async fn describe_requests(db: &DatabaseConnection, requests: Vec<friend_requests::Model>) -> Result<Vec<FriendRequestResponse>> {
+    let user_ids: HashSet<i64> = requests
+        .iter()
+        .flat_map(|r| [r.sender_id, r.receiver_id])
+        .collect();
+    let profiles = profiles(db, user_ids.into_iter().collect()).await?;
+
+    Ok(requests
+        .into_iter()
+        .filter_map(|r| {
+            Some(FriendRequestResponse {
+                id: r.id,
+                sender: profiles.get(&r.sender_id)?.clone(),
+                receiver: profiles.get(&r.receiver_id)?.clone(),
+                message: r.message,
+                created_at: r.created_at,
+            })
+        })
+        .collect())
+}
+
+// Contacts of a user with their current presence, sorted by username
+pub async fn list(db: &DatabaseConnection, clients: &Clients, user_id: i64) -> Result<Vec<ContactResponse>> {
+    let rows = Contacts::find()
+        .filter(contacts::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+    let profiles = profiles(db, rows.iter().map(|row| row.contact_id).collect()).await?;
+    let visible = presence::visible(clients).await;
+
+    let mut contacts: Vec<ContactResponse> = rows
+        .into_iter()
+        .filter_map(|row| {
+            let status = visible.get(&row.contact_id).copied().unwrap_or(PresenceStatus::Offline);
+            Some(ContactResponse {
+                online: status != PresenceStatus::Offline,
+                status,
+                user: profiles.get(&row.contact_id)?.clone(),
+                since: row.created_at,
+            })
+        })
+        .collect();
+    contacts.sort_by_key(|contact| contact.user.username.to_lowercase());
+    Ok(contacts)
+}
+
+pub async fn list_requests(db: &DatabaseConnection, user_id: i64) -> Result<FriendRequestsResponse> {
+    let requests = FriendRequests::find()
+        .filter(
+            Condition::any()
+                .add(friend_requests::Column::SenderId.eq(user_id))
+                .add(friend_requests::Column::ReceiverId.eq(user_id)),
+        )
+        .order_by_desc(friend_requests::Column::Id)
+        .all(db)
+        .await?;
+
+    let (incoming, outgoing): (Vec<_>, Vec<_>) = describe_requests(db, requests)
+        .await?
+        .into_iter()
+        .partition(|r| r.receiver.id == user_id);
+    Ok(FriendRequestsResponse { incoming, outgoing })
+}
+
+pub async fn send(
+    db: &DatabaseConnection,
+    clients: &Clients,
+    sender_id: i64,
+    receiver_id: i64,
+    message: Option<String>,
+) -> Result<SendOutcome> {
+    // Bots cannot answer requests, and suspended accounts are hidden
+    let receiver = Users::find_by_id(receiver_id).one(db).await?;
+    if !receiver.is_some_and(|user| !user.is_bot && user.suspended_at.is_none()) {
+        return Ok(SendOutcome::UserNotFound);
+    }
+    // Blocked in either direction looks the same as a missing user
+    if blocks::is_blocked_between(db, sender_id, receiver_id).await? {
+        return Ok(SendOutcome::UserNotFound);
+    }
+
+    if are_contacts(db, sender_id, receiver_id).await? {
+        return Ok(SendOutcome::AlreadyContacts);
+    }
+
+    let reverse = FriendRequests::find()
+        .filter(friend_requests::Column::SenderId.eq(receiver_id))
+        .filter(friend_requests::Column::ReceiverId.eq(sender_id))
+        .one(db)
+        .await?;
+    if let Some(reverse) = reverse {
+        return Ok(match accept(db, clients, sender_id, reverse.id).await? {
+            Some(contact) => SendOutcome::Accepted(Box::new(contact)),
+            None => SendOutcome::AlreadyRequested,
+        });
+    }
+
+    let message = message.map(|m| m.trim().to_string()).filter(|m| !m.is_empty());
+    let request = friend_requests::ActiveModel {
+        sender_id: Set(sender_id),
+        receiver_id: Set(receiver_id),
+        message: Set(message),
+        created_at: Set(Some(chrono::Utc::now().naive_utc())),
+        ..Default::default()
+    };
+    let request = match request.insert(db).await {
+        Ok(request) => request,
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+            return Ok(SendOutcome::AlreadyRequested);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let Some(request) = describe_requests(db, vec![request]).await?.pop() else {
+        return Ok(SendOutcome::UserNotFound);
+    };
+
+    let event = WsEvent {
+        event_type: "friend_request".to_string(),
+        user_id: sender_id,
+        receiver_id: Some(receiver_id),
+        group_id: None,
+        content: None,
+        data: Some(serde_json::to_value(&request)?),
+    };
+    send_to_users(clients, &HashSet::from([receiver_id]), &event).await;
+
+    Ok(SendOutcome::Sent(Box::new(request)))
+}
+
+// Accept a request addressed to user_id. Returns the new contact, or None if
+// there is no such pending request.
+pub async fn accept(db: &DatabaseConnection, clients: &Clients, user_id: i64, request_id: i64) -> Result<Option<ContactResponse>> {
+    let Some(request) = FriendRequests::find_by_id(request_id)
+        .filter(friend_requests::Column::ReceiverId.eq(user_id))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let requester_id = request.sender_id;
+
+    let now = chrono::Utc::now().naive_utc();
+    let txn = db.begin().await?;
+    FriendRequests::delete_by_id(request.id).exec(&txn).await?;
+    for (owner, contact) in [(user_id, requester_id), (requester_id, user_id)] {
+        let exists = Contacts::find()
+            .filter(contacts::Column::UserId.eq(owner))
+            .filter(contacts::Column::ContactId.eq(contact))
+            .one(&txn)
+            .await?
+            .is_some();
+        if !exists {
+            contacts::ActiveModel {
+                user_id: Set(owner),
+                contact_id: Set(contact),
+                created_at: Set(Some(now)),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await?;
+        }
+    }
+    txn.commit().await?;
+
+    let profiles = profiles(db, vec![user_id, requester_id]).await?;
+    let (Some(accepter), Some(requester)) = (profiles.get(&user_id), profiles.get(&requester_id)) else {
+        return Ok(None);
+    };
+
+    let visible = presence::visible(clients).await;
+    let status_of = |user_id: i64| visible.get(&user_id).copied().unwrap_or(PresenceStatus::Offline);
+    let event = WsEvent {
+        event_type: "friend_accepted".to_string(),
+        user_id,
+        receiver_id: Some(requester_id),
+        group_id: None,
+        content: None,
+        data: Some(serde_json::to_value(ContactResponse {
+            user: accepter.clone(),
+            online: status_of(user_id) != PresenceStatus::Offline,
+            status: status_of(user_id),
+            since: Some(now),
+        })?),
+    };
+    send_to_users(clients, &HashSet::from([requester_id]), &event).await;
+
+    Ok(Some(ContactResponse {
+        user: requester.clone(),
+        online: status_of(requester_id) != PresenceStatus::Offline,
+        status: status_of(requester_id),
+        since: Some(now),
+    }))
+}
+
+// The receiver turns a request down; the sender is not told
+pub async fn decline(db: &DatabaseConnection, user_id: i64, request_id: i64) -> Result<bool> {
+    let result = FriendRequests::delete_many()
+        .filter(friend_requests::Column::Id.eq(request_id))
+        .filter(friend_requests::Column::ReceiverId.eq(user_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+// The sender withdraws a request they made
+pub async fn cancel(db: &DatabaseConnection, user_id: i64, request_id: i64) -> Result<bool> {
+    let result = FriendRequests::delete_many()
+        .filter(friend_requests::Column::Id.eq(request_id))
+        .filter(friend_requests::Column::SenderId.eq(user_id))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+// End a friendship from either side
+pub async fn remove(db: &DatabaseConnection, user_id: i64, contact_id: i64) -> Result<bool> {
+    let result = Contacts::delete_many()
+        .filter(
+            Condition::any()
+                .add(
+                    Condition::all()
+                        .add(contacts::Column::UserId.eq(user_id))
+                        .add(contacts::Column::ContactId.eq(contact_id)),
+                )
+                .add(
+                    Condition::all()
+                        .add(contacts::Column::UserId.eq(contact_id))
+                        .add(contacts::Column::ContactId.eq(user_id)),
+                ),
+        )
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::services::bot;
+    use crate::websocket::{create_clients, ClientEntry};
+
+    async fn connect(clients: &Clients, user_id: i64) -> mpsc::UnboundedReceiver<String> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        clients.lock().await.insert(format!("{}_test", user_id), ClientEntry {
+            sender,
+            user_id,
+            session_id: "session".to_string(),
+            status: PresenceStatus::Online,
+            last_active: std::time::Instant::now(),
+            idle: false,
+        });
+        receiver
+    }
+
+    fn event_type(socket: &mut mpsc::UnboundedReceiver<String>) -> String {
+        let event: serde_json::Value = serde_json::from_str(&socket.try_recv().unwrap()).unwrap();
+        event["event_type"].as_str().unwrap().to_string()
+    }
+
+    async fn request_id(db: &DatabaseConnection, clients: &Clients, sender_id: i64, receiver_id: i64) -> i64 {
+        match send(db, clients, sender_id, receiver_id, Some(" hi ".to_string())).await.unwrap() {
+            SendOutcome::Sent(request) => request.id,
+            _ => panic!("request was not sent"),
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_sent_once() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let mut bob_socket = connect(&clients, bob.id).await;
+
+        let SendOutcome::Sent(request) = send(&db, &clients, alice.id, bob.id, Some(" hi ".to_string())).await.unwrap() else {
+            panic!("request was not sent")
+        };
+        assert_eq!(request.sender.id, alice.id);
+        assert_eq!(request.receiver.id, bob.id);
+        assert_eq!(request.message.as_deref(), Some("hi"));
+        assert_eq!(event_type(&mut bob_socket), "friend_request");
+
+        let outcome = send(&db, &clients, alice.id, bob.id, None).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::AlreadyRequested));
+        assert!(bob_socket.try_recv().is_err());
+
+        let requests = list_requests(&db, bob.id).await.unwrap();
+        assert_eq!(requests.incoming.len(), 1);
+        assert!(requests.outgoing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn asking_back_accepts_the_pending_request() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        request_id(&db, &clients, alice.id, bob.id).await;
+        let mut alice_socket = connect(&clients, alice.id).await;
+
+        let SendOutcome::Accepted(contact) = send(&db, &clients, bob.id, alice.id, None).await.unwrap() else {
+            panic!("request was not accepted")
+        };
+        assert_eq!(contact.user.id, alice.id);
+        assert_eq!(event_type(&mut alice_socket), "friend_accepted");
+        assert!(are_contacts(&db, alice.id, bob.id).await.unwrap());
+        assert!(are_contacts(&db, bob.id, alice.id).await.unwrap());
+        assert!(list_requests(&db, alice.id).await.unwrap().outgoing.is_empty());
+
+        let outcome = send(&db, &clients, alice.id, bob.id, None).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::AlreadyContacts));
+    }
+
+    #[tokio::test]
+    async fn only_the_receiver_can_accept_a_request() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let carol = create_test_user(&db, "carol").await;
+        let request = request_id(&db, &clients, alice.id, bob.id).await;
+
+        assert!(accept(&db, &clients, carol.id, request).await.unwrap().is_none());
+        assert!(accept(&db, &clients, alice.id, request).await.unwrap().is_none());
+        assert!(!are_contacts(&db, alice.id, carol.id).await.unwrap());
+
+        let contact = accept(&db, &clients, bob.id, request).await.unwrap().unwrap();
+        assert_eq!(contact.user.id, alice.id);
+        assert!(accept(&db, &clients, bob.id, request).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn bots_missing_and_blocked_users_cannot_be_asked() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let helper = bot::create(&db, bob.id, "helper").await.unwrap();
+        blocks::block(&db, &clients, bob.id, alice.id).await.unwrap();
+
+        for receiver_id in [helper.id, bob.id, bob.id + 100] {
+            let outcome = send(&db, &clients, alice.id, receiver_id, None).await.unwrap();
+            assert!(matches!(outcome, SendOutcome::UserNotFound));
+        }
+    }
+}
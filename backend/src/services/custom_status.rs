@@ -0,0 +1,232 @@
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, ColumnTrait, Set, ActiveModelTrait};
+use sea_orm::sea_query::Expr;
+use anyhow::Result;
+use crate::entity::{users, users::Entity as Users};
+use crate::models::{CustomStatus, SetCustomStatusRequest};
+use crate::services::{presence, profile};
+use crate::websocket::{send_to_users, Clients, WsEvent};
+
+// How often expired statuses are cleared
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+fn optional_text(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|value| !value.is_empty()).map(str::to_string)
+}
+
+// Statuses past their expiry but not yet cleared by the background task
+// read as cleared
+pub fn hide_expired(user: &mut users::Model) {
+    let now = chrono::Utc::now().naive_utc();
+    if user.status_expires_at.is_some_and(|expires_at| expires_at <= now) {
+        user.status_text = None;
+        user.status_emoji = None;
+        user.status_expires_at = None;
+    }
+}
+
+pub async fn set(db: &DatabaseConnection, clients: &Clients, user: users::Model, status: &SetCustomStatusRequest) -> Result<users::Model> {
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.status_text = Set(optional_text(status.text.as_deref()));
+    user_active.status_emoji = Set(optional_text(status.emoji.as_deref()));
+    user_active.status_expires_at = Set(status.expires_at.map(|expires_at| expires_at.naive_utc()));
+    let user = user_active.update(db).await?;
+
+    notify_peers(db, clients, &user).await;
+    Ok(user)
+}
+
+pub async fn clear(db: &DatabaseConnection, clients: &Clients, user: users::Model) -> Result<users::Model> {
+    if user.status_text.is_none() && user.status_emoji.is_none() && user.status_expires_at.is_none() {
+        return Ok(user);
+    }
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.status_text = Set(None);
+    user_active.status_emoji = Set(None);
+    user_active.status_expires_at = Set(None);
+    let user = user_active.update(db).await?;
+
+    notify_peers(db, clients, &user).await;
+    Ok(user)
+}
+
+// Push a status_changed event to the user's peers, leaving out anyone on
+// either side of a block. The change itself already succeeded.
+async fn notify_peers(db: &DatabaseConnection, clients: &Clients, user: &users::Model) {
+    let mut peers = match profile::peer_ids(db, user.id).await {
+        Ok(peers) => peers,
+        Err(e) => {
+            tracing::warn!("Failed to find peers of user {}: {:?}", user.id, e);
+            return;
+        }
+    };
+    for hidden in presence::hidden_users(db, user.id).await {
+        peers.remove(&hidden);
+    }
+
+    let event = WsEvent {
+        event_type: "status_changed".to_string(),
+        user_id: user.id,
+        receiver_id: None,
+        group_id: None,
+        content: None,
+        data: serde_json::to_value(CustomStatus::from(user)).ok(),
+    };
+    send_to_users(clients, &peers, &event).await;
+}
+
+async fn expired_user_ids(db: &DatabaseConnection, now: NaiveDateTime) -> Result<Vec<i64>> {
+    Ok(Users::find()
+        .select_only()
+        .column(users::Column::Id)
+        .filter(users::Column::StatusExpiresAt.lte(now))
+        .into_tuple()
+        .all(db)
+        .await?)
+}
+
+// Clear a status that has expired by now. The condition is checked again in
+// the update, so a status the user set after the sweep found them survives.
+// Returns whether anything was cleared.
+async fn expire(db: &DatabaseConnection, clients: &Clients, user_id: i64, now: NaiveDateTime) -> Result<bool> {
+    let result = Users::update_many()
+        .col_expr(users::Column::StatusText, Expr::value(Option::<String>::None))
+        .col_expr(users::Column::StatusEmoji, Expr::value(Option::<String>::None))
+        .col_expr(users::Column::StatusExpiresAt, Expr::value(Option::<NaiveDateTime>::None))
+        .filter(users::Column::Id.eq(user_id))
+        .filter(users::Column::StatusExpiresAt.lte(now))
+        .exec(db)
+        .await?;
+    if result.rows_affected == 0 {
+        return Ok(false);
+    }
+
+    if let Some(user) = Users::find_by_id(user_id).one(db).await? {
+        notify_peers(db, clients, &user).await;
+    }
+    Ok(true)
+}
+
+async fn expire_due(db: &DatabaseConnection, clients: &Clients) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    for user_id in expired_user_ids(db, now).await? {
+        if let Err(e) = expire(db, clients, user_id, now).await {
+            tracing::warn!("Failed to expire status of user {}: {:?}", user_id, e);
+        }
+    }
+    Ok(())
+}
+
+pub fn spawn_expiry_task(db: DatabaseConnection, clients: Clients) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expire_due(&db, &clients).await {
+                tracing::error!("Failed to expire custom statuses: {:?}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::entity::messages;
+    use crate::models::PresenceStatus;
+    use crate::websocket::{create_clients, ClientEntry};
+
+    async fn connect(clients: &Clients, user_id: i64) -> mpsc::UnboundedReceiver<String> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        clients.lock().await.insert(format!("{}_test", user_id), ClientEntry {
+            sender,
+            user_id,
+            session_id: "session".to_string(),
+            status: PresenceStatus::Online,
+            last_active: std::time::Instant::now(),
+            idle: false,
+        });
+        receiver
+    }
+
+    // alice with a status that expires at the given offset from now, and a
+    // peer bob who hears about her status changes
+    async fn setup(db: &DatabaseConnection, expires_in: chrono::Duration) -> (users::Model, users::Model) {
+        let alice = create_test_user(db, "alice").await;
+        let bob = create_test_user(db, "bob").await;
+        messages::ActiveModel {
+            sender_id: Set(bob.id),
+            receiver_id: Set(Some(alice.id)),
+            content: Set("hi".to_string()),
+            message_type: Set("text".to_string()),
+            is_read: Set(false),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+
+        let mut alice: users::ActiveModel = alice.into();
+        alice.status_text = Set(Some("In a meeting".to_string()));
+        alice.status_emoji = Set(Some("📅".to_string()));
+        alice.status_expires_at = Set(Some(chrono::Utc::now().naive_utc() + expires_in));
+        (alice.update(db).await.unwrap(), bob)
+    }
+
+    async fn reload(db: &DatabaseConnection, user_id: i64) -> users::Model {
+        Users::find_by_id(user_id).one(db).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn expired_statuses_are_cleared_and_announced() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let (alice, bob) = setup(&db, chrono::Duration::minutes(-1)).await;
+        let mut bob_socket = connect(&clients, bob.id).await;
+
+        expire_due(&db, &clients).await.unwrap();
+        let alice = reload(&db, alice.id).await;
+        assert_eq!((alice.status_text, alice.status_emoji, alice.status_expires_at), (None, None, None));
+
+        let event: serde_json::Value = serde_json::from_str(&bob_socket.try_recv().unwrap()).unwrap();
+        assert_eq!(event["event_type"], "status_changed");
+        assert_eq!(event["user_id"], alice.id);
+    }
+
+    #[tokio::test]
+    async fn statuses_that_have_not_expired_are_kept() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let (alice, bob) = setup(&db, chrono::Duration::minutes(10)).await;
+        let mut bob_socket = connect(&clients, bob.id).await;
+
+        expire_due(&db, &clients).await.unwrap();
+        assert_eq!(reload(&db, alice.id).await.status_text.as_deref(), Some("In a meeting"));
+        assert!(bob_socket.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn a_status_set_after_the_sweep_loaded_it_survives() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let (alice, bob) = setup(&db, chrono::Duration::minutes(-1)).await;
+        let now = chrono::Utc::now().naive_utc();
+        assert_eq!(expired_user_ids(&db, now).await.unwrap(), vec![alice.id]);
+
+        // alice picks a new status before the sweep gets to her
+        let status = SetCustomStatusRequest {
+            text: Some("Back at my desk".to_string()),
+            emoji: None,
+            expires_at: None,
+        };
+        set(&db, &clients, alice.clone(), &status).await.unwrap();
+        let mut bob_socket = connect(&clients, bob.id).await;
+
+        assert!(!expire(&db, &clients, alice.id, now).await.unwrap());
+        assert_eq!(reload(&db, alice.id).await.status_text.as_deref(), Some("Back at my desk"));
+        assert!(bob_socket.try_recv().is_err());
+    }
+}
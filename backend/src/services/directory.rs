@@ -0,0 +1,328 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, ColumnTrait, Condition};
+use sea_orm::sea_query::{Expr, LikeExpr, Query};
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use crate::entity::{blocks, users, users::Entity as Users};
+use crate::models::{DirectoryPage, DirectoryQuery, DirectorySearch, DirectorySort, DirectoryUser, PresenceStatus, PublicProfile, SortOrder};
+use crate::services::{custom_status, presence};
+use crate::websocket::Clients;
+
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+
+// Keyset position: the sort key of the last user on the previous page.
+// Usernames are unique, so they order users without ties.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cursor {
+    Username(String),
+    Id(i64),
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn matches(&self, sort: DirectorySort) -> bool {
+        matches!(
+            (self, sort),
+            (Cursor::Username(_), DirectorySort::Username) | (Cursor::Id(_), DirectorySort::CreatedAt)
+        )
+    }
+}
+
+// LIKE treats %, _ and the escape character specially. The escape character
+// is named in the query because only MySQL defaults to a backslash.
+const LIKE_ESCAPE: char = '\\';
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | LIKE_ESCAPE) {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn like(column: users::Column, pattern: &str) -> sea_orm::sea_query::SimpleExpr {
+    Expr::col((Users, column)).like(LikeExpr::new(pattern).escape(LIKE_ESCAPE))
+}
+
+// users.status is what each user picked, which may be invisible, so entries
+// carry the presence the viewer should see instead. Users hidden by a block
+// appear offline, without a last-seen time or custom status.
+async fn entries(db: &DatabaseConnection, clients: &Clients, viewer_id: i64, users: Vec<users::Model>) -> Vec<DirectoryUser> {
+    let visible = presence::visible(clients).await;
+    let hidden = presence::hidden_users(db, viewer_id).await;
+    users
+        .into_iter()
+        .map(|mut user| {
+            custom_status::hide_expired(&mut user);
+            if hidden.contains(&user.id) {
+                return DirectoryUser {
+                    profile: PublicProfile::from(&user),
+                    status: PresenceStatus::Offline,
+                    last_seen_at: None,
+                    status_text: None,
+                    status_emoji: None,
+                    status_expires_at: None,
+                };
+            }
+            DirectoryUser {
+                profile: PublicProfile::from(&user),
+                status: visible.get(&user.id).copied().unwrap_or(PresenceStatus::Offline),
+                last_seen_at: user.last_seen_at,
+                status_text: user.status_text,
+                status_emoji: user.status_emoji,
+                status_expires_at: user.status_expires_at,
+            }
+        })
+        .collect()
+}
+
+// One page of active accounts other than the caller. Returns None when the
+// cursor is malformed or belongs to a different sort.
+//
+// Every query starts with suspended_at IS NULL so it can use the directory
+// indexes: idx_directory (suspended_at, username, email) serves username
+// order, prefix ranges and the username cursor, and substring matches are
+// checked against the index entries before rows are read; idx_suspended
+// (suspended_at, implicitly id) serves creation order.
+pub async fn list(db: &DatabaseConnection, clients: &Clients, user_id: i64, params: &DirectoryQuery) -> Result<Option<DirectoryPage>> {
+    let cursor = match params.cursor.as_deref() {
+        Some(cursor) => match Cursor::decode(cursor) {
+            Some(cursor) if cursor.matches(params.sort) => Some(cursor),
+            _ => return Ok(None),
+        },
+        None => None,
+    };
+
+    let mut query = Users::find()
+        .filter(users::Column::SuspendedAt.is_null())
+        .filter(users::Column::Id.ne(user_id));
+
+    if let Some(q) = params.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let pattern = match params.search {
+            DirectorySearch::Prefix => format!("{}%", escape_like(q)),
+            DirectorySearch::Substring => format!("%{}%", escape_like(q)),
+        };
+        query = query.filter(
+            Condition::any()
+                .add(like(users::Column::Username, &pattern))
+                .add(like(users::Column::Email, &pattern)),
+        );
+    }
+
+    if params.exclude_blocked {
+        query = query.filter(
+            users::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(blocks::Column::BlockedId)
+                    .from(blocks::Entity)
+                    .and_where(blocks::Column::BlockerId.eq(user_id))
+                    .to_owned(),
+            ),
+        );
+    }
+
+    let descending = params.order == SortOrder::Desc;
+    query = match params.sort {
+        DirectorySort::Username => {
+            if let Some(Cursor::Username(after)) = cursor {
+                query = query.filter(if descending {
+                    users::Column::Username.lt(after)
+                } else {
+                    users::Column::Username.gt(after)
+                });
+            }
+            if descending {
+                query.order_by_desc(users::Column::Username)
+            } else {
+                query.order_by_asc(users::Column::Username)
+            }
+        }
+        DirectorySort::CreatedAt => {
+            if let Some(Cursor::Id(after)) = cursor {
+                query = query.filter(if descending {
+                    users::Column::Id.lt(after)
+                } else {
+                    users::Column::Id.gt(after)
+                });
+            }
+            if descending {
+                query.order_by_desc(users::Column::Id)
+            } else {
+                query.order_by_asc(users::Column::Id)
+            }
+        }
+    };
+
+    // Fetch one extra row to learn whether another page follows
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let mut users = query.limit(limit + 1).all(db).await?;
+    let has_more = users.len() as u64 > limit;
+    users.truncate(limit as usize);
+
+    let next_cursor = match users.last() {
+        Some(last) if has_more => Some(match params.sort {
+            DirectorySort::Username => Cursor::Username(last.username.clone()),
+            DirectorySort::CreatedAt => Cursor::Id(last.id),
+        }.encode()),
+        _ => None,
+    };
+
+    let users = entries(db, clients, user_id, users).await;
+    Ok(Some(DirectoryPage { users, next_cursor }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{ActiveModelTrait, Set};
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::websocket::{create_clients, ClientEntry};
+
+    #[test]
+    fn cursors_round_trip() {
+        for cursor in [Cursor::Username("alice".to_string()), Cursor::Id(42)] {
+            assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        }
+        assert_eq!(Cursor::decode("not base64!"), None);
+        assert_eq!(Cursor::decode(&URL_SAFE_NO_PAD.encode(b"{\"page\":2}")), None);
+    }
+
+    #[test]
+    fn cursors_belong_to_one_sort() {
+        assert!(Cursor::Username("a".to_string()).matches(DirectorySort::Username));
+        assert!(!Cursor::Username("a".to_string()).matches(DirectorySort::CreatedAt));
+        assert!(Cursor::Id(1).matches(DirectorySort::CreatedAt));
+        assert!(!Cursor::Id(1).matches(DirectorySort::Username));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("alice"), "alice");
+        assert_eq!(escape_like("100%_sure"), "100\\%\\_sure");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+    }
+
+    fn query(q: Option<&str>) -> DirectoryQuery {
+        DirectoryQuery {
+            q: q.map(str::to_string),
+            search: DirectorySearch::Prefix,
+            sort: DirectorySort::Username,
+            order: SortOrder::Asc,
+            limit: None,
+            cursor: None,
+            exclude_blocked: false,
+        }
+    }
+
+    fn usernames(page: &DirectoryPage) -> Vec<&str> {
+        page.users.iter().map(|user| user.profile.username.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn search_treats_wildcards_literally() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let viewer = create_test_user(&db, "viewer").await;
+        create_test_user(&db, "alice").await;
+        create_test_user(&db, "al_bob").await;
+
+        let page = list(&db, &clients, viewer.id, &query(Some("al_"))).await.unwrap().unwrap();
+        assert_eq!(usernames(&page), ["al_bob"]);
+
+        let page = list(&db, &clients, viewer.id, &query(Some("al"))).await.unwrap().unwrap();
+        assert_eq!(usernames(&page), ["al_bob", "alice"]);
+    }
+
+    #[tokio::test]
+    async fn pages_skip_the_viewer_and_suspended_accounts() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let viewer = create_test_user(&db, "viewer").await;
+        for username in ["dave", "alice", "carol", "bob"] {
+            create_test_user(&db, username).await;
+        }
+        let mut erin: users::ActiveModel = create_test_user(&db, "erin").await.into();
+        erin.suspended_at = Set(Some(chrono::Utc::now().naive_utc()));
+        erin.update(&db).await.unwrap();
+
+        let mut params = query(None);
+        params.limit = Some(3);
+        let first = list(&db, &clients, viewer.id, &params).await.unwrap().unwrap();
+        assert_eq!(usernames(&first), ["alice", "bob", "carol"]);
+
+        params.cursor = first.next_cursor.clone();
+        let second = list(&db, &clients, viewer.id, &params).await.unwrap().unwrap();
+        assert_eq!(usernames(&second), ["dave"]);
+        assert!(second.next_cursor.is_none());
+
+        params.sort = DirectorySort::CreatedAt;
+        assert!(list(&db, &clients, viewer.id, &params).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn entries_show_presence_but_not_private_fields() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let viewer = create_test_user(&db, "viewer").await;
+        let mut alice: users::ActiveModel = create_test_user(&db, "alice").await.into();
+        alice.status_text = Set(Some("In a meeting".to_string()));
+        let alice = alice.update(&db).await.unwrap();
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        clients.lock().await.insert("alice_1".to_string(), ClientEntry {
+            sender,
+            user_id: alice.id,
+            session_id: "session".to_string(),
+            status: PresenceStatus::Online,
+            last_active: std::time::Instant::now(),
+            idle: false,
+        });
+
+        let page = list(&db, &clients, viewer.id, &query(None)).await.unwrap().unwrap();
+        let entry = serde_json::to_value(&page.users[0]).unwrap();
+        assert_eq!(entry["username"], "alice");
+        assert_eq!(entry["status"], "online");
+        assert_eq!(entry["status_text"], "In a meeting");
+        for private in ["email", "role", "suspended_at", "totp_enabled", "email_verified", "owner_id"] {
+            assert!(entry.get(private).is_none(), "{} leaked", private);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_users_look_offline() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let viewer = create_test_user(&db, "viewer").await;
+        let mut alice: users::ActiveModel = create_test_user(&db, "alice").await.into();
+        alice.status_text = Set(Some("Around".to_string()));
+        alice.last_seen_at = Set(Some(chrono::Utc::now().naive_utc()));
+        let alice = alice.update(&db).await.unwrap();
+        blocks::ActiveModel {
+            id: sea_orm::ActiveValue::NotSet,
+            blocker_id: Set(alice.id),
+            blocked_id: Set(viewer.id),
+            created_at: Set(None),
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let page = list(&db, &clients, viewer.id, &query(None)).await.unwrap().unwrap();
+        let entry = &page.users[0];
+        assert_eq!(entry.status, PresenceStatus::Offline);
+        assert!(entry.last_seen_at.is_none());
+        assert!(entry.status_text.is_none());
+    }
+}
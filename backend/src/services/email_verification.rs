@@ -0,0 +1,171 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
+use redis::aio::MultiplexedConnection;
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::{users, users::Entity as Users};
+use crate::entity::{messages, messages::Entity as Messages};
+use crate::services::contacts;
+use crate::services::mailer::{Mail, Mailer};
+use crate::services::one_time_token::{self, Purpose};
+
+pub async fn send_verification(
+    redis: &mut MultiplexedConnection,
+    mailer: &dyn Mailer,
+    config: &Config,
+    user: &users::Model,
+) -> Result<()> {
+    let token = one_time_token::create(redis, Purpose::EmailVerification, user.id, config.email_verification_ttl()).await?;
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Verify your IM-TT email address".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email address by opening the link below. It expires in {} hours.\n\n{}/verify-email?token={}",
+            user.username, config.email_verification_ttl_hours, config.app_base_url, token
+        ),
+    };
+    mailer.send(&mail).await
+}
+
+// Mark the token's user as verified, or None if the token is not valid
+pub async fn verify(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    token: &str,
+) -> Result<Option<users::Model>> {
+    let Some(user_id) = one_time_token::consume(redis, Purpose::EmailVerification, token).await? else {
+        return Ok(None);
+    };
+    let Some(user) = Users::find_by_id(user_id).one(db).await? else {
+        return Ok(None);
+    };
+
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.email_verified = Set(true);
+    user_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+    Ok(Some(user_active.update(db).await?))
+}
+
+async fn is_verified(db: &DatabaseConnection, user_id: i64) -> Result<bool> {
+    let user = Users::find_by_id(user_id).one(db).await?;
+    Ok(user.map(|u| u.email_verified).unwrap_or(false))
+}
+
+// Policy for unverified accounts, see UNVERIFIED_* settings
+
+pub async fn can_create_group(db: &DatabaseConnection, config: &Config, user_id: i64) -> Result<bool> {
+    if config.unverified_can_create_groups {
+        return Ok(true);
+    }
+    is_verified(db, user_id).await
+}
+
+// Unverified users may still reply to people who wrote to them first and
+// talk to their contacts. Sharing a group does not count: anyone can join a
+// group, so it would let an unverified account reach any member.
+pub async fn can_message_user(
+    db: &DatabaseConnection,
+    config: &Config,
+    sender_id: i64,
+    receiver_id: i64,
+) -> Result<bool> {
+    if config.unverified_can_message_strangers || is_verified(db, sender_id).await? {
+        return Ok(true);
+    }
+
+    let wrote_first = Messages::find()
+        .filter(messages::Column::SenderId.eq(receiver_id))
+        .filter(messages::Column::ReceiverId.eq(sender_id))
+        .one(db)
+        .await?
+        .is_some();
+    if wrote_first {
+        return Ok(true);
+    }
+    contacts::are_contacts(db, sender_id, receiver_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::services::bot;
+    use crate::websocket::create_clients;
+
+    async fn unverified_user(db: &DatabaseConnection, username: &str) -> users::Model {
+        let mut user: users::ActiveModel = create_test_user(db, username).await.into();
+        user.email_verified = Set(false);
+        user.update(db).await.unwrap()
+    }
+
+    async fn message(db: &DatabaseConnection, sender_id: i64, receiver_id: i64) {
+        messages::ActiveModel {
+            sender_id: Set(sender_id),
+            receiver_id: Set(Some(receiver_id)),
+            content: Set("hi".to_string()),
+            message_type: Set("text".to_string()),
+            is_read: Set(false),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    fn strict_config() -> Config {
+        let mut config = Config::from_env();
+        config.unverified_can_message_strangers = false;
+        config
+    }
+
+    #[tokio::test]
+    async fn verified_users_can_message_anyone() {
+        let db = create_test_connection().await;
+        let config = strict_config();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = unverified_user(&db, "bob").await;
+        let owner = create_test_user(&db, "owner").await;
+        let helper = bot::create(&db, owner.id, "helper").await.unwrap();
+
+        assert!(can_message_user(&db, &config, alice.id, bob.id).await.unwrap());
+        assert!(can_message_user(&db, &config, alice.id, helper.id).await.unwrap());
+        // Bots are created verified, so they can reach anyone as well
+        assert!(can_message_user(&db, &config, helper.id, bob.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unverified_users_cannot_message_strangers_or_bots() {
+        let db = create_test_connection().await;
+        let alice = unverified_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let helper = bot::create(&db, bob.id, "helper").await.unwrap();
+
+        assert!(!can_message_user(&db, &strict_config(), alice.id, bob.id).await.unwrap());
+        assert!(!can_message_user(&db, &strict_config(), alice.id, helper.id).await.unwrap());
+
+        let mut config = strict_config();
+        config.unverified_can_message_strangers = true;
+        assert!(can_message_user(&db, &config, alice.id, bob.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unverified_users_can_reply_and_talk_to_contacts() {
+        let db = create_test_connection().await;
+        let config = strict_config();
+        let alice = unverified_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let carol = create_test_user(&db, "carol").await;
+
+        // Writing first does not open the conversation for the unverified side
+        message(&db, alice.id, bob.id).await;
+        assert!(!can_message_user(&db, &config, alice.id, bob.id).await.unwrap());
+        message(&db, bob.id, alice.id).await;
+        assert!(can_message_user(&db, &config, alice.id, bob.id).await.unwrap());
+
+        let clients = create_clients();
+        contacts::send(&db, &clients, carol.id, alice.id, None).await.unwrap();
+        assert!(!can_message_user(&db, &config, alice.id, carol.id).await.unwrap());
+        // Asking back accepts carol's request
+        contacts::send(&db, &clients, alice.id, carol.id, None).await.unwrap();
+        assert!(can_message_user(&db, &config, alice.id, carol.id).await.unwrap());
+    }
+}
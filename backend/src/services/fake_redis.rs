@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use redis::aio::ConnectionLike;
+use redis::{Arg, Cmd, Pipeline, RedisFuture, Value};
+
+// In-memory stand-in for the handful of Redis commands the services use, so
+// their tests run without a server. Time only moves when a test calls
+// advance, so expiry is deterministic.
+#[derive(Default)]
+pub struct FakeRedis {
+    data: HashMap<Vec<u8>, Vec<u8>>,
+    // Second on the fake clock at which each key expires
+    expires_at: HashMap<Vec<u8>, u64>,
+    now: u64,
+}
+
+fn number(arg: &[u8]) -> u64 {
+    String::from_utf8_lossy(arg).parse().unwrap()
+}
+
+impl FakeRedis {
+    // Move the clock forward, dropping every key whose TTL runs out
+    pub fn advance(&mut self, secs: u64) {
+        self.now += secs;
+        let now = self.now;
+        let expired: Vec<Vec<u8>> = self.expires_at.iter().filter(|(_, at)| **at <= now).map(|(key, _)| key.clone()).collect();
+        for key in expired {
+            self.remove(&key);
+        }
+    }
+
+    // Remaining TTL of a key in seconds, as the TTL command reports it
+    pub fn ttl(&self, key: &str) -> i64 {
+        let key = key.as_bytes();
+        match self.expires_at.get(key) {
+            Some(at) => (at - self.now) as i64,
+            None if self.data.contains_key(key) => -1,
+            None => -2,
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> bool {
+        self.expires_at.remove(key);
+        self.data.remove(key).is_some()
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], ttl: Option<u64>) {
+        self.data.insert(key.to_vec(), value.to_vec());
+        match ttl {
+            Some(secs) => self.expires_at.insert(key.to_vec(), self.now + secs),
+            None => self.expires_at.remove(key),
+        };
+    }
+
+    fn execute(&mut self, args: Vec<Vec<u8>>) -> Value {
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+        match name.as_str() {
+            "GET" => self.data.get(&args[1]).cloned().map_or(Value::Nil, Value::Data),
+            "SETEX" => {
+                self.set(&args[1], &args[3], Some(number(&args[2])));
+                Value::Okay
+            }
+            "SET" => {
+                let options = &args[3..];
+                let nx = options.iter().any(|arg| arg.eq_ignore_ascii_case(b"NX"));
+                if nx && self.data.contains_key(&args[1]) {
+                    return Value::Nil;
+                }
+                let ttl = options
+                    .iter()
+                    .position(|arg| arg.eq_ignore_ascii_case(b"EX"))
+                    .map(|i| number(&options[i + 1]));
+                self.set(&args[1], &args[2], ttl);
+                Value::Okay
+            }
+            "INCRBY" => {
+                let value = self.data.get(&args[1]).map_or(0, |value| number(value)) + number(&args[2]);
+                self.data.insert(args[1].clone(), value.to_string().into_bytes());
+                Value::Int(value as i64)
+            }
+            "EXPIRE" => {
+                if !self.data.contains_key(&args[1]) {
+                    return Value::Int(0);
+                }
+                self.expires_at.insert(args[1].clone(), self.now + number(&args[2]));
+                Value::Int(1)
+            }
+            "TTL" => Value::Int(self.ttl(&String::from_utf8_lossy(&args[1]))),
+            "EXISTS" => Value::Int(args[1..].iter().filter(|key| self.data.contains_key(*key)).count() as i64),
+            "DEL" => Value::Int(args[1..].iter().filter(|key| self.remove(key)).count() as i64),
+            other => panic!("unexpected command {}", other),
+        }
+    }
+}
+
+fn args(cmd: &Cmd) -> Vec<Vec<u8>> {
+    cmd.args_iter()
+        .map(|arg| match arg {
+            Arg::Simple(bytes) => bytes.to_vec(),
+            Arg::Cursor => panic!("unexpected cursor"),
+        })
+        .collect()
+}
+
+impl ConnectionLike for FakeRedis {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let value = self.execute(args(cmd));
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn req_packed_commands<'a>(&'a mut self, pipeline: &'a Pipeline, offset: usize, count: usize) -> RedisFuture<'a, Vec<Value>> {
+        let values: Vec<Value> = pipeline.cmd_iter().map(|cmd| self.execute(args(cmd))).collect();
+        // Atomic pipelines skip the MULTI and QUEUED replies and read the
+        // EXEC reply, which holds every result
+        let values = if offset == values.len() + 1 && count == 1 {
+            vec![Value::Bulk(values)]
+        } else {
+            values.into_iter().skip(offset).take(count).collect()
+        };
+        Box::pin(async move { Ok(values) })
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
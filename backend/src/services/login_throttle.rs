@@ -0,0 +1,222 @@
+use salvo::Request;
+use sea_orm::{DatabaseConnection, Set, ActiveModelTrait};
+use redis::{aio::ConnectionLike, AsyncCommands};
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::login_lockouts;
+
+// Failed logins are counted per account and per client IP. Once a counter
+// passes its limit the key is locked for a backoff that doubles with every
+// further failure inside the counting window. Each lockout is also written
+// to login_lockouts so administrators can review it.
+
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    Account,
+    Ip,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Account => "account",
+            Scope::Ip => "ip",
+        }
+    }
+}
+
+fn failures_key(scope: Scope, identifier: &str) -> String {
+    format!("login_failures:{}:{}", scope.as_str(), identifier)
+}
+
+fn lock_key(scope: Scope, identifier: &str) -> String {
+    format!("login_lock:{}:{}", scope.as_str(), identifier)
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn targets(email: &str, ip: Option<&str>) -> Vec<(Scope, String)> {
+    let mut targets = vec![(Scope::Account, normalize_email(email))];
+    if let Some(ip) = ip {
+        targets.push((Scope::Ip, ip.to_string()));
+    }
+    targets
+}
+
+pub fn client_ip(req: &Request) -> Option<String> {
+    req.remote_addr()
+        .clone()
+        .into_std()
+        .map(|addr| addr.ip().to_string())
+}
+
+// Seconds until the account or IP may try again, or None if neither is locked
+pub async fn check<C: ConnectionLike + Send>(redis: &mut C, email: &str, ip: Option<&str>) -> Result<Option<u64>> {
+    let mut retry_after = 0;
+    for (scope, identifier) in targets(email, ip) {
+        let ttl: i64 = redis.ttl(lock_key(scope, &identifier)).await?;
+        retry_after = retry_after.max(ttl);
+    }
+    Ok((retry_after > 0).then_some(retry_after as u64))
+}
+
+// Count a failed attempt; returns the lockout length if this attempt locked
+// the account or IP
+pub async fn record_failure<C: ConnectionLike + Send>(
+    db: &DatabaseConnection,
+    redis: &mut C,
+    config: &Config,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<Option<u64>> {
+    let mut locked_for = None;
+
+    for (scope, identifier) in targets(email, ip) {
+        let limit = match scope {
+            Scope::Account => config.login_max_failures,
+            Scope::Ip => config.login_max_failures_per_ip,
+        };
+
+        let key = failures_key(scope, &identifier);
+        let failures: i64 = redis.incr(&key, 1).await?;
+        redis.expire::<_, ()>(&key, config.login_failure_window_minutes * 60).await?;
+
+        if failures < limit {
+            continue;
+        }
+
+        let exponent = (failures - limit).min(20) as u32;
+        let lockout = config
+            .login_lockout_base_seconds
+            .saturating_mul(1 << exponent)
+            .min(config.login_lockout_max_seconds)
+            .max(1) as u64;
+        redis.set_ex::<_, _, ()>(lock_key(scope, &identifier), 1, lockout).await?;
+        // Keep counting for as long as the lock lasts so the backoff keeps growing
+        redis.expire::<_, ()>(&key, (config.login_failure_window_minutes * 60).max(lockout as i64 * 2)).await?;
+
+        tracing::warn!("Locked {} {} for {}s after {} failed logins", scope.as_str(), identifier, lockout, failures);
+
+        let now = chrono::Utc::now().naive_utc();
+        let record = login_lockouts::ActiveModel {
+            scope: Set(scope.as_str().to_string()),
+            identifier: Set(identifier.clone()),
+            failures: Set(failures as i32),
+            locked_until: Set(now + chrono::Duration::seconds(lockout as i64)),
+            created_at: Set(Some(now)),
+            ..Default::default()
+        };
+        if let Err(e) = record.insert(db).await {
+            tracing::error!("Failed to record login lockout: {:?}", e);
+        }
+
+        locked_for = Some(locked_for.unwrap_or(0).max(lockout));
+    }
+
+    Ok(locked_for)
+}
+
+// A completed login clears the account counter; the IP counter is left to
+// expire so one good account cannot be used to reset guessing from an IP
+pub async fn record_success<C: ConnectionLike + Send>(redis: &mut C, email: &str) -> Result<()> {
+    redis.del::<_, ()>(failures_key(Scope::Account, &normalize_email(email))).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sqlite::create_test_connection;
+    use crate::entity::login_lockouts::Entity as LoginLockouts;
+    use crate::services::fake_redis::FakeRedis;
+    use sea_orm::EntityTrait;
+
+    fn config() -> Config {
+        let mut config = Config::from_env();
+        config.login_max_failures = 3;
+        config.login_max_failures_per_ip = 10;
+        config.login_failure_window_minutes = 15;
+        config.login_lockout_base_seconds = 30;
+        config.login_lockout_max_seconds = 100;
+        config
+    }
+
+    #[tokio::test]
+    async fn accounts_lock_once_the_limit_is_reached() {
+        let db = create_test_connection().await;
+        let mut redis = FakeRedis::default();
+        let config = config();
+
+        for _ in 0..2 {
+            assert_eq!(record_failure(&db, &mut redis, &config, "alice@example.com", None).await.unwrap(), None);
+        }
+        assert_eq!(check(&mut redis, "alice@example.com", None).await.unwrap(), None);
+
+        assert_eq!(record_failure(&db, &mut redis, &config, "alice@example.com", None).await.unwrap(), Some(30));
+        // Addresses are matched case-insensitively
+        assert_eq!(check(&mut redis, " Alice@Example.com", None).await.unwrap(), Some(30));
+        assert_eq!(check(&mut redis, "bob@example.com", None).await.unwrap(), None);
+
+        let lockouts = LoginLockouts::find().all(&db).await.unwrap();
+        assert_eq!(lockouts.len(), 1);
+        assert_eq!(lockouts[0].scope, "account");
+        assert_eq!(lockouts[0].identifier, "alice@example.com");
+        assert_eq!(lockouts[0].failures, 3);
+
+        redis.advance(30);
+        assert_eq!(check(&mut redis, "alice@example.com", None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn backoff_doubles_up_to_the_maximum() {
+        let db = create_test_connection().await;
+        let mut redis = FakeRedis::default();
+        let config = config();
+
+        let mut lockouts = Vec::new();
+        for _ in 0..6 {
+            lockouts.push(record_failure(&db, &mut redis, &config, "alice@example.com", None).await.unwrap());
+        }
+        assert_eq!(lockouts, vec![None, None, Some(30), Some(60), Some(100), Some(100)]);
+        assert_eq!(check(&mut redis, "alice@example.com", None).await.unwrap(), Some(100));
+        // The counter outlives the lock so the next failure keeps backing off
+        assert_eq!(redis.ttl("login_failures:account:alice@example.com"), 15 * 60);
+    }
+
+    #[tokio::test]
+    async fn ip_limits_apply_across_accounts() {
+        let db = create_test_connection().await;
+        let mut redis = FakeRedis::default();
+        let mut config = config();
+        config.login_max_failures_per_ip = 2;
+
+        record_failure(&db, &mut redis, &config, "alice@example.com", Some("10.0.0.1")).await.unwrap();
+        let locked = record_failure(&db, &mut redis, &config, "bob@example.com", Some("10.0.0.1")).await.unwrap();
+        assert_eq!(locked, Some(30));
+        assert_eq!(check(&mut redis, "carol@example.com", Some("10.0.0.1")).await.unwrap(), Some(30));
+        assert_eq!(check(&mut redis, "carol@example.com", Some("10.0.0.2")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn success_clears_the_account_counter_but_not_the_ip_counter() {
+        let db = create_test_connection().await;
+        let mut redis = FakeRedis::default();
+        let mut config = config();
+        config.login_max_failures_per_ip = 4;
+
+        for _ in 0..2 {
+            record_failure(&db, &mut redis, &config, "alice@example.com", Some("10.0.0.1")).await.unwrap();
+        }
+        record_success(&mut redis, "alice@example.com").await.unwrap();
+
+        // The account starts counting again, while the IP reaches its limit
+        let locked = record_failure(&db, &mut redis, &config, "alice@example.com", Some("10.0.0.1")).await.unwrap();
+        assert_eq!(locked, None);
+        let locked = record_failure(&db, &mut redis, &config, "alice@example.com", Some("10.0.0.1")).await.unwrap();
+        assert_eq!(locked, Some(30));
+        assert_eq!(check(&mut redis, "alice@example.com", None).await.unwrap(), None);
+        assert_eq!(check(&mut redis, "alice@example.com", Some("10.0.0.1")).await.unwrap(), Some(30));
+    }
+}
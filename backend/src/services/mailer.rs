@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::config::Config;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Outgoing mail transport, selected with MAIL_TRANSPORT
+#[async_trait]
+pub trait Mailer: Send + Sync + std::fmt::Debug {
+    async fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer>;
+
+pub fn create_mailer(config: &Config) -> Result<SharedMailer> {
+    match config.mail_transport.as_str() {
+        "log" => Ok(Arc::new(LogMailer { from: config.mail_from.clone() })),
+        "file" => Ok(Arc::new(FileMailer::new(&config.mail_dir, &config.mail_from)?)),
+        "smtp" => Ok(Arc::new(SmtpMailer::new(config)?)),
+        other => anyhow::bail!("Unknown MAIL_TRANSPORT: {}", other),
+    }
+}
+
+// Writes mail to the server log, for local development
+#[derive(Debug)]
+pub struct LogMailer {
+    from: String,
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        tracing::info!(
+            "Mail from {} to {}: {}\n{}",
+            self.from, mail.to, mail.subject, mail.body
+        );
+        Ok(())
+    }
+}
+
+// Writes each mail to its own file in a directory
+#[derive(Debug)]
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    pub fn new(dir: &str, from: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(FileMailer {
+            dir: PathBuf::from(dir),
+            from: from.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        let file_name = format!(
+            "{}_{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            uuid::Uuid::new_v4().simple()
+        );
+        let contents = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            self.from, mail.to, mail.subject, mail.body
+        );
+        tokio::fs::write(self.dir.join(file_name), contents).await?;
+        Ok(())
+    }
+}
+
+// Delivers mail through an SMTP relay
+#[derive(Debug)]
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &Config) -> Result<Self> {
+        let host = config.smtp_host.as_str();
+        let builder = match config.smtp_security.as_str() {
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            other => anyhow::bail!("Unknown SMTP_SECURITY: {}", other),
+        };
+        let mut builder = builder.port(config.smtp_port);
+        if !config.smtp_username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                config.smtp_username.clone(),
+                config.smtp_password.clone(),
+            ));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from: config.mail_from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse()?)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait};
+use anyhow::Result;
+use crate::entity::{group_members, group_members::Entity as GroupMembers};
+use crate::services::message_history::Conversation;
+
+pub async fn is_group_member(db: &DatabaseConnection, group_id: i64, user_id: i64) -> Result<bool> {
+    let member = GroupMembers::find()
+        .filter(group_members::Column::GroupId.eq(group_id))
+        .filter(group_members::Column::UserId.eq(user_id))
+        .one(db)
+        .await?;
+    Ok(member.is_some())
+}
+
+// Owners and admins moderate a group
+pub async fn is_group_admin(db: &DatabaseConnection, group_id: i64, user_id: i64) -> Result<bool> {
+    let member = GroupMembers::find()
+        .filter(group_members::Column::GroupId.eq(group_id))
+        .filter(group_members::Column::UserId.eq(user_id))
+        .one(db)
+        .await?;
+    Ok(member.is_some_and(|member| matches!(member.role.as_str(), "owner" | "admin")))
+}
+
+// Whether a user may read and post in a conversation: one of the two sides
+// of a direct conversation, or a member of the group. Every message read,
+// message write and member listing goes through this check.
+pub async fn can_access(db: &DatabaseConnection, user_id: i64, conversation: &Conversation) -> Result<bool> {
+    match conversation {
+        Conversation::Direct { user_id: a, other_id: b } => Ok(user_id == *a || user_id == *b),
+        Conversation::Group(group_id) => is_group_member(db, *group_id, user_id).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{ActiveModelTrait, ModelTrait, Set};
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::entity::groups;
+
+    async fn group(db: &DatabaseConnection, owner_id: i64) -> i64 {
+        groups::ActiveModel {
+            name: Set("team".to_string()),
+            owner_id: Set(owner_id),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+        .id
+    }
+
+    async fn join(db: &DatabaseConnection, group_id: i64, user_id: i64, role: &str) -> group_members::Model {
+        group_members::ActiveModel {
+            group_id: Set(group_id),
+            user_id: Set(user_id),
+            role: Set(role.to_string()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_current_members_can_access_a_group() {
+        let db = create_test_connection().await;
+        let alice = create_test_user(&db, "alice").await.id;
+        let bob = create_test_user(&db, "bob").await.id;
+        let carol = create_test_user(&db, "carol").await.id;
+        let team = group(&db, alice).await;
+        let other = group(&db, carol).await;
+        join(&db, team, alice, "owner").await;
+        let bob_membership = join(&db, team, bob, "member").await;
+        join(&db, other, carol, "owner").await;
+
+        assert!(can_access(&db, alice, &Conversation::Group(team)).await.unwrap());
+        assert!(can_access(&db, bob, &Conversation::Group(team)).await.unwrap());
+        assert!(!can_access(&db, carol, &Conversation::Group(team)).await.unwrap());
+        assert!(!can_access(&db, alice, &Conversation::Group(other)).await.unwrap());
+        assert!(!can_access(&db, alice, &Conversation::Group(other + 100)).await.unwrap());
+
+        // Leaving removes the membership and with it the access
+        bob_membership.delete(&db).await.unwrap();
+        assert!(!can_access(&db, bob, &Conversation::Group(team)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_the_two_sides_can_access_a_direct_conversation() {
+        let db = create_test_connection().await;
+        let conversation = Conversation::Direct { user_id: 1, other_id: 2 };
+        assert!(can_access(&db, 1, &conversation).await.unwrap());
+        assert!(can_access(&db, 2, &conversation).await.unwrap());
+        assert!(!can_access(&db, 3, &conversation).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn owners_and_admins_moderate_a_group() {
+        let db = create_test_connection().await;
+        let alice = create_test_user(&db, "alice").await.id;
+        let bob = create_test_user(&db, "bob").await.id;
+        let carol = create_test_user(&db, "carol").await.id;
+        let dave = create_test_user(&db, "dave").await.id;
+        let team = group(&db, alice).await;
+        join(&db, team, alice, "owner").await;
+        join(&db, team, bob, "admin").await;
+        join(&db, team, carol, "member").await;
+
+        assert!(is_group_admin(&db, team, alice).await.unwrap());
+        assert!(is_group_admin(&db, team, bob).await.unwrap());
+        assert!(!is_group_admin(&db, team, carol).await.unwrap());
+        assert!(!is_group_admin(&db, team, dave).await.unwrap());
+    }
+}
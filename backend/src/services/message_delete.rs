@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait, TransactionTrait, SqlErr};
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::{messages, message_edits, message_edits::Entity as MessageEdits, message_hides};
+use crate::services::{membership, message_dispatch};
+use crate::services::message_history::Conversation;
+use crate::websocket::{send_to_users, Clients, WsEvent};
+
+pub enum RecallOutcome {
+    Recalled(Box<messages::Model>),
+    NotSender,
+    NotMember,
+    WindowClosed,
+}
+
+// message_deleted event. content says whether the message is gone for
+// everyone or only for the user who deleted it; user_id is who deleted it.
+fn deleted_event(user_id: i64, message: &messages::Model, everyone: bool) -> WsEvent {
+    WsEvent {
+        event_type: "message_deleted".to_string(),
+        user_id,
+        receiver_id: message.receiver_id,
+        group_id: message.group_id,
+        content: Some(if everyone { "everyone" } else { "me" }.to_string()),
+        data: serde_json::to_value(message).ok(),
+    }
+}
+
+// Hide a message from one user's history; it is untouched for everyone
+// else. Callers check that the user can read the conversation.
+pub async fn delete_for_me(db: &DatabaseConnection, clients: &Clients, user_id: i64, message: &messages::Model) -> Result<()> {
+    let inserted = message_hides::ActiveModel {
+        message_id: Set(message.id),
+        user_id: Set(user_id),
+        created_at: Set(Some(chrono::Utc::now().naive_utc())),
+        ..Default::default()
+    }
+    .insert(db)
+    .await;
+    match inserted {
+        Ok(_) => {}
+        // Already deleted for this user
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+
+    // The user's other devices drop it too
+    send_to_users(clients, &HashSet::from([user_id]), &deleted_event(user_id, message, false)).await;
+    Ok(())
+}
+
+// Recall a message for everyone. The sender may do this within the recall
+// window, group owners and admins at any time. The row stays as a tombstone
+// with its content and edit history removed, so the conversation shows where
+// the message was.
+pub async fn recall(
+    db: &DatabaseConnection,
+    clients: &Clients,
+    config: &Config,
+    user_id: i64,
+    message: messages::Model,
+) -> Result<RecallOutcome> {
+    let conversation = Conversation::of(&message);
+    let moderator = match conversation {
+        Conversation::Group(group_id) => membership::is_group_admin(db, group_id, user_id).await?,
+        Conversation::Direct { .. } => false,
+    };
+    if !moderator {
+        if message.sender_id != user_id {
+            return Ok(RecallOutcome::NotSender);
+        }
+        if !membership::can_access(db, user_id, &conversation).await? {
+            return Ok(RecallOutcome::NotMember);
+        }
+    }
+    // Recalling again is a no-op, but only for someone allowed to recall it
+    if message.deleted_at.is_some() {
+        return Ok(RecallOutcome::Recalled(Box::new(message)));
+    }
+    if !moderator {
+        let now = chrono::Utc::now().naive_utc();
+        if message.created_at.is_none_or(|created_at| now - created_at > config.message_recall_window()) {
+            return Ok(RecallOutcome::WindowClosed);
+        }
+    }
+
+    let txn = db.begin().await?;
+    MessageEdits::delete_many()
+        .filter(message_edits::Column::MessageId.eq(message.id))
+        .exec(&txn)
+        .await?;
+    let mut message_active: messages::ActiveModel = message.into();
+    message_active.content = Set(String::new());
+    message_active.deleted_at = Set(Some(chrono::Utc::now().naive_utc()));
+    let message = message_active.update(&txn).await?;
+    txn.commit().await?;
+
+    match message_dispatch::recipients(db, &message).await {
+        Ok(user_ids) => send_to_users(clients, &user_ids, &deleted_event(user_id, &message, true)).await,
+        Err(e) => tracing::warn!("Failed to find recipients of message {}: {:?}", message.id, e),
+    }
+    Ok(RecallOutcome::Recalled(Box::new(message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::websocket::create_clients;
+
+    async fn message(db: &DatabaseConnection, sender_id: i64, receiver_id: i64) -> messages::Model {
+        messages::ActiveModel {
+            sender_id: Set(sender_id),
+            receiver_id: Set(Some(receiver_id)),
+            content: Set("hi".to_string()),
+            message_type: Set("text".to_string()),
+            created_at: Set(Some(chrono::Utc::now().naive_utc())),
+            is_read: Set(false),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn recalls_leave_an_empty_tombstone() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let original = message(&db, alice.id, bob.id).await;
+
+        let outcome = recall(&db, &clients, &config, bob.id, original.clone()).await.unwrap();
+        assert!(matches!(outcome, RecallOutcome::NotSender));
+
+        let outcome = recall(&db, &clients, &config, alice.id, original).await.unwrap();
+        let RecallOutcome::Recalled(recalled) = outcome else { panic!("message was not recalled") };
+        assert!(recalled.deleted_at.is_some());
+        assert_eq!(recalled.content, "");
+    }
+
+    #[tokio::test]
+    async fn recalled_messages_are_only_returned_to_those_allowed_to_recall_them() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let mut config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let carol = create_test_user(&db, "carol").await;
+        let original = message(&db, alice.id, bob.id).await;
+        let RecallOutcome::Recalled(recalled) = recall(&db, &clients, &config, alice.id, original).await.unwrap() else {
+            panic!("message was not recalled")
+        };
+
+        let outcome = recall(&db, &clients, &config, carol.id, (*recalled).clone()).await.unwrap();
+        assert!(matches!(outcome, RecallOutcome::NotSender));
+
+        // Recalling again stays a no-op for the sender after the window closes
+        config.message_recall_window_seconds = 0;
+        let outcome = recall(&db, &clients, &config, alice.id, (*recalled).clone()).await.unwrap();
+        assert!(matches!(outcome, RecallOutcome::Recalled(_)));
+    }
+}
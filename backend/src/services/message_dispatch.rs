@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, ColumnTrait, Set, ActiveModelTrait};
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::{group_members, group_members::Entity as GroupMembers, messages, users::Entity as Users};
+use crate::models::SendMessageRequest;
+use crate::services::{blocks, email_verification, membership};
+use crate::services::message_history::Conversation;
+use crate::websocket::{Clients, WsEvent};
+
+pub enum SendOutcome {
+    Sent(Box<messages::Model>),
+    MissingTarget,
+    // Direct messages need an existing account other than the sender
+    UnknownReceiver,
+    SelfMessage,
+    NotMember,
+    Blocked,
+    EmailUnverified,
+}
+
+// Persist a message and deliver the stored row. Used by POST
+// /api/messages/send and the WebSocket "message" event, so both apply the
+// same checks and clients always receive the server id and timestamp.
+// origin_conn is the WebSocket connection the message came from, if any; it
+// gets the message back as a message_sent acknowledgement instead.
+pub async fn send(
+    db: &DatabaseConnection,
+    clients: &Clients,
+    config: &Config,
+    sender_id: i64,
+    origin_conn: Option<&str>,
+    request: SendMessageRequest,
+) -> Result<SendOutcome> {
+    // A receiver takes precedence when both are given
+    let conversation = match (request.receiver_id, request.group_id) {
+        (Some(receiver_id), _) => Conversation::Direct { user_id: sender_id, other_id: receiver_id },
+        (None, Some(group_id)) => Conversation::Group(group_id),
+        (None, None) => return Ok(SendOutcome::MissingTarget),
+    };
+
+    let group_id = match conversation {
+        Conversation::Direct { other_id, .. } => {
+            if other_id == sender_id {
+                return Ok(SendOutcome::SelfMessage);
+            }
+            if Users::find_by_id(other_id).one(db).await?.is_none() {
+                return Ok(SendOutcome::UnknownReceiver);
+            }
+            if blocks::is_blocked_between(db, sender_id, other_id).await? {
+                return Ok(SendOutcome::Blocked);
+            }
+            if !email_verification::can_message_user(db, config, sender_id, other_id).await? {
+                return Ok(SendOutcome::EmailUnverified);
+            }
+            None
+        }
+        Conversation::Group(group_id) => {
+            if !membership::can_access(db, sender_id, &conversation).await? {
+                return Ok(SendOutcome::NotMember);
+            }
+            Some(group_id)
+        }
+    };
+
+    let message = messages::ActiveModel {
+        sender_id: Set(sender_id),
+        receiver_id: Set(request.receiver_id),
+        group_id: Set(group_id),
+        content: Set(request.content),
+        message_type: Set(request.message_type),
+        created_at: Set(Some(chrono::Utc::now().naive_utc())),
+        is_read: Set(false),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    // The message is stored; a failed fan-out must not turn it into an error
+    if let Err(e) = deliver(db, clients, &message, origin_conn).await {
+        tracing::warn!("Failed to deliver message {}: {:?}", message.id, e);
+    }
+
+    Ok(SendOutcome::Sent(Box::new(message)))
+}
+
+// Users who should see a message: both sides of a direct message, or every
+// member of the group. Includes the sender for their other devices.
+pub async fn recipients(db: &DatabaseConnection, message: &messages::Model) -> Result<HashSet<i64>> {
+    let mut user_ids = HashSet::from([message.sender_id]);
+    if let Some(receiver_id) = message.receiver_id {
+        user_ids.insert(receiver_id);
+    } else if let Some(group_id) = message.group_id {
+        let members: Vec<i64> = GroupMembers::find()
+            .select_only()
+            .column(group_members::Column::UserId)
+            .filter(group_members::Column::GroupId.eq(group_id))
+            .into_tuple()
+            .all(db)
+            .await?;
+        user_ids.extend(members);
+    }
+    Ok(user_ids)
+}
+
+// Tell everyone in a message's conversation, on every connection, that the
+// message changed. The change itself already succeeded.
+pub async fn notify(db: &DatabaseConnection, clients: &Clients, event_type: &str, message: &messages::Model) {
+    let user_ids = match recipients(db, message).await {
+        Ok(user_ids) => user_ids,
+        Err(e) => {
+            tracing::warn!("Failed to find recipients of message {}: {:?}", message.id, e);
+            return;
+        }
+    };
+    let payload = event(event_type, message);
+
+    let clients_lock = clients.lock().await;
+    for entry in clients_lock.values() {
+        if user_ids.contains(&entry.user_id) {
+            let _ = entry.sender.send(payload.clone());
+        }
+    }
+}
+
+fn event(event_type: &str, message: &messages::Model) -> String {
+    serde_json::to_string(&WsEvent {
+        event_type: event_type.to_string(),
+        user_id: message.sender_id,
+        receiver_id: message.receiver_id,
+        group_id: message.group_id,
+        content: Some(message.content.clone()),
+        data: serde_json::to_value(message).ok(),
+    }).unwrap()
+}
+
+async fn deliver(db: &DatabaseConnection, clients: &Clients, message: &messages::Model, origin_conn: Option<&str>) -> Result<()> {
+    let user_ids = recipients(db, message).await?;
+    let payload = event("message", message);
+    let ack = event("message_sent", message);
+
+    let clients_lock = clients.lock().await;
+    for (conn_id, entry) in clients_lock.iter() {
+        if Some(conn_id.as_str()) == origin_conn {
+            let _ = entry.sender.send(ack.clone());
+        } else if user_ids.contains(&entry.user_id) {
+            let _ = entry.sender.send(payload.clone());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::entity::{messages::Entity as Messages, users};
+    use crate::services::bot;
+    use crate::websocket::create_clients;
+    use sea_orm::PaginatorTrait;
+
+    fn direct(receiver_id: i64) -> SendMessageRequest {
+        SendMessageRequest {
+            receiver_id: Some(receiver_id),
+            group_id: None,
+            content: "hello".to_string(),
+            message_type: "text".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn direct_messages_need_another_existing_user() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+
+        let outcome = send(&db, &clients, &config, alice.id, None, direct(alice.id + 100)).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::UnknownReceiver));
+        let outcome = send(&db, &clients, &config, alice.id, None, direct(alice.id)).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::SelfMessage));
+        assert_eq!(Messages::find().count(&db).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn blocks_stop_direct_messages_either_way() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        blocks::block(&db, &clients, alice.id, bob.id).await.unwrap();
+
+        let outcome = send(&db, &clients, &config, alice.id, None, direct(bob.id)).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::Blocked));
+        let outcome = send(&db, &clients, &config, bob.id, None, direct(alice.id)).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::Blocked));
+        assert_eq!(Messages::find().count(&db).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn bots_can_be_messaged_by_verified_users() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let mut config = Config::from_env();
+        config.unverified_can_message_strangers = false;
+        let alice = create_test_user(&db, "alice").await;
+        let helper = bot::create(&db, alice.id, "helper").await.unwrap();
+
+        let outcome = send(&db, &clients, &config, alice.id, None, direct(helper.id)).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::Sent(_)));
+
+        let mut alice: users::ActiveModel = alice.into();
+        alice.email_verified = Set(false);
+        let alice = alice.update(&db).await.unwrap();
+        let outcome = send(&db, &clients, &config, alice.id, None, direct(helper.id)).await.unwrap();
+        assert!(matches!(outcome, SendOutcome::EmailUnverified));
+    }
+
+    #[tokio::test]
+    async fn direct_messages_are_stored_for_existing_users() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+
+        let outcome = send(&db, &clients, &config, alice.id, None, direct(bob.id)).await.unwrap();
+        let SendOutcome::Sent(message) = outcome else { panic!("message was not sent") };
+        assert_eq!(message.sender_id, alice.id);
+        assert_eq!(message.receiver_id, Some(bob.id));
+        assert_eq!(Messages::find().count(&db).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, ColumnTrait, Set, ActiveModelTrait, TransactionTrait};
+use sea_orm::sea_query::Expr;
+use anyhow::{anyhow, Result};
+use crate::config::Config;
+use crate::entity::{messages, messages::Entity as Messages, message_edits, message_edits::Entity as MessageEdits};
+use crate::services::{membership, message_dispatch};
+use crate::services::message_history::Conversation;
+use crate::websocket::Clients;
+
+pub enum EditOutcome {
+    Edited(Box<messages::Model>),
+    NotSender,
+    NotMember,
+    WindowClosed,
+    Recalled,
+}
+
+// Replace the content of a message, keeping the old text as a revision.
+// Only the sender may edit, only within the configured window, and only
+// while still in the conversation.
+pub async fn edit(
+    db: &DatabaseConnection,
+    clients: &Clients,
+    config: &Config,
+    user_id: i64,
+    message: messages::Model,
+    content: String,
+) -> Result<EditOutcome> {
+    if message.sender_id != user_id {
+        return Ok(EditOutcome::NotSender);
+    }
+    if message.deleted_at.is_some() {
+        return Ok(EditOutcome::Recalled);
+    }
+    if !membership::can_access(db, user_id, &Conversation::of(&message)).await? {
+        return Ok(EditOutcome::NotMember);
+    }
+    let now = chrono::Utc::now().naive_utc();
+    if message.created_at.is_none_or(|created_at| now - created_at > config.message_edit_window()) {
+        return Ok(EditOutcome::WindowClosed);
+    }
+    if message.content == content {
+        return Ok(EditOutcome::Edited(Box::new(message)));
+    }
+
+    // The message was loaded outside the transaction; only touch it if it
+    // has not been recalled since, so an edit never overwrites a tombstone
+    let txn = db.begin().await?;
+    let updated = Messages::update_many()
+        .col_expr(messages::Column::Content, Expr::value(content))
+        .col_expr(messages::Column::EditedAt, Expr::value(now))
+        .filter(messages::Column::Id.eq(message.id))
+        .filter(messages::Column::DeletedAt.is_null())
+        .exec(&txn)
+        .await?;
+    if updated.rows_affected == 0 {
+        return Ok(EditOutcome::Recalled);
+    }
+    message_edits::ActiveModel {
+        message_id: Set(message.id),
+        content: Set(message.content.clone()),
+        created_at: Set(message.edited_at.or(message.created_at)),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+    let message = Messages::find_by_id(message.id)
+        .one(&txn)
+        .await?
+        .ok_or_else(|| anyhow!("Message {} disappeared while editing", message.id))?;
+    txn.commit().await?;
+
+    message_dispatch::notify(db, clients, "message_edited", &message).await;
+    Ok(EditOutcome::Edited(Box::new(message)))
+}
+
+// Earlier revisions of a message, oldest first
+pub async fn revisions(db: &DatabaseConnection, message_id: i64) -> Result<Vec<message_edits::Model>> {
+    Ok(MessageEdits::find()
+        .filter(message_edits::Column::MessageId.eq(message_id))
+        .order_by_asc(message_edits::Column::Id)
+        .all(db)
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::services::message_delete::{self, RecallOutcome};
+    use crate::websocket::create_clients;
+
+    async fn message(db: &DatabaseConnection, sender_id: i64, receiver_id: i64) -> messages::Model {
+        messages::ActiveModel {
+            sender_id: Set(sender_id),
+            receiver_id: Set(Some(receiver_id)),
+            content: Set("hi".to_string()),
+            message_type: Set("text".to_string()),
+            created_at: Set(Some(chrono::Utc::now().naive_utc())),
+            is_read: Set(false),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn edits_keep_the_previous_text_as_a_revision() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let original = message(&db, alice.id, bob.id).await;
+
+        let outcome = edit(&db, &clients, &config, bob.id, original.clone(), "hey".to_string()).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::NotSender));
+
+        let outcome = edit(&db, &clients, &config, alice.id, original.clone(), "hey".to_string()).await.unwrap();
+        let EditOutcome::Edited(edited) = outcome else { panic!("message was not edited") };
+        assert_eq!(edited.content, "hey");
+        assert!(edited.edited_at.is_some());
+        let revisions = revisions(&db, original.id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].content, "hi");
+    }
+
+    #[tokio::test]
+    async fn stale_edits_do_not_overwrite_a_recall() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let config = Config::from_env();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let loaded = message(&db, alice.id, bob.id).await;
+
+        // The message is recalled after the edit request loaded it
+        let outcome = message_delete::recall(&db, &clients, &config, alice.id, loaded.clone()).await.unwrap();
+        assert!(matches!(outcome, RecallOutcome::Recalled(_)));
+
+        let outcome = edit(&db, &clients, &config, alice.id, loaded.clone(), "hey".to_string()).await.unwrap();
+        assert!(matches!(outcome, EditOutcome::Recalled));
+        let stored = Messages::find_by_id(loaded.id).one(&db).await.unwrap().unwrap();
+        assert!(stored.deleted_at.is_some());
+        assert_eq!(stored.content, "");
+        assert!(revisions(&db, loaded.id).await.unwrap().is_empty());
+    }
+}
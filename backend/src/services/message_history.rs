@@ -0,0 +1,212 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, ColumnTrait, Condition};
+use sea_orm::sea_query::Query;
+use anyhow::Result;
+use crate::entity::{messages, messages::Entity as Messages, message_hides};
+use crate::models::{MessageHistoryQuery, MessagePage};
+
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+
+pub enum Conversation {
+    Direct { user_id: i64, other_id: i64 },
+    Group(i64),
+}
+
+impl Conversation {
+    // The conversation a stored message belongs to. Messages without a
+    // receiver are group messages; one without a group as well matches a
+    // group nobody is a member of.
+    pub fn of(message: &messages::Model) -> Self {
+        match message.receiver_id {
+            Some(receiver_id) => Conversation::Direct { user_id: message.sender_id, other_id: receiver_id },
+            None => Conversation::Group(message.group_id.unwrap_or_default()),
+        }
+    }
+}
+
+// One page of a conversation as viewer_id sees it, keyed on message id. Ids
+// only grow, so unlike created_at (nullable, and equal for messages sent in
+// the same second) they order messages without gaps or duplicates between
+// pages. Messages the viewer deleted for themselves are left out; recalled
+// ones stay as tombstones.
+pub async fn page(db: &DatabaseConnection, viewer_id: i64, conversation: Conversation, params: &MessageHistoryQuery) -> Result<MessagePage> {
+    let mut query = match conversation {
+        Conversation::Direct { user_id, other_id } => Messages::find().filter(
+            Condition::any()
+                .add(
+                    Condition::all()
+                        .add(messages::Column::SenderId.eq(user_id))
+                        .add(messages::Column::ReceiverId.eq(other_id)),
+                )
+                .add(
+                    Condition::all()
+                        .add(messages::Column::SenderId.eq(other_id))
+                        .add(messages::Column::ReceiverId.eq(user_id)),
+                ),
+        ),
+        Conversation::Group(group_id) => Messages::find().filter(messages::Column::GroupId.eq(group_id)),
+    };
+
+    query = query.filter(
+        messages::Column::Id.not_in_subquery(
+            Query::select()
+                .column(message_hides::Column::MessageId)
+                .from(message_hides::Entity)
+                .and_where(message_hides::Column::UserId.eq(viewer_id))
+                .to_owned(),
+        ),
+    );
+
+    // Newer messages when catching up, older ones otherwise
+    let forward = params.after.is_some();
+    query = match (params.before, params.after) {
+        (_, Some(after)) => query.filter(messages::Column::Id.gt(after)).order_by_asc(messages::Column::Id),
+        (Some(before), None) => query.filter(messages::Column::Id.lt(before)).order_by_desc(messages::Column::Id),
+        (None, None) => query.order_by_desc(messages::Column::Id),
+    };
+
+    // Fetch one extra row to learn whether another page follows
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let mut messages = query.limit(limit + 1).all(db).await?;
+    let has_more = messages.len() as u64 > limit;
+    messages.truncate(limit as usize);
+    if !forward {
+        messages.reverse();
+    }
+
+    Ok(MessagePage { messages, has_more })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{ActiveModelTrait, Set};
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::entity::groups;
+
+    async fn message(db: &DatabaseConnection, sender_id: i64, receiver_id: Option<i64>, group_id: Option<i64>) -> i64 {
+        messages::ActiveModel {
+            sender_id: Set(sender_id),
+            receiver_id: Set(receiver_id),
+            group_id: Set(group_id),
+            content: Set("hi".to_string()),
+            message_type: Set("text".to_string()),
+            is_read: Set(false),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+        .id
+    }
+
+    async fn hide(db: &DatabaseConnection, user_id: i64, message_id: i64) {
+        message_hides::ActiveModel {
+            message_id: Set(message_id),
+            user_id: Set(user_id),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    fn query(before: Option<i64>, after: Option<i64>, limit: u64) -> MessageHistoryQuery {
+        MessageHistoryQuery { receiver_id: None, group_id: None, before, after, limit: Some(limit) }
+    }
+
+    fn direct(user_id: i64, other_id: i64) -> Conversation {
+        Conversation::Direct { user_id, other_id }
+    }
+
+    // Seven messages between alice and bob, interleaved with messages from
+    // other conversations, with the third hidden by alice. Returns alice,
+    // bob, the ids alice should see oldest first, and the hidden id.
+    async fn setup(db: &DatabaseConnection) -> (i64, i64, Vec<i64>, i64) {
+        let alice = create_test_user(db, "alice").await.id;
+        let bob = create_test_user(db, "bob").await.id;
+        let carol = create_test_user(db, "carol").await.id;
+        let group = groups::ActiveModel {
+            name: Set("team".to_string()),
+            owner_id: Set(alice),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap()
+        .id;
+
+        let mut ids = Vec::new();
+        for i in 0..7 {
+            let (sender, receiver) = if i % 2 == 0 { (alice, bob) } else { (bob, alice) };
+            ids.push(message(db, sender, Some(receiver), None).await);
+            message(db, alice, Some(carol), None).await;
+            message(db, alice, None, Some(group)).await;
+        }
+        let hidden = ids.remove(2);
+        hide(db, alice, hidden).await;
+        (alice, bob, ids, hidden)
+    }
+
+    fn ids(page: &MessagePage) -> Vec<i64> {
+        page.messages.iter().map(|m| m.id).collect()
+    }
+
+    #[tokio::test]
+    async fn paging_backwards_covers_the_conversation_once() {
+        let db = create_test_connection().await;
+        let (alice, bob, expected, _) = setup(&db).await;
+
+        let latest = page(&db, alice, direct(alice, bob), &query(None, None, 4)).await.unwrap();
+        assert_eq!(ids(&latest), expected[2..]);
+        assert!(latest.has_more);
+
+        let older = page(&db, alice, direct(alice, bob), &query(Some(expected[2]), None, 4)).await.unwrap();
+        assert_eq!(ids(&older), expected[..2]);
+        assert!(!older.has_more);
+
+        // A page that ends exactly at the oldest message has nothing beyond it
+        let exact = page(&db, alice, direct(alice, bob), &query(Some(expected[2]), None, 2)).await.unwrap();
+        assert_eq!(ids(&exact), expected[..2]);
+        assert!(!exact.has_more);
+    }
+
+    #[tokio::test]
+    async fn paging_forwards_covers_the_conversation_once() {
+        let db = create_test_connection().await;
+        let (alice, bob, expected, _) = setup(&db).await;
+
+        let mut seen = Vec::new();
+        let mut after = 0;
+        loop {
+            let next = page(&db, alice, direct(alice, bob), &query(None, Some(after), 2)).await.unwrap();
+            seen.extend(ids(&next));
+            let Some(last) = next.messages.last() else { break };
+            after = last.id;
+            assert_eq!(next.has_more, seen.len() < expected.len());
+            if !next.has_more {
+                break;
+            }
+        }
+        assert_eq!(seen, expected);
+
+        let caught_up = page(&db, alice, direct(alice, bob), &query(None, Some(*expected.last().unwrap()), 2)).await.unwrap();
+        assert!(caught_up.messages.is_empty());
+        assert!(!caught_up.has_more);
+    }
+
+    #[tokio::test]
+    async fn hidden_messages_only_disappear_for_the_user_who_hid_them() {
+        let db = create_test_connection().await;
+        let (alice, bob, expected, hidden) = setup(&db).await;
+
+        for params in [query(None, None, 100), query(None, Some(0), 100), query(Some(hidden + 1), None, 100)] {
+            let alice_page = page(&db, alice, direct(alice, bob), &params).await.unwrap();
+            assert!(!ids(&alice_page).contains(&hidden));
+            let bob_page = page(&db, bob, direct(bob, alice), &params).await.unwrap();
+            assert!(ids(&bob_page).contains(&hidden));
+        }
+
+        let everything = page(&db, alice, direct(alice, bob), &query(None, None, 100)).await.unwrap();
+        assert_eq!(ids(&everything), expected);
+    }
+}
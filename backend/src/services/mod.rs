@@ -0,0 +1,28 @@
+pub mod admin;
+pub mod api_key;
+pub mod avatar;
+pub mod blocks;
+pub mod bot;
+pub mod contacts;
+pub mod custom_status;
+pub mod directory;
+pub mod email_verification;
+#[cfg(test)]
+pub mod fake_redis;
+pub mod login_throttle;
+pub mod mailer;
+pub mod membership;
+pub mod message_delete;
+pub mod message_dispatch;
+pub mod message_edit;
+pub mod message_history;
+pub mod oidc;
+pub mod one_time_token;
+pub mod presence;
+pub mod profile;
+pub mod refresh_token;
+pub mod session;
+pub mod storage;
+pub mod token_denylist;
+pub mod two_factor;
+pub mod ws_ticket;
@@ -0,0 +1,719 @@
+use std::sync::Arc;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use redis::{aio::ConnectionLike, AsyncCommands};
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, RwLock};
+use crate::config::Config;
+use crate::entity::{oidc_identities, oidc_identities::Entity as OidcIdentities, users, users::Entity as Users};
+use crate::models::PresenceStatus;
+use crate::models::validation::{validate_username, USERNAME_MAX_LENGTH, USERNAME_MIN_LENGTH};
+use crate::services::admin;
+use crate::utils::UNUSABLE_PASSWORD;
+
+// OpenID Connect single sign-on using the authorization code flow with PKCE.
+// The provider is discovered from OIDC_ISSUER_URL on first use. External
+// subjects are linked to users rows through oidc_identities.
+
+pub type SharedOidcClient = Arc<OidcClient>;
+
+// Only signatures from the provider's published public keys are accepted
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+    Algorithm::ES256, Algorithm::ES384, Algorithm::EdDSA,
+];
+
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+// What we remember between redirecting to the provider and its callback.
+// binding_hash ties the login to the client that started it: the code and
+// state travel through URLs and can leak, the binding never leaves it.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingLogin {
+    code_verifier: String,
+    nonce: String,
+    binding_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct OidcClient {
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    http: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+fn state_key(state: &str) -> String {
+    format!("oidc_state:{}", state)
+}
+
+fn random_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+fn hash_binding(binding: &str) -> String {
+    format!("{:x}", Sha256::digest(binding.as_bytes()))
+}
+
+impl OidcClient {
+    // None when single sign-on is not configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.oidc_issuer_url.is_empty() {
+            return None;
+        }
+
+        Some(OidcClient {
+            issuer_url: config.oidc_issuer_url.trim_end_matches('/').to_string(),
+            client_id: config.oidc_client_id.clone(),
+            client_secret: Some(config.oidc_client_secret.clone()).filter(|s| !s.is_empty()),
+            redirect_uri: config.oidc_redirect_uri.clone(),
+            scopes: config.oidc_scopes.clone(),
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            discovery: OnceCell::new(),
+            jwks: RwLock::new(None),
+        })
+    }
+
+    async fn discovery(&self) -> Result<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer_url);
+                let discovery: Discovery = self.http.get(&url).send().await?.error_for_status()?.json().await
+                    .with_context(|| format!("Failed to fetch {}", url))?;
+                Ok(discovery)
+            })
+            .await
+    }
+
+    // Build the provider URL to send the browser to, remembering the PKCE
+    // verifier and nonce under the state parameter. Also returns the binding
+    // the client must present with the callback.
+    pub async fn authorization_url<C: ConnectionLike + Send>(&self, redis: &mut C, ttl: chrono::Duration) -> Result<(String, String)> {
+        let discovery = self.discovery().await?;
+
+        let state = random_token();
+        let binding = random_token();
+        let pending = PendingLogin {
+            code_verifier: random_token(),
+            nonce: random_token(),
+            binding_hash: hash_binding(&binding),
+        };
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.code_verifier.as_bytes()));
+
+        let secs = ttl.num_seconds().max(1) as u64;
+        redis.set_ex::<_, _, ()>(state_key(&state), serde_json::to_string(&pending)?, secs).await?;
+
+        let url = reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("scope", self.scopes.as_str()),
+                ("state", state.as_str()),
+                ("nonce", pending.nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )?;
+
+        Ok((url.into(), binding))
+    }
+
+    // Redeem the authorization code and return the verified ID token claims.
+    // Ok(None) means the state is unknown, expired or was already used, or
+    // the binding is not the one handed out with it.
+    pub async fn exchange_code<C: ConnectionLike + Send>(
+        &self,
+        redis: &mut C,
+        code: &str,
+        state: &str,
+        binding: &str,
+    ) -> Result<Option<IdTokenClaims>> {
+        let (pending, _): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(state_key(state))
+            .del(state_key(state))
+            .query_async(redis)
+            .await?;
+        let Some(pending) = pending else {
+            return Ok(None);
+        };
+        let pending: PendingLogin = serde_json::from_str(&pending)?;
+        // The state is spent either way, so a leaked one cannot be retried
+        if hash_binding(binding) != pending.binding_hash {
+            return Ok(None);
+        }
+
+        let discovery = self.discovery().await?;
+        let mut request = self.http.post(&discovery.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ]);
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, Some(secret));
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("Token endpoint returned {}: {}", status, body);
+        }
+        let tokens: TokenResponse = response.json().await?;
+
+        let claims = self.verify_id_token(&tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            bail!("ID token nonce does not match");
+        }
+
+        Ok(Some(claims))
+    }
+
+    async fn verify_id_token(&self, id_token: &str) -> Result<IdTokenClaims> {
+        let discovery = self.discovery().await?;
+        let header = decode_header(id_token)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            bail!("Unsupported ID token algorithm {:?}", header.alg);
+        }
+
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&discovery.issuer]);
+
+        Ok(decode::<IdTokenClaims>(id_token, &key, &validation)?.claims)
+    }
+
+    // Keys are cached and refetched once when a token names an unknown kid,
+    // which is how providers roll their signing keys
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        if let Some(key) = self.cached_key(kid).await? {
+            return Ok(key);
+        }
+
+        let discovery = self.discovery().await?;
+        let jwks: JwkSet = self.http.get(&discovery.jwks_uri).send().await?.error_for_status()?.json().await?;
+        *self.jwks.write().await = Some(jwks);
+
+        self.cached_key(kid).await?
+            .ok_or_else(|| anyhow!("No provider key matches the ID token"))
+    }
+
+    async fn cached_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>> {
+        let jwks = self.jwks.read().await;
+        let Some(jwks) = jwks.as_ref() else {
+            return Ok(None);
+        };
+        let jwk = match kid {
+            Some(kid) => jwks.find(kid),
+            // Providers with a single key may omit the kid
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        };
+        jwk.map(|jwk| DecodingKey::from_jwk(jwk).map_err(Into::into)).transpose()
+    }
+}
+
+#[derive(Debug)]
+pub enum Resolution {
+    User(Box<users::Model>),
+    // First login of an unknown subject while OIDC_AUTO_PROVISION is off
+    ProvisioningDisabled,
+    // A local account already uses the email and linking it is not allowed
+    EmailInUse,
+}
+
+// Map verified ID token claims to a local user, linking or creating one on
+// the first login of a subject
+pub async fn resolve_user(db: &DatabaseConnection, config: &Config, claims: &IdTokenClaims) -> Result<Resolution> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let identity = OidcIdentities::find()
+        .filter(oidc_identities::Column::Issuer.eq(&claims.iss))
+        .filter(oidc_identities::Column::Subject.eq(&claims.sub))
+        .one(db)
+        .await?;
+
+    if let Some(identity) = identity {
+        let user_id = identity.user_id;
+        let mut identity_active: oidc_identities::ActiveModel = identity.into();
+        identity_active.email = Set(claims.email.clone());
+        identity_active.last_login_at = Set(Some(now));
+        identity_active.update(db).await?;
+
+        let user = Users::find_by_id(user_id)
+            .one(db)
+            .await?
+            .ok_or_else(|| anyhow!("Linked user {} no longer exists", user_id))?;
+        return Ok(Resolution::User(Box::new(user)));
+    }
+
+    let email_verified = claims.email_verified.unwrap_or(false);
+    let existing = match &claims.email {
+        Some(email) => Users::find().filter(users::Column::Email.eq(email)).one(db).await?,
+        None => None,
+    };
+
+    let user = match existing {
+        // Only trust the provider's word on ownership of a verified address
+        Some(user) if config.oidc_link_by_email && email_verified && !user.is_bot => user,
+        Some(_) => return Ok(Resolution::EmailInUse),
+        None if !config.oidc_auto_provision => return Ok(Resolution::ProvisioningDisabled),
+        None => provision_user(db, claims, email_verified).await?,
+    };
+
+    let identity = oidc_identities::ActiveModel {
+        id: sea_orm::ActiveValue::NotSet,
+        user_id: Set(user.id),
+        issuer: Set(claims.iss.clone()),
+        subject: Set(claims.sub.clone()),
+        email: Set(claims.email.clone()),
+        created_at: Set(Some(now)),
+        last_login_at: Set(Some(now)),
+    };
+    identity.insert(db).await?;
+
+    Ok(Resolution::User(Box::new(user)))
+}
+
+async fn provision_user(db: &DatabaseConnection, claims: &IdTokenClaims, email_verified: bool) -> Result<users::Model> {
+    let username = available_username(db, claims).await?;
+    // users.email is required and is where password resets go, so subjects
+    // without a verified address get an unroutable placeholder instead
+    let email = match &claims.email {
+        Some(email) if email_verified => email.clone(),
+        _ => {
+            let digest = format!("{:x}", Sha256::digest(format!("{}|{}", claims.iss, claims.sub)));
+            format!("{}@oidc.invalid", &digest[..32])
+        }
+    };
+
+    let display_name = claims
+        .name
+        .as_deref()
+        .map(|name| name.trim().chars().take(64).collect::<String>())
+        .filter(|name| !name.is_empty());
+
+    let now = chrono::Utc::now().naive_utc();
+    let user = users::ActiveModel {
+        id: sea_orm::ActiveValue::NotSet,
+        username: Set(username),
+        email: Set(email),
+        password_hash: Set(UNUSABLE_PASSWORD.to_string()),
+        display_name: Set(display_name),
+        bio: Set(None),
+        avatar: Set(None),
+        status: Set(PresenceStatus::Online.as_str().to_string()),
+        last_seen_at: Set(None),
+        status_text: Set(None),
+        status_emoji: Set(None),
+        status_expires_at: Set(None),
+        email_verified: Set(email_verified),
+        totp_secret: Set(None),
+        totp_enabled: Set(false),
+        is_bot: Set(false),
+        owner_id: Set(None),
+        role: Set(admin::ROLE_USER.to_string()),
+        suspended_at: Set(None),
+        created_at: Set(Some(now)),
+        updated_at: Set(Some(now)),
+    };
+
+    Ok(user.insert(db).await?)
+}
+
+// Derive a valid, unused username from the provider's claims
+async fn available_username(db: &DatabaseConnection, claims: &IdTokenClaims) -> Result<String> {
+    let source = claims
+        .preferred_username
+        .clone()
+        .or_else(|| claims.email.as_ref().and_then(|e| e.split('@').next().map(str::to_string)))
+        .or_else(|| claims.name.clone())
+        .unwrap_or_default();
+
+    let mut base: String = source
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') { c } else { '_' })
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .collect();
+    // Leave room for a numeric suffix
+    base.truncate(USERNAME_MAX_LENGTH - 5);
+    if base.len() < USERNAME_MIN_LENGTH {
+        base = format!("user{}", base);
+    }
+
+    let mut candidate = base.clone();
+    for _ in 0..10 {
+        let taken = Users::find()
+            .filter(users::Column::Username.eq(&candidate))
+            .one(db)
+            .await?
+            .is_some();
+        if !taken && validate_username(&candidate).is_ok() {
+            return Ok(candidate);
+        }
+        candidate = format!("{}-{}", base, uuid::Uuid::new_v4().as_u128() % 10_000);
+    }
+
+    bail!("Could not find a free username for {}", source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use salvo::conn::TcpListener;
+    use salvo::prelude::*;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::services::fake_redis::FakeRedis;
+
+    const CLIENT_ID: &str = "im-tt";
+    const CODE: &str = "good-code";
+
+    // Stand-in identity provider serving discovery, the token endpoint and
+    // its JWKS, and signing whatever claims the test asks for next
+    struct Provider {
+        issuer: String,
+        signing_key: EncodingKey,
+        jwk: serde_json::Value,
+        claims: Mutex<serde_json::Value>,
+        code_challenge: Mutex<String>,
+    }
+
+    #[derive(Clone)]
+    struct Stub(Arc<Provider>);
+
+    #[handler]
+    impl Stub {
+        async fn handle(&self, req: &mut Request, res: &mut Response) {
+            let provider = &self.0;
+            match req.uri().path() {
+                "/.well-known/openid-configuration" => res.render(Json(serde_json::json!({
+                    "issuer": provider.issuer,
+                    "authorization_endpoint": format!("{}/authorize", provider.issuer),
+                    "token_endpoint": format!("{}/token", provider.issuer),
+                    "jwks_uri": format!("{}/jwks", provider.issuer),
+                }))),
+                "/jwks" => res.render(Json(serde_json::json!({ "keys": [provider.jwk] }))),
+                "/token" => {
+                    let code = req.form::<String>("code").await.unwrap_or_default();
+                    let verifier = req.form::<String>("code_verifier").await.unwrap_or_default();
+                    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+                    if code != CODE || challenge != *provider.code_challenge.lock().unwrap() {
+                        res.status_code(StatusCode::BAD_REQUEST);
+                        res.render(Json(serde_json::json!({ "error": "invalid_grant" })));
+                        return;
+                    }
+                    res.render(Json(serde_json::json!({ "id_token": provider.id_token() })));
+                }
+                _ => {
+                    res.status_code(StatusCode::NOT_FOUND);
+                }
+            }
+        }
+    }
+
+    impl Provider {
+        async fn start() -> Arc<Provider> {
+            let acceptor = TcpListener::new("127.0.0.1:0").bind().await;
+            let issuer = format!("http://{}", acceptor.local_addr().unwrap());
+
+            let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+            let pem = key.to_pkcs8_pem(LineEnding::LF).unwrap();
+            let provider = Arc::new(Provider {
+                issuer,
+                signing_key: EncodingKey::from_ed_pem(pem.as_bytes()).unwrap(),
+                jwk: serde_json::json!({
+                    "kty": "OKP", "crv": "Ed25519", "alg": "EdDSA", "use": "sig", "kid": "stub-key",
+                    "x": URL_SAFE_NO_PAD.encode(key.verifying_key().to_bytes()),
+                }),
+                claims: Mutex::new(serde_json::json!({})),
+                code_challenge: Mutex::new(String::new()),
+            });
+
+            let router = Router::with_path("{**rest}").goal(Stub(provider.clone()));
+            tokio::spawn(Server::new(acceptor).serve(router));
+            provider
+        }
+
+        fn client(&self) -> OidcClient {
+            let mut config = Config::from_env();
+            config.oidc_issuer_url = self.issuer.clone();
+            config.oidc_client_id = CLIENT_ID.to_string();
+            OidcClient::from_config(&config).unwrap()
+        }
+
+        fn id_token(&self) -> String {
+            let mut claims = serde_json::json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "exp": chrono::Utc::now().timestamp() + 300,
+            });
+            for (name, value) in self.claims.lock().unwrap().as_object().unwrap() {
+                claims[name] = value.clone();
+            }
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some("stub-key".to_string());
+            encode(&header, &claims, &self.signing_key).unwrap()
+        }
+
+        // Start a login and have the provider answer the callback with these
+        // claims plus the nonce it was sent; returns the state parameter and
+        // the client's binding
+        async fn begin(&self, client: &OidcClient, redis: &mut FakeRedis, claims: serde_json::Value) -> (String, String) {
+            let (url, binding) = client.authorization_url(redis, chrono::Duration::minutes(5)).await.unwrap();
+            assert!(!url.contains(&binding));
+            let params: HashMap<String, String> = reqwest::Url::parse(&url).unwrap().query_pairs().into_owned().collect();
+            assert_eq!(params["client_id"], CLIENT_ID);
+            assert_eq!(params["code_challenge_method"], "S256");
+
+            let mut claims = claims;
+            if claims.get("nonce").is_none() {
+                claims["nonce"] = serde_json::json!(params["nonce"]);
+            }
+            *self.claims.lock().unwrap() = claims;
+            *self.code_challenge.lock().unwrap() = params["code_challenge"].clone();
+            (params["state"].clone(), binding)
+        }
+    }
+
+    #[tokio::test]
+    async fn exchange_code_returns_verified_claims_once() {
+        let provider = Provider::start().await;
+        let client = provider.client();
+        let mut redis = FakeRedis::default();
+
+        let (state, binding) = provider
+            .begin(&client, &mut redis, serde_json::json!({ "sub": "alice", "email": "alice@example.com", "email_verified": true }))
+            .await;
+        let claims = client.exchange_code(&mut redis, CODE, &state, &binding).await.unwrap().unwrap();
+        assert_eq!(claims.iss, provider.issuer);
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(claims.email_verified, Some(true));
+
+        // The state is single use
+        assert!(client.exchange_code(&mut redis, CODE, &state, &binding).await.unwrap().is_none());
+        assert!(client.exchange_code(&mut redis, CODE, "unknown", &binding).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn exchange_code_requires_the_binding_of_the_client_that_started_the_login() {
+        let provider = Provider::start().await;
+        let client = provider.client();
+        let mut redis = FakeRedis::default();
+
+        // Someone who only saw the redirect has the code and state, not the binding
+        let (state, binding) = provider.begin(&client, &mut redis, serde_json::json!({ "sub": "alice" })).await;
+        assert!(client.exchange_code(&mut redis, CODE, &state, "").await.unwrap().is_none());
+        // and a failed attempt spends the state
+        assert!(client.exchange_code(&mut redis, CODE, &state, &binding).await.unwrap().is_none());
+
+        let (state, binding) = provider.begin(&client, &mut redis, serde_json::json!({ "sub": "alice" })).await;
+        let (_, other_binding) = provider.begin(&client, &mut redis, serde_json::json!({ "sub": "alice" })).await;
+        assert!(client.exchange_code(&mut redis, CODE, &state, &other_binding).await.unwrap().is_none());
+        assert_ne!(binding, other_binding);
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_a_mismatched_nonce() {
+        let provider = Provider::start().await;
+        let client = provider.client();
+        let mut redis = FakeRedis::default();
+
+        let (state, binding) = provider
+            .begin(&client, &mut redis, serde_json::json!({ "sub": "alice", "nonce": "replayed" }))
+            .await;
+        assert!(client.exchange_code(&mut redis, CODE, &state, &binding).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_tokens_for_another_client() {
+        let provider = Provider::start().await;
+        let client = provider.client();
+        let mut redis = FakeRedis::default();
+
+        let (state, binding) = provider
+            .begin(&client, &mut redis, serde_json::json!({ "sub": "alice", "aud": "someone-else" }))
+            .await;
+        assert!(client.exchange_code(&mut redis, CODE, &state, &binding).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exchange_code_surfaces_token_endpoint_errors() {
+        let provider = Provider::start().await;
+        let client = provider.client();
+        let mut redis = FakeRedis::default();
+
+        let (state, binding) = provider.begin(&client, &mut redis, serde_json::json!({ "sub": "alice" })).await;
+        assert!(client.exchange_code(&mut redis, "wrong-code", &state, &binding).await.is_err());
+    }
+
+    fn claims(sub: &str, email: Option<&str>, email_verified: bool) -> IdTokenClaims {
+        IdTokenClaims {
+            iss: "https://idp.example.com".to_string(),
+            sub: sub.to_string(),
+            nonce: None,
+            email: email.map(str::to_string),
+            email_verified: Some(email_verified),
+            preferred_username: None,
+            name: None,
+        }
+    }
+
+    async fn local_user(db: &DatabaseConnection, username: &str, email: &str, is_bot: bool) -> users::Model {
+        let mut user: users::ActiveModel = create_test_user(db, username).await.into();
+        user.email = Set(email.to_string());
+        user.is_bot = Set(is_bot);
+        user.update(db).await.unwrap()
+    }
+
+    fn resolved(resolution: Resolution) -> users::Model {
+        match resolution {
+            Resolution::User(user) => *user,
+            other => panic!("expected a user, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn verified_email_links_to_the_existing_account() {
+        let db = create_test_connection().await;
+        let config = Config::from_env();
+        let alice = local_user(&db, "alice", "alice@example.com", false).await;
+
+        let user = resolved(resolve_user(&db, &config, &claims("sub-1", Some("alice@example.com"), true)).await.unwrap());
+        assert_eq!(user.id, alice.id);
+
+        // Later logins find the subject directly, even if the email changed
+        let user = resolved(resolve_user(&db, &config, &claims("sub-1", Some("new@example.com"), false)).await.unwrap());
+        assert_eq!(user.id, alice.id);
+    }
+
+    #[tokio::test]
+    async fn unverified_or_unlinkable_emails_are_refused() {
+        let db = create_test_connection().await;
+        let mut config = Config::from_env();
+        local_user(&db, "alice", "alice@example.com", false).await;
+        local_user(&db, "helper", "bot@example.com", true).await;
+
+        let unverified = resolve_user(&db, &config, &claims("sub-1", Some("alice@example.com"), false)).await.unwrap();
+        assert!(matches!(unverified, Resolution::EmailInUse));
+
+        let bot = resolve_user(&db, &config, &claims("sub-2", Some("bot@example.com"), true)).await.unwrap();
+        assert!(matches!(bot, Resolution::EmailInUse));
+
+        config.oidc_link_by_email = false;
+        let unlinked = resolve_user(&db, &config, &claims("sub-3", Some("alice@example.com"), true)).await.unwrap();
+        assert!(matches!(unlinked, Resolution::EmailInUse));
+    }
+
+    #[tokio::test]
+    async fn new_subjects_are_provisioned() {
+        let db = create_test_connection().await;
+        let config = Config::from_env();
+
+        let user = resolved(resolve_user(&db, &config, &claims("sub-1", Some("carol@example.com"), true)).await.unwrap());
+        assert_eq!(user.email, "carol@example.com");
+        assert_eq!(user.username, "carol");
+        assert!(user.email_verified);
+        assert_eq!(user.password_hash, UNUSABLE_PASSWORD);
+    }
+
+    #[tokio::test]
+    async fn unverified_emails_are_not_stored_on_new_accounts() {
+        let db = create_test_connection().await;
+        let config = Config::from_env();
+
+        let user = resolved(resolve_user(&db, &config, &claims("sub-1", Some("victim@example.com"), false)).await.unwrap());
+        assert!(user.email.ends_with("@oidc.invalid"));
+        assert!(!user.email_verified);
+
+        // The address stays free for its real owner
+        let victim = local_user(&db, "owner", "victim@example.com", false).await;
+        assert_ne!(victim.id, user.id);
+    }
+
+    #[tokio::test]
+    async fn provisioning_can_be_disabled() {
+        let db = create_test_connection().await;
+        let mut config = Config::from_env();
+        config.oidc_auto_provision = false;
+
+        let resolution = resolve_user(&db, &config, &claims("sub-1", Some("dave@example.com"), true)).await.unwrap();
+        assert!(matches!(resolution, Resolution::ProvisioningDisabled));
+    }
+
+    #[tokio::test]
+    async fn usernames_are_derived_from_the_claims() {
+        let db = create_test_connection().await;
+
+        let mut named = claims("sub-1", Some("someone@example.com"), true);
+        named.preferred_username = Some("__Jane Doe!".to_string());
+        assert_eq!(available_username(&db, &named).await.unwrap(), "Jane_Doe_");
+
+        let from_email = claims("sub-2", Some("bob@example.com"), true);
+        assert_eq!(available_username(&db, &from_email).await.unwrap(), "bob");
+
+        let short = claims("sub-3", Some("x@example.com"), true);
+        assert_eq!(available_username(&db, &short).await.unwrap(), "userx");
+
+        let anonymous = claims("sub-4", None, false);
+        assert_eq!(available_username(&db, &anonymous).await.unwrap(), "user");
+
+        let mut long = claims("sub-5", None, false);
+        long.preferred_username = Some("a".repeat(64));
+        assert_eq!(available_username(&db, &long).await.unwrap().len(), USERNAME_MAX_LENGTH - 5);
+    }
+
+    #[tokio::test]
+    async fn taken_usernames_get_a_suffix() {
+        let db = create_test_connection().await;
+        local_user(&db, "bob", "bob@example.com", false).await;
+
+        let username = available_username(&db, &claims("sub-1", Some("bob@elsewhere.com"), true)).await.unwrap();
+        assert!(username.starts_with("bob-"), "{}", username);
+        assert!(validate_username(&username).is_ok());
+    }
+}
@@ -0,0 +1,103 @@
+use redis::{aio::ConnectionLike, AsyncCommands};
+use anyhow::Result;
+
+// Single-use tokens mailed to users. Each purpose keeps its own keyspace and
+// only the most recently issued token of a user is valid for that purpose.
+
+#[derive(Debug, Clone, Copy)]
+pub enum Purpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl Purpose {
+    fn prefix(self) -> &'static str {
+        match self {
+            Purpose::PasswordReset => "password_reset",
+            Purpose::EmailVerification => "email_verification",
+        }
+    }
+}
+
+fn token_key(purpose: Purpose, token: &str) -> String {
+    format!("{}:{}", purpose.prefix(), token)
+}
+
+fn user_key(purpose: Purpose, user_id: i64) -> String {
+    format!("{}_user:{}", purpose.prefix(), user_id)
+}
+
+pub async fn create<C: ConnectionLike + Send>(
+    redis: &mut C,
+    purpose: Purpose,
+    user_id: i64,
+    ttl: chrono::Duration,
+) -> Result<String> {
+    let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let secs = ttl.num_seconds() as u64;
+
+    let previous: Option<String> = redis.get(user_key(purpose, user_id)).await?;
+    if let Some(previous) = previous {
+        redis.del::<_, ()>(token_key(purpose, &previous)).await?;
+    }
+
+    redis.set_ex::<_, _, ()>(token_key(purpose, &token), user_id, secs).await?;
+    redis.set_ex::<_, _, ()>(user_key(purpose, user_id), &token, secs).await?;
+
+    Ok(token)
+}
+
+// Return the user the token was issued for and invalidate it
+pub async fn consume<C: ConnectionLike + Send>(redis: &mut C, purpose: Purpose, token: &str) -> Result<Option<i64>> {
+    let (user_id, _): (Option<i64>, i64) = redis::pipe()
+        .atomic()
+        .get(token_key(purpose, token))
+        .del(token_key(purpose, token))
+        .query_async(redis)
+        .await?;
+
+    if let Some(user_id) = user_id {
+        redis.del::<_, ()>(user_key(purpose, user_id)).await?;
+    }
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fake_redis::FakeRedis;
+
+    fn ttl() -> chrono::Duration {
+        chrono::Duration::minutes(30)
+    }
+
+    #[tokio::test]
+    async fn tokens_can_be_used_once() {
+        let mut redis = FakeRedis::default();
+        let token = create(&mut redis, Purpose::PasswordReset, 7, ttl()).await.unwrap();
+
+        assert_eq!(consume(&mut redis, Purpose::PasswordReset, &token).await.unwrap(), Some(7));
+        assert_eq!(consume(&mut redis, Purpose::PasswordReset, &token).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_token_is_accepted() {
+        let mut redis = FakeRedis::default();
+        let first = create(&mut redis, Purpose::PasswordReset, 7, ttl()).await.unwrap();
+        let second = create(&mut redis, Purpose::PasswordReset, 7, ttl()).await.unwrap();
+
+        assert_eq!(consume(&mut redis, Purpose::PasswordReset, &first).await.unwrap(), None);
+        assert_eq!(consume(&mut redis, Purpose::PasswordReset, &second).await.unwrap(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn tokens_expire_and_stay_within_their_purpose() {
+        let mut redis = FakeRedis::default();
+        let token = create(&mut redis, Purpose::PasswordReset, 7, ttl()).await.unwrap();
+        assert_eq!(consume(&mut redis, Purpose::EmailVerification, &token).await.unwrap(), None);
+
+        redis.advance(30 * 60);
+        assert_eq!(consume(&mut redis, Purpose::PasswordReset, &token).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set};
+use anyhow::Result;
+use crate::entity::{users, users::Entity as Users};
+use crate::models::{PresenceResponse, PresenceStatus};
+use crate::services::blocks;
+use crate::websocket::{ClientEntry, Clients, WsEvent};
+
+// How often connections are checked for inactivity
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+// Presence as others see it, from the picked status and idle flag of each
+// connection. All connections of a user carry the same picked status.
+fn effective(connections: impl Iterator<Item = (PresenceStatus, bool)>) -> PresenceStatus {
+    let mut status = None;
+    let mut active = false;
+    for (picked, idle) in connections {
+        status = Some(picked);
+        active |= !idle;
+    }
+    match status {
+        None | Some(PresenceStatus::Invisible) => PresenceStatus::Offline,
+        Some(PresenceStatus::Online) if !active => PresenceStatus::Away,
+        Some(status) => status,
+    }
+}
+
+pub fn presence_of(clients: &HashMap<String, ClientEntry>, user_id: i64) -> PresenceStatus {
+    effective(
+        clients
+            .values()
+            .filter(|entry| entry.user_id == user_id)
+            .map(|entry| (entry.status, entry.idle)),
+    )
+}
+
+// Presence of a user whose connection was just removed, as it was while that
+// connection was still open
+pub fn presence_before_leaving(
+    clients: &HashMap<String, ClientEntry>,
+    user_id: i64,
+    leaving: (PresenceStatus, bool),
+) -> PresenceStatus {
+    effective(
+        clients
+            .values()
+            .filter(|entry| entry.user_id == user_id)
+            .map(|entry| (entry.status, entry.idle))
+            .chain([leaving]),
+    )
+}
+
+fn event(event_type: &str, user_id: i64, status: Option<PresenceStatus>) -> String {
+    serde_json::to_string(&WsEvent {
+        event_type: event_type.to_string(),
+        user_id,
+        receiver_id: None,
+        group_id: None,
+        content: status.map(|status| status.as_str().to_string()),
+        data: None,
+    }).unwrap()
+}
+
+// Events describing a user's presence: online/offline when they cross that
+// line, for clients that only track connectivity, then the presence state
+fn transition_events(user_id: i64, previous: PresenceStatus, current: PresenceStatus) -> Vec<String> {
+    let mut events = Vec::with_capacity(2);
+    if previous == PresenceStatus::Offline {
+        events.push(event("online", user_id, None));
+    } else if current == PresenceStatus::Offline {
+        events.push(event("offline", user_id, None));
+    }
+    events.push(event("presence", user_id, Some(current)));
+    events
+}
+
+// Tell every other connected user, except those hidden by a block, that a
+// user's presence changed. Call with the clients lock held so that events
+// about one user go out in order.
+pub fn announce(
+    clients: &HashMap<String, ClientEntry>,
+    user_id: i64,
+    previous: PresenceStatus,
+    current: PresenceStatus,
+    hidden: &HashSet<i64>,
+) {
+    if previous == current {
+        return;
+    }
+    let events = transition_events(user_id, previous, current);
+    for entry in clients.values() {
+        if entry.user_id != user_id && !hidden.contains(&entry.user_id) {
+            for event in &events {
+                let _ = entry.sender.send(event.clone());
+            }
+        }
+    }
+}
+
+// Tell one connection about everyone else who appears online
+pub fn send_snapshot(clients: &HashMap<String, ClientEntry>, conn_id: &str, hidden: &HashSet<i64>) {
+    let Some(target) = clients.get(conn_id) else {
+        return;
+    };
+    let mut seen = HashSet::from([target.user_id]);
+    for entry in clients.values() {
+        if hidden.contains(&entry.user_id) || !seen.insert(entry.user_id) {
+            continue;
+        }
+        let current = presence_of(clients, entry.user_id);
+        if current != PresenceStatus::Offline {
+            for event in transition_events(entry.user_id, PresenceStatus::Offline, current) {
+                let _ = target.sender.send(event);
+            }
+        }
+    }
+}
+
+fn send_to_user(clients: &HashMap<String, ClientEntry>, user_id: i64, events: &[String]) {
+    for entry in clients.values().filter(|entry| entry.user_id == user_id) {
+        for event in events {
+            let _ = entry.sender.send(event.clone());
+        }
+    }
+}
+
+// Show a user's presence to one other user, e.g. after a block is lifted
+pub fn reveal(clients: &HashMap<String, ClientEntry>, user_id: i64, to_user_id: i64) {
+    let current = presence_of(clients, user_id);
+    if current != PresenceStatus::Offline {
+        send_to_user(clients, to_user_id, &transition_events(user_id, PresenceStatus::Offline, current));
+    }
+}
+
+// Make a user appear offline to one other user, e.g. after a block
+pub fn conceal(clients: &HashMap<String, ClientEntry>, user_id: i64, from_user_id: i64) {
+    let current = presence_of(clients, user_id);
+    if current != PresenceStatus::Offline {
+        send_to_user(clients, from_user_id, &transition_events(user_id, current, PresenceStatus::Offline));
+    }
+}
+
+// Every connected user who does not appear offline
+pub async fn visible(clients: &Clients) -> HashMap<i64, PresenceStatus> {
+    let clients_lock = clients.lock().await;
+    let user_ids: HashSet<i64> = clients_lock.values().map(|entry| entry.user_id).collect();
+    user_ids
+        .into_iter()
+        .map(|user_id| (user_id, presence_of(&clients_lock, user_id)))
+        .filter(|(_, status)| *status != PresenceStatus::Offline)
+        .collect()
+}
+
+pub async fn load_status(db: &DatabaseConnection, user_id: i64) -> Result<PresenceStatus> {
+    let user = Users::find_by_id(user_id).one(db).await?;
+    Ok(user.map(|user| PresenceStatus::parse(&user.status)).unwrap_or_default())
+}
+
+// last_seen_at marks the moment a user appeared or disappeared, so it is only
+// written when presence crosses the offline line. Invisible users keep the
+// time they were last visible.
+pub async fn record_last_seen(db: &DatabaseConnection, user_id: i64, previous: PresenceStatus, current: PresenceStatus) {
+    if (previous == PresenceStatus::Offline) == (current == PresenceStatus::Offline) {
+        return;
+    }
+    let updated = Users::update(users::ActiveModel {
+        id: Set(user_id),
+        last_seen_at: Set(Some(chrono::Utc::now().naive_utc())),
+        ..Default::default()
+    })
+    .exec(db)
+    .await;
+    if let Err(e) = updated {
+        tracing::warn!("Failed to record last seen time of user {}: {:?}", user_id, e);
+    }
+}
+
+// Users hidden from this user's presence because of a block either way
+pub async fn hidden_users(db: &DatabaseConnection, user_id: i64) -> HashSet<i64> {
+    blocks::hidden_user_ids(db, user_id).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to load blocks of user {}: {:?}", user_id, e);
+        HashSet::new()
+    })
+}
+
+// Save the status a user picked and apply it to their open connections
+pub async fn set_status(db: &DatabaseConnection, clients: &Clients, user_id: i64, status: PresenceStatus) -> Result<()> {
+    Users::update(users::ActiveModel {
+        id: Set(user_id),
+        status: Set(status.as_str().to_string()),
+        ..Default::default()
+    })
+    .exec(db)
+    .await?;
+
+    let hidden = hidden_users(db, user_id).await;
+    let (previous, current) = {
+        let mut clients_lock = clients.lock().await;
+        let previous = presence_of(&clients_lock, user_id);
+        for entry in clients_lock.values_mut().filter(|entry| entry.user_id == user_id) {
+            entry.status = status;
+        }
+        let current = presence_of(&clients_lock, user_id);
+        announce(&clients_lock, user_id, previous, current, &hidden);
+
+        // The user's other devices learn the picked status, even invisible
+        send_to_user(&clients_lock, user_id, &[event("presence", user_id, Some(status))]);
+        (previous, current)
+    };
+    record_last_seen(db, user_id, previous, current).await;
+    Ok(())
+}
+
+// Note activity on a connection, bringing its user back from idle
+pub async fn mark_active(db: &DatabaseConnection, clients: &Clients, conn_id: &str) {
+    let user_id = {
+        let mut clients_lock = clients.lock().await;
+        let Some(entry) = clients_lock.get_mut(conn_id) else {
+            return;
+        };
+        entry.last_active = Instant::now();
+        if !entry.idle {
+            return;
+        }
+        entry.user_id
+    };
+
+    let hidden = hidden_users(db, user_id).await;
+    let mut clients_lock = clients.lock().await;
+    let previous = presence_of(&clients_lock, user_id);
+    if let Some(entry) = clients_lock.get_mut(conn_id) {
+        entry.idle = false;
+    }
+    let current = presence_of(&clients_lock, user_id);
+    announce(&clients_lock, user_id, previous, current, &hidden);
+}
+
+// Flag connections without activity for idle_timeout, turning users whose
+// connections are all idle from online to away
+async fn sweep(db: &DatabaseConnection, clients: &Clients, idle_timeout: Duration) {
+    let mut changes = Vec::new();
+    {
+        let mut clients_lock = clients.lock().await;
+        let user_ids: HashSet<i64> = clients_lock
+            .values()
+            .filter(|entry| !entry.idle && entry.last_active.elapsed() >= idle_timeout)
+            .map(|entry| entry.user_id)
+            .collect();
+        for user_id in user_ids {
+            let previous = presence_of(&clients_lock, user_id);
+            for entry in clients_lock.values_mut().filter(|entry| entry.user_id == user_id) {
+                if entry.last_active.elapsed() >= idle_timeout {
+                    entry.idle = true;
+                }
+            }
+            let current = presence_of(&clients_lock, user_id);
+            if previous != current {
+                changes.push((user_id, previous, current));
+            }
+        }
+    }
+
+    for (user_id, previous, current) in changes {
+        let hidden = hidden_users(db, user_id).await;
+        let clients_lock = clients.lock().await;
+        // Skip if the user came back or left while blocks were loading
+        if presence_of(&clients_lock, user_id) == current {
+            announce(&clients_lock, user_id, previous, current, &hidden);
+        }
+    }
+}
+
+pub fn spawn_idle_sweeper(db: DatabaseConnection, clients: Clients, idle_timeout: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweep(&db, &clients, idle_timeout).await;
+        }
+    });
+}
+
+// Presence of the given users as viewer_id sees it, in the order asked.
+// Unknown ids are left out; users hidden by a block appear offline.
+pub async fn lookup(db: &DatabaseConnection, clients: &Clients, viewer_id: i64, user_ids: &[i64]) -> Result<Vec<PresenceResponse>> {
+    let users: HashMap<i64, users::Model> = Users::find()
+        .filter(users::Column::Id.is_in(user_ids.iter().copied()))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+    let hidden = hidden_users(db, viewer_id).await;
+
+    let clients_lock = clients.lock().await;
+    let mut seen = HashSet::new();
+    Ok(user_ids
+        .iter()
+        .filter(|user_id| seen.insert(**user_id))
+        .filter_map(|user_id| users.get(user_id))
+        .map(|user| {
+            if hidden.contains(&user.id) {
+                return PresenceResponse {
+                    user_id: user.id,
+                    status: PresenceStatus::Offline,
+                    last_seen_at: None,
+                };
+            }
+            PresenceResponse {
+                user_id: user.id,
+                status: presence_of(&clients_lock, user.id),
+                last_seen_at: user.last_seen_at,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PresenceStatus::*;
+
+    #[test]
+    fn no_connections_is_offline() {
+        assert_eq!(effective(std::iter::empty()), Offline);
+    }
+
+    #[test]
+    fn invisible_is_offline_to_others() {
+        assert_eq!(effective([(Invisible, false)].into_iter()), Offline);
+        assert_eq!(effective([(Invisible, true)].into_iter()), Offline);
+    }
+
+    #[test]
+    fn online_turns_away_once_every_connection_is_idle() {
+        assert_eq!(effective([(Online, false)].into_iter()), Online);
+        assert_eq!(effective([(Online, true), (Online, false)].into_iter()), Online);
+        assert_eq!(effective([(Online, true), (Online, true)].into_iter()), Away);
+    }
+
+    #[test]
+    fn picked_statuses_ignore_idleness() {
+        for status in [Away, Busy, Dnd] {
+            assert_eq!(effective([(status, true)].into_iter()), status);
+            assert_eq!(effective([(status, false)].into_iter()), status);
+        }
+    }
+
+    #[test]
+    fn presence_is_computed_per_user() {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let entry = |user_id, status, idle| ClientEntry {
+            sender: sender.clone(),
+            user_id,
+            session_id: "session".to_string(),
+            status,
+            last_active: Instant::now(),
+            idle,
+        };
+        let clients = HashMap::from([
+            ("1_a".to_string(), entry(1, Online, true)),
+            ("2_a".to_string(), entry(2, Busy, false)),
+        ]);
+
+        assert_eq!(presence_of(&clients, 1), Away);
+        assert_eq!(presence_of(&clients, 2), Busy);
+        assert_eq!(presence_of(&clients, 3), Offline);
+        // An active connection that just closed still counted a moment ago
+        assert_eq!(presence_before_leaving(&clients, 1, (Online, false)), Online);
+        assert_eq!(presence_before_leaving(&clients, 3, (Online, false)), Online);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, ColumnTrait, Set, ActiveModelTrait, SqlErr};
+use anyhow::Result;
+use crate::entity::{
+    group_members, group_members::Entity as GroupMembers,
+    messages, messages::Entity as Messages,
+    users, users::Entity as Users,
+};
+use crate::models::{PublicProfile, UpdateProfileRequest};
+use crate::services::presence;
+use crate::websocket::{send_to_users, Clients, WsEvent};
+
+pub enum ProfileUpdate {
+    Updated(Box<users::Model>),
+    UsernameTaken,
+}
+
+// Empty strings clear optional text fields
+fn optional_text(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+pub async fn update(db: &DatabaseConnection, user: users::Model, changes: &UpdateProfileRequest) -> Result<ProfileUpdate> {
+    let user_id = user.id;
+    let mut user_active: users::ActiveModel = user.clone().into();
+
+    if let Some(username) = changes.username.as_deref().filter(|username| *username != user.username) {
+        let taken = Users::find()
+            .filter(users::Column::Username.eq(username))
+            .filter(users::Column::Id.ne(user_id))
+            .one(db)
+            .await?;
+        if taken.is_some() {
+            return Ok(ProfileUpdate::UsernameTaken);
+        }
+        user_active.username = Set(username.to_string());
+    }
+    if let Some(display_name) = &changes.display_name {
+        user_active.display_name = Set(optional_text(display_name));
+    }
+    if let Some(bio) = &changes.bio {
+        user_active.bio = Set(optional_text(bio));
+    }
+    user_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+
+    match user_active.update(db).await {
+        Ok(user) => Ok(ProfileUpdate::Updated(Box::new(user))),
+        // Lost a race with another account claiming the same username
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => Ok(ProfileUpdate::UsernameTaken),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn set_avatar(db: &DatabaseConnection, user: users::Model, avatar: Option<String>) -> Result<users::Model> {
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.avatar = Set(avatar);
+    user_active.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
+    Ok(user_active.update(db).await?)
+}
+
+// Users who can see this user's profile in a conversation: members of the
+// same groups and direct message partners. Includes the user, whose other
+// devices need the update too.
+pub async fn peer_ids(db: &DatabaseConnection, user_id: i64) -> Result<HashSet<i64>> {
+    let mut peers = HashSet::from([user_id]);
+
+    let group_ids: Vec<i64> = GroupMembers::find()
+        .select_only()
+        .column(group_members::Column::GroupId)
+        .filter(group_members::Column::UserId.eq(user_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+    if !group_ids.is_empty() {
+        let members: Vec<i64> = GroupMembers::find()
+            .select_only()
+            .column(group_members::Column::UserId)
+            .filter(group_members::Column::GroupId.is_in(group_ids))
+            .distinct()
+            .into_tuple()
+            .all(db)
+            .await?;
+        peers.extend(members);
+    }
+
+    let receivers: Vec<Option<i64>> = Messages::find()
+        .select_only()
+        .column(messages::Column::ReceiverId)
+        .filter(messages::Column::SenderId.eq(user_id))
+        .filter(messages::Column::ReceiverId.is_not_null())
+        .distinct()
+        .into_tuple()
+        .all(db)
+        .await?;
+    peers.extend(receivers.into_iter().flatten());
+
+    let senders: Vec<i64> = Messages::find()
+        .select_only()
+        .column(messages::Column::SenderId)
+        .filter(messages::Column::ReceiverId.eq(user_id))
+        .distinct()
+        .into_tuple()
+        .all(db)
+        .await?;
+    peers.extend(senders);
+
+    Ok(peers)
+}
+
+// Push a profile_updated event with the public profile to connected peers,
+// leaving out anyone on either side of a block
+pub async fn notify_peers(db: &DatabaseConnection, clients: &Clients, user: &users::Model) -> Result<()> {
+    let mut peers = peer_ids(db, user.id).await?;
+    for hidden in presence::hidden_users(db, user.id).await {
+        peers.remove(&hidden);
+    }
+    let event = WsEvent {
+        event_type: "profile_updated".to_string(),
+        user_id: user.id,
+        receiver_id: None,
+        group_id: None,
+        content: None,
+        data: Some(serde_json::to_value(PublicProfile::from(user))?),
+    };
+    send_to_users(clients, &peers, &event).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+    use crate::db::sqlite::{create_test_connection, create_test_user};
+    use crate::entity::blocks;
+    use crate::models::PresenceStatus;
+    use crate::websocket::{create_clients, ClientEntry};
+
+    async fn connect(clients: &Clients, user_id: i64) -> mpsc::UnboundedReceiver<String> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        clients.lock().await.insert(format!("{}_test", user_id), ClientEntry {
+            sender,
+            user_id,
+            session_id: "session".to_string(),
+            status: PresenceStatus::Online,
+            last_active: std::time::Instant::now(),
+            idle: false,
+        });
+        receiver
+    }
+
+    async fn message(db: &DatabaseConnection, sender_id: i64, receiver_id: i64) {
+        messages::ActiveModel {
+            id: sea_orm::ActiveValue::NotSet,
+            sender_id: Set(sender_id),
+            receiver_id: Set(Some(receiver_id)),
+            group_id: Set(None),
+            content: Set("hi".to_string()),
+            message_type: Set("text".to_string()),
+            created_at: Set(None),
+            is_read: Set(false),
+            edited_at: Set(None),
+            deleted_at: Set(None),
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn profile_updates_skip_blocked_peers() {
+        let db = create_test_connection().await;
+        let clients = create_clients();
+        let alice = create_test_user(&db, "alice").await;
+        let bob = create_test_user(&db, "bob").await;
+        let carol = create_test_user(&db, "carol").await;
+        message(&db, bob.id, alice.id).await;
+        message(&db, alice.id, carol.id).await;
+        blocks::ActiveModel {
+            id: sea_orm::ActiveValue::NotSet,
+            blocker_id: Set(carol.id),
+            blocked_id: Set(alice.id),
+            created_at: Set(None),
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let peers = peer_ids(&db, alice.id).await.unwrap();
+        assert_eq!(peers, HashSet::from([alice.id, bob.id, carol.id]));
+
+        let mut bob_socket = connect(&clients, bob.id).await;
+        let mut carol_socket = connect(&clients, carol.id).await;
+        notify_peers(&db, &clients, &alice).await.unwrap();
+
+        let event: serde_json::Value = serde_json::from_str(&bob_socket.try_recv().unwrap()).unwrap();
+        assert_eq!(event["event_type"], "profile_updated");
+        assert_eq!(event["data"]["username"], "alice");
+        assert!(event["data"].get("email").is_none());
+        assert!(carol_socket.try_recv().is_err());
+    }
+}
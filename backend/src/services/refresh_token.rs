@@ -0,0 +1,164 @@
+use redis::{aio::ConnectionLike, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+
+// Refresh tokens are opaque random strings stored in Redis. Every token
+// belongs to a family that starts at login and is identified by the login
+// session id; each refresh consumes the presented token and issues the next
+// one in the same family. Presenting a token that was already consumed
+// revokes the whole family.
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RefreshTokenRecord {
+    user_id: i64,
+    family_id: String,
+}
+
+fn token_key(token: &str) -> String {
+    format!("refresh_token:{}", token)
+}
+
+fn used_key(token: &str) -> String {
+    format!("refresh_token_used:{}", token)
+}
+
+fn family_key(family_id: &str) -> String {
+    format!("refresh_family:{}", family_id)
+}
+
+fn new_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+async fn store_token<C: ConnectionLike + Send>(
+    redis: &mut C,
+    user_id: i64,
+    family_id: &str,
+    ttl: chrono::Duration,
+) -> Result<String> {
+    let token = new_token();
+    let secs = ttl.num_seconds() as u64;
+    let record = serde_json::to_string(&RefreshTokenRecord {
+        user_id,
+        family_id: family_id.to_string(),
+    })?;
+
+    redis.set_ex::<_, _, ()>(family_key(family_id), user_id, secs).await?;
+    redis.set_ex::<_, _, ()>(token_key(&token), record, secs).await?;
+
+    Ok(token)
+}
+
+// Start a new token family for a fresh login session
+pub async fn issue<C: ConnectionLike + Send>(
+    redis: &mut C,
+    user_id: i64,
+    session_id: &str,
+    ttl: chrono::Duration,
+) -> Result<String> {
+    store_token(redis, user_id, session_id, ttl).await
+}
+
+// Consume a refresh token and return the owner, the session id and the next
+// token in its family, or None if the token is unknown, expired, revoked or
+// replayed
+pub async fn rotate<C: ConnectionLike + Send>(
+    redis: &mut C,
+    token: &str,
+    ttl: chrono::Duration,
+) -> Result<Option<(i64, String, String)>> {
+    let raw: Option<String> = redis.get(token_key(token)).await?;
+    let Some(raw) = raw else {
+        return Ok(None);
+    };
+    let record: RefreshTokenRecord = serde_json::from_str(&raw)?;
+
+    let family_alive: bool = redis.exists(family_key(&record.family_id)).await?;
+    if !family_alive {
+        return Ok(None);
+    }
+
+    // SET NX makes consumption atomic, so two concurrent refreshes with the
+    // same token cannot both succeed
+    let first_use: Option<String> = redis::cmd("SET")
+        .arg(used_key(token))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl.num_seconds())
+        .query_async(redis)
+        .await?;
+
+    if first_use.is_none() {
+        tracing::warn!(
+            "Refresh token reuse detected for user {}, revoking family {}",
+            record.user_id, record.family_id
+        );
+        revoke_family(redis, &record.family_id).await?;
+        return Ok(None);
+    }
+
+    let next = store_token(redis, record.user_id, &record.family_id, ttl).await?;
+    Ok(Some((record.user_id, record.family_id, next)))
+}
+
+pub async fn revoke_family<C: ConnectionLike + Send>(redis: &mut C, family_id: &str) -> Result<()> {
+    redis.del::<_, ()>(family_key(family_id)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fake_redis::FakeRedis;
+
+    fn ttl() -> chrono::Duration {
+        chrono::Duration::days(1)
+    }
+
+    #[tokio::test]
+    async fn rotation_issues_a_new_token_in_the_same_family() {
+        let mut redis = FakeRedis::default();
+        let first = issue(&mut redis, 7, "session-1", ttl()).await.unwrap();
+
+        let (user_id, family_id, second) = rotate(&mut redis, &first, ttl()).await.unwrap().unwrap();
+        assert_eq!(user_id, 7);
+        assert_eq!(family_id, "session-1");
+        assert_ne!(second, first);
+
+        assert!(rotate(&mut redis, &second, ttl()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn reusing_a_consumed_token_revokes_the_family() {
+        let mut redis = FakeRedis::default();
+        let first = issue(&mut redis, 7, "session-1", ttl()).await.unwrap();
+        let (_, _, second) = rotate(&mut redis, &first, ttl()).await.unwrap().unwrap();
+
+        // Replaying the consumed token is refused and kills the family, so
+        // the legitimate holder of the newer token is logged out as well
+        assert!(rotate(&mut redis, &first, ttl()).await.unwrap().is_none());
+        assert!(rotate(&mut redis, &second, ttl()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn revoked_families_and_unknown_tokens_are_refused() {
+        let mut redis = FakeRedis::default();
+        let token = issue(&mut redis, 7, "session-1", ttl()).await.unwrap();
+        revoke_family(&mut redis, "session-1").await.unwrap();
+
+        assert!(rotate(&mut redis, &token, ttl()).await.unwrap().is_none());
+        assert!(rotate(&mut redis, "not-a-token", ttl()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn families_are_independent() {
+        let mut redis = FakeRedis::default();
+        let phone = issue(&mut redis, 7, "phone", ttl()).await.unwrap();
+        let laptop = issue(&mut redis, 7, "laptop", ttl()).await.unwrap();
+        rotate(&mut redis, &phone, ttl()).await.unwrap().unwrap();
+
+        assert!(rotate(&mut redis, &phone, ttl()).await.unwrap().is_none());
+        assert!(rotate(&mut redis, &laptop, ttl()).await.unwrap().is_some());
+    }
+}
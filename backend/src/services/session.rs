@@ -0,0 +1,188 @@
+use std::time::Duration;
+use salvo::Request;
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, ColumnTrait, Condition, Set, ActiveModelTrait};
+use sea_orm::sea_query::Expr;
+use redis::aio::MultiplexedConnection;
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::{sessions, sessions::Entity as Sessions};
+use crate::services::{refresh_token, token_denylist};
+use crate::services::login_throttle::client_ip;
+use crate::utils::{create_token, JwtKeys};
+use crate::websocket::{close_connections, Clients};
+
+// Requests on a session record activity at most this often
+const TOUCH_INTERVAL_SECONDS: u64 = 60;
+// How often sessions past the refresh token lifetime are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Client details recorded when a login session is created
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn from_request(req: &Request, device_name: Option<String>) -> Self {
+        let user_agent = req
+            .headers()
+            .get("User-Agent")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| truncate(v, 255));
+        let ip_address = client_ip(req);
+
+        DeviceInfo {
+            device_name: device_name.map(|name| truncate(&name, 100)),
+            user_agent,
+            ip_address,
+        }
+    }
+}
+
+fn truncate(value: &str, max_chars: usize) -> String {
+    value.chars().take(max_chars).collect()
+}
+
+async fn create(
+    db: &DatabaseConnection,
+    session_id: &str,
+    user_id: i64,
+    device: DeviceInfo,
+) -> Result<sessions::Model> {
+    let now = chrono::Utc::now().naive_utc();
+    let new_session = sessions::ActiveModel {
+        id: Set(session_id.to_string()),
+        user_id: Set(user_id),
+        device_name: Set(device.device_name),
+        user_agent: Set(device.user_agent),
+        ip_address: Set(device.ip_address),
+        created_at: Set(Some(now)),
+        last_active_at: Set(Some(now)),
+    };
+
+    Ok(new_session.insert(db).await?)
+}
+
+// Start a new login session: a short-lived access token plus the first
+// refresh token of a new family
+pub async fn start(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    jwt_keys: &JwtKeys,
+    config: &Config,
+    user_id: i64,
+    device: DeviceInfo,
+) -> Result<(String, String)> {
+    let session_id = uuid::Uuid::new_v4().simple().to_string();
+    create(db, &session_id, user_id, device).await?;
+    let token = create_token(user_id, &session_id, jwt_keys, config.access_token_ttl())?;
+    let refresh_token = refresh_token::issue(redis, user_id, &session_id, config.refresh_token_ttl()).await?;
+    Ok((token, refresh_token))
+}
+
+pub async fn touch(db: &DatabaseConnection, session_id: &str) -> Result<()> {
+    Sessions::update_many()
+        .col_expr(sessions::Column::LastActiveAt, Expr::value(chrono::Utc::now().naive_utc()))
+        .filter(sessions::Column::Id.eq(session_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+// Record activity for an authenticated request. A Redis marker limits the
+// database write to once per TOUCH_INTERVAL_SECONDS per session.
+pub async fn touch_throttled(db: &DatabaseConnection, redis: &mut MultiplexedConnection, session_id: &str) -> Result<()> {
+    let first: Option<String> = redis::cmd("SET")
+        .arg(format!("session_touch:{}", session_id))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(TOUCH_INTERVAL_SECONDS)
+        .query_async(redis)
+        .await?;
+    if first.is_some() {
+        touch(db, session_id).await?;
+    }
+    Ok(())
+}
+
+// Sessions whose refresh tokens could still be used
+pub async fn list_active(db: &DatabaseConnection, user_id: i64, config: &Config) -> Result<Vec<sessions::Model>> {
+    let cutoff = chrono::Utc::now().naive_utc() - config.refresh_token_ttl();
+    let sessions = Sessions::find()
+        .filter(sessions::Column::UserId.eq(user_id))
+        .filter(sessions::Column::LastActiveAt.gte(cutoff))
+        .order_by_desc(sessions::Column::LastActiveAt)
+        .all(db)
+        .await?;
+    Ok(sessions)
+}
+
+// Delete sessions idle for longer than a refresh token lives. Their refresh
+// tokens have expired in Redis, so nothing can use them any more.
+async fn prune_expired(db: &DatabaseConnection, refresh_token_ttl: chrono::Duration) -> Result<u64> {
+    let cutoff = chrono::Utc::now().naive_utc() - refresh_token_ttl;
+    let result = Sessions::delete_many()
+        .filter(
+            Condition::any()
+                .add(sessions::Column::LastActiveAt.lt(cutoff))
+                .add(sessions::Column::LastActiveAt.is_null()),
+        )
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+pub fn spawn_prune_task(db: DatabaseConnection, refresh_token_ttl: chrono::Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match prune_expired(&db, refresh_token_ttl).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!("Pruned {} expired sessions", pruned),
+                Err(e) => tracing::error!("Failed to prune expired sessions: {:?}", e),
+            }
+        }
+    });
+}
+
+// End a session everywhere: its access tokens stop being accepted, its
+// refresh token family is revoked and its live sockets are closed
+pub async fn revoke(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    clients: &Clients,
+    config: &Config,
+    session_id: &str,
+) -> Result<()> {
+    token_denylist::revoke_session(redis, session_id, config.access_token_ttl()).await?;
+    refresh_token::revoke_family(redis, session_id).await?;
+    Sessions::delete_by_id(session_id.to_string()).exec(db).await?;
+    close_connections(clients, |entry| entry.session_id == session_id).await;
+    Ok(())
+}
+
+// Revoke every session of a user, e.g. after a password change
+pub async fn revoke_all_for_user(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    clients: &Clients,
+    config: &Config,
+    user_id: i64,
+) -> Result<()> {
+    let sessions = Sessions::find()
+        .filter(sessions::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+
+    for s in sessions {
+        revoke(db, redis, clients, config, &s.id).await?;
+    }
+
+    // Sockets are keyed by session, but close any stragglers for the user too
+    close_connections(clients, |entry| entry.user_id == user_id).await;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use crate::config::Config;
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+}
+
+// Where uploaded files live, selected with STORAGE_BACKEND. Keys are
+// slash-separated relative paths such as "avatars/users/7/<token>/256.png".
+#[async_trait]
+pub trait Storage: Send + Sync + std::fmt::Debug {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()>;
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+    // Public URL clients use to fetch the object
+    fn url(&self, key: &str) -> String;
+    // Inverse of url(), for URLs this storage handed out
+    fn key_from_url(&self, url: &str) -> Option<String>;
+}
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+pub fn create_storage(config: &Config) -> Result<SharedStorage> {
+    match config.storage_backend.as_str() {
+        "local" => Ok(Arc::new(LocalStorage::new(&config.storage_local_dir, &config.storage_public_url)?)),
+        other => anyhow::bail!("Unknown STORAGE_BACKEND: {}", other),
+    }
+}
+
+// Keys must stay inside the storage root
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && Path::new(key)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn content_type_for(key: &str) -> &'static str {
+    match Path::new(key).extension().and_then(|ext| ext.to_str()) {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+// Files in a local directory, served by the backend under /uploads
+#[derive(Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(dir: &str, public_url: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(LocalStorage {
+            root: PathBuf::from(dir),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if !is_valid_key(key) {
+            anyhow::bail!("Invalid storage key: {}", key);
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(StoredObject {
+                bytes,
+                content_type: content_type_for(key).to_string(),
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    fn key_from_url(&self, url: &str) -> Option<String> {
+        let key = url.strip_prefix(&self.public_url)?.strip_prefix('/')?;
+        is_valid_key(key).then(|| key.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_stay_inside_the_root() {
+        assert!(is_valid_key("avatars/users/7/abc/256.png"));
+        assert!(is_valid_key("file.txt"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("../secret"));
+        assert!(!is_valid_key("avatars/../../secret"));
+        assert!(!is_valid_key("/etc/passwd"));
+        assert!(!is_valid_key("./avatars/1.png"));
+        assert!(!is_valid_key(".."));
+    }
+
+    #[test]
+    fn content_type_follows_the_extension() {
+        assert_eq!(content_type_for("a/b.png"), "image/png");
+        assert_eq!(content_type_for("a/b.jpeg"), "image/jpeg");
+        assert_eq!(content_type_for("a/b.webp"), "image/webp");
+        assert_eq!(content_type_for("a/b"), "application/octet-stream");
+    }
+
+    fn local_storage() -> (LocalStorage, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("im-tt-storage-{}", uuid::Uuid::new_v4().simple()));
+        let storage = LocalStorage::new(dir.to_str().unwrap(), "http://localhost:8080/uploads/").unwrap();
+        (storage, dir)
+    }
+
+    #[test]
+    fn urls_round_trip_only_for_this_storage() {
+        let (storage, dir) = local_storage();
+        let url = storage.url("avatars/7/256.png");
+        assert_eq!(url, "http://localhost:8080/uploads/avatars/7/256.png");
+        assert_eq!(storage.key_from_url(&url).as_deref(), Some("avatars/7/256.png"));
+        assert_eq!(storage.key_from_url("https://elsewhere.example.com/avatars/7/256.png"), None);
+        assert_eq!(storage.key_from_url("http://localhost:8080/uploads/../config"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_storage_round_trip() {
+        let (storage, dir) = local_storage();
+        storage.put("avatars/7/256.png", vec![1, 2, 3], "image/png").await.unwrap();
+
+        let object = storage.get("avatars/7/256.png").await.unwrap().unwrap();
+        assert_eq!(object.bytes, vec![1, 2, 3]);
+        assert_eq!(object.content_type, "image/png");
+
+        storage.delete("avatars/7/256.png").await.unwrap();
+        assert!(storage.get("avatars/7/256.png").await.unwrap().is_none());
+        // Deleting twice is fine, escaping the root is not
+        storage.delete("avatars/7/256.png").await.unwrap();
+        assert!(storage.put("../escape.png", vec![1], "image/png").await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use redis::{aio::ConnectionLike, AsyncCommands};
+use anyhow::Result;
+
+// Revoked access tokens are remembered by jti, and revoked sessions by
+// session id, until every token they cover would have expired anyway, so
+// the denylist never grows past one access token lifetime.
+
+fn denylist_key(jti: &str) -> String {
+    format!("revoked_jti:{}", jti)
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("revoked_session:{}", session_id)
+}
+
+pub async fn revoke<C: ConnectionLike + Send>(redis: &mut C, jti: &str, ttl: chrono::Duration) -> Result<()> {
+    let secs = ttl.num_seconds().max(1) as u64;
+    redis.set_ex::<_, _, ()>(denylist_key(jti), 1, secs).await?;
+    Ok(())
+}
+
+pub async fn revoke_session<C: ConnectionLike + Send>(redis: &mut C, session_id: &str, ttl: chrono::Duration) -> Result<()> {
+    let secs = ttl.num_seconds().max(1) as u64;
+    redis.set_ex::<_, _, ()>(session_key(session_id), 1, secs).await?;
+    Ok(())
+}
+
+pub async fn is_revoked<C: ConnectionLike + Send>(redis: &mut C, jti: &str, session_id: &str) -> Result<bool> {
+    let matches: usize = redis.exists(&[denylist_key(jti), session_key(session_id)]).await?;
+    Ok(matches > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fake_redis::FakeRedis;
+
+    #[tokio::test]
+    async fn revoked_tokens_are_rejected_until_they_would_expire() {
+        let mut redis = FakeRedis::default();
+        revoke(&mut redis, "jti-1", chrono::Duration::minutes(15)).await.unwrap();
+
+        assert!(is_revoked(&mut redis, "jti-1", "session-1").await.unwrap());
+        assert!(!is_revoked(&mut redis, "jti-2", "session-1").await.unwrap());
+        assert_eq!(redis.ttl("revoked_jti:jti-1"), 15 * 60);
+
+        redis.advance(15 * 60);
+        assert!(!is_revoked(&mut redis, "jti-1", "session-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn revoked_sessions_cover_every_token_in_them() {
+        let mut redis = FakeRedis::default();
+        revoke_session(&mut redis, "session-1", chrono::Duration::minutes(15)).await.unwrap();
+
+        assert!(is_revoked(&mut redis, "jti-1", "session-1").await.unwrap());
+        assert!(is_revoked(&mut redis, "jti-2", "session-1").await.unwrap());
+        assert!(!is_revoked(&mut redis, "jti-1", "session-2").await.unwrap());
+        assert_eq!(redis.ttl("revoked_session:session-1"), 15 * 60);
+
+        redis.advance(15 * 60 - 1);
+        assert!(is_revoked(&mut redis, "jti-1", "session-1").await.unwrap());
+        redis.advance(1);
+        assert!(!is_revoked(&mut redis, "jti-1", "session-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn entries_live_at_least_a_second() {
+        let mut redis = FakeRedis::default();
+        revoke(&mut redis, "jti-1", chrono::Duration::zero()).await.unwrap();
+        assert_eq!(redis.ttl("revoked_jti:jti-1"), 1);
+    }
+}
@@ -0,0 +1,171 @@
+use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
+use sea_orm::sea_query::Expr;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use anyhow::Result;
+use crate::config::Config;
+use crate::entity::users;
+use crate::entity::{recovery_codes, recovery_codes::Entity as RecoveryCodes};
+use crate::utils::{generate_recovery_codes, generate_totp_secret, hash_recovery_code, totp_matching_step, totp_uri};
+
+// Wrong codes allowed per login challenge before it is thrown away
+const MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+// Long enough to cover every step accepted with clock skew
+const USED_STEP_TTL_SECS: u64 = 120;
+
+fn challenge_key(token: &str) -> String {
+    format!("login_challenge:{}", token)
+}
+
+fn attempts_key(token: &str) -> String {
+    format!("login_challenge_attempts:{}", token)
+}
+
+fn used_step_key(user_id: i64, step: u64) -> String {
+    format!("totp_used_step:{}:{}", user_id, step)
+}
+
+// Challenge handed out after a correct password when a second factor is required
+pub async fn create_challenge(redis: &mut MultiplexedConnection, user_id: i64, ttl: chrono::Duration) -> Result<String> {
+    let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    redis.set_ex::<_, _, ()>(challenge_key(&token), user_id, ttl.num_seconds() as u64).await?;
+    Ok(token)
+}
+
+// Look up the challenge and count this attempt against it; None once the
+// challenge expired or ran out of attempts
+pub async fn check_challenge(redis: &mut MultiplexedConnection, token: &str, ttl: chrono::Duration) -> Result<Option<i64>> {
+    let user_id: Option<i64> = redis.get(challenge_key(token)).await?;
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+
+    let attempts: i64 = redis.incr(attempts_key(token), 1).await?;
+    if attempts == 1 {
+        redis.expire::<_, ()>(attempts_key(token), ttl.num_seconds()).await?;
+    }
+    if attempts > MAX_CHALLENGE_ATTEMPTS {
+        finish_challenge(redis, token).await?;
+        return Ok(None);
+    }
+
+    Ok(Some(user_id))
+}
+
+pub async fn finish_challenge(redis: &mut MultiplexedConnection, token: &str) -> Result<()> {
+    redis.del::<_, ()>(&[challenge_key(token), attempts_key(token)]).await?;
+    Ok(())
+}
+
+// Store a new pending secret; it only takes effect once confirmed with a code
+pub async fn begin_enrollment(db: &DatabaseConnection, config: &Config, user: users::Model) -> Result<(String, String)> {
+    let secret = generate_totp_secret();
+    let uri = totp_uri(&secret, &config.totp_issuer, &user.email)?;
+
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.totp_secret = Set(Some(secret.clone()));
+    user_active.update(db).await?;
+
+    Ok((secret, uri))
+}
+
+// Enable 2FA if the code matches the pending secret and return fresh
+// recovery codes, or None if the code is wrong
+pub async fn confirm_enrollment(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    user: users::Model,
+    code: &str,
+) -> Result<Option<Vec<String>>> {
+    let Some(secret) = user.totp_secret.clone() else {
+        return Ok(None);
+    };
+    if !check_totp(redis, user.id, &secret, code).await? {
+        return Ok(None);
+    }
+
+    let codes = replace_recovery_codes(db, user.id).await?;
+
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.totp_enabled = Set(true);
+    user_active.update(db).await?;
+
+    Ok(Some(codes))
+}
+
+pub async fn disable(db: &DatabaseConnection, user: users::Model) -> Result<()> {
+    RecoveryCodes::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user.id))
+        .exec(db)
+        .await?;
+
+    let mut user_active: users::ActiveModel = user.into();
+    user_active.totp_secret = Set(None);
+    user_active.totp_enabled = Set(false);
+    user_active.update(db).await?;
+    Ok(())
+}
+
+// Accept a current authenticator code or an unused recovery code
+pub async fn verify_code(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    user: &users::Model,
+    code: &str,
+) -> Result<bool> {
+    let Some(secret) = user.totp_secret.as_deref().filter(|_| user.totp_enabled) else {
+        return Ok(false);
+    };
+    if check_totp(redis, user.id, secret, code).await? {
+        return Ok(true);
+    }
+    use_recovery_code(db, user.id, code).await
+}
+
+pub async fn replace_recovery_codes(db: &DatabaseConnection, user_id: i64) -> Result<Vec<String>> {
+    RecoveryCodes::delete_many()
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    let codes = generate_recovery_codes();
+    let now = chrono::Utc::now().naive_utc();
+    let rows = codes.iter().map(|code| recovery_codes::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(hash_recovery_code(code)),
+        used_at: Set(None),
+        created_at: Set(Some(now)),
+        ..Default::default()
+    });
+    RecoveryCodes::insert_many(rows).exec(db).await?;
+
+    Ok(codes)
+}
+
+async fn check_totp(redis: &mut MultiplexedConnection, user_id: i64, secret: &str, code: &str) -> Result<bool> {
+    let Some(step) = totp_matching_step(secret, code)? else {
+        return Ok(false);
+    };
+
+    // Each time step is accepted once, so an observed code cannot be replayed
+    let first_use: Option<String> = redis::cmd("SET")
+        .arg(used_step_key(user_id, step))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(USED_STEP_TTL_SECS)
+        .query_async(redis)
+        .await?;
+    Ok(first_use.is_some())
+}
+
+async fn use_recovery_code(db: &DatabaseConnection, user_id: i64, code: &str) -> Result<bool> {
+    // Conditional update so a code cannot be spent twice concurrently
+    let result = RecoveryCodes::update_many()
+        .col_expr(recovery_codes::Column::UsedAt, Expr::value(chrono::Utc::now().naive_utc()))
+        .filter(recovery_codes::Column::UserId.eq(user_id))
+        .filter(recovery_codes::Column::CodeHash.eq(hash_recovery_code(code)))
+        .filter(recovery_codes::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
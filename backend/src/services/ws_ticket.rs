@@ -0,0 +1,109 @@
+use redis::{aio::ConnectionLike, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use super::token_denylist;
+
+// Short-lived, single-use tickets for opening a WebSocket, so access tokens
+// never have to travel in a URL. A ticket remembers the access token it was
+// minted with and stops working once that token or its session is revoked.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TicketOwner {
+    pub user_id: i64,
+    pub session_id: String,
+    pub jti: String,
+}
+
+fn ticket_key(ticket: &str) -> String {
+    format!("ws_ticket:{}", ticket)
+}
+
+pub async fn issue<C: ConnectionLike + Send>(redis: &mut C, owner: &TicketOwner, ttl: chrono::Duration) -> Result<String> {
+    let ticket = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let secs = ttl.num_seconds().max(1) as u64;
+    redis.set_ex::<_, _, ()>(ticket_key(&ticket), serde_json::to_string(owner)?, secs).await?;
+    Ok(ticket)
+}
+
+// Invalidate the ticket and return who it was issued to, if it is still good
+pub async fn redeem<C: ConnectionLike + Send>(redis: &mut C, ticket: &str) -> Result<Option<TicketOwner>> {
+    let (owner, _): (Option<String>, i64) = redis::pipe()
+        .atomic()
+        .get(ticket_key(ticket))
+        .del(ticket_key(ticket))
+        .query_async(redis)
+        .await?;
+
+    let Some(owner) = owner else {
+        return Ok(None);
+    };
+    let owner: TicketOwner = serde_json::from_str(&owner)?;
+
+    if token_denylist::is_revoked(redis, &owner.jti, &owner.session_id).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(owner))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::fake_redis::FakeRedis;
+
+    fn owner(user_id: i64) -> TicketOwner {
+        TicketOwner {
+            user_id,
+            session_id: format!("session-{}", user_id),
+            jti: format!("jti-{}", user_id),
+        }
+    }
+
+    fn ttl() -> chrono::Duration {
+        chrono::Duration::seconds(30)
+    }
+
+    #[tokio::test]
+    async fn tickets_can_be_redeemed_once() {
+        let mut redis = FakeRedis::default();
+        let ticket = issue(&mut redis, &owner(7), ttl()).await.unwrap();
+
+        let redeemed = redeem(&mut redis, &ticket).await.unwrap().unwrap();
+        assert_eq!(redeemed.user_id, 7);
+        assert!(redeem(&mut redis, &ticket).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_tickets_fail() {
+        let mut redis = FakeRedis::default();
+        let ticket = issue(&mut redis, &owner(7), ttl()).await.unwrap();
+
+        redis.advance(30);
+        assert!(redeem(&mut redis, &ticket).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn tickets_only_ever_identify_their_own_user() {
+        let mut redis = FakeRedis::default();
+        let alice = issue(&mut redis, &owner(1), ttl()).await.unwrap();
+        let bob = issue(&mut redis, &owner(2), ttl()).await.unwrap();
+        assert!(redeem(&mut redis, "not-a-ticket").await.unwrap().is_none());
+
+        let redeemed = redeem(&mut redis, &bob).await.unwrap().unwrap();
+        assert_eq!((redeemed.user_id, redeemed.session_id.as_str()), (2, "session-2"));
+        let redeemed = redeem(&mut redis, &alice).await.unwrap().unwrap();
+        assert_eq!((redeemed.user_id, redeemed.session_id.as_str()), (1, "session-1"));
+    }
+
+    #[tokio::test]
+    async fn tickets_die_with_their_token_or_session() {
+        let mut redis = FakeRedis::default();
+        let by_token = issue(&mut redis, &owner(1), ttl()).await.unwrap();
+        let by_session = issue(&mut redis, &owner(2), ttl()).await.unwrap();
+
+        token_denylist::revoke(&mut redis, "jti-1", ttl()).await.unwrap();
+        token_denylist::revoke_session(&mut redis, "session-2", ttl()).await.unwrap();
+        assert!(redeem(&mut redis, &by_token).await.unwrap().is_none());
+        assert!(redeem(&mut redis, &by_session).await.unwrap().is_none());
+    }
+}
@@ -1,33 +1,92 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Validation};
 use crate::models::Claims;
+use super::jwt_keys::JwtKeys;
 use anyhow::Result;
 
-pub fn create_token(user_id: i64, secret: &str) -> Result<String> {
+pub fn create_token(user_id: i64, session_id: &str, keys: &JwtKeys, ttl: chrono::Duration) -> Result<String> {
     let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::days(7))
+        .checked_add_signed(ttl)
         .expect("valid timestamp")
         .timestamp() as usize;
 
     let claims = Claims {
         sub: user_id,
         exp: expiration,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: session_id.to_string(),
+        iss: keys.issuer().to_string(),
+        aud: keys.audience().to_string(),
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )?;
+    let token = encode(&keys.header(), &claims, keys.encoding_key())?;
 
     Ok(token)
 }
 
-pub fn verify_token(token: &str, secret: &str) -> Result<Claims> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::default(),
-    )?;
+pub fn verify_token(token: &str, keys: &JwtKeys) -> Result<Claims> {
+    let header = decode_header(token)?;
+    // The algorithm comes from the key we hold, never from the token header
+    let (key, algorithm) = keys
+        .decoding_key(header.kid.as_deref())
+        .ok_or_else(|| anyhow::anyhow!("Unknown signing key"))?;
+
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(&[keys.issuer()]);
+    validation.set_audience(&[keys.audience()]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    let token_data = decode::<Claims>(token, key, &validation)?;
 
     Ok(token_data.claims)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn keys(issuer: &str, audience: &str) -> JwtKeys {
+        let mut config = Config::from_env();
+        config.jwt_algorithm = "HS256".to_string();
+        config.jwt_issuer = issuer.to_string();
+        config.jwt_audience = audience.to_string();
+        JwtKeys::load(&config, Some("secret".to_string())).unwrap()
+    }
+
+    fn ttl() -> chrono::Duration {
+        chrono::Duration::minutes(15)
+    }
+
+    #[test]
+    fn tokens_carry_issuer_and_audience() {
+        let keys = keys("im-tt", "im-tt-api");
+        let token = create_token(7, "session-1", &keys, ttl()).unwrap();
+
+        let claims = verify_token(&token, &keys).unwrap();
+        assert_eq!(claims.sub, 7);
+        assert_eq!(claims.sid, "session-1");
+        assert_eq!(claims.iss, "im-tt");
+        assert_eq!(claims.aud, "im-tt-api");
+    }
+
+    #[test]
+    fn tokens_for_another_audience_or_issuer_are_rejected() {
+        let token = create_token(7, "session-1", &keys("im-tt", "other-api"), ttl()).unwrap();
+        assert!(verify_token(&token, &keys("im-tt", "im-tt-api")).is_err());
+
+        let token = create_token(7, "session-1", &keys("other", "im-tt-api"), ttl()).unwrap();
+        assert!(verify_token(&token, &keys("im-tt", "im-tt-api")).is_err());
+    }
+
+    #[test]
+    fn tokens_without_issuer_and_audience_are_rejected() {
+        let keys = keys("im-tt", "im-tt-api");
+        let claims = serde_json::json!({
+            "sub": 7,
+            "exp": (chrono::Utc::now() + ttl()).timestamp(),
+            "jti": "jti-1",
+            "sid": "session-1",
+        });
+        let token = encode(&keys.header(), &claims, keys.encoding_key()).unwrap();
+        assert!(verify_token(&token, &keys).is_err());
+    }
+}
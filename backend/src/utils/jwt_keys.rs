@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::traits::PublicKeyParts;
+use sha2::{Digest, Sha256};
+use crate::config::Config;
+
+pub type SharedJwtKeys = Arc<JwtKeys>;
+
+// Public half of an asymmetric key, as published in the JWKS
+#[derive(Clone, Debug)]
+enum PublicKey {
+    Ed25519 { x: String },
+    Rsa { n: String, e: String },
+}
+
+impl PublicKey {
+    fn from_pem(pem: &str) -> Result<Self> {
+        if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+            return Ok(PublicKey::Ed25519 { x: URL_SAFE_NO_PAD.encode(key.to_bytes()) });
+        }
+        let key = rsa::RsaPublicKey::from_public_key_pem(pem)
+            .or_else(|_| rsa::RsaPublicKey::from_pkcs1_pem(pem))
+            .map_err(|_| anyhow!("Unsupported public key, expected an Ed25519 or RSA PEM"))?;
+        Ok(PublicKey::from_rsa(&key))
+    }
+
+    fn from_rsa(key: &rsa::RsaPublicKey) -> Self {
+        PublicKey::Rsa {
+            n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        }
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            PublicKey::Ed25519 { .. } => Algorithm::EdDSA,
+            PublicKey::Rsa { .. } => Algorithm::RS256,
+        }
+    }
+
+    fn decoding_key(&self) -> Result<DecodingKey> {
+        Ok(match self {
+            PublicKey::Ed25519 { x } => DecodingKey::from_ed_components(x)?,
+            PublicKey::Rsa { n, e } => DecodingKey::from_rsa_components(n, e)?,
+        })
+    }
+
+    // RFC 7638 thumbprint, used as the kid when none is configured
+    fn thumbprint(&self) -> String {
+        let canonical = match self {
+            PublicKey::Ed25519 { x } => format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x),
+            PublicKey::Rsa { n, e } => format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n),
+        };
+        URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+    }
+
+    fn to_jwk(&self, kid: &str) -> serde_json::Value {
+        match self {
+            PublicKey::Ed25519 { x } => serde_json::json!({
+                "kty": "OKP", "crv": "Ed25519", "x": x,
+                "kid": kid, "alg": "EdDSA", "use": "sig"
+            }),
+            PublicKey::Rsa { n, e } => serde_json::json!({
+                "kty": "RSA", "n": n, "e": e,
+                "kid": kid, "alg": "RS256", "use": "sig"
+            }),
+        }
+    }
+}
+
+struct VerificationKey {
+    public: PublicKey,
+    key: DecodingKey,
+}
+
+// Keys for signing new tokens and verifying incoming ones. Asymmetric keys
+// are looked up by the token's kid, so retired keys can stay listed in
+// JWT_VERIFICATION_KEY_FILES until the tokens they signed have expired.
+// After moving off HS256, JWT_SECRET can keep verifying tokens that carry no
+// kid, but only when JWT_ACCEPT_LEGACY_HS256 is set and only until the last
+// token signed with it before the restart has expired.
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    signing_kid: Option<String>,
+    signing_key: EncodingKey,
+    verification: HashMap<String, VerificationKey>,
+    legacy_secret: Option<DecodingKey>,
+    // None while the secret is the active signing key
+    legacy_until: Option<Instant>,
+    // Written into every token and required on every token we accept, so a
+    // token cannot be replayed against another service trusting these keys
+    issuer: String,
+    audience: String,
+}
+
+impl std::fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtKeys")
+            .field("algorithm", &self.algorithm)
+            .field("signing_kid", &self.signing_kid)
+            .field("verification_kids", &self.verification.keys().collect::<Vec<_>>())
+            .field("legacy_secret", &self.legacy_secret.is_some())
+            .field("legacy_until", &self.legacy_until)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .finish()
+    }
+}
+
+impl JwtKeys {
+    pub fn load(config: &Config, secret: Option<String>) -> Result<Self> {
+        let algorithm = match config.jwt_algorithm.as_str() {
+            "HS256" => {
+                let secret = secret.context("JWT_SECRET must be set when JWT_ALGORITHM is HS256")?;
+                return Ok(JwtKeys {
+                    algorithm: Algorithm::HS256,
+                    signing_kid: None,
+                    signing_key: EncodingKey::from_secret(secret.as_bytes()),
+                    verification: HashMap::new(),
+                    legacy_secret: Some(DecodingKey::from_secret(secret.as_bytes())),
+                    legacy_until: None,
+                    issuer: config.jwt_issuer.clone(),
+                    audience: config.jwt_audience.clone(),
+                });
+            }
+            "EdDSA" => Algorithm::EdDSA,
+            "RS256" => Algorithm::RS256,
+            other => bail!("Unsupported JWT_ALGORITHM: {}", other),
+        };
+
+        if config.jwt_signing_key_file.is_empty() {
+            bail!("JWT_SIGNING_KEY_FILE must be set when JWT_ALGORITHM is {:?}", algorithm);
+        }
+        let pem = std::fs::read_to_string(&config.jwt_signing_key_file)
+            .with_context(|| format!("Failed to read {}", config.jwt_signing_key_file))?;
+
+        let (public, signing_key) = match algorithm {
+            Algorithm::EdDSA => {
+                let key = ed25519_dalek::SigningKey::from_pkcs8_pem(&pem)
+                    .map_err(|_| anyhow!("JWT_SIGNING_KEY_FILE is not a PKCS#8 Ed25519 private key"))?;
+                let public = PublicKey::Ed25519 {
+                    x: URL_SAFE_NO_PAD.encode(key.verifying_key().to_bytes()),
+                };
+                (public, EncodingKey::from_ed_pem(pem.as_bytes())?)
+            }
+            _ => {
+                let key = rsa::RsaPrivateKey::from_pkcs8_pem(&pem)
+                    .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_pem(&pem))
+                    .map_err(|_| anyhow!("JWT_SIGNING_KEY_FILE is not an RSA private key"))?;
+                (PublicKey::from_rsa(&key.to_public_key()), EncodingKey::from_rsa_pem(pem.as_bytes())?)
+            }
+        };
+
+        let signing_kid = if config.jwt_signing_key_id.is_empty() {
+            public.thumbprint()
+        } else {
+            config.jwt_signing_key_id.clone()
+        };
+
+        let mut verification = HashMap::new();
+        verification.insert(signing_kid.clone(), VerificationKey { key: public.decoding_key()?, public });
+
+        // Entries are either "path" or "kid=path"
+        for entry in config.jwt_verification_key_files.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kid, path) = match entry.split_once('=') {
+                Some((kid, path)) => (Some(kid.trim().to_string()), path.trim()),
+                None => (None, entry),
+            };
+            let pem = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path))?;
+            let public = PublicKey::from_pem(&pem).with_context(|| format!("Invalid key in {}", path))?;
+            let kid = kid.unwrap_or_else(|| public.thumbprint());
+            verification.insert(kid, VerificationKey { key: public.decoding_key()?, public });
+        }
+
+        let legacy_secret = match secret {
+            Some(secret) if config.jwt_accept_legacy_hs256 => {
+                tracing::warn!(
+                    "Accepting HS256 tokens without a kid for the next {} minutes",
+                    config.access_token_ttl_minutes
+                );
+                Some(DecodingKey::from_secret(secret.as_bytes()))
+            }
+            _ => None,
+        };
+        let legacy_until = config.access_token_ttl().to_std().ok().map(|ttl| Instant::now() + ttl);
+
+        Ok(JwtKeys {
+            algorithm,
+            signing_kid: Some(signing_kid),
+            signing_key,
+            verification,
+            legacy_secret,
+            legacy_until,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+        })
+    }
+
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_kid.clone();
+        header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.signing_key
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    // Tokens without a kid fall back to the shared secret while it is accepted
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<(&DecodingKey, Algorithm)> {
+        match kid {
+            Some(kid) => self
+                .verification
+                .get(kid)
+                .map(|v| (&v.key, v.public.algorithm())),
+            None => self.legacy_key().map(|key| (key, Algorithm::HS256)),
+        }
+    }
+
+    fn legacy_key(&self) -> Option<&DecodingKey> {
+        match self.legacy_until {
+            Some(until) if Instant::now() >= until => None,
+            _ => self.legacy_secret.as_ref(),
+        }
+    }
+
+    // Public verification keys for /.well-known/jwks.json. Empty in HS256 mode.
+    pub fn jwks(&self) -> serde_json::Value {
+        let mut kids: Vec<&String> = self.verification.keys().collect();
+        kids.sort();
+        let keys: Vec<serde_json::Value> = kids
+            .into_iter()
+            .map(|kid| self.verification[kid].public.to_jwk(kid))
+            .collect();
+        serde_json::json!({ "keys": keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn keys(legacy_until: Option<Instant>) -> JwtKeys {
+        JwtKeys {
+            algorithm: Algorithm::EdDSA,
+            signing_kid: Some("current".to_string()),
+            signing_key: EncodingKey::from_secret(b"unused"),
+            verification: HashMap::new(),
+            legacy_secret: Some(DecodingKey::from_secret(b"secret")),
+            legacy_until,
+            issuer: "im-tt".to_string(),
+            audience: "im-tt-api".to_string(),
+        }
+    }
+
+    #[test]
+    fn active_secret_never_expires() {
+        assert!(keys(None).decoding_key(None).is_some());
+    }
+
+    #[test]
+    fn legacy_secret_is_accepted_until_the_cutoff() {
+        let open = keys(Some(Instant::now() + Duration::from_secs(60)));
+        assert!(matches!(open.decoding_key(None), Some((_, Algorithm::HS256))));
+
+        let closed = keys(Some(Instant::now() - Duration::from_secs(1)));
+        assert!(closed.decoding_key(None).is_none());
+    }
+
+    #[test]
+    fn unknown_kid_is_rejected() {
+        assert!(keys(None).decoding_key(Some("retired")).is_none());
+    }
+}
@@ -1,5 +1,9 @@
 pub mod jwt;
+pub mod jwt_keys;
 pub mod password;
+pub mod totp;
 
 pub use jwt::*;
+pub use jwt_keys::*;
 pub use password::*;
+pub use totp::*;
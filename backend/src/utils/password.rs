@@ -1,12 +1,123 @@
-use bcrypt::{hash, verify, DEFAULT_COST};
 use anyhow::Result;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
 
-pub fn hash_password(password: &str) -> Result<String> {
-    let hashed = hash(password, DEFAULT_COST)?;
-    Ok(hashed)
+// Argon2id cost parameters for newly created hashes
+#[derive(Clone, Copy, Debug)]
+pub struct PasswordParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
-    let valid = verify(password, hash)?;
-    Ok(valid)
+// Stored for accounts that have no password, such as bots and SSO users.
+// It is not a valid hash, so password verification always fails.
+pub const UNUSABLE_PASSWORD: &str = "!";
+
+fn argon2(params: PasswordParams) -> Result<Argon2<'static>> {
+    let params = Params::new(params.memory_kib, params.iterations, params.parallelism, None)
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    hash.starts_with("$2")
+}
+
+// Hashing is deliberately slow, so it runs on the blocking pool to keep the
+// async workers free for WebSocket traffic
+pub async fn hash_password(password: &str, params: PasswordParams) -> Result<String> {
+    let password = password.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = argon2(params)?.hash_password(password.as_bytes(), &salt)?;
+        Ok(hash.to_string())
+    })
+    .await?
+}
+
+// Accepts both Argon2 PHC strings and legacy bcrypt hashes
+pub async fn verify_password(password: &str, hash: &str) -> Result<bool> {
+    let password = password.to_owned();
+    let hash = hash.to_owned();
+    tokio::task::spawn_blocking(move || {
+        if is_bcrypt(&hash) {
+            return Ok(bcrypt::verify(&password, &hash)?);
+        }
+        let parsed = PasswordHash::new(&hash)?;
+        match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await?
+}
+
+// True for bcrypt hashes and Argon2 hashes made with other parameters, so a
+// successful login can upgrade them
+pub fn needs_rehash(hash: &str, params: PasswordParams) -> bool {
+    if is_bcrypt(hash) {
+        return true;
+    }
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        return true;
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(&parsed) {
+        Ok(current) => {
+            current.m_cost() != params.memory_kib
+                || current.t_cost() != params.iterations
+                || current.p_cost() != params.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the tests do not spend seconds hashing
+    const PARAMS: PasswordParams = PasswordParams { memory_kib: 1024, iterations: 1, parallelism: 1 };
+
+    #[tokio::test]
+    async fn hash_round_trip() {
+        let hash = hash_password("correct horse 1", PARAMS).await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse 1", &hash).await.unwrap());
+        assert!(!verify_password("wrong horse 1", &hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn current_hashes_need_no_rehash() {
+        let hash = hash_password("password1", PARAMS).await.unwrap();
+        assert!(!needs_rehash(&hash, PARAMS));
+        assert!(needs_rehash(&hash, PasswordParams { iterations: 2, ..PARAMS }));
+        assert!(needs_rehash(&hash, PasswordParams { memory_kib: 2048, ..PARAMS }));
+        assert!(needs_rehash(&hash, PasswordParams { parallelism: 2, ..PARAMS }));
+    }
+
+    #[tokio::test]
+    async fn legacy_bcrypt_hashes_verify_and_need_rehash() {
+        let hash = bcrypt::hash("password1", 4).unwrap();
+        assert!(verify_password("password1", &hash).await.unwrap());
+        assert!(needs_rehash(&hash, PARAMS));
+    }
+
+    #[test]
+    fn other_hashes_need_rehash() {
+        assert!(needs_rehash("not a hash", PARAMS));
+        assert!(needs_rehash(UNUSABLE_PASSWORD, PARAMS));
+        let argon2i = "$argon2i$v=19$m=1024,t=1,p=1$c29tZXNhbHQ$iWh06vD8Fy27wf9npn6FXWiCX4K6pW6Ue1Bnzz07Z8A";
+        assert!(needs_rehash(argon2i, PARAMS));
+    }
+
+    #[tokio::test]
+    async fn unusable_password_never_verifies() {
+        assert!(!matches!(verify_password("", UNUSABLE_PASSWORD).await, Ok(true)));
+    }
 }
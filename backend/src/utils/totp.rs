@@ -0,0 +1,119 @@
+use totp_rs::{Algorithm, Secret, TOTP};
+use sha2::{Digest, Sha256};
+use anyhow::Result;
+
+// RFC 6238 defaults understood by every authenticator app
+const DIGITS: usize = 6;
+const STEP_SECS: u64 = 30;
+const SKEW_STEPS: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn build_totp(secret: &str, issuer: &str, account_name: &str) -> Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes()?;
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        SKEW_STEPS as u8,
+        STEP_SECS,
+        secret,
+        Some(issuer.replace(':', "")),
+        account_name.replace(':', ""),
+    )?;
+    Ok(totp)
+}
+
+pub fn totp_uri(secret: &str, issuer: &str, account_name: &str) -> Result<String> {
+    Ok(build_totp(secret, issuer, account_name)?.get_url())
+}
+
+// Return the time step the code belongs to, allowing one step of clock skew
+// either way, so callers can reject a step that was already used
+pub fn totp_matching_step(secret: &str, code: &str) -> Result<Option<u64>> {
+    let totp = build_totp(secret, "", "")?;
+    let code = code.trim().replace(' ', "");
+    let current = chrono::Utc::now().timestamp() as u64 / STEP_SECS;
+
+    for step in current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS {
+        if totp.generate(step * STEP_SECS) == code {
+            return Ok(Some(step));
+        }
+    }
+    Ok(None)
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw = uuid::Uuid::new_v4().simple().to_string();
+            format!("{}-{}", &raw[..5], &raw[5..10])
+        })
+        .collect()
+}
+
+// Recovery codes are random, so a fast hash is enough to keep them unreadable at rest
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_at(secret: &str, step: u64) -> String {
+        build_totp(secret, "", "").unwrap().generate(step * STEP_SECS)
+    }
+
+    fn current_step() -> u64 {
+        chrono::Utc::now().timestamp() as u64 / STEP_SECS
+    }
+
+    #[test]
+    fn current_code_matches_its_step() {
+        let secret = generate_totp_secret();
+        let step = current_step();
+        let matched = totp_matching_step(&secret, &code_at(&secret, step)).unwrap().unwrap();
+        // The clock may tick over between generating and checking the code
+        assert!(matched == step || matched + 1 == step);
+    }
+
+    #[test]
+    fn accepts_one_step_of_skew_either_way() {
+        let secret = generate_totp_secret();
+        let step = current_step();
+        for candidate in [step - 1, step + 1] {
+            assert!(totp_matching_step(&secret, &code_at(&secret, candidate)).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_codes_outside_the_window() {
+        let secret = generate_totp_secret();
+        let step = current_step();
+        assert_eq!(totp_matching_step(&secret, &code_at(&secret, step - 5)).unwrap(), None);
+        assert_eq!(totp_matching_step(&secret, &code_at(&secret, step + 5)).unwrap(), None);
+        assert_eq!(totp_matching_step(&secret, "not-a-code").unwrap(), None);
+    }
+
+    #[test]
+    fn ignores_spaces_in_the_code() {
+        let secret = generate_totp_secret();
+        let code = code_at(&secret, current_step());
+        let spaced = format!(" {} {} ", &code[..3], &code[3..]);
+        assert!(totp_matching_step(&secret, &spaced).unwrap().is_some());
+    }
+
+    #[test]
+    fn recovery_code_hash_ignores_case_and_separators() {
+        assert_eq!(hash_recovery_code("abcde-12345"), hash_recovery_code("ABCDE 12345"));
+        assert_ne!(hash_recovery_code("abcde-12345"), hash_recovery_code("abcde-12346"));
+    }
+}
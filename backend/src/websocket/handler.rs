@@ -2,11 +2,26 @@ use salvo::prelude::*;
 use salvo::websocket::{Message as WsMessage, WebSocket, WebSocketUpgrade};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::{Mutex, mpsc};
 use serde::{Deserialize, Serialize};
 use futures_util::{StreamExt, SinkExt};
-use sea_orm::{DatabaseConnection, EntityTrait, QueryFilter, ColumnTrait, Set, ActiveModelTrait};
-use crate::entity::{messages, group_members, group_members::Entity as GroupMembers};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use crate::entity::messages::Entity as Messages;
+use validator::{Validate, ValidationErrors};
+use crate::config::Config;
+use redis::aio::MultiplexedConnection;
+use crate::services::{blocks, presence, session, token_denylist};
+use crate::services::message_dispatch::{self, SendOutcome};
+use crate::services::message_edit::{self, EditOutcome};
+use crate::models::{EditMessageRequest, PresenceStatus, SendMessageRequest};
+use crate::models::validation::field_errors;
+use crate::services::ws_ticket::{self, TicketOwner};
+
+// Close code sent when the server ends a connection whose session was revoked
+pub const WS_CLOSE_REVOKED: u16 = 4001;
+// Close code sent when a connection does not authenticate in time
+pub const WS_CLOSE_UNAUTHENTICATED: u16 = 4002;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsEvent {
@@ -22,6 +37,11 @@ pub struct WsEvent {
 pub struct ClientEntry {
     pub sender: mpsc::UnboundedSender<String>,
     pub user_id: i64,
+    pub session_id: String,
+    // Presence the user picked, and whether this connection went idle
+    pub status: PresenceStatus,
+    pub last_active: Instant,
+    pub idle: bool,
 }
 
 pub type Clients = Arc<Mutex<HashMap<String, ClientEntry>>>;
@@ -30,68 +50,181 @@ pub fn create_clients() -> Clients {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+// Drop every connection matching the predicate. Removing the entry drops
+// its sender, which makes the socket task send a close frame and exit.
+pub async fn close_connections<F>(clients: &Clients, predicate: F) -> usize
+where
+    F: Fn(&ClientEntry) -> bool,
+{
+    let mut clients_lock = clients.lock().await;
+    let before = clients_lock.len();
+    clients_lock.retain(|_, entry| !predicate(entry));
+    before - clients_lock.len()
+}
+
+// Send an event to every connection of the given users
+pub async fn send_to_users(clients: &Clients, user_ids: &HashSet<i64>, event: &WsEvent) {
+    let payload = serde_json::to_string(event).unwrap();
+    let clients_lock = clients.lock().await;
+    for entry in clients_lock.values() {
+        if user_ids.contains(&entry.user_id) {
+            let _ = entry.sender.send(payload.clone());
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthFrame {
+    event_type: String,
+    ticket: String,
+}
+
+// Connections authenticate with a ticket from POST /api/ws/ticket, either as
+// ?ticket= on the upgrade request or in an auth frame sent as the first message
 #[handler]
 pub async fn websocket_handler(
     req: &mut Request,
     res: &mut Response,
     depot: &mut Depot,
 ) -> Result<(), salvo::http::StatusError> {
-    let user_id = depot.get::<i64>("user_id").ok();
-    let clients = depot.get::<Clients>("clients").ok();
+    let clients = depot.get::<Clients>("clients").ok().cloned();
     let db = depot.get::<DatabaseConnection>("db").ok().cloned();
+    let config = depot.get::<Config>("config").ok().cloned();
+    let redis = depot.get::<MultiplexedConnection>("redis").ok().cloned();
 
-    if user_id.is_none() || clients.is_none() || db.is_none() {
-        return Err(salvo::http::StatusError::unauthorized());
-    }
-
-    let user_id = *user_id.unwrap();
-    let clients = clients.unwrap().clone();
-    let db = db.unwrap();
+    let (Some(clients), Some(db), Some(config), Some(mut redis)) = (clients, db, config, redis) else {
+        return Err(salvo::http::StatusError::internal_server_error());
+    };
 
-    tracing::info!("WS upgrade request from user {}", user_id);
+    let owner = match req.query::<String>("ticket") {
+        Some(ticket) => match ws_ticket::redeem(&mut redis, &ticket).await {
+            Ok(Some(owner)) => Some(owner),
+            Ok(None) => return Err(salvo::http::StatusError::unauthorized()),
+            Err(e) => {
+                tracing::error!("Failed to redeem WebSocket ticket: {:?}", e);
+                return Err(salvo::http::StatusError::unauthorized());
+            }
+        },
+        None => None,
+    };
 
     WebSocketUpgrade::new()
-        .upgrade(req, res, move |ws| handle_socket(ws, user_id, clients, db))
+        .upgrade(req, res, move |mut ws| async move {
+            let owner = match owner {
+                Some(owner) => owner,
+                None => match authenticate(&mut ws, &mut redis, config.ws_auth_timeout()).await {
+                    Some(owner) => owner,
+                    None => {
+                        let _ = ws.send(WsMessage::close_with(WS_CLOSE_UNAUTHENTICATED, "authentication required")).await;
+                        return;
+                    }
+                },
+            };
+
+            tracing::info!("WS authenticated user {}", owner.user_id);
+
+            if let Err(e) = session::touch(&db, &owner.session_id).await {
+                tracing::warn!("Failed to update session {}: {:?}", owner.session_id, e);
+            }
+
+            handle_socket(ws, owner, clients, db, redis, config).await
+        })
         .await
 }
 
-async fn handle_socket(ws: WebSocket, user_id: i64, clients: Clients, db: DatabaseConnection) {
+// Wait for {"event_type": "auth", "ticket": "..."} as the first frame
+async fn authenticate(
+    ws: &mut WebSocket,
+    redis: &mut MultiplexedConnection,
+    timeout: std::time::Duration,
+) -> Option<TicketOwner> {
+    let msg = tokio::time::timeout(timeout, ws.recv()).await.ok()??.ok()?;
+    let frame: AuthFrame = serde_json::from_str(msg.as_str().ok()?).ok()?;
+    if frame.event_type != "auth" {
+        return None;
+    }
+
+    match ws_ticket::redeem(redis, &frame.ticket).await {
+        Ok(owner) => owner,
+        Err(e) => {
+            tracing::error!("Failed to redeem WebSocket ticket: {:?}", e);
+            None
+        }
+    }
+}
+
+// Event sent back to the client when one of its events is rejected
+fn error_event(user_id: i64, message: &str) -> String {
+    serde_json::to_string(&WsEvent {
+        event_type: "error".to_string(),
+        user_id, receiver_id: None, group_id: None,
+        content: Some(message.to_string()),
+        data: None,
+    }).unwrap()
+}
+
+// First validation message, for reporting in an error event
+fn validation_message(errors: &ValidationErrors) -> String {
+    field_errors(errors)
+        .as_object()
+        .and_then(|fields| fields.values().next())
+        .and_then(|messages| messages.get(0))
+        .and_then(|message| message.as_str())
+        .unwrap_or("Invalid message")
+        .to_string()
+}
+
+async fn handle_socket(
+    ws: WebSocket,
+    owner: TicketOwner,
+    clients: Clients,
+    db: DatabaseConnection,
+    mut redis: MultiplexedConnection,
+    config: Config,
+) {
+    let TicketOwner { user_id, session_id, jti } = owner;
     let conn_id = format!("{}_{}", user_id, uuid::Uuid::new_v4());
     tracing::info!("WS connected: user {} conn {}", user_id, conn_id);
 
     let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let status = presence::load_status(&db, user_id).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to load presence of user {}: {:?}", user_id, e);
+        PresenceStatus::default()
+    });
+    let hidden = presence::hidden_users(&db, user_id).await;
 
-    // Register connection and broadcast online — all under one lock
-    {
+    // Register connection and announce presence — all under one lock
+    let (previous, current) = {
         let mut clients_lock = clients.lock().await;
-        let is_first = !clients_lock.values().any(|e| e.user_id == user_id);
-        clients_lock.insert(conn_id.clone(), ClientEntry { sender: tx, user_id });
-
-        if is_first {
-            // Tell this user who is already online
-            if let Some(my_entry) = clients_lock.get(&conn_id) {
-                let mut seen: HashSet<i64> = HashSet::new();
-                for (_, entry) in clients_lock.iter() {
-                    if entry.user_id != user_id && seen.insert(entry.user_id) {
-                        let ev = WsEvent {
-                            event_type: "online".to_string(),
-                            user_id: entry.user_id,
-                            receiver_id: None, group_id: None, content: None, data: None,
-                        };
-                        let _ = my_entry.sender.send(serde_json::to_string(&ev).unwrap());
-                    }
-                }
-            }
-            // Tell others this user is online
-            let online_str = serde_json::to_string(&WsEvent {
-                event_type: "online".to_string(),
-                user_id, receiver_id: None, group_id: None, content: None, data: None,
-            }).unwrap();
-            for (cid, entry) in clients_lock.iter() {
-                if *cid != conn_id && entry.user_id != user_id {
-                    let _ = entry.sender.send(online_str.clone());
-                }
-            }
+        let previous = presence::presence_of(&clients_lock, user_id);
+        clients_lock.insert(conn_id.clone(), ClientEntry {
+            sender: tx,
+            user_id,
+            session_id: session_id.clone(),
+            status,
+            last_active: Instant::now(),
+            idle: false,
+        });
+
+        // Tell this connection who is already online, then tell others
+        presence::send_snapshot(&clients_lock, &conn_id, &hidden);
+        let current = presence::presence_of(&clients_lock, user_id);
+        presence::announce(&clients_lock, user_id, previous, current, &hidden);
+        (previous, current)
+    };
+    presence::record_last_seen(&db, user_id, previous, current).await;
+
+    // The ticket was checked before the connection was registered, so a
+    // revocation in between would have missed it; dropping the entry now
+    // closes the socket through the usual path below
+    match token_denylist::is_revoked(&mut redis, &jti, &session_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            clients.lock().await.remove(&conn_id);
+        }
+        Err(e) => {
+            tracing::error!("Failed to check revocation for conn {}: {:?}", conn_id, e);
+            clients.lock().await.remove(&conn_id);
         }
     }
 
@@ -104,48 +237,93 @@ async fn handle_socket(ws: WebSocket, user_id: i64, clients: Clients, db: Databa
                     Some(Ok(msg)) => {
                         if msg.is_text() {
                             if let Ok(text) = msg.as_str() {
+                                // Any frame counts as activity; clients may also send
+                                // {"event_type": "activity"} on user input
+                                presence::mark_active(&db, &clients, &conn_id).await;
                                 if let Ok(event) = serde_json::from_str::<WsEvent>(text) {
                                     tracing::debug!("WS event from {}: {}", user_id, event.event_type);
                                     match event.event_type.as_str() {
                                         "message" => {
-                                            if let Some(content) = &event.content {
-                                                let _ = save_message(
-                                                    &db, user_id, event.receiver_id, event.group_id,
-                                                    content, "text",
-                                                ).await;
+                                            let request = SendMessageRequest {
+                                                receiver_id: event.receiver_id,
+                                                group_id: event.group_id,
+                                                content: event.content.unwrap_or_default(),
+                                                message_type: "text".to_string(),
+                                            };
+                                            if let Err(errors) = request.validate() {
+                                                let error = error_event(user_id, &validation_message(&errors));
+                                                let _ = sink.send(WsMessage::text(error)).await;
+                                                continue;
                                             }
-                                            let clients_lock = clients.lock().await;
-                                            if let Some(receiver_id) = event.receiver_id {
-                                                let ev_str = serde_json::to_string(&event).unwrap();
-                                                for (_, entry) in clients_lock.iter() {
-                                                    if entry.user_id == receiver_id {
-                                                        let _ = entry.sender.send(ev_str.clone());
-                                                    }
+                                            let rejection = match message_dispatch::send(&db, &clients, &config, user_id, Some(&conn_id), request).await {
+                                                Ok(SendOutcome::Sent(_)) => None,
+                                                Ok(SendOutcome::MissingTarget) => Some("Either receiver_id or group_id must be provided"),
+                                                Ok(SendOutcome::UnknownReceiver) => Some("User not found"),
+                                                Ok(SendOutcome::SelfMessage) => Some("You cannot message yourself"),
+                                                Ok(SendOutcome::NotMember) => Some("You are not a member of this group"),
+                                                Ok(SendOutcome::Blocked) => Some("You cannot message this user"),
+                                                Ok(SendOutcome::EmailUnverified) => Some("Verify your email address to message this user"),
+                                                Err(e) => {
+                                                    tracing::error!("Failed to send message from user {}: {:?}", user_id, e);
+                                                    Some("Failed to send message")
                                                 }
-                                            } else if let Some(group_id) = event.group_id {
-                                                if let Ok(members) = GroupMembers::find()
-                                                    .filter(group_members::Column::GroupId.eq(group_id))
-                                                    .all(&db).await
-                                                {
-                                                    let ev_str = serde_json::to_string(&event).unwrap();
-                                                    let member_ids: HashSet<i64> = members.iter().map(|m| m.user_id).collect();
-                                                    for (_, entry) in clients_lock.iter() {
-                                                        if member_ids.contains(&entry.user_id) && entry.user_id != user_id {
-                                                            let _ = entry.sender.send(ev_str.clone());
-                                                        }
-                                                    }
+                                            };
+                                            if let Some(message) = rejection {
+                                                let error = error_event(user_id, message);
+                                                let _ = sink.send(WsMessage::text(error)).await;
+                                            }
+                                        }
+                                        "edit_message" => {
+                                            let message_id = event.data.as_ref()
+                                                .and_then(|data| data.get("message_id"))
+                                                .and_then(|id| id.as_i64())
+                                                .unwrap_or(0);
+                                            let request = EditMessageRequest {
+                                                content: event.content.unwrap_or_default(),
+                                            };
+                                            if let Err(errors) = request.validate() {
+                                                let error = error_event(user_id, &validation_message(&errors));
+                                                let _ = sink.send(WsMessage::text(error)).await;
+                                                continue;
+                                            }
+                                            let outcome = match Messages::find_by_id(message_id).one(&db).await {
+                                                Ok(Some(message)) => message_edit::edit(&db, &clients, &config, user_id, message, request.content).await.map(Some),
+                                                Ok(None) => Ok(None),
+                                                Err(e) => Err(e.into()),
+                                            };
+                                            let rejection = match outcome {
+                                                Ok(Some(EditOutcome::Edited(_))) => None,
+                                                Ok(None) => Some("Message not found"),
+                                                Ok(Some(EditOutcome::NotSender)) => Some("You can only edit your own messages"),
+                                                Ok(Some(EditOutcome::NotMember)) => Some("You are not a member of this group"),
+                                                Ok(Some(EditOutcome::WindowClosed)) => Some("This message can no longer be edited"),
+                                                Ok(Some(EditOutcome::Recalled)) => Some("This message was recalled"),
+                                                Err(e) => {
+                                                    tracing::error!("Failed to edit message {} from user {}: {:?}", message_id, user_id, e);
+                                                    Some("Failed to edit message")
                                                 }
+                                            };
+                                            if let Some(message) = rejection {
+                                                let error = error_event(user_id, message);
+                                                let _ = sink.send(WsMessage::text(error)).await;
                                             }
                                         }
                                         "typing" => {
                                             if let Some(receiver_id) = event.receiver_id {
-                                                let clients_lock = clients.lock().await;
-                                                let ev_str = serde_json::to_string(&event).unwrap();
-                                                for (_, entry) in clients_lock.iter() {
-                                                    if entry.user_id == receiver_id {
-                                                        let _ = entry.sender.send(ev_str.clone());
-                                                    }
+                                                // Dropped silently so a blocked user learns nothing
+                                                if !matches!(blocks::is_blocked_between(&db, user_id, receiver_id).await, Ok(false)) {
+                                                    continue;
                                                 }
+                                                // Built here rather than relayed so the sender cannot be spoofed
+                                                let typing = WsEvent {
+                                                    event_type: "typing".to_string(),
+                                                    user_id,
+                                                    receiver_id: Some(receiver_id),
+                                                    group_id: None,
+                                                    content: None,
+                                                    data: None,
+                                                };
+                                                send_to_users(&clients, &HashSet::from([receiver_id]), &typing).await;
                                             }
                                         }
                                         _ => {}
@@ -169,10 +347,20 @@ async fn handle_socket(ws: WebSocket, user_id: i64, clients: Clients, db: Databa
                     }
                 }
             }
-            Some(msg) = rx.recv() => {
-                if let Err(e) = sink.send(WsMessage::text(msg)).await {
-                    tracing::error!("Failed to send to user {} conn {}: {:?}", user_id, conn_id, e);
-                    break;
+            msg = rx.recv() => {
+                match msg {
+                    Some(msg) => {
+                        if let Err(e) = sink.send(WsMessage::text(msg)).await {
+                            tracing::error!("Failed to send to user {} conn {}: {:?}", user_id, conn_id, e);
+                            break;
+                        }
+                    }
+                    None => {
+                        // Entry was removed from Clients, e.g. the session was revoked
+                        tracing::info!("WS closed by server for user {} conn {}", user_id, conn_id);
+                        let _ = sink.send(WsMessage::close_with(WS_CLOSE_REVOKED, "session revoked")).await;
+                        break;
+                    }
                 }
             }
             else => {
@@ -182,45 +370,22 @@ async fn handle_socket(ws: WebSocket, user_id: i64, clients: Clients, db: Databa
         }
     }
 
-    // Unregister and broadcast offline — all under one lock
-    {
+    // Unregister and announce presence — all under one lock. A revoked
+    // session's entry is already gone, so fall back to the saved status.
+    let status = presence::load_status(&db, user_id).await.unwrap_or(status);
+    let hidden = presence::hidden_users(&db, user_id).await;
+    let (previous, current) = {
         let mut clients_lock = clients.lock().await;
-        clients_lock.remove(&conn_id);
-        let is_last = !clients_lock.values().any(|e| e.user_id == user_id);
-
-        if is_last {
-            let offline_str = serde_json::to_string(&WsEvent {
-                event_type: "offline".to_string(),
-                user_id, receiver_id: None, group_id: None, content: None, data: None,
-            }).unwrap();
-            for (_, entry) in clients_lock.iter() {
-                if entry.user_id != user_id {
-                    let _ = entry.sender.send(offline_str.clone());
-                }
-            }
-        }
-    }
-
-    tracing::info!("WS cleanup done for user {} conn {}", user_id, conn_id);
-}
-
-async fn save_message(
-    db: &DatabaseConnection,
-    sender_id: i64,
-    receiver_id: Option<i64>,
-    group_id: Option<i64>,
-    content: &str,
-    message_type: &str,
-) -> Result<messages::Model, sea_orm::DbErr> {
-    let new_message = messages::ActiveModel {
-        sender_id: Set(sender_id),
-        receiver_id: Set(receiver_id),
-        group_id: Set(group_id),
-        content: Set(content.to_string()),
-        message_type: Set(message_type.to_string()),
-        is_read: Set(false),
-        ..Default::default()
+        let leaving = match clients_lock.remove(&conn_id) {
+            Some(entry) => (entry.status, entry.idle),
+            None => (status, false),
+        };
+        let previous = presence::presence_before_leaving(&clients_lock, user_id, leaving);
+        let current = presence::presence_of(&clients_lock, user_id);
+        presence::announce(&clients_lock, user_id, previous, current, &hidden);
+        (previous, current)
     };
+    presence::record_last_seen(&db, user_id, previous, current).await;
 
-    new_message.insert(db).await
+    tracing::info!("WS cleanup done for user {} conn {}", user_id, conn_id);
 }
@@ -0,0 +1,2 @@
+static REV_MAPPINGS: &[(UniCase<&'static str>, TopLevelExts)] = &[(UniCase::ascii("application"), TopLevelExts { start: 0, end: 942, subs: &[(UniCase::ascii("acad"), (0, 1)),(UniCase::ascii("andrew-inset"), (1, 2)),(UniCase::ascii("annodex"), (2, 3)),(UniCase::ascii("applixware"), (3, 4)),(UniCase::ascii("atom+xml"), (4, 5)),(UniCase::ascii("atomcat+xml"), (5, 6)),(UniCase::ascii("atomsvc+xml"), (6, 7)),(UniCase::ascii("bdoc"), (7, 8)),(UniCase::ascii("ccxml+xml"), (8, 9)),(UniCase::ascii("cdmi-capability"), (9, 10)),(UniCase::ascii("cdmi-container"), (10, 11)),(UniCase::ascii("cdmi-domain"), (11, 12)),(UniCase::ascii("cdmi-object"), (12, 13)),(UniCase::ascii("cdmi-queue"), (13, 14)),(UniCase::ascii("cu-seeme"), (14, 15)),(UniCase::ascii("dash+xml"), (15, 16)),(UniCase::ascii("davmount+xml"), (16, 17)),(UniCase::ascii("directx"), (17, 18)),(UniCase::ascii("docbook+xml"), (18, 19)),(UniCase::ascii("dssc+der"), (19, 20)),(UniCase::ascii("dssc+xml"), (20, 21)),(UniCase::ascii("ecmascript"), (21, 23)),(UniCase::ascii("emma+xml"), (23, 24)),(UniCase::ascii("envoy"), (24, 25)),(UniCase::ascii("epub+zip"), (25, 26)),(UniCase::ascii("etl"), (26, 27)),(UniCase::ascii("exi"), (27, 28)),(UniCase::ascii("font-sfnt"), (28, 30)),(UniCase::ascii("font-tdpfr"), (30, 31)),(UniCase::ascii("font-woff"), (31, 32)),(UniCase::ascii("fractals"), (32, 33)),(UniCase::ascii("fsharp-script"), (33, 35)),(UniCase::ascii("futuresplash"), (35, 36)),(UniCase::ascii("geo+json"), (36, 37)),(UniCase::ascii("gml+xml"), (37, 38)),(UniCase::ascii("gpx+xml"), (38, 39)),(UniCase::ascii("gxf"), (39, 40)),(UniCase::ascii("gzip"), (40, 41)),(UniCase::ascii("hjson"), (41, 42)),(UniCase::ascii("hta"), (42, 43)),(UniCase::ascii("hyperstudio"), (43, 44)),(UniCase::ascii("inkml+xml"), (44, 46)),(UniCase::ascii("internet-property-stream"), (46, 47)),(UniCase::ascii("ipfix"), (47, 48)),(UniCase::ascii("java-archive"), (48, 51)),(UniCase::ascii("java-serialized-object"), (51, 52)),(UniCase::ascii("javascript"), (52, 55)),(UniCase::ascii("json"), (55, 56)),(UniCase::ascii("json5"), (56, 57)),(UniCase::ascii("jsonml+json"), (57, 58)),(UniCase::ascii("ld+json"), (58, 59)),(UniCase::ascii("liquidmotion"), (59, 61)),(UniCase::ascii("lost+xml"), (61, 62)),(UniCase::ascii("mac-binhex40"), (62, 63)),(UniCase::ascii("mac-compactpro"), (63, 64)),(UniCase::ascii("mads+xml"), (64, 65)),(UniCase::ascii("manifest+json"), (65, 66)),(UniCase::ascii("marc"), (66, 67)),(UniCase::ascii("marcxml+xml"), (67, 68)),(UniCase::ascii("mathematica"), (68, 71)),(UniCase::ascii("mathml+xml"), (71, 72)),(UniCase::ascii("mbox"), (72, 73)),(UniCase::ascii("mediaservercontrol+xml"), (73, 74)),(UniCase::ascii("metalink+xml"), (74, 75)),(UniCase::ascii("metalink4+xml"), (75, 76)),(UniCase::ascii("mets+xml"), (76, 77)),(UniCase::ascii("mods+xml"), (77, 78)),(UniCase::ascii("mp21"), (78, 80)),(UniCase::ascii("mp4"), (80, 81)),(UniCase::ascii("mpeg"), (81, 82)),(UniCase::ascii("ms-vsi"), (82, 83)),(UniCase::ascii("msaccess"), (83, 90)),(UniCase::ascii("msaccess.addin"), (90, 91)),(UniCase::ascii("msaccess.cab"), (91, 92)),(UniCase::ascii("msaccess.ftemplate"), (92, 93)),(UniCase::ascii("msaccess.runtime"), (93, 94)),(UniCase::ascii("msaccess.webapplication"), (94, 95)),(UniCase::ascii("msword"), (95, 99)),(UniCase::ascii("mxf"), (99, 100)),(UniCase::ascii("n-quads"), (100, 101)),(UniCase::ascii("n-triples"), (101, 102)),(UniCase::ascii("octet-stream"), (102, 174)),(UniCase::ascii("oda"), (174, 175)),(UniCase::ascii("oebps-package+xml"), (175, 176)),(UniCase::ascii("ogg"), (176, 177)),(UniCase::ascii("olescript"), (177, 178)),(UniCase::ascii("omdoc+xml"), (178, 179)),(UniCase::ascii("onenote"), (179, 185)),(UniCase::ascii("opensearchdescription+xml"), (185, 186)),(UniCase::ascii("oxps"), (186, 187)),(UniCase::ascii("patch-ops-error+xml"), (187, 188)),(UniCase::ascii("pdf"), (188, 189)),(UniCase::ascii("pgp-encrypted"), (189, 190)),(UniCase::ascii("pgp-signature"), (190, 192)),(UniCase::ascii("pics-rules"), (192, 193)),(UniCase::ascii("pkcs10"), (193, 194)),(UniCase::ascii("pkcs7-mime"), (194, 196)),(UniCase::ascii("pkcs7-signature"), (196, 197)),(UniCase::ascii("pkcs8"), (197, 198)),(UniCase::ascii("pkix-attr-cert"), (198, 199)),(UniCase::ascii("pkix-crl"), (199, 200)),(UniCase::ascii("pkix-pkipath"), (200, 201)),(UniCase::ascii("pkixcmp"), (201, 202)),(UniCase::ascii("postscript"), (202, 205)),(UniCase::ascii("PowerShell"), (205, 206)),(UniCase::ascii("prs.cww"), (206, 207)),(UniCase::ascii("pskc+xml"), (207, 208)),(UniCase::ascii("raml+yaml"), (208, 209)),(UniCase::ascii("rat-file"), (209, 210)),(UniCase::ascii("rdf+xml"), (210, 211)),(UniCase::ascii("reginfo+xml"), (211, 212)),(UniCase::ascii("relax-ng-compact-syntax"), (212, 213)),(UniCase::ascii("resource-lists+xml"), (213, 214)),(UniCase::ascii("resource-lists-diff+xml"), (214, 215)),(UniCase::ascii("rpki-ghostbusters"), (215, 216)),(UniCase::ascii("rpki-manifest"), (216, 217)),(UniCase::ascii("rpki-roa"), (217, 218)),(UniCase::ascii("rsd+xml"), (218, 219)),(UniCase::ascii("rss+xml"), (219, 220)),(UniCase::ascii("rtf"), (220, 221)),(UniCase::ascii("sbml+xml"), (221, 222)),(UniCase::ascii("scvp-cv-request"), (222, 223)),(UniCase::ascii("scvp-cv-response"), (223, 224)),(UniCase::ascii("scvp-vp-request"), (224, 225)),(UniCase::ascii("scvp-vp-response"), (225, 226)),(UniCase::ascii("sdp"), (226, 227)),(UniCase::ascii("set-payment-initiation"), (227, 228)),(UniCase::ascii("set-registration-initiation"), (228, 229)),(UniCase::ascii("shf+xml"), (229, 230)),(UniCase::ascii("smil+xml"), (230, 231)),(UniCase::ascii("sparql-query"), (231, 232)),(UniCase::ascii("sparql-results+xml"), (232, 233)),(UniCase::ascii("srgs"), (233, 234)),(UniCase::ascii("srgs+xml"), (234, 235)),(UniCase::ascii("sru+xml"), (235, 236)),(UniCase::ascii("ssdl+xml"), (236, 237)),(UniCase::ascii("ssml+xml"), (237, 238)),(UniCase::ascii("step"), (238, 240)),(UniCase::ascii("streamingmedia"), (240, 241)),(UniCase::ascii("tei+xml"), (241, 243)),(UniCase::ascii("thraud+xml"), (243, 244)),(UniCase::ascii("timestamped-data"), (244, 245)),(UniCase::ascii("trig"), (245, 246)),(UniCase::ascii("vnd.3gpp.pic-bw-large"), (246, 247)),(UniCase::ascii("vnd.3gpp.pic-bw-small"), (247, 248)),(UniCase::ascii("vnd.3gpp.pic-bw-var"), (248, 249)),(UniCase::ascii("vnd.3gpp2.tcap"), (249, 250)),(UniCase::ascii("vnd.3m.post-it-notes"), (250, 251)),(UniCase::ascii("vnd.accpac.simply.aso"), (251, 252)),(UniCase::ascii("vnd.accpac.simply.imp"), (252, 253)),(UniCase::ascii("vnd.acucobol"), (253, 254)),(UniCase::ascii("vnd.acucorp"), (254, 256)),(UniCase::ascii("vnd.adobe.air-application-installer-package+zip"), (256, 257)),(UniCase::ascii("vnd.adobe.formscentral.fcdt"), (257, 258)),(UniCase::ascii("vnd.adobe.fxp"), (258, 260)),(UniCase::ascii("vnd.adobe.xdp+xml"), (260, 261)),(UniCase::ascii("vnd.adobe.xfdf"), (261, 262)),(UniCase::ascii("vnd.ahead.space"), (262, 263)),(UniCase::ascii("vnd.airzip.filesecure.azf"), (263, 264)),(UniCase::ascii("vnd.airzip.filesecure.azs"), (264, 265)),(UniCase::ascii("vnd.amazon.ebook"), (265, 266)),(UniCase::ascii("vnd.americandynamics.acc"), (266, 267)),(UniCase::ascii("vnd.amiga.ami"), (267, 268)),(UniCase::ascii("vnd.android.package-archive"), (268, 269)),(UniCase::ascii("vnd.anser-web-certificate-issue-initiation"), (269, 270)),(UniCase::ascii("vnd.anser-web-funds-transfer-initiation"), (270, 271)),(UniCase::ascii("vnd.antix.game-component"), (271, 272)),(UniCase::ascii("vnd.apple.installer+xml"), (272, 273)),(UniCase::ascii("vnd.apple.pkpass"), (273, 274)),(UniCase::ascii("vnd.aristanetworks.swi"), (274, 275)),(UniCase::ascii("vnd.astraea-software.iota"), (275, 276)),(UniCase::ascii("vnd.audiograph"), (276, 277)),(UniCase::ascii("vnd.blueice.multipass"), (277, 278)),(UniCase::ascii("vnd.bmi"), (278, 279)),(UniCase::ascii("vnd.businessobjects"), (279, 280)),(UniCase::ascii("vnd.chemdraw+xml"), (280, 281)),(UniCase::ascii("vnd.chipnuts.karaoke-mmd"), (281, 282)),(UniCase::ascii("vnd.cinderella"), (282, 283)),(UniCase::ascii("vnd.citationstyles.style+xml"), (283, 284)),(UniCase::ascii("vnd.claymore"), (284, 285)),(UniCase::ascii("vnd.cloanto.rp9"), (285, 286)),(UniCase::ascii("vnd.clonk.c4group"), (286, 291)),(UniCase::ascii("vnd.cluetrust.cartomobile-config"), (291, 292)),(UniCase::ascii("vnd.cluetrust.cartomobile-config-pkg"), (292, 293)),(UniCase::ascii("vnd.commonspace"), (293, 294)),(UniCase::ascii("vnd.contact.cmsg"), (294, 295)),(UniCase::ascii("vnd.cosmocaller"), (295, 296)),(UniCase::ascii("vnd.crick.clicker"), (296, 297)),(UniCase::ascii("vnd.crick.clicker.keyboard"), (297, 298)),(UniCase::ascii("vnd.crick.clicker.palette"), (298, 299)),(UniCase::ascii("vnd.crick.clicker.template"), (299, 300)),(UniCase::ascii("vnd.crick.clicker.wordbank"), (300, 301)),(UniCase::ascii("vnd.criticaltools.wbs+xml"), (301, 302)),(UniCase::ascii("vnd.cups-ppd"), (302, 303)),(UniCase::ascii("vnd.curl.car"), (303, 304)),(UniCase::ascii("vnd.curl.pcurl"), (304, 305)),(UniCase::ascii("vnd.dart"), (305, 306)),(UniCase::ascii("vnd.data-vision.rdz"), (306, 307)),(UniCase::ascii("vnd.dece.data"), (307, 311)),(UniCase::ascii("vnd.dece.ttml+xml"), (311, 313)),(UniCase::ascii("vnd.dece.unspecified"), (313, 315)),(UniCase::ascii("vnd.dece.zip"), (315, 317)),(UniCase::ascii("vnd.denovo.fcselayout-link"), (317, 318)),(UniCase::ascii("vnd.dna"), (318, 319)),(UniCase::ascii("vnd.dolby.mlp"), (319, 320)),(UniCase::ascii("vnd.dpgraph"), (320, 321)),(UniCase::ascii("vnd.dreamfactory"), (321, 322)),(UniCase::ascii("vnd.ds-keypoint"), (322, 323)),(UniCase::ascii("vnd.dvb.ait"), (323, 324)),(UniCase::ascii("vnd.dynageo"), (324, 325)),(UniCase::ascii("vnd.ecowin.chart"), (325, 326)),(UniCase::ascii("vnd.enliven"), (326, 327)),(UniCase::ascii("vnd.epson.esf"), (327, 328)),(UniCase::ascii("vnd.epson.msf"), (328, 329)),(UniCase::ascii("vnd.epson.quickanime"), (329, 330)),(UniCase::ascii("vnd.epson.salt"), (330, 331)),(UniCase::ascii("vnd.epson.ssf"), (331, 332)),(UniCase::ascii("vnd.eszigno3+xml"), (332, 334)),(UniCase::ascii("vnd.ezpix-album"), (334, 335)),(UniCase::ascii("vnd.ezpix-package"), (335, 336)),(UniCase::ascii("vnd.fdf"), (336, 337)),(UniCase::ascii("vnd.fdsn.mseed"), (337, 338)),(UniCase::ascii("vnd.fdsn.seed"), (338, 340)),(UniCase::ascii("vnd.flographit"), (340, 341)),(UniCase::ascii("vnd.fluxtime.clip"), (341, 342)),(UniCase::ascii("vnd.framemaker"), (342, 346)),(UniCase::ascii("vnd.frogans.fnc"), (346, 347)),(UniCase::ascii("vnd.frogans.ltf"), (347, 348)),(UniCase::ascii("vnd.fsc.weblaunch"), (348, 349)),(UniCase::ascii("vnd.fujitsu.oasys"), (349, 350)),(UniCase::ascii("vnd.fujitsu.oasys2"), (350, 351)),(UniCase::ascii("vnd.fujitsu.oasys3"), (351, 352)),(UniCase::ascii("vnd.fujitsu.oasysgp"), (352, 353)),(UniCase::ascii("vnd.fujitsu.oasysprs"), (353, 354)),(UniCase::ascii("vnd.fujixerox.ddd"), (354, 355)),(UniCase::ascii("vnd.fujixerox.docuworks"), (355, 356)),(UniCase::ascii("vnd.fujixerox.docuworks.binder"), (356, 357)),(UniCase::ascii("vnd.fuzzysheet"), (357, 358)),(UniCase::ascii("vnd.genomatix.tuxedo"), (358, 359)),(UniCase::ascii("vnd.geogebra.file"), (359, 360)),(UniCase::ascii("vnd.geogebra.tool"), (360, 361)),(UniCase::ascii("vnd.geometry-explorer"), (361, 363)),(UniCase::ascii("vnd.geonext"), (363, 364)),(UniCase::ascii("vnd.geoplan"), (364, 365)),(UniCase::ascii("vnd.geospace"), (365, 366)),(UniCase::ascii("vnd.gmx"), (366, 367)),(UniCase::ascii("vnd.google-apps.document"), (367, 368)),(UniCase::ascii("vnd.google-apps.presentation"), (368, 369)),(UniCase::ascii("vnd.google-apps.spreadsheet"), (369, 370)),(UniCase::ascii("vnd.google-earth.kml+xml"), (370, 371)),(UniCase::ascii("vnd.google-earth.kmz"), (371, 372)),(UniCase::ascii("vnd.grafeq"), (372, 374)),(UniCase::ascii("vnd.groove-account"), (374, 375)),(UniCase::ascii("vnd.groove-help"), (375, 376)),(UniCase::ascii("vnd.groove-identity-message"), (376, 377)),(UniCase::ascii("vnd.groove-injector"), (377, 378)),(UniCase::ascii("vnd.groove-tool-message"), (378, 379)),(UniCase::ascii("vnd.groove-tool-template"), (379, 380)),(UniCase::ascii("vnd.groove-vcard"), (380, 381)),(UniCase::ascii("vnd.hal+xml"), (381, 382)),(UniCase::ascii("vnd.handheld-entertainment+xml"), (382, 383)),(UniCase::ascii("vnd.hbci"), (383, 384)),(UniCase::ascii("vnd.hhe.lesson-player"), (384, 385)),(UniCase::ascii("vnd.hp-hpgl"), (385, 386)),(UniCase::ascii("vnd.hp-hpid"), (386, 387)),(UniCase::ascii("vnd.hp-hps"), (387, 388)),(UniCase::ascii("vnd.hp-jlyt"), (388, 389)),(UniCase::ascii("vnd.hp-pcl"), (389, 390)),(UniCase::ascii("vnd.hp-pclxl"), (390, 391)),(UniCase::ascii("vnd.hydrostatix.sof-data"), (391, 392)),(UniCase::ascii("vnd.ibm.minipay"), (392, 393)),(UniCase::ascii("vnd.ibm.modcap"), (393, 396)),(UniCase::ascii("vnd.ibm.rights-management"), (396, 397)),(UniCase::ascii("vnd.ibm.secure-container"), (397, 398)),(UniCase::ascii("vnd.iccprofile"), (398, 400)),(UniCase::ascii("vnd.igloader"), (400, 401)),(UniCase::ascii("vnd.immervision-ivp"), (401, 402)),(UniCase::ascii("vnd.immervision-ivu"), (402, 403)),(UniCase::ascii("vnd.insors.igm"), (403, 404)),(UniCase::ascii("vnd.intercon.formnet"), (404, 406)),(UniCase::ascii("vnd.intergeo"), (406, 407)),(UniCase::ascii("vnd.intu.qbo"), (407, 408)),(UniCase::ascii("vnd.intu.qfx"), (408, 409)),(UniCase::ascii("vnd.ipunplugged.rcprofile"), (409, 410)),(UniCase::ascii("vnd.irepository.package+xml"), (410, 411)),(UniCase::ascii("vnd.is-xpr"), (411, 412)),(UniCase::ascii("vnd.isac.fcs"), (412, 413)),(UniCase::ascii("vnd.jam"), (413, 414)),(UniCase::ascii("vnd.jcp.javame.midlet-rms"), (414, 415)),(UniCase::ascii("vnd.jisp"), (415, 416)),(UniCase::ascii("vnd.joost.joda-archive"), (416, 417)),(UniCase::ascii("vnd.kahootz"), (417, 419)),(UniCase::ascii("vnd.kde.karbon"), (419, 420)),(UniCase::ascii("vnd.kde.kchart"), (420, 421)),(UniCase::ascii("vnd.kde.kformula"), (421, 422)),(UniCase::ascii("vnd.kde.kivio"), (422, 423)),(UniCase::ascii("vnd.kde.kontour"), (423, 424)),(UniCase::ascii("vnd.kde.kpresenter"), (424, 426)),(UniCase::ascii("vnd.kde.kspread"), (426, 427)),(UniCase::ascii("vnd.kde.kword"), (427, 429)),(UniCase::ascii("vnd.kenameaapp"), (429, 430)),(UniCase::ascii("vnd.kidspiration"), (430, 431)),(UniCase::ascii("vnd.kinar"), (431, 433)),(UniCase::ascii("vnd.koan"), (433, 436)),(UniCase::ascii("vnd.kodak-descriptor"), (436, 437)),(UniCase::ascii("vnd.las.las+xml"), (437, 438)),(UniCase::ascii("vnd.llamagraphics.life-balance.desktop"), (438, 439)),(UniCase::ascii("vnd.llamagraphics.life-balance.exchange+xml"), (439, 440)),(UniCase::ascii("vnd.lotus-1-2-3"), (440, 441)),(UniCase::ascii("vnd.lotus-approach"), (441, 442)),(UniCase::ascii("vnd.lotus-freelance"), (442, 443)),(UniCase::ascii("vnd.lotus-notes"), (443, 444)),(UniCase::ascii("vnd.lotus-organizer"), (444, 445)),(UniCase::ascii("vnd.lotus-screencam"), (445, 446)),(UniCase::ascii("vnd.lotus-wordpro"), (446, 447)),(UniCase::ascii("vnd.macports.portpkg"), (447, 448)),(UniCase::ascii("vnd.mcd"), (448, 449)),(UniCase::ascii("vnd.medcalcdata"), (449, 450)),(UniCase::ascii("vnd.mediastation.cdkey"), (450, 451)),(UniCase::ascii("vnd.mfer"), (451, 452)),(UniCase::ascii("vnd.mfmp"), (452, 453)),(UniCase::ascii("vnd.micrografx.flo"), (453, 454)),(UniCase::ascii("vnd.micrografx.igx"), (454, 455)),(UniCase::ascii("vnd.mif"), (455, 456)),(UniCase::ascii("vnd.mobius.daf"), (456, 457)),(UniCase::ascii("vnd.mobius.dis"), (457, 458)),(UniCase::ascii("vnd.mobius.mbk"), (458, 459)),(UniCase::ascii("vnd.mobius.mqy"), (459, 460)),(UniCase::ascii("vnd.mobius.msl"), (460, 461)),(UniCase::ascii("vnd.mobius.plc"), (461, 462)),(UniCase::ascii("vnd.mobius.txf"), (462, 463)),(UniCase::ascii("vnd.mophun.application"), (463, 464)),(UniCase::ascii("vnd.mophun.certificate"), (464, 465)),(UniCase::ascii("vnd.mozilla.xul+xml"), (465, 466)),(UniCase::ascii("vnd.ms-artgalry"), (466, 467)),(UniCase::ascii("vnd.ms-excel"), (467, 477)),(UniCase::ascii("vnd.ms-excel.addin.macroEnabled.12"), (477, 478)),(UniCase::ascii("vnd.ms-excel.sheet.binary.macroEnabled.12"), (478, 479)),(UniCase::ascii("vnd.ms-excel.sheet.macroEnabled.12"), (479, 480)),(UniCase::ascii("vnd.ms-excel.template.macroEnabled.12"), (480, 481)),(UniCase::ascii("vnd.ms-fontobject"), (481, 482)),(UniCase::ascii("vnd.ms-htmlhelp"), (482, 483)),(UniCase::ascii("vnd.ms-ims"), (483, 484)),(UniCase::ascii("vnd.ms-lrm"), (484, 485)),(UniCase::ascii("vnd.ms-mediapackage"), (485, 486)),(UniCase::ascii("vnd.ms-office.calx"), (486, 487)),(UniCase::ascii("vnd.ms-officetheme"), (487, 488)),(UniCase::ascii("vnd.ms-outlook"), (488, 490)),(UniCase::ascii("vnd.ms-pki.certstore"), (490, 491)),(UniCase::ascii("vnd.ms-pki.pko"), (491, 492)),(UniCase::ascii("vnd.ms-pki.seccat"), (492, 493)),(UniCase::ascii("vnd.ms-pki.stl"), (493, 494)),(UniCase::ascii("vnd.ms-powerpoint"), (494, 499)),(UniCase::ascii("vnd.ms-powerpoint.addin.macroEnabled.12"), (499, 500)),(UniCase::ascii("vnd.ms-powerpoint.presentation.macroEnabled.12"), (500, 501)),(UniCase::ascii("vnd.ms-powerpoint.slide.macroEnabled.12"), (501, 502)),(UniCase::ascii("vnd.ms-powerpoint.slideshow.macroEnabled.12"), (502, 503)),(UniCase::ascii("vnd.ms-powerpoint.template.macroEnabled.12"), (503, 504)),(UniCase::ascii("vnd.ms-project"), (504, 506)),(UniCase::ascii("vnd.ms-visio.viewer"), (506, 507)),(UniCase::ascii("vnd.ms-word.document.macroEnabled.12"), (507, 508)),(UniCase::ascii("vnd.ms-word.template.macroEnabled.12"), (508, 509)),(UniCase::ascii("vnd.ms-works"), (509, 513)),(UniCase::ascii("vnd.ms-wpl"), (513, 514)),(UniCase::ascii("vnd.ms-xpsdocument"), (514, 515)),(UniCase::ascii("vnd.mseq"), (515, 516)),(UniCase::ascii("vnd.musician"), (516, 517)),(UniCase::ascii("vnd.muvee.style"), (517, 518)),(UniCase::ascii("vnd.mynfc"), (518, 519)),(UniCase::ascii("vnd.neurolanguage.nlu"), (519, 520)),(UniCase::ascii("vnd.nitf"), (520, 522)),(UniCase::ascii("vnd.noblenet-directory"), (522, 523)),(UniCase::ascii("vnd.noblenet-sealer"), (523, 524)),(UniCase::ascii("vnd.noblenet-web"), (524, 525)),(UniCase::ascii("vnd.nokia.n-gage.data"), (525, 526)),(UniCase::ascii("vnd.nokia.n-gage.symbian.install"), (526, 527)),(UniCase::ascii("vnd.nokia.radio-preset"), (527, 528)),(UniCase::ascii("vnd.nokia.radio-presets"), (528, 529)),(UniCase::ascii("vnd.novadigm.edm"), (529, 530)),(UniCase::ascii("vnd.novadigm.edx"), (530, 531)),(UniCase::ascii("vnd.novadigm.ext"), (531, 532)),(UniCase::ascii("vnd.oasis.opendocument.chart"), (532, 533)),(UniCase::ascii("vnd.oasis.opendocument.chart-template"), (533, 534)),(UniCase::ascii("vnd.oasis.opendocument.database"), (534, 535)),(UniCase::ascii("vnd.oasis.opendocument.formula"), (535, 536)),(UniCase::ascii("vnd.oasis.opendocument.formula-template"), (536, 537)),(UniCase::ascii("vnd.oasis.opendocument.graphics"), (537, 538)),(UniCase::ascii("vnd.oasis.opendocument.graphics-template"), (538, 539)),(UniCase::ascii("vnd.oasis.opendocument.image"), (539, 540)),(UniCase::ascii("vnd.oasis.opendocument.image-template"), (540, 541)),(UniCase::ascii("vnd.oasis.opendocument.presentation"), (541, 542)),(UniCase::ascii("vnd.oasis.opendocument.presentation-template"), (542, 543)),(UniCase::ascii("vnd.oasis.opendocument.spreadsheet"), (543, 544)),(UniCase::ascii("vnd.oasis.opendocument.spreadsheet-template"), (544, 545)),(UniCase::ascii("vnd.oasis.opendocument.text"), (545, 546)),(UniCase::ascii("vnd.oasis.opendocument.text-master"), (546, 547)),(UniCase::ascii("vnd.oasis.opendocument.text-template"), (547, 548)),(UniCase::ascii("vnd.oasis.opendocument.text-web"), (548, 549)),(UniCase::ascii("vnd.olpc-sugar"), (549, 550)),(UniCase::ascii("vnd.oma.dd2+xml"), (550, 551)),(UniCase::ascii("vnd.openofficeorg.extension"), (551, 552)),(UniCase::ascii("vnd.openxmlformats-officedocument.presentationml.presentation"), (552, 553)),(UniCase::ascii("vnd.openxmlformats-officedocument.presentationml.slide"), (553, 554)),(UniCase::ascii("vnd.openxmlformats-officedocument.presentationml.slideshow"), (554, 555)),(UniCase::ascii("vnd.openxmlformats-officedocument.presentationml.template"), (555, 556)),(UniCase::ascii("vnd.openxmlformats-officedocument.spreadsheetml.sheet"), (556, 557)),(UniCase::ascii("vnd.openxmlformats-officedocument.spreadsheetml.template"), (557, 558)),(UniCase::ascii("vnd.openxmlformats-officedocument.wordprocessingml.document"), (558, 559)),(UniCase::ascii("vnd.openxmlformats-officedocument.wordprocessingml.template"), (559, 560)),(UniCase::ascii("vnd.osgeo.mapguide.package"), (560, 561)),(UniCase::ascii("vnd.osgi.dp"), (561, 562)),(UniCase::ascii("vnd.osgi.subsystem"), (562, 563)),(UniCase::ascii("vnd.palm"), (563, 566)),(UniCase::ascii("vnd.pawaafile"), (566, 567)),(UniCase::ascii("vnd.pg.format"), (567, 568)),(UniCase::ascii("vnd.pg.osasli"), (568, 569)),(UniCase::ascii("vnd.picsel"), (569, 570)),(UniCase::ascii("vnd.pmi.widget"), (570, 571)),(UniCase::ascii("vnd.pocketlearn"), (571, 572)),(UniCase::ascii("vnd.powerbuilder6"), (572, 573)),(UniCase::ascii("vnd.previewsystems.box"), (573, 574)),(UniCase::ascii("vnd.proteus.magazine"), (574, 575)),(UniCase::ascii("vnd.publishare-delta-tree"), (575, 576)),(UniCase::ascii("vnd.pvi.ptid1"), (576, 577)),(UniCase::ascii("vnd.quark.quarkxpress"), (577, 582)),(UniCase::ascii("vnd.realvnc.bed"), (582, 583)),(UniCase::ascii("vnd.recordare.musicxml"), (583, 584)),(UniCase::ascii("vnd.recordare.musicxml+xml"), (584, 585)),(UniCase::ascii("vnd.rig.cryptonote"), (585, 586)),(UniCase::ascii("vnd.rn-realmedia"), (586, 587)),(UniCase::ascii("vnd.rn-realmedia-vbr"), (587, 588)),(UniCase::ascii("vnd.rn-rn_music_package"), (588, 589)),(UniCase::ascii("vnd.route66.link66+xml"), (589, 590)),(UniCase::ascii("vnd.sailingtracker.track"), (590, 591)),(UniCase::ascii("vnd.seemail"), (591, 592)),(UniCase::ascii("vnd.sema"), (592, 593)),(UniCase::ascii("vnd.semd"), (593, 594)),(UniCase::ascii("vnd.semf"), (594, 595)),(UniCase::ascii("vnd.shana.informed.formdata"), (595, 596)),(UniCase::ascii("vnd.shana.informed.formtemplate"), (596, 597)),(UniCase::ascii("vnd.shana.informed.interchange"), (597, 598)),(UniCase::ascii("vnd.shana.informed.package"), (598, 599)),(UniCase::ascii("vnd.simtech-mindmapper"), (599, 601)),(UniCase::ascii("vnd.smart.teacher"), (601, 602)),(UniCase::ascii("vnd.solent.sdkm+xml"), (602, 604)),(UniCase::ascii("vnd.spotfire.dxp"), (604, 605)),(UniCase::ascii("vnd.spotfire.sfs"), (605, 606)),(UniCase::ascii("vnd.stardivision.calc"), (606, 607)),(UniCase::ascii("vnd.stardivision.draw"), (607, 608)),(UniCase::ascii("vnd.stardivision.impress"), (608, 609)),(UniCase::ascii("vnd.stardivision.math"), (609, 610)),(UniCase::ascii("vnd.stardivision.writer"), (610, 612)),(UniCase::ascii("vnd.stardivision.writer-global"), (612, 613)),(UniCase::ascii("vnd.stepmania.package"), (613, 614)),(UniCase::ascii("vnd.stepmania.stepchart"), (614, 615)),(UniCase::ascii("vnd.sun.wadl+xml"), (615, 616)),(UniCase::ascii("vnd.sun.xml.calc"), (616, 617)),(UniCase::ascii("vnd.sun.xml.calc.template"), (617, 618)),(UniCase::ascii("vnd.sun.xml.draw"), (618, 619)),(UniCase::ascii("vnd.sun.xml.draw.template"), (619, 620)),(UniCase::ascii("vnd.sun.xml.impress"), (620, 621)),(UniCase::ascii("vnd.sun.xml.impress.template"), (621, 622)),(UniCase::ascii("vnd.sun.xml.math"), (622, 623)),(UniCase::ascii("vnd.sun.xml.writer"), (623, 624)),(UniCase::ascii("vnd.sun.xml.writer.global"), (624, 625)),(UniCase::ascii("vnd.sun.xml.writer.template"), (625, 626)),(UniCase::ascii("vnd.sus-calendar"), (626, 628)),(UniCase::ascii("vnd.svd"), (628, 629)),(UniCase::ascii("vnd.symbian.install"), (629, 631)),(UniCase::ascii("vnd.syncml+xml"), (631, 632)),(UniCase::ascii("vnd.syncml.dm+wbxml"), (632, 633)),(UniCase::ascii("vnd.syncml.dm+xml"), (633, 634)),(UniCase::ascii("vnd.tao.intent-module-archive"), (634, 635)),(UniCase::ascii("vnd.tcpdump.pcap"), (635, 638)),(UniCase::ascii("vnd.tmobile-livetv"), (638, 639)),(UniCase::ascii("vnd.trid.tpt"), (639, 640)),(UniCase::ascii("vnd.triscape.mxs"), (640, 641)),(UniCase::ascii("vnd.trueapp"), (641, 642)),(UniCase::ascii("vnd.ufdl"), (642, 644)),(UniCase::ascii("vnd.uiq.theme"), (644, 645)),(UniCase::ascii("vnd.umajin"), (645, 646)),(UniCase::ascii("vnd.unity"), (646, 647)),(UniCase::ascii("vnd.uoml+xml"), (647, 648)),(UniCase::ascii("vnd.vcx"), (648, 649)),(UniCase::ascii("vnd.visio"), (649, 655)),(UniCase::ascii("vnd.visionary"), (655, 656)),(UniCase::ascii("vnd.vsf"), (656, 657)),(UniCase::ascii("vnd.wap.wbxml"), (657, 658)),(UniCase::ascii("vnd.wap.wmlc"), (658, 659)),(UniCase::ascii("vnd.wap.wmlscriptc"), (659, 660)),(UniCase::ascii("vnd.webturbo"), (660, 661)),(UniCase::ascii("vnd.wolfram.player"), (661, 662)),(UniCase::ascii("vnd.wordperfect"), (662, 663)),(UniCase::ascii("vnd.wqd"), (663, 664)),(UniCase::ascii("vnd.wt.stf"), (664, 665)),(UniCase::ascii("vnd.xara"), (665, 666)),(UniCase::ascii("vnd.xfdl"), (666, 667)),(UniCase::ascii("vnd.yamaha.hv-dic"), (667, 668)),(UniCase::ascii("vnd.yamaha.hv-script"), (668, 669)),(UniCase::ascii("vnd.yamaha.hv-voice"), (669, 670)),(UniCase::ascii("vnd.yamaha.openscoreformat"), (670, 671)),(UniCase::ascii("vnd.yamaha.openscoreformat.osfpvg+xml"), (671, 672)),(UniCase::ascii("vnd.yamaha.smaf-audio"), (672, 673)),(UniCase::ascii("vnd.yamaha.smaf-phrase"), (673, 674)),(UniCase::ascii("vnd.yellowriver-custom-menu"), (674, 675)),(UniCase::ascii("vnd.zul"), (675, 677)),(UniCase::ascii("vnd.zzazz.deck+xml"), (677, 678)),(UniCase::ascii("voicexml+xml"), (678, 679)),(UniCase::ascii("vsix"), (679, 680)),(UniCase::ascii("wasm"), (680, 681)),(UniCase::ascii("widget"), (681, 682)),(UniCase::ascii("windows-library+xml"), (682, 683)),(UniCase::ascii("windows-search-connector+xml"), (683, 684)),(UniCase::ascii("winhlp"), (684, 685)),(UniCase::ascii("wlmoviemaker"), (685, 686)),(UniCase::ascii("wspolicy+xml"), (686, 687)),(UniCase::ascii("x-7z-compressed"), (687, 688)),(UniCase::ascii("x-abiword"), (688, 689)),(UniCase::ascii("x-ace-compressed"), (689, 690)),(UniCase::ascii("x-arj"), (690, 691)),(UniCase::ascii("x-authorware-bin"), (691, 694)),(UniCase::ascii("x-authorware-map"), (694, 695)),(UniCase::ascii("x-authorware-seg"), (695, 696)),(UniCase::ascii("x-bcpio"), (696, 697)),(UniCase::ascii("x-bittorrent"), (697, 698)),(UniCase::ascii("x-blorb"), (698, 700)),(UniCase::ascii("x-bridge-url"), (700, 701)),(UniCase::ascii("x-bzip"), (701, 702)),(UniCase::ascii("x-bzip2"), (702, 704)),(UniCase::ascii("x-cbr"), (704, 709)),(UniCase::ascii("x-cdf"), (709, 710)),(UniCase::ascii("x-cdlink"), (710, 711)),(UniCase::ascii("x-cfs-compressed"), (711, 712)),(UniCase::ascii("x-chat"), (712, 713)),(UniCase::ascii("x-chess-pgn"), (713, 714)),(UniCase::ascii("x-chrome-extension"), (714, 715)),(UniCase::ascii("x-cocoa"), (715, 716)),(UniCase::ascii("x-compress"), (716, 717)),(UniCase::ascii("x-compressed"), (717, 718)),(UniCase::ascii("x-cpio"), (718, 719)),(UniCase::ascii("x-csh"), (719, 720)),(UniCase::ascii("x-debian-package"), (720, 721)),(UniCase::ascii("x-dgc-compressed"), (721, 722)),(UniCase::ascii("x-director"), (722, 731)),(UniCase::ascii("x-doom"), (731, 732)),(UniCase::ascii("x-dtbncx+xml"), (732, 733)),(UniCase::ascii("x-dtbook+xml"), (733, 734)),(UniCase::ascii("x-dtbresource+xml"), (734, 735)),(UniCase::ascii("x-dvi"), (735, 736)),(UniCase::ascii("x-dxf"), (736, 737)),(UniCase::ascii("x-eva"), (737, 738)),(UniCase::ascii("x-font-bdf"), (738, 739)),(UniCase::ascii("x-font-ghostscript"), (739, 740)),(UniCase::ascii("x-font-linux-psf"), (740, 741)),(UniCase::ascii("x-font-pcf"), (741, 742)),(UniCase::ascii("x-font-snf"), (742, 743)),(UniCase::ascii("x-font-ttf"), (743, 744)),(UniCase::ascii("x-font-type1"), (744, 745)),(UniCase::ascii("x-freearc"), (745, 746)),(UniCase::ascii("x-gca-compressed"), (746, 747)),(UniCase::ascii("x-glulx"), (747, 748)),(UniCase::ascii("x-gnumeric"), (748, 749)),(UniCase::ascii("x-gramps-xml"), (749, 750)),(UniCase::ascii("x-gtar"), (750, 751)),(UniCase::ascii("x-hdf"), (751, 752)),(UniCase::ascii("x-httpd-php"), (752, 753)),(UniCase::ascii("x-install-instructions"), (753, 754)),(UniCase::ascii("x-internet-signup"), (754, 756)),(UniCase::ascii("x-iphone"), (756, 757)),(UniCase::ascii("x-itunes-ipa"), (757, 758)),(UniCase::ascii("x-itunes-ipg"), (758, 759)),(UniCase::ascii("x-itunes-ipsw"), (759, 760)),(UniCase::ascii("x-itunes-ite"), (760, 761)),(UniCase::ascii("x-itunes-itlp"), (761, 762)),(UniCase::ascii("x-itunes-itms"), (762, 763)),(UniCase::ascii("x-itunes-itpc"), (763, 764)),(UniCase::ascii("x-java-applet"), (764, 765)),(UniCase::ascii("x-java-archive-diff"), (765, 766)),(UniCase::ascii("x-java-jnlp-file"), (766, 767)),(UniCase::ascii("x-koan"), (767, 768)),(UniCase::ascii("x-latex"), (768, 769)),(UniCase::ascii("x-lua-bytecode"), (769, 770)),(UniCase::ascii("x-lzh-compressed"), (770, 771)),(UniCase::ascii("x-makeself"), (771, 772)),(UniCase::ascii("x-mie"), (772, 773)),(UniCase::ascii("x-miva-compiled"), (773, 774)),(UniCase::ascii("x-mmxp"), (774, 775)),(UniCase::ascii("x-mobipocket-ebook"), (775, 777)),(UniCase::ascii("x-ms-application"), (777, 778)),(UniCase::ascii("x-ms-license"), (778, 779)),(UniCase::ascii("x-ms-manifest"), (779, 780)),(UniCase::ascii("x-ms-reader"), (780, 781)),(UniCase::ascii("x-ms-shortcut"), (781, 782)),(UniCase::ascii("x-ms-vsto"), (782, 783)),(UniCase::ascii("x-ms-wmd"), (783, 784)),(UniCase::ascii("x-ms-wmz"), (784, 785)),(UniCase::ascii("x-ms-xbap"), (785, 786)),(UniCase::ascii("x-msaccess"), (786, 787)),(UniCase::ascii("x-msbinder"), (787, 788)),(UniCase::ascii("x-mscardfile"), (788, 789)),(UniCase::ascii("x-msclip"), (789, 790)),(UniCase::ascii("x-msdownload"), (790, 793)),(UniCase::ascii("x-msmediaview"), (793, 796)),(UniCase::ascii("x-msmetafile"), (796, 798)),(UniCase::ascii("x-msmoney"), (798, 799)),(UniCase::ascii("x-mspublisher"), (799, 800)),(UniCase::ascii("x-msschedule"), (800, 801)),(UniCase::ascii("x-msterminal"), (801, 802)),(UniCase::ascii("x-mswrite"), (802, 803)),(UniCase::ascii("x-netcdf"), (803, 804)),(UniCase::ascii("x-ns-proxy-autoconfig"), (804, 805)),(UniCase::ascii("x-nzb"), (805, 806)),(UniCase::ascii("x-oleobject"), (806, 807)),(UniCase::ascii("x-perfmon"), (807, 812)),(UniCase::ascii("x-perl"), (812, 814)),(UniCase::ascii("x-pkcs12"), (814, 816)),(UniCase::ascii("x-pkcs7-certificates"), (816, 818)),(UniCase::ascii("x-pkcs7-certreqresp"), (818, 819)),(UniCase::ascii("x-podcast"), (819, 820)),(UniCase::ascii("x-quicktimeplayer"), (820, 821)),(UniCase::ascii("x-rar-compressed"), (821, 822)),(UniCase::ascii("x-research-info-systems"), (822, 823)),(UniCase::ascii("x-safari-safariextz"), (823, 824)),(UniCase::ascii("x-safari-webarchive"), (824, 825)),(UniCase::ascii("x-sgimb"), (825, 826)),(UniCase::ascii("x-sh"), (826, 827)),(UniCase::ascii("x-shar"), (827, 828)),(UniCase::ascii("x-shockwave-flash"), (828, 830)),(UniCase::ascii("x-silverlight-app"), (830, 831)),(UniCase::ascii("x-smaf"), (831, 832)),(UniCase::ascii("x-sql"), (832, 833)),(UniCase::ascii("x-stuffit"), (833, 834)),(UniCase::ascii("x-stuffitx"), (834, 835)),(UniCase::ascii("x-subrip"), (835, 836)),(UniCase::ascii("x-sv4cpio"), (836, 837)),(UniCase::ascii("x-sv4crc"), (837, 838)),(UniCase::ascii("x-t3vm-image"), (838, 839)),(UniCase::ascii("x-tads"), (839, 840)),(UniCase::ascii("x-tar"), (840, 841)),(UniCase::ascii("x-tcl"), (841, 843)),(UniCase::ascii("x-tex"), (843, 844)),(UniCase::ascii("x-tex-tfm"), (844, 845)),(UniCase::ascii("x-texinfo"), (845, 847)),(UniCase::ascii("x-tgif"), (847, 848)),(UniCase::ascii("x-troff"), (848, 851)),(UniCase::ascii("x-troff-man"), (851, 852)),(UniCase::ascii("x-troff-me"), (852, 853)),(UniCase::ascii("x-troff-ms"), (853, 854)),(UniCase::ascii("x-ustar"), (854, 855)),(UniCase::ascii("x-virtualbox-hdd"), (855, 856)),(UniCase::ascii("x-virtualbox-ova"), (856, 857)),(UniCase::ascii("x-virtualbox-ovf"), (857, 858)),(UniCase::ascii("x-virtualbox-vbox"), (858, 859)),(UniCase::ascii("x-virtualbox-vbox-extpack"), (859, 860)),(UniCase::ascii("x-virtualbox-vdi"), (860, 861)),(UniCase::ascii("x-virtualbox-vhd"), (861, 862)),(UniCase::ascii("x-virtualbox-vmdk"), (862, 863)),(UniCase::ascii("x-wais-source"), (863, 864)),(UniCase::ascii("x-web-app-manifest+json"), (864, 865)),(UniCase::ascii("x-wlpg-detect"), (865, 866)),(UniCase::ascii("x-wlpg3-detect"), (866, 867)),(UniCase::ascii("x-x509-ca-cert"), (867, 871)),(UniCase::ascii("x-xfig"), (871, 872)),(UniCase::ascii("x-xliff+xml"), (872, 873)),(UniCase::ascii("x-xpinstall"), (873, 874)),(UniCase::ascii("x-xz"), (874, 875)),(UniCase::ascii("x-zmachine"), (875, 883)),(UniCase::ascii("xaml+xml"), (883, 884)),(UniCase::ascii("xcap-diff+xml"), (884, 885)),(UniCase::ascii("xenc+xml"), (885, 886)),(UniCase::ascii("xhtml+xml"), (886, 888)),(UniCase::ascii("xml"), (888, 932)),(UniCase::ascii("xop+xml"), (932, 933)),(UniCase::ascii("xproc+xml"), (933, 934)),(UniCase::ascii("xspf+xml"), (934, 935)),(UniCase::ascii("xv+xml"), (935, 939)),(UniCase::ascii("yang"), (939, 940)),(UniCase::ascii("yin+xml"), (940, 941)),(UniCase::ascii("zip"), (941, 942)),] }),(UniCase::ascii("audio"), TopLevelExts { start: 942, end: 1008, subs: &[(UniCase::ascii("aac"), (942, 944)),(UniCase::ascii("ac3"), (944, 945)),(UniCase::ascii("aiff"), (945, 949)),(UniCase::ascii("annodex"), (949, 950)),(UniCase::ascii("audible"), (950, 951)),(UniCase::ascii("basic"), (951, 953)),(UniCase::ascii("flac"), (953, 954)),(UniCase::ascii("m4a"), (954, 955)),(UniCase::ascii("m4b"), (955, 956)),(UniCase::ascii("m4p"), (956, 957)),(UniCase::ascii("mid"), (957, 960)),(UniCase::ascii("midi"), (960, 961)),(UniCase::ascii("mp4"), (961, 962)),(UniCase::ascii("mpeg"), (962, 968)),(UniCase::ascii("ogg"), (968, 972)),(UniCase::ascii("s3m"), (972, 973)),(UniCase::ascii("scpls"), (973, 974)),(UniCase::ascii("silk"), (974, 975)),(UniCase::ascii("vnd.audible.aax"), (975, 976)),(UniCase::ascii("vnd.dece.audio"), (976, 978)),(UniCase::ascii("vnd.digital-winds"), (978, 979)),(UniCase::ascii("vnd.dlna.adts"), (979, 980)),(UniCase::ascii("vnd.dra"), (980, 981)),(UniCase::ascii("vnd.dts"), (981, 982)),(UniCase::ascii("vnd.dts.hd"), (982, 983)),(UniCase::ascii("vnd.lucent.voice"), (983, 984)),(UniCase::ascii("vnd.ms-playready.media.pya"), (984, 985)),(UniCase::ascii("vnd.nuera.ecelp4800"), (985, 986)),(UniCase::ascii("vnd.nuera.ecelp7470"), (986, 987)),(UniCase::ascii("vnd.nuera.ecelp9600"), (987, 988)),(UniCase::ascii("vnd.rip"), (988, 989)),(UniCase::ascii("wav"), (989, 991)),(UniCase::ascii("webm"), (991, 992)),(UniCase::ascii("x-caf"), (992, 993)),(UniCase::ascii("x-gsm"), (993, 994)),(UniCase::ascii("x-m4r"), (994, 995)),(UniCase::ascii("x-matroska"), (995, 996)),(UniCase::ascii("x-mpegurl"), (996, 998)),(UniCase::ascii("x-ms-wax"), (998, 999)),(UniCase::ascii("x-ms-wma"), (999, 1000)),(UniCase::ascii("x-pn-realaudio"), (1000, 1002)),(UniCase::ascii("x-pn-realaudio-plugin"), (1002, 1003)),(UniCase::ascii("x-sd2"), (1003, 1004)),(UniCase::ascii("x-smd"), (1004, 1007)),(UniCase::ascii("xm"), (1007, 1008)),] }),(UniCase::ascii("chemical"), TopLevelExts { start: 1008, end: 1014, subs: &[(UniCase::ascii("x-cdx"), (1008, 1009)),(UniCase::ascii("x-cif"), (1009, 1010)),(UniCase::ascii("x-cmdf"), (1010, 1011)),(UniCase::ascii("x-cml"), (1011, 1012)),(UniCase::ascii("x-csml"), (1012, 1013)),(UniCase::ascii("x-xyz"), (1013, 1014)),] }),(UniCase::ascii("drawing"), TopLevelExts { start: 1014, end: 1015, subs: &[(UniCase::ascii("x-dwf"), (1014, 1015)),] }),(UniCase::ascii("font"), TopLevelExts { start: 1015, end: 1018, subs: &[(UniCase::ascii("collection"), (1015, 1016)),(UniCase::ascii("ttf"), (1016, 1017)),(UniCase::ascii("woff2"), (1017, 1018)),] }),(UniCase::ascii("image"), TopLevelExts { start: 1018, end: 1099, subs: &[(UniCase::ascii("apng"), (1018, 1019)),(UniCase::ascii("avif"), (1019, 1020)),(UniCase::ascii("avif-sequence"), (1020, 1021)),(UniCase::ascii("bmp"), (1021, 1023)),(UniCase::ascii("cgm"), (1023, 1024)),(UniCase::ascii("cis-cod"), (1024, 1025)),(UniCase::ascii("g3fax"), (1025, 1026)),(UniCase::ascii("gif"), (1026, 1027)),(UniCase::ascii("heic"), (1027, 1028)),(UniCase::ascii("heic-sequence"), (1028, 1029)),(UniCase::ascii("heif"), (1029, 1030)),(UniCase::ascii("heif-sequence"), (1030, 1031)),(UniCase::ascii("ief"), (1031, 1032)),(UniCase::ascii("jp2"), (1032, 1034)),(UniCase::ascii("jpeg"), (1034, 1038)),(UniCase::ascii("jpm"), (1038, 1039)),(UniCase::ascii("jpx"), (1039, 1041)),(UniCase::ascii("jxl"), (1041, 1042)),(UniCase::ascii("ktx"), (1042, 1043)),(UniCase::ascii("pict"), (1043, 1046)),(UniCase::ascii("png"), (1046, 1048)),(UniCase::ascii("prs.btif"), (1048, 1049)),(UniCase::ascii("sgi"), (1049, 1050)),(UniCase::ascii("svg+xml"), (1050, 1052)),(UniCase::ascii("tiff"), (1052, 1054)),(UniCase::ascii("vnd.dece.graphic"), (1054, 1058)),(UniCase::ascii("vnd.djvu"), (1058, 1060)),(UniCase::ascii("vnd.fastbidsheet"), (1060, 1061)),(UniCase::ascii("vnd.fpx"), (1061, 1062)),(UniCase::ascii("vnd.fst"), (1062, 1063)),(UniCase::ascii("vnd.fujixerox.edmics-mmr"), (1063, 1064)),(UniCase::ascii("vnd.fujixerox.edmics-rlc"), (1064, 1065)),(UniCase::ascii("vnd.ms-modi"), (1065, 1066)),(UniCase::ascii("vnd.ms-photo"), (1066, 1067)),(UniCase::ascii("vnd.net-fpx"), (1067, 1068)),(UniCase::ascii("vnd.radiance"), (1068, 1069)),(UniCase::ascii("vnd.rn-realflash"), (1069, 1070)),(UniCase::ascii("vnd.wap.wbmp"), (1070, 1071)),(UniCase::ascii("vnd.xiff"), (1071, 1072)),(UniCase::ascii("webp"), (1072, 1073)),(UniCase::ascii("x-3ds"), (1073, 1074)),(UniCase::ascii("x-cmu-raster"), (1074, 1075)),(UniCase::ascii("x-cmx"), (1075, 1076)),(UniCase::ascii("x-freehand"), (1076, 1081)),(UniCase::ascii("x-icon"), (1081, 1082)),(UniCase::ascii("x-jg"), (1082, 1083)),(UniCase::ascii("x-jng"), (1083, 1084)),(UniCase::ascii("x-macpaint"), (1084, 1087)),(UniCase::ascii("x-mrsid-image"), (1087, 1088)),(UniCase::ascii("x-portable-anymap"), (1088, 1089)),(UniCase::ascii("x-portable-bitmap"), (1089, 1090)),(UniCase::ascii("x-portable-graymap"), (1090, 1091)),(UniCase::ascii("x-portable-pixmap"), (1091, 1092)),(UniCase::ascii("x-quicktime"), (1092, 1094)),(UniCase::ascii("x-rgb"), (1094, 1095)),(UniCase::ascii("x-tga"), (1095, 1096)),(UniCase::ascii("x-xbitmap"), (1096, 1097)),(UniCase::ascii("x-xpixmap"), (1097, 1098)),(UniCase::ascii("x-xwindowdump"), (1098, 1099)),] }),(UniCase::ascii("message"), TopLevelExts { start: 1099, end: 1109, subs: &[(UniCase::ascii("disposition-notification"), (1099, 1100)),(UniCase::ascii("global"), (1100, 1101)),(UniCase::ascii("global-delivery-status"), (1101, 1102)),(UniCase::ascii("global-disposition-notification"), (1102, 1103)),(UniCase::ascii("global-headers"), (1103, 1104)),(UniCase::ascii("rfc822"), (1104, 1109)),] }),(UniCase::ascii("model"), TopLevelExts { start: 1109, end: 1127, subs: &[(UniCase::ascii("gltf+json"), (1109, 1110)),(UniCase::ascii("gltf-binary"), (1110, 1111)),(UniCase::ascii("iges"), (1111, 1113)),(UniCase::ascii("mesh"), (1113, 1116)),(UniCase::ascii("vnd.collada+xml"), (1116, 1117)),(UniCase::ascii("vnd.gdl"), (1117, 1118)),(UniCase::ascii("vnd.gtw"), (1118, 1119)),(UniCase::ascii("vnd.vtu"), (1119, 1120)),(UniCase::ascii("vrml"), (1120, 1121)),(UniCase::ascii("x3d+binary"), (1121, 1123)),(UniCase::ascii("x3d+vrml"), (1123, 1125)),(UniCase::ascii("x3d+xml"), (1125, 1127)),] }),(UniCase::ascii("text"), TopLevelExts { start: 1127, end: 1305, subs: &[(UniCase::ascii("cache-manifest"), (1127, 1128)),(UniCase::ascii("calendar"), (1128, 1129)),(UniCase::ascii("coffeescript"), (1129, 1131)),(UniCase::ascii("css"), (1131, 1132)),(UniCase::ascii("csv"), (1132, 1133)),(UniCase::ascii("dlm"), (1133, 1134)),(UniCase::ascii("gemini"), (1134, 1136)),(UniCase::ascii("h323"), (1136, 1137)),(UniCase::ascii("html"), (1137, 1141)),(UniCase::ascii("iuls"), (1141, 1142)),(UniCase::ascii("jade"), (1142, 1143)),(UniCase::ascii("jscript"), (1143, 1144)),(UniCase::ascii("less"), (1144, 1145)),(UniCase::ascii("markdown"), (1145, 1147)),(UniCase::ascii("mathml"), (1147, 1148)),(UniCase::ascii("n3"), (1148, 1149)),(UniCase::ascii("plain"), (1149, 1217)),(UniCase::ascii("prs.lines.tag"), (1217, 1218)),(UniCase::ascii("richtext"), (1218, 1219)),(UniCase::ascii("scriptlet"), (1219, 1221)),(UniCase::ascii("sgml"), (1221, 1223)),(UniCase::ascii("shex"), (1223, 1224)),(UniCase::ascii("slim"), (1224, 1226)),(UniCase::ascii("stylus"), (1226, 1228)),(UniCase::ascii("tab-separated-values"), (1228, 1229)),(UniCase::ascii("turtle"), (1229, 1230)),(UniCase::ascii("uri-list"), (1230, 1233)),(UniCase::ascii("vbscript"), (1233, 1234)),(UniCase::ascii("vcard"), (1234, 1235)),(UniCase::ascii("vnd.curl"), (1235, 1236)),(UniCase::ascii("vnd.curl.dcurl"), (1236, 1237)),(UniCase::ascii("vnd.curl.mcurl"), (1237, 1238)),(UniCase::ascii("vnd.curl.scurl"), (1238, 1239)),(UniCase::ascii("vnd.dvb.subtitle"), (1239, 1240)),(UniCase::ascii("vnd.fly"), (1240, 1241)),(UniCase::ascii("vnd.fmi.flexstor"), (1241, 1242)),(UniCase::ascii("vnd.graphviz"), (1242, 1243)),(UniCase::ascii("vnd.in3d.3dml"), (1243, 1244)),(UniCase::ascii("vnd.in3d.spot"), (1244, 1245)),(UniCase::ascii("vnd.sun.j2me.app-descriptor"), (1245, 1246)),(UniCase::ascii("vnd.wap.wml"), (1246, 1247)),(UniCase::ascii("vnd.wap.wmlscript"), (1247, 1248)),(UniCase::ascii("vtt"), (1248, 1249)),(UniCase::ascii("webviewhtml"), (1249, 1250)),(UniCase::ascii("x-c"), (1250, 1251)),(UniCase::ascii("x-component"), (1251, 1252)),(UniCase::ascii("x-fortran"), (1252, 1256)),(UniCase::ascii("x-gherkin"), (1256, 1257)),(UniCase::ascii("x-handlebars-template"), (1257, 1258)),(UniCase::ascii("x-hdml"), (1258, 1259)),(UniCase::ascii("x-html-insertion"), (1259, 1261)),(UniCase::ascii("x-lua"), (1261, 1262)),(UniCase::ascii("x-markdown"), (1262, 1264)),(UniCase::ascii("x-ms-contact"), (1264, 1265)),(UniCase::ascii("x-ms-group"), (1265, 1266)),(UniCase::ascii("x-ms-iqy"), (1266, 1267)),(UniCase::ascii("x-ms-rqy"), (1267, 1268)),(UniCase::ascii("x-nfo"), (1268, 1269)),(UniCase::ascii("x-opml"), (1269, 1270)),(UniCase::ascii("x-pascal"), (1270, 1272)),(UniCase::ascii("x-processing"), (1272, 1273)),(UniCase::ascii("x-rust"), (1273, 1274)),(UniCase::ascii("x-sass"), (1274, 1275)),(UniCase::ascii("x-scss"), (1275, 1276)),(UniCase::ascii("x-setext"), (1276, 1277)),(UniCase::ascii("x-sfv"), (1277, 1278)),(UniCase::ascii("x-suse-ymp"), (1278, 1279)),(UniCase::ascii("x-toml"), (1279, 1280)),(UniCase::ascii("x-uuencode"), (1280, 1281)),(UniCase::ascii("x-vcard"), (1281, 1282)),(UniCase::ascii("x-yaml"), (1282, 1284)),(UniCase::ascii("xml"), (1284, 1305)),] }),(UniCase::ascii("video"), TopLevelExts { start: 1305, end: 1386, subs: &[(UniCase::ascii("3gpp"), (1305, 1307)),(UniCase::ascii("3gpp2"), (1307, 1309)),(UniCase::ascii("annodex"), (1309, 1310)),(UniCase::ascii("divx"), (1310, 1311)),(UniCase::ascii("h261"), (1311, 1312)),(UniCase::ascii("h263"), (1312, 1313)),(UniCase::ascii("h264"), (1313, 1314)),(UniCase::ascii("jpeg"), (1314, 1315)),(UniCase::ascii("jpm"), (1315, 1316)),(UniCase::ascii("mj2"), (1316, 1318)),(UniCase::ascii("mp4"), (1318, 1321)),(UniCase::ascii("mpeg"), (1321, 1332)),(UniCase::ascii("ogg"), (1332, 1333)),(UniCase::ascii("quicktime"), (1333, 1336)),(UniCase::ascii("vnd.dece.hd"), (1336, 1338)),(UniCase::ascii("vnd.dece.mobile"), (1338, 1340)),(UniCase::ascii("vnd.dece.pd"), (1340, 1342)),(UniCase::ascii("vnd.dece.sd"), (1342, 1344)),(UniCase::ascii("vnd.dece.video"), (1344, 1346)),(UniCase::ascii("vnd.dlna.mpeg-tts"), (1346, 1351)),(UniCase::ascii("vnd.dvb.file"), (1351, 1352)),(UniCase::ascii("vnd.fvt"), (1352, 1353)),(UniCase::ascii("vnd.mpegurl"), (1353, 1355)),(UniCase::ascii("vnd.ms-playready.media.pyv"), (1355, 1356)),(UniCase::ascii("vnd.uvvu.mp4"), (1356, 1358)),(UniCase::ascii("vnd.vivo"), (1358, 1359)),(UniCase::ascii("webm"), (1359, 1360)),(UniCase::ascii("x-dv"), (1360, 1362)),(UniCase::ascii("x-f4v"), (1362, 1363)),(UniCase::ascii("x-fli"), (1363, 1364)),(UniCase::ascii("x-flv"), (1364, 1365)),(UniCase::ascii("x-ivf"), (1365, 1366)),(UniCase::ascii("x-la-asf"), (1366, 1368)),(UniCase::ascii("x-m4v"), (1368, 1369)),(UniCase::ascii("x-matroska"), (1369, 1372)),(UniCase::ascii("x-mng"), (1372, 1373)),(UniCase::ascii("x-ms-asf"), (1373, 1377)),(UniCase::ascii("x-ms-vob"), (1377, 1378)),(UniCase::ascii("x-ms-wm"), (1378, 1379)),(UniCase::ascii("x-ms-wmp"), (1379, 1380)),(UniCase::ascii("x-ms-wmv"), (1380, 1381)),(UniCase::ascii("x-ms-wmx"), (1381, 1382)),(UniCase::ascii("x-ms-wvx"), (1382, 1383)),(UniCase::ascii("x-msvideo"), (1383, 1384)),(UniCase::ascii("x-sgi-movie"), (1384, 1385)),(UniCase::ascii("x-smv"), (1385, 1386)),] }),(UniCase::ascii("x-conference"), TopLevelExts { start: 1386, end: 1387, subs: &[(UniCase::ascii("x-cooltalk"), (1386, 1387)),] }),(UniCase::ascii("x-world"), TopLevelExts { start: 1387, end: 1392, subs: &[(UniCase::ascii("x-vrml"), (1387, 1392)),] }),];
+const EXTS: &[&str] = &["dwg", "ez", "anx", "aw", "atom", "atomcat", "atomsvc", "bdoc", "ccxml", "cdmia", "cdmic", "cdmid", "cdmio", "cdmiq", "cu", "mpd", "davmount", "x", "dbk", "dssc", "xdssc", "ecma", "es", "emma", "evy", "epub", "etl", "exi", "otf", "ttf", "pfr", "woff", "fif", "fsscript", "fsx", "spl", "geojson", "gml", "gpx", "gxf", "gz", "hjson", "hta", "stk", "ink", "inkml", "acx", "ipfix", "ear", "jar", "war", "ser", "js", "jsm", "mjs", "json", "json5", "jsonml", "jsonld", "jck", "jcz", "lostxml", "hqx", "cpt", "mads", "webmanifest", "mrc", "mrcx", "ma", "mb", "nb", "mathml", "mbox", "mscml", "metalink", "meta4", "mets", "mods", "m21", "mp21", "mp4s", "amc", "vsi", "accdb", "accde", "accdt", "ade", "adp", "mda", "mde", "accda", "accdc", "accft", "accdr", "accdw", "doc", "dot", "wbk", "wiz", "mxf", "nq", "nt", "aaf", "aca", "afm", "asd", "asi", "bin", "bpk", "buffer", "cab", "cur", "dat", "deb", "deploy", "dist", "distz", "dmg", "dms", "dsp", "dump", "dwp", "elc", "emz", "exe", "fla", "hhk", "hhp", "hxd", "hxh", "hxi", "hxq", "hxr", "hxs", "hxw", "ics", "img", "inf", "iso", "java", "jpb", "lpk", "lrf", "lzh", "mar", "mdp", "mix", "msi", "msm", "mso", "msp", "ocx", "pcx", "pcz", "pfb", "pfm", "pkg", "prm", "prx", "psd", "psm", "psp", "qxd", "rvt", "sea", "smi", "snp", "so", "thn", "toc", "u32", "xmp", "xsn", "xtp", "oda", "opf", "ogx", "axs", "omdoc", "one", "onea", "onepkg", "onetmp", "onetoc", "onetoc2", "osdx", "oxps", "xer", "pdf", "pgp", "asc", "sig", "prf", "p10", "p7c", "p7m", "p7s", "p8", "ac", "crl", "pkipath", "pki", "ai", "eps", "ps", "psc1", "cww", "pskcxml", "raml", "rat", "rdf", "rif", "rnc", "rl", "rld", "gbr", "mft", "roa", "rsd", "rss", "rtf", "sbml", "scq", "scs", "spq", "spp", "sdp", "setpay", "setreg", "shf", "smil", "rq", "srx", "gram", "grxml", "sru", "ssdl", "ssml", "step", "stp", "ssm", "tei", "teicorpus", "tfi", "tsd", "trig", "plb", "psb", "pvb", "tcap", "pwn", "aso", "imp", "acu", "acutc", "atc", "air", "fcdt", "fxp", "fxpl", "xdp", "xfdf", "ahead", "azf", "azs", "azw", "acc", "ami", "apk", "cii", "fti", "atx", "mpkg", "pkpass", "swi", "iota", "aep", "mpm", "bmi", "rep", "cdxml", "mmd", "cdy", "csl", "cla", "rp9", "c4d", "c4f", "c4g", "c4p", "c4u", "c11amc", "c11amz", "csp", "cdbcmsg", "cmc", "clkx", "clkk", "clkp", "clkt", "clkw", "wbs", "ppd", "car", "pcurl", "dart", "rdz", "uvd", "uvf", "uvvd", "uvvf", "uvt", "uvvt", "uvvx", "uvx", "uvvz", "uvz", "fe_launch", "dna", "mlp", "dpg", "dfac", "kpxx", "ait", "geo", "mag", "nml", "esf", "msf", "qam", "slt", "ssf", "es3", "et3", "ez2", "ez3", "fdf", "mseed", "dataless", "seed", "gph", "ftc", "book", "fm", "frame", "maker", "fnc", "ltf", "fsc", "oas", "oa2", "oa3", "fg5", "bh2", "ddd", "xdw", "xbd", "fzs", "txd", "ggb", "ggt", "gex", "gre", "gxt", "g2w", "g3w", "gmx", "gdoc", "gslides", "gsheet", "kml", "kmz", "gqf", "gqs", "gac", "ghf", "gim", "grv", "gtm", "tpl", "vcg", "hal", "zmm", "hbci", "les", "hpgl", "hpid", "hps", "jlt", "pcl", "pclxl", "sfd-hdstx", "mpy", "afp", "list3820", "listafp", "irm", "sc", "icc", "icm", "igl", "ivp", "ivu", "igm", "xpw", "xpx", "i2g", "qbo", "qfx", "rcprofile", "irp", "xpr", "fcs", "jam", "rms", "jisp", "joda", "ktr", "ktz", "karbon", "chrt", "kfo", "flw", "kon", "kpr", "kpt", "ksp", "kwd", "kwt", "htke", "kia", "kne", "knp", "skd", "skm", "skt", "sse", "lasxml", "lbd", "lbe", "123", "apr", "pre", "nsf", "org", "scm", "lwp", "portpkg", "mcd", "mc1", "cdkey", "mwf", "mfm", "flo", "igx", "mif", "daf", "dis", "mbk", "mqy", "msl", "plc", "txf", "mpn", "mpc", "xul", "cil", "slk", "xla", "xlc", "xld", "xlk", "xll", "xlm", "xls", "xlt", "xlw", "xlam", "xlsb", "xlsm", "xltm", "eot", "chm", "ims", "lrm", "mpf", "calx", "thmx", "msg", "pst", "sst", "pko", "cat", "stl", "pot", "ppa", "pps", "ppt", "pwz", "ppam", "pptm", "sldm", "ppsm", "potm", "mpp", "mpt", "vdx", "docm", "dotm", "wcm", "wdb", "wks", "wps", "wpl", "xps", "mseq", "mus", "msty", "taglet", "nlu", "nitf", "ntf", "nnd", "nns", "nnw", "ngdat", "n-gage", "rpst", "rpss", "edm", "edx", "ext", "odc", "otc", "odb", "odf", "odft", "odg", "otg", "odi", "oti", "odp", "otp", "ods", "ots", "odt", "odm", "ott", "oth", "xo", "dd2", "oxt", "pptx", "sldx", "ppsx", "potx", "xlsx", "xltx", "docx", "dotx", "mgp", "dp", "esa", "oprc", "pdb", "pqa", "paw", "str", "ei6", "efif", "wg", "plf", "pbd", "box", "mgz", "qps", "ptid", "qwd", "qwt", "qxb", "qxl", "qxt", "bed", "mxl", "musicxml", "cryptonote", "rm", "rmvb", "rmp", "link66", "st", "see", "sema", "semd", "semf", "ifm", "itp", "iif", "ipk", "twd", "twds", "teacher", "sdkd", "sdkm", "dxp", "sfs", "sdc", "sda", "sdd", "smf", "sdw", "vor", "sgl", "smzip", "sm", "wadl", "sxc", "stc", "sxd", "std", "sxi", "sti", "sxm", "sxw", "sxg", "stw", "sus", "susp", "svd", "sis", "sisx", "xsm", "bdm", "xdm", "tao", "cap", "dmp", "pcap", "tmo", "tpt", "mxs", "tra", "ufd", "ufdl", "utz", "umj", "unityweb", "uoml", "vcx", "vsd", "vss", "vst", "vsw", "vsx", "vtx", "vis", "vsf", "wbxml", "wmlc", "wmlsc", "wtb", "nbp", "wpd", "wqd", "stf", "xar", "xfdl", "hvd", "hvs", "hvp", "osf", "osfpvg", "saf", "spf", "cmp", "zir", "zirz", "zaz", "vxml", "vsix", "wasm", "wgt", "library-ms", "searchconnector-ms", "hlp", "wlmp", "wspolicy", "7z", "abw", "ace", "arj", "aab", "vox", "x32", "aam", "aas", "bcpio", "torrent", "blb", "blorb", "adobebridge", "bz", "boz", "bz2", "cb7", "cba", "cbr", "cbt", "cbz", "cdf", "vcd", "cfs", "chat", "pgn", "crx", "cco", "z", "tgz", "cpio", "csh", "udeb", "dgc", "cct", "cst", "cxt", "dcr", "dir", "dxr", "fgd", "swa", "w3d", "wad", "ncx", "dtb", "res", "dvi", "dxf", "eva", "bdf", "gsf", "psf", "pcf", "snf", "ttf", "pfa", "arc", "gca", "ulx", "gnumeric", "gramps", "gtar", "hdf", "php", "install", "ins", "isp", "iii", "ipa", "ipg", "ipsw", "ite", "itlp", "itms", "itpc", "class", "jardiff", "jnlp", "skp", "latex", "luac", "lha", "run", "mie", "mvc", "mxp", "mobi", "prc", "application", "slupkg-ms", "manifest", "lit", "lnk", "vsto", "wmd", "wmz", "xbap", "mdb", "obd", "crd", "clp", "bat", "com", "dll", "m13", "m14", "mvb", "emf", "wmf", "mny", "pub", "scd", "trm", "wri", "nc", "pac", "nzb", "hhc", "pma", "pmc", "pml", "pmr", "pmw", "pl", "pm", "p12", "pfx", "p7b", "spc", "p7r", "pcast", "qtl", "rar", "ris", "safariextz", "webarchive", "sgimb", "sh", "shar", "mfp", "swf", "xap", "mmf", "sql", "sit", "sitx", "srt", "sv4cpio", "sv4crc", "t3", "gam", "tar", "tcl", "tk", "tex", "tfm", "texi", "texinfo", "obj", "roff", "t", "tr", "man", "me", "ms", "ustar", "hdd", "ova", "ovf", "vbox", "vbox-extpack", "vdi", "vhd", "vmdk", "src", "webapp", "wlpginstall", "wlpginstall3", "cer", "crt", "der", "pem", "fig", "xlf", "xpi", "xz", "z1", "z2", "z3", "z4", "z5", "z6", "z7", "z8", "xaml", "xdf", "xenc", "xht", "xhtml", "asa", "asax", "ascx", "ashx", "asmx", "aspx", "config", "coverage", "datasource", "dgml", "filters", "generictest", "hxa", "hxc", "hxe", "hxf", "hxk", "hxv", "loadtest", "master", "mtx", "orderedtest", "psess", "rdlc", "resx", "rng", "ruleset", "settings", "sitemap", "skin", "snippet", "svc", "testrunconfig", "testsettings", "trx", "vcproj", "vcxproj", "vscontent", "vsmdi", "webtest", "wiq", "xmta", "xsc", "xss", "xop", "xpl", "xspf", "mxml", "xhvml", "xvm", "xvml", "yang", "yin", "zip", "aac", "adts", "ac3", "aif", "aifc", "aiff", "cdda", "axa", "aa", "au", "snd", "flac", "m4a", "m4b", "m4p", "mid", "midi", "rmi", "kar", "mp4a", "m2a", "m3a", "mp2", "mp2a", "mp3", "mpga", "oga", "ogg", "opus", "spx", "s3m", "pls", "sil", "aax", "uva", "uvva", "eol", "adt", "dra", "dts", "dtshd", "lvp", "pya", "ecelp4800", "ecelp7470", "ecelp9600", "rip", "wav", "wave", "weba", "caf", "gsm", "m4r", "mka", "m3u", "m3u8", "wax", "wma", "ra", "ram", "rpm", "sd2", "smd", "smx", "smz", "xm", "cdx", "cif", "cmdf", "cml", "csml", "xyz", "dwf", "ttc", "ttf", "woff2", "apng", "avif", "avifs", "bmp", "dib", "cgm", "cod", "g3", "gif", "heic", "heics", "heif", "heifs", "ief", "jp2", "jpg2", "jfif", "jpe", "jpeg", "jpg", "jpm", "jpf", "jpx", "jxl", "ktx", "pct", "pic", "pict", "png", "pnz", "btif", "sgi", "svg", "svgz", "tif", "tiff", "uvg", "uvi", "uvvg", "uvvi", "djv", "djvu", "fbs", "fpx", "fst", "mmr", "rlc", "mdi", "wdp", "npx", "hdr", "rf", "wbmp", "xif", "webp", "3ds", "ras", "cmx", "fh", "fh4", "fh5", "fh7", "fhc", "ico", "art", "jng", "mac", "pnt", "pntg", "sid", "pnm", "pbm", "pgm", "ppm", "qti", "qtif", "rgb", "tga", "xbm", "xpm", "xwd", "disposition-notification", "u8msg", "u8dsn", "u8mdn", "u8hdr", "eml", "mht", "mhtml", "mime", "nws", "gltf", "glb", "iges", "igs", "mesh", "msh", "silo", "dae", "gdl", "gtw", "vtu", "vrml", "x3db", "x3dbz", "x3dv", "x3dvz", "x3d", "x3dz", "appcache", "ifb", "coffee", "litcoffee", "css", "csv", "dlm", "gemini", "gmi", "323", "htm", "html", "hxt", "shtml", "uls", "jade", "jsx", "less", "markdown", "md", "mml", "n3", "asm", "bas", "c", "cc", "cd", "cfg", "cmd", "cnf", "conf", "cpp", "cs", "csdproj", "csproj", "cxx", "dbproj", "def", "dsw", "h", "hh", "hpp", "hxx", "i", "idl", "in", "inc", "ini", "inl", "ipproj", "jsxbin", "list", "log", "lst", "mak", "map", "mk", "odh", "odl", "pkgdef", "pkgundef", "py", "rc", "rc2", "rct", "reg", "rgs", "s", "scr", "sln", "sol", "sor", "srf", "text", "tlh", "tli", "txt", "user", "vb", "vbdproj", "vbproj", "vcs", "vddproj", "vdp", "vdproj", "vspscc", "vsscc", "vssscc", "xdr", "xoml", "dsc", "rtx", "sct", "wsc", "sgm", "sgml", "shex", "slim", "slm", "styl", "stylus", "tsv", "ttl", "uri", "uris", "urls", "vbs", "vcard", "curl", "dcurl", "mcurl", "scurl", "sub", "fly", "flx", "gv", "3dml", "spot", "jad", "wml", "wmls", "vtt", "htt", "dic", "htc", "f", "f77", "f90", "for", "feature", "hbs", "hdml", "qht", "qhtm", "lua", "md", "mkd", "contact", "group", "iqy", "rqy", "nfo", "opml", "p", "pas", "pde", "rs", "sass", "scss", "etx", "sfv", "ymp", "toml", "uu", "vcf", "yaml", "yml", "addin", "disco", "dll.config", "dtd", "dtsconfig", "exe.config", "mno", "ssisdeploymentmanifest", "vml", "vsct", "vsixlangpack", "vsixmanifest", "vssettings", "vstemplate", "wsdl", "xml", "xrm-ms", "xsd", "xsf", "xsl", "xslt", "3gp", "3gpp", "3g2", "3gp2", "axv", "divx", "h261", "h263", "h264", "jpgv", "jpgm", "mj2", "mjp2", "mp4", "mp4v", "mpg4", "m1v", "m2v", "mod", "mp2", "mp2v", "mpa", "mpe", "mpeg", "mpg", "mpv2", "vbk", "ogv", "mov", "mqv", "qt", "uvh", "uvvh", "uvm", "uvvm", "uvp", "uvvp", "uvs", "uvvs", "uvv", "uvvv", "m2t", "m2ts", "mts", "ts", "tts", "dvb", "fvt", "m4u", "mxu", "pyv", "uvu", "uvvu", "viv", "webm", "dif", "dv", "f4v", "fli", "flv", "ivf", "lsf", "lsx", "m4v", "mk3d", "mks", "mkv", "mng", "asf", "asr", "asx", "nsc", "vob", "wm", "wmp", "wmv", "wmx", "wvx", "avi", "movie", "smv", "ice", "flr", "wrl", "wrz", "xaf", "xof"];
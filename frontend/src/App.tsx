@@ -16,7 +16,7 @@ function App() {
       apiService.getCurrentUser()
         .then(userData => {
           setUser(userData);
-          wsService.connect(token);
+          wsService.connect();
         })
         .catch(() => {
           apiService.clearToken();
@@ -29,9 +29,8 @@ function App() {
 
   const handleLogin = (userData: User) => {
     setUser(userData);
-    const token = apiService.getToken();
-    if (token && !wsService.isConnected()) {
-      wsService.connect(token);
+    if (apiService.getToken() && !wsService.isConnected()) {
+      wsService.connect();
     }
   };
 
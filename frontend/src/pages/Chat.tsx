@@ -1,5 +1,5 @@
 import { useState, useEffect, useRef, useCallback } from 'react';
-import { User, Message, Group } from '../types';
+import { DirectoryUser, User, Message, Group, PresenceStatus } from '../types';
 import { apiService } from '../services/api';
 import { wsService } from '../services/websocket';
 import './Chat.css';
@@ -11,10 +11,20 @@ interface ChatProps {
 
 type ChatTarget = { type: 'contact' | 'group'; id: number; name: string };
 
+const PRESENCE_LABELS: Record<PresenceStatus, string> = {
+  online: '在线',
+  away: '离开',
+  busy: '忙碌',
+  dnd: '请勿打扰',
+  invisible: '隐身',
+  offline: '离线',
+};
+
 export default function Chat({ user, onLogout }: ChatProps) {
-  const [contacts, setContacts] = useState<User[]>([]);
+  const [contacts, setContacts] = useState<DirectoryUser[]>([]);
   const [groups, setGroups] = useState<Group[]>([]);
   const [messages, setMessages] = useState<Message[]>([]);
+  const [hasOlderMessages, setHasOlderMessages] = useState(false);
   const [currentChat, setCurrentChat] = useState<ChatTarget | null>(null);
   const [messageInput, setMessageInput] = useState('');
   const [activeTab, setActiveTab] = useState<'contacts' | 'groups'>('contacts');
@@ -34,56 +44,61 @@ export default function Chat({ user, onLogout }: ChatProps) {
 
   const loadData = useCallback(async () => {
     try {
-      const [usersList, groupsList] = await Promise.all([
-        apiService.getAllUsers(),
+      const [directory, groupsList] = await Promise.all([
+        apiService.getUserDirectory({ limit: 100, exclude_blocked: true }),
         apiService.getUserGroups(),
       ]);
-      setContacts(usersList);
+      setContacts(directory.users);
       setGroups(groupsList);
     } catch (error) {
       console.error('Failed to load data:', error);
     }
   }, []);
 
+  // The same message can arrive both as the send response and over WebSocket
+  const appendMessage = useCallback((message: Message) => {
+    setMessages(prev => prev.some(m => m.id === message.id) ? prev : [...prev, message]);
+  }, []);
+
+  const replaceMessage = useCallback((message: Message) => {
+    setMessages(prev => prev.map(m => m.id === message.id ? message : m));
+  }, []);
+
+  const removeMessage = useCallback((messageId: number) => {
+    setMessages(prev => prev.filter(m => m.id !== messageId));
+  }, []);
+
   useEffect(() => {
     loadData();
 
     const handleWsMessage = (event: any) => {
-      if (event.event_type === 'message') {
-        const senderId = event.user_id;
-        const receiverId = event.receiver_id;
-        const groupId = event.group_id;
-
-        // Skip if this is our own message
-        if (senderId === user.id) return;
-
+      if (event.event_type === 'message' && event.data) {
+        // The stored message, also sent for our own messages from other devices
+        const message: Message = event.data;
+        const isOwn = message.sender_id === user.id;
         const chat = currentChatRef.current;
 
         // Check if the message is for the currently open chat
         const isCurrentChat =
-          (chat?.type === 'contact' && senderId === chat.id && receiverId === user.id) ||
-          (chat?.type === 'group' && groupId === chat.id);
+          (chat?.type === 'contact' && !message.group_id &&
+            (isOwn ? message.receiver_id === chat.id : message.sender_id === chat.id)) ||
+          (chat?.type === 'group' && message.group_id === chat.id);
 
         if (isCurrentChat) {
-          setMessages(prev => [...prev, {
-            id: Date.now(),
-            sender_id: senderId,
-            receiver_id: receiverId,
-            group_id: groupId,
-            content: event.content,
-            message_type: 'text',
-            created_at: new Date().toISOString(),
-            is_read: false,
-          }]);
-        } else {
+          appendMessage(message);
+        } else if (!isOwn) {
           // Increment unread count
-          if (groupId) {
-            const key = `group_${groupId}`;
-            setUnreadCounts(prev => ({ ...prev, [key]: (prev[key] || 0) + 1 }));
-          } else if (receiverId === user.id) {
-            const key = `contact_${senderId}`;
-            setUnreadCounts(prev => ({ ...prev, [key]: (prev[key] || 0) + 1 }));
-          }
+          const key = message.group_id ? `group_${message.group_id}` : `contact_${message.sender_id}`;
+          setUnreadCounts(prev => ({ ...prev, [key]: (prev[key] || 0) + 1 }));
+        }
+      } else if (event.event_type === 'message_edited' && event.data) {
+        replaceMessage(event.data);
+      } else if (event.event_type === 'message_deleted' && event.data) {
+        // "me": deleted on another of our devices; "everyone": recalled
+        if (event.content === 'me') {
+          removeMessage(event.data.id);
+        } else {
+          replaceMessage(event.data);
         }
       }
     };
@@ -99,23 +114,39 @@ export default function Chat({ user, onLogout }: ChatProps) {
       wsService.removeListener(handleWsMessage);
       wsService.removeOnlineListener(handleOnlineUsers);
     };
-  }, [loadData, user.id]);
+  }, [loadData, appendMessage, replaceMessage, removeMessage, user.id]);
 
   useEffect(() => {
     messagesEndRef.current?.scrollIntoView({ behavior: 'smooth' });
   }, [messages]);
 
+  const conversationParams = (type: 'contact' | 'group', id: number) =>
+    type === 'contact' ? { receiver_id: id } : { group_id: id };
+
   const loadMessages = async (type: 'contact' | 'group', id: number) => {
     try {
-      const msgs = type === 'contact'
-        ? await apiService.getMessages(id)
-        : await apiService.getMessages(undefined, id);
-      setMessages(msgs);
+      const page = await apiService.getMessages(conversationParams(type, id));
+      setMessages(page.messages);
+      setHasOlderMessages(page.has_more);
     } catch (error) {
       console.error('Failed to load messages:', error);
     }
   };
 
+  const loadOlderMessages = async () => {
+    if (!currentChat || messages.length === 0) return;
+    try {
+      const page = await apiService.getMessages({
+        ...conversationParams(currentChat.type, currentChat.id),
+        before: messages[0].id,
+      });
+      setMessages(prev => [...page.messages, ...prev]);
+      setHasOlderMessages(page.has_more);
+    } catch (error) {
+      console.error('Failed to load older messages:', error);
+    }
+  };
+
   const handleSelectChat = (type: 'contact' | 'group', id: number, name: string) => {
     setCurrentChat({ type, id, name });
     loadMessages(type, id);
@@ -136,26 +167,9 @@ export default function Chat({ user, onLogout }: ChatProps) {
       const receiverId = currentChat.type === 'contact' ? currentChat.id : null;
       const groupId = currentChat.type === 'group' ? currentChat.id : null;
 
-      await apiService.sendMessage(receiverId, groupId, messageInput);
-
-      wsService.send({
-        event_type: 'message',
-        user_id: user.id,
-        receiver_id: receiverId || undefined,
-        group_id: groupId || undefined,
-        content: messageInput,
-      });
-
-      setMessages(prev => [...prev, {
-        id: Date.now(),
-        sender_id: user.id,
-        receiver_id: receiverId || undefined,
-        group_id: groupId || undefined,
-        content: messageInput,
-        message_type: 'text',
-        created_at: new Date().toISOString(),
-        is_read: false,
-      }]);
+      // The server delivers the message to everyone else in the conversation
+      const message = await apiService.sendMessage(receiverId, groupId, messageInput);
+      appendMessage(message);
 
       setMessageInput('');
     } catch (error) {
@@ -163,6 +177,35 @@ export default function Chat({ user, onLogout }: ChatProps) {
     }
   };
 
+  const handleEditMessage = async (message: Message) => {
+    const content = window.prompt('编辑消息', message.content);
+    if (content === null || !content.trim() || content === message.content) return;
+    try {
+      replaceMessage(await apiService.editMessage(message.id, content));
+    } catch (error: any) {
+      alert(error.message || 'Failed to edit message');
+    }
+  };
+
+  const handleDeleteMessage = async (message: Message) => {
+    if (!window.confirm('删除后只对你隐藏，其他人仍能看到。确定删除？')) return;
+    try {
+      await apiService.deleteMessage(message.id);
+      removeMessage(message.id);
+    } catch (error: any) {
+      alert(error.message || 'Failed to delete message');
+    }
+  };
+
+  const handleRecallMessage = async (message: Message) => {
+    if (!window.confirm('撤回后所有人都将看不到这条消息。确定撤回？')) return;
+    try {
+      replaceMessage(await apiService.recallMessage(message.id));
+    } catch (error: any) {
+      alert(error.message || 'Failed to recall message');
+    }
+  };
+
   const handleCreateGroup = async () => {
     if (!newGroupName.trim()) return;
     try {
@@ -210,6 +253,10 @@ export default function Chat({ user, onLogout }: ChatProps) {
     return onlineUsers.has(contactId);
   };
 
+  const getPresenceLabel = (contactId: number) => {
+    return PRESENCE_LABELS[wsService.getPresence(contactId)];
+  };
+
   return (
     <div className="chat-container">
       <div className="sidebar">
@@ -254,7 +301,7 @@ export default function Chat({ user, onLogout }: ChatProps) {
                     </div>
                     <div className="item-info">
                       <span className="item-name">{contact.username}</span>
-                      <span className="item-status">{getContactStatus(contact.id) ? '在线' : '离线'}</span>
+                      <span className="item-status">{getPresenceLabel(contact.id)}</span>
                     </div>
                     {count > 0 && <span className="unread-badge">{count > 99 ? '99+' : count}</span>}
                   </div>
@@ -308,13 +355,16 @@ export default function Chat({ user, onLogout }: ChatProps) {
               <div className="chat-header-info">
                 <h2>{currentChat.name}</h2>
                 <span className="chat-header-status">
-                  {currentChat.type === 'group' ? '群聊' : (getContactStatus(currentChat.id) ? '在线' : '离线')}
+                  {currentChat.type === 'group' ? '群聊' : getPresenceLabel(currentChat.id)}
                 </span>
               </div>
               <button className="close-chat-btn" onClick={() => setCurrentChat(null)} title="关闭聊天">✕</button>
             </div>
 
             <div className="messages-list">
+              {hasOlderMessages && (
+                <button className="load-older-btn" onClick={loadOlderMessages}>加载更早的消息</button>
+              )}
               {messages.map(msg => (
                 <div
                   key={msg.id}
@@ -325,9 +375,27 @@ export default function Chat({ user, onLogout }: ChatProps) {
                       {contacts.find(c => c.id === msg.sender_id)?.username || `User ${msg.sender_id}`}
                     </div>
                   )}
-                  <div className="message-content">{msg.content}</div>
+                  {msg.deleted_at ? (
+                    <div className="message-content message-recalled">消息已撤回</div>
+                  ) : (
+                    <div className="message-content">{msg.content}</div>
+                  )}
                   <div className="message-time">
                     {new Date(msg.created_at).toLocaleTimeString()}
+                    {msg.edited_at && !msg.deleted_at && <span className="message-edited">（已编辑）</span>}
+                    {msg.sender_id === user.id && !msg.deleted_at && (
+                      <>
+                        <button className="message-edit-btn" onClick={() => handleEditMessage(msg)}>
+                          编辑
+                        </button>
+                        <button className="message-edit-btn" onClick={() => handleRecallMessage(msg)}>
+                          撤回
+                        </button>
+                      </>
+                    )}
+                    <button className="message-edit-btn" onClick={() => handleDeleteMessage(msg)}>
+                      删除
+                    </button>
                   </div>
                 </div>
               ))}
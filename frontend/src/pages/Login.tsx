@@ -1,4 +1,4 @@
-import { useState } from 'react';
+import { useState, useEffect } from 'react';
 import { User } from '../types';
 import { apiService } from '../services/api';
 import './Login.css';
@@ -14,6 +14,40 @@ export default function Login({ onLogin }: LoginProps) {
   const [password, setPassword] = useState('');
   const [error, setError] = useState('');
   const [loading, setLoading] = useState(false);
+  const [challengeToken, setChallengeToken] = useState<string | null>(null);
+  const [code, setCode] = useState('');
+
+  // The identity provider redirects back here with ?code=...&state=...
+  useEffect(() => {
+    const params = new URLSearchParams(window.location.search);
+    const oidcCode = params.get('code');
+    const oidcState = params.get('state');
+    if (!oidcCode || !oidcState) {
+      return;
+    }
+    window.history.replaceState(null, '', '/');
+
+    setLoading(true);
+    apiService.loginOidc(oidcCode, oidcState)
+      .then(response => {
+        if ('two_factor_required' in response) {
+          setChallengeToken(response.challenge_token);
+          return;
+        }
+        onLogin(response.user);
+      })
+      .catch((err: any) => setError(err.message || '单点登录失败'))
+      .finally(() => setLoading(false));
+  }, []);
+
+  const handleSso = async () => {
+    setError('');
+    try {
+      window.location.href = await apiService.getOidcAuthorizeUrl();
+    } catch (err: any) {
+      setError(err.message || '单点登录不可用');
+    }
+  };
 
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
@@ -21,10 +55,21 @@ export default function Login({ onLogin }: LoginProps) {
     setLoading(true);
 
     try {
+      if (challengeToken) {
+        const response = await apiService.loginTwoFactor(challengeToken, code);
+        onLogin(response.user);
+        return;
+      }
+
       const response = isRegister
         ? await apiService.register(username, email, password)
         : await apiService.login(email, password);
-      
+
+      if ('two_factor_required' in response) {
+        setChallengeToken(response.challenge_token);
+        return;
+      }
+
       onLogin(response.user);
     } catch (err: any) {
       setError(err.message || '操作失败');
@@ -39,6 +84,25 @@ export default function Login({ onLogin }: LoginProps) {
         <h1>IM-TT 即时通讯</h1>
         <h2>{isRegister ? '注册' : '登录'}</h2>
         
+        {challengeToken ? (
+        <form onSubmit={handleSubmit}>
+          <input
+            type="text"
+            inputMode="numeric"
+            autoComplete="one-time-code"
+            placeholder="验证码或恢复码"
+            value={code}
+            onChange={(e) => setCode(e.target.value)}
+            required
+          />
+
+          {error && <div className="error">{error}</div>}
+
+          <button type="submit" disabled={loading}>
+            {loading ? '处理中...' : '验证'}
+          </button>
+        </form>
+        ) : (
         <form onSubmit={handleSubmit}>
           {isRegister && (
             <input
@@ -72,6 +136,13 @@ export default function Login({ onLogin }: LoginProps) {
             {loading ? '处理中...' : (isRegister ? '注册' : '登录')}
           </button>
         </form>
+        )}
+
+        {!challengeToken && !isRegister && (
+          <button className="link-button" onClick={handleSso} disabled={loading}>
+            使用单点登录 (SSO)
+          </button>
+        )}
         
         <p>
           {isRegister ? '已有账号？' : '还没有账号？'}
@@ -1,9 +1,10 @@
-import { AuthResponse, User, Message, Group, GroupMember } from '../types';
+import { AuthResponse, BlockedUser, Contact, CustomStatus, DirectoryPage, DirectoryParams, FriendRequest, FriendRequests, Presence, PresenceStatus, TokenResponse, TwoFactorChallengeResponse, WsTicketResponse, User, Message, MessageEdit, MessageHistoryParams, MessagePage, Group, GroupMember } from '../types';
 
 const API_BASE_URL = import.meta.env.VITE_API_BASE_URL || 'http://localhost:8080/api';
 
 class ApiService {
   private token: string | null = null;
+  private refreshPromise: Promise<boolean> | null = null;
 
   setToken(token: string) {
     this.token = token;
@@ -17,14 +18,51 @@ class ApiService {
     return this.token;
   }
 
+  setRefreshToken(refreshToken: string) {
+    localStorage.setItem('refreshToken', refreshToken);
+  }
+
   clearToken() {
     this.token = null;
     localStorage.removeItem('authToken');
+    localStorage.removeItem('refreshToken');
   }
 
-  private async request(endpoint: string, options: RequestInit = {}) {
+  // Exchange the stored refresh token for a new token pair. Concurrent
+  // callers share one in-flight request because each refresh token is
+  // single-use on the server.
+  private refreshTokens(): Promise<boolean> {
+    if (!this.refreshPromise) {
+      this.refreshPromise = (async () => {
+        const refreshToken = localStorage.getItem('refreshToken');
+        if (!refreshToken) return false;
+
+        const response = await fetch(`${API_BASE_URL}/auth/refresh`, {
+          method: 'POST',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify({ refresh_token: refreshToken }),
+        });
+
+        if (!response.ok) {
+          this.clearToken();
+          return false;
+        }
+
+        const data: TokenResponse = await response.json();
+        this.setToken(data.token);
+        this.setRefreshToken(data.refresh_token);
+        return true;
+      })().finally(() => {
+        this.refreshPromise = null;
+      });
+    }
+    return this.refreshPromise;
+  }
+
+  private async request(endpoint: string, options: RequestInit = {}, retry = true): Promise<any> {
+    // Let the browser set the multipart boundary for uploads
     const headers: Record<string, string> = {
-      'Content-Type': 'application/json',
+      ...(options.body instanceof FormData ? {} : { 'Content-Type': 'application/json' }),
       ...(options.headers as Record<string, string>),
     };
 
@@ -37,6 +75,10 @@ class ApiService {
       headers,
     });
 
+    if (response.status === 401 && retry && this.token && await this.refreshTokens()) {
+      return this.request(endpoint, options, false);
+    }
+
     if (!response.ok) {
       const error = await response.json().catch(() => ({ error: 'Request failed' }));
       throw new Error(error.error || 'Request failed');
@@ -51,15 +93,57 @@ class ApiService {
       body: JSON.stringify({ username, email, password }),
     });
     this.setToken(data.token);
+    this.setRefreshToken(data.refresh_token);
     return data;
   }
 
-  async login(email: string, password: string): Promise<AuthResponse> {
+  // Accounts with two-factor authentication get a challenge instead of
+  // tokens; finish signing in with loginTwoFactor
+  async login(email: string, password: string): Promise<AuthResponse | TwoFactorChallengeResponse> {
     const data = await this.request('/auth/login', {
       method: 'POST',
       body: JSON.stringify({ email, password }),
     });
+    if (data.two_factor_required) {
+      return data;
+    }
+    this.setToken(data.token);
+    this.setRefreshToken(data.refresh_token);
+    return data;
+  }
+
+  async loginTwoFactor(challengeToken: string, code: string): Promise<AuthResponse> {
+    const data = await this.request('/auth/login/2fa', {
+      method: 'POST',
+      body: JSON.stringify({ challenge_token: challengeToken, code }),
+    });
     this.setToken(data.token);
+    this.setRefreshToken(data.refresh_token);
+    return data;
+  }
+
+  // Single sign-on: fetch the identity provider URL to redirect to. The
+  // binding stays in this tab and proves the callback comes from the same
+  // client that started the login.
+  async getOidcAuthorizeUrl(): Promise<string> {
+    const data = await this.request('/auth/oidc/authorize');
+    sessionStorage.setItem('oidcBinding', data.binding);
+    return data.authorization_url;
+  }
+
+  // Finish single sign-on with the code and state from the provider redirect
+  async loginOidc(code: string, state: string): Promise<AuthResponse | TwoFactorChallengeResponse> {
+    const binding = sessionStorage.getItem('oidcBinding') || '';
+    sessionStorage.removeItem('oidcBinding');
+    const data = await this.request('/auth/oidc/callback', {
+      method: 'POST',
+      body: JSON.stringify({ code, state, binding }),
+    });
+    if (data.two_factor_required) {
+      return data;
+    }
+    this.setToken(data.token);
+    this.setRefreshToken(data.refresh_token);
     return data;
   }
 
@@ -68,12 +152,121 @@ class ApiService {
     this.clearToken();
   }
 
+  // Single-use ticket for authenticating the WebSocket connection
+  async getWsTicket(): Promise<WsTicketResponse> {
+    return this.request('/ws/ticket', { method: 'POST' });
+  }
+
   async getCurrentUser(): Promise<User> {
     return this.request('/user/me');
   }
 
-  async getAllUsers(): Promise<User[]> {
-    return this.request('/user/list');
+  async updateProfile(changes: { username?: string; display_name?: string; bio?: string }): Promise<User> {
+    return this.request('/user/me', {
+      method: 'PUT',
+      body: JSON.stringify(changes),
+    });
+  }
+
+  async uploadAvatar(file: File): Promise<User> {
+    const form = new FormData();
+    form.append('avatar', file);
+    return this.request('/user/me/avatar', {
+      method: 'POST',
+      body: form,
+    });
+  }
+
+  async deleteAvatar(): Promise<User> {
+    return this.request('/user/me/avatar', { method: 'DELETE' });
+  }
+
+  async uploadGroupAvatar(groupId: number, file: File): Promise<Group> {
+    const form = new FormData();
+    form.append('avatar', file);
+    return this.request(`/groups/${groupId}/avatar`, {
+      method: 'POST',
+      body: form,
+    });
+  }
+
+  // One page of the user directory; pass next_cursor back to continue
+  async getUserDirectory(params: DirectoryParams = {}): Promise<DirectoryPage> {
+    const query = new URLSearchParams();
+    Object.entries(params).forEach(([key, value]) => {
+      if (value !== undefined && value !== '') query.append(key, String(value));
+    });
+    return this.request(`/user/list?${query}`);
+  }
+
+  async getContacts(): Promise<Contact[]> {
+    return this.request('/contacts');
+  }
+
+  async removeContact(userId: number): Promise<any> {
+    return this.request(`/contacts/${userId}`, { method: 'DELETE' });
+  }
+
+  async getFriendRequests(): Promise<FriendRequests> {
+    return this.request('/contacts/requests');
+  }
+
+  // Accepts instead if the other user already sent a request
+  async sendFriendRequest(userId: number, message?: string): Promise<FriendRequest | Contact> {
+    return this.request('/contacts/requests', {
+      method: 'POST',
+      body: JSON.stringify({ user_id: userId, message }),
+    });
+  }
+
+  async acceptFriendRequest(requestId: number): Promise<Contact> {
+    return this.request(`/contacts/requests/${requestId}/accept`, { method: 'POST' });
+  }
+
+  async declineFriendRequest(requestId: number): Promise<any> {
+    return this.request(`/contacts/requests/${requestId}/decline`, { method: 'POST' });
+  }
+
+  async cancelFriendRequest(requestId: number): Promise<any> {
+    return this.request(`/contacts/requests/${requestId}`, { method: 'DELETE' });
+  }
+
+  async setPresence(status: Exclude<PresenceStatus, 'offline'>): Promise<{ status: PresenceStatus }> {
+    return this.request('/user/me/presence', {
+      method: 'PUT',
+      body: JSON.stringify({ status }),
+    });
+  }
+
+  // expires_at is an ISO 8601 time; omit it to keep the status until cleared
+  async setCustomStatus(status: CustomStatus): Promise<User> {
+    return this.request('/user/me/status', {
+      method: 'PUT',
+      body: JSON.stringify(status),
+    });
+  }
+
+  async clearCustomStatus(): Promise<User> {
+    return this.request('/user/me/status', { method: 'DELETE' });
+  }
+
+  async getPresence(userIds: number[]): Promise<Presence[]> {
+    return this.request(`/user/presence?ids=${userIds.join(',')}`);
+  }
+
+  async getBlockedUsers(): Promise<BlockedUser[]> {
+    return this.request('/blocks');
+  }
+
+  async blockUser(userId: number): Promise<any> {
+    return this.request('/blocks', {
+      method: 'POST',
+      body: JSON.stringify({ user_id: userId }),
+    });
+  }
+
+  async unblockUser(userId: number): Promise<any> {
+    return this.request(`/blocks/${userId}`, { method: 'DELETE' });
   }
 
   async sendMessage(receiverId: number | null, groupId: number | null, content: string): Promise<Message> {
@@ -88,11 +281,36 @@ class ApiService {
     });
   }
 
-  async getMessages(receiverId?: number, groupId?: number): Promise<Message[]> {
-    const params = new URLSearchParams();
-    if (receiverId) params.append('receiver_id', receiverId.toString());
-    if (groupId) params.append('group_id', groupId.toString());
-    return this.request(`/messages/list?${params}`);
+  // Newest messages by default; pass the first id as `before` to scroll back
+  async getMessages(params: MessageHistoryParams): Promise<MessagePage> {
+    const query = new URLSearchParams();
+    Object.entries(params).forEach(([key, value]) => {
+      if (value !== undefined) query.append(key, String(value));
+    });
+    return this.request(`/messages/list?${query}`);
+  }
+
+  // Only the sender can edit, within the server's edit window
+  async editMessage(messageId: number, content: string): Promise<Message> {
+    return this.request(`/messages/${messageId}`, {
+      method: 'PUT',
+      body: JSON.stringify({ content }),
+    });
+  }
+
+  async getMessageEdits(messageId: number): Promise<MessageEdit[]> {
+    return this.request(`/messages/${messageId}/edits`);
+  }
+
+  // Removes the message from your own history only
+  async deleteMessage(messageId: number): Promise<void> {
+    return this.request(`/messages/${messageId}`, { method: 'DELETE' });
+  }
+
+  // Removes the message for everyone: the sender within the recall window,
+  // group admins at any time
+  async recallMessage(messageId: number): Promise<Message> {
+    return this.request(`/messages/${messageId}/recall`, { method: 'POST' });
   }
 
   async createGroup(name: string, description?: string): Promise<Group> {
@@ -1,22 +1,33 @@
-import { WsEvent } from '../types';
+import { PresenceStatus, WsEvent } from '../types';
+import { apiService } from './api';
 
 const WS_URL = import.meta.env.VITE_WS_URL || 'ws://localhost:8080/api/ws';
+const WS_CLOSE_REVOKED = 4001;
+const WS_CLOSE_UNAUTHENTICATED = 4002;
+// Tell the server about user input at most this often so idle users turn away
+const ACTIVITY_INTERVAL_MS = 60_000;
 
 export class WebSocketService {
   private ws: WebSocket | null = null;
   private listeners: Array<(event: WsEvent) => void> = [];
   private onlineUsers: Set<number> = new Set();
   private onlineListeners: Array<(users: Set<number>) => void> = [];
+  private presence: Map<number, PresenceStatus> = new Map();
+  private lastActivity = 0;
   private intentionalClose = false;
-  private token: string = '';
   private reconnectTimer: ReturnType<typeof setTimeout> | null = null;
 
-  connect(token: string) {
+  constructor() {
+    ['keydown', 'mousedown', 'touchstart'].forEach(type =>
+      window.addEventListener(type, () => this.reportActivity(), { passive: true })
+    );
+  }
+
+  async connect() {
     if (this.ws && this.ws.readyState === WebSocket.OPEN) {
       return;
     }
 
-    this.token = token;
     this.intentionalClose = false;
 
     if (this.reconnectTimer) {
@@ -24,10 +35,26 @@ export class WebSocketService {
       this.reconnectTimer = null;
     }
 
-    this.ws = new WebSocket(`${WS_URL}?token=${token}`);
+    // Authenticate with a short-lived ticket sent in the first frame so the
+    // access token never appears in a URL
+    let ticket: string;
+    try {
+      ticket = (await apiService.getWsTicket()).ticket;
+    } catch (error) {
+      console.error('Failed to get WebSocket ticket:', error);
+      this.scheduleReconnect();
+      return;
+    }
+
+    if (this.intentionalClose) {
+      return;
+    }
+
+    this.ws = new WebSocket(WS_URL);
 
     this.ws.onopen = () => {
       console.log('WebSocket connected');
+      this.ws?.send(JSON.stringify({ event_type: 'auth', ticket }));
     };
 
     this.ws.onmessage = (event) => {
@@ -39,6 +66,10 @@ export class WebSocketService {
           this.notifyOnlineUsers();
         } else if (data.event_type === 'offline') {
           this.onlineUsers.delete(data.user_id);
+          this.presence.delete(data.user_id);
+          this.notifyOnlineUsers();
+        } else if (data.event_type === 'presence' && data.content) {
+          this.presence.set(data.user_id, data.content as PresenceStatus);
           this.notifyOnlineUsers();
         }
 
@@ -48,17 +79,23 @@ export class WebSocketService {
       }
     };
 
-    this.ws.onclose = () => {
+    this.ws.onclose = (event) => {
       console.log('WebSocket disconnected');
       this.onlineUsers.clear();
+      this.presence.clear();
       this.notifyOnlineUsers();
       this.ws = null;
 
-      if (!this.intentionalClose && this.token) {
-        this.reconnectTimer = setTimeout(() => {
-          this.connect(this.token);
-        }, 3000);
+      // The server closes with WS_CLOSE_REVOKED when the session was revoked
+      if (event.code === WS_CLOSE_REVOKED) {
+        this.intentionalClose = true;
+      }
+
+      if (event.code === WS_CLOSE_UNAUTHENTICATED) {
+        console.warn('WebSocket authentication failed');
       }
+
+      this.scheduleReconnect();
     };
 
     this.ws.onerror = (error) => {
@@ -66,6 +103,15 @@ export class WebSocketService {
     };
   }
 
+  private scheduleReconnect() {
+    if (this.intentionalClose || !apiService.getToken()) {
+      return;
+    }
+    this.reconnectTimer = setTimeout(() => {
+      this.connect();
+    }, 3000);
+  }
+
   disconnect() {
     this.intentionalClose = true;
 
@@ -95,6 +141,19 @@ export class WebSocketService {
     this.listeners = this.listeners.filter(l => l !== listener);
   }
 
+  private reportActivity() {
+    const now = Date.now();
+    if (now - this.lastActivity < ACTIVITY_INTERVAL_MS || !this.isConnected()) {
+      return;
+    }
+    this.lastActivity = now;
+    this.ws?.send(JSON.stringify({ event_type: 'activity' }));
+  }
+
+  getPresence(userId: number): PresenceStatus {
+    return this.presence.get(userId) ?? (this.onlineUsers.has(userId) ? 'online' : 'offline');
+  }
+
   isUserOnline(userId: number): boolean {
     return this.onlineUsers.has(userId);
   }
@@ -2,12 +2,39 @@ export interface User {
   id: number;
   username: string;
   email: string;
+  display_name?: string | null;
+  bio?: string | null;
   avatar?: string;
-  status: string;
+  // Own profile: the picked presence; other users: presence as you see it
+  status: PresenceStatus;
+  last_seen_at?: string | null;
+  status_text?: string | null;
+  status_emoji?: string | null;
+  status_expires_at?: string | null;
+  email_verified: boolean;
+  is_bot: boolean;
+  owner_id?: number | null;
+  role: 'user' | 'admin';
+  suspended_at?: string | null;
   created_at: string;
   updated_at: string;
 }
 
+// Payload of status_changed events; fields are null once cleared
+export interface CustomStatus {
+  text?: string | null;
+  emoji?: string | null;
+  expires_at?: string | null;
+}
+
+export type PresenceStatus = 'online' | 'away' | 'busy' | 'dnd' | 'invisible' | 'offline';
+
+export interface Presence {
+  user_id: number;
+  status: PresenceStatus;
+  last_seen_at?: string | null;
+}
+
 export interface Message {
   id: number;
   sender_id: number;
@@ -17,6 +44,31 @@ export interface Message {
   message_type: string;
   created_at: string;
   is_read: boolean;
+  edited_at?: string;
+  // Set when the message was recalled; content is then empty
+  deleted_at?: string;
+}
+
+// An earlier revision of an edited message
+export interface MessageEdit {
+  id: number;
+  message_id: number;
+  content: string;
+  created_at: string;
+}
+
+// One page of GET /api/messages/list, oldest message first
+export interface MessagePage {
+  messages: Message[];
+  has_more: boolean;
+}
+
+export interface MessageHistoryParams {
+  receiver_id?: number;
+  group_id?: number;
+  before?: number;
+  after?: number;
+  limit?: number;
 }
 
 export interface Group {
@@ -39,9 +91,88 @@ export interface GroupMember {
 
 export interface AuthResponse {
   token: string;
+  refresh_token: string;
+  expires_in: number;
   user: User;
 }
 
+export interface TwoFactorChallengeResponse {
+  two_factor_required: true;
+  challenge_token: string;
+  expires_in: number;
+}
+
+export interface TokenResponse {
+  token: string;
+  refresh_token: string;
+  expires_in: number;
+}
+
+export interface WsTicketResponse {
+  ticket: string;
+  expires_in: number;
+}
+
+// Entry of the user directory: public profile plus presence as you see it
+export interface DirectoryUser extends PublicProfile {
+  status: PresenceStatus;
+  last_seen_at?: string | null;
+  status_text?: string | null;
+  status_emoji?: string | null;
+  status_expires_at?: string | null;
+}
+
+export interface DirectoryPage {
+  users: DirectoryUser[];
+  next_cursor: string | null;
+}
+
+export interface DirectoryParams {
+  q?: string;
+  search?: 'prefix' | 'substring';
+  sort?: 'username' | 'created_at';
+  order?: 'asc' | 'desc';
+  limit?: number;
+  cursor?: string;
+  exclude_blocked?: boolean;
+}
+
+// Payload of profile_updated events
+export interface PublicProfile {
+  id: number;
+  username: string;
+  display_name?: string | null;
+  bio?: string | null;
+  avatar?: string | null;
+  is_bot: boolean;
+}
+
+export interface Contact {
+  user: PublicProfile;
+  online: boolean;
+  status: PresenceStatus;
+  since?: string | null;
+}
+
+// Payload of friend_request events
+export interface FriendRequest {
+  id: number;
+  sender: PublicProfile;
+  receiver: PublicProfile;
+  message?: string | null;
+  created_at?: string | null;
+}
+
+export interface FriendRequests {
+  incoming: FriendRequest[];
+  outgoing: FriendRequest[];
+}
+
+export interface BlockedUser {
+  user: PublicProfile;
+  blocked_at?: string | null;
+}
+
 export interface WsEvent {
   event_type: string;
   user_id: number;